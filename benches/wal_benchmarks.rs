@@ -1,6 +1,9 @@
 use bytes::Bytes;
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
-use nano_wal::{Wal, WalOptions};
+use nano_wal::{ManualClock, Wal, WalOptions};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
 use std::time::Duration;
 use tempfile::TempDir;
 
@@ -48,6 +51,101 @@ fn bench_append_entry(c: &mut Criterion) {
             BatchSize::SmallInput,
         );
     });
+
+    c.bench_function("append_entry_non_durable_100_small_records", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                let wal =
+                    Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+                (wal, temp_dir)
+            },
+            |(mut wal, _temp_dir)| {
+                for i in 0..100u32 {
+                    wal.append_entry(
+                        black_box("bench_key"),
+                        black_box(None),
+                        black_box(Bytes::from(i.to_le_bytes().to_vec())),
+                        black_box(false),
+                    )
+                    .unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    // Baseline for the buffered appends above: the same small frames, each
+    // written as separate pieces, straight to the file or through a buffer
+    // flushed once per record
+    c.bench_function("frame_writes_unbuffered_100_small_records", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                let file = File::create(temp_dir.path().join("frames.log")).unwrap();
+                (file, temp_dir)
+            },
+            |(mut file, _temp_dir)| {
+                for i in 0..100u32 {
+                    write_small_frame(&mut file, black_box(&i.to_le_bytes()));
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    c.bench_function("frame_writes_buffered_100_small_records", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                let file = File::create(temp_dir.path().join("frames.log")).unwrap();
+                (BufWriter::new(file), temp_dir)
+            },
+            |(mut writer, _temp_dir)| {
+                for i in 0..100u32 {
+                    write_small_frame(&mut writer, black_box(&i.to_le_bytes()));
+                    writer.flush().unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    for (name, preallocate) in [
+        ("append_entry_durable_100_records", 0),
+        ("append_entry_durable_100_records_preallocated", 1024 * 1024),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let temp_dir = TempDir::new().unwrap();
+                    let options = WalOptions::default().preallocate(preallocate);
+                    let wal = Wal::new(temp_dir.path().to_str().unwrap(), options).unwrap();
+                    (wal, temp_dir)
+                },
+                |(mut wal, _temp_dir)| {
+                    for _ in 0..100 {
+                        wal.append_entry(
+                            black_box("bench_key"),
+                            black_box(None),
+                            black_box(Bytes::from("test data for benchmarking")),
+                            black_box(true),
+                        )
+                        .unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+/// Writes a headerless record frame piece by piece, as appends do.
+fn write_small_frame<W: Write>(writer: &mut W, content: &[u8]) {
+    writer.write_all(b"NANORC").unwrap();
+    writer.write_all(&0u16.to_le_bytes()).unwrap();
+    writer.write_all(&(content.len() as u64).to_le_bytes()).unwrap();
+    writer.write_all(content).unwrap();
 }
 
 fn bench_batch_operations(c: &mut Criterion) {
@@ -94,6 +192,73 @@ fn bench_batch_operations(c: &mut Criterion) {
             BatchSize::SmallInput,
         );
     });
+
+    c.bench_function("write_batch_100_entries", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                let wal =
+                    Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+                let entries: Vec<_> = (0..100)
+                    .map(|i| {
+                        (
+                            format!("key_{}", i % 10), // Use 10 different keys
+                            None,
+                            Bytes::from(format!("data_{}", i)),
+                        )
+                    })
+                    .collect();
+                (wal, entries, temp_dir)
+            },
+            |(mut wal, entries, _temp_dir)| {
+                let mut batch = wal.begin_batch();
+                for (key, header, content) in entries {
+                    batch.add(key, header, content).unwrap();
+                }
+                batch.commit(black_box(false)).unwrap()
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    // Records larger than the segment write buffer, so append_batch issues
+    // one write per record while a committed batch issues one per key
+    for (name, coalesce) in [
+        ("append_batch_100_large_entries", false),
+        ("write_batch_100_large_entries", true),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let temp_dir = TempDir::new().unwrap();
+                    let wal =
+                        Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+                    let entries: Vec<_> = (0..100)
+                        .map(|i| {
+                            (
+                                format!("key_{}", i % 10),
+                                None,
+                                Bytes::from(vec![i as u8; 16 * 1024]),
+                            )
+                        })
+                        .collect();
+                    (wal, entries, temp_dir)
+                },
+                |(mut wal, entries, _temp_dir)| {
+                    if coalesce {
+                        let mut batch = wal.begin_batch();
+                        for (key, header, content) in entries {
+                            batch.add(key, header, content).unwrap();
+                        }
+                        batch.commit(black_box(false)).unwrap()
+                    } else {
+                        wal.append_batch(entries, black_box(false)).unwrap()
+                    }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
 }
 
 fn bench_read_operations(c: &mut Criterion) {
@@ -106,7 +271,7 @@ fn bench_read_operations(c: &mut Criterion) {
         for i in 0..100 {
             let content = Bytes::from(format!("test data {}", i));
             refs.push(
-                wal.append_entry(&format!("key_{}", i % 10), None, content, false)
+                wal.append_entry(format!("key_{}", i % 10), None, content, false)
                     .unwrap(),
             );
         }
@@ -139,6 +304,46 @@ fn bench_read_operations(c: &mut Criterion) {
     });
 }
 
+/// Serial against parallel enumeration of a key spread over 50 segments.
+fn bench_enumerate_segments(c: &mut Criterion) {
+    let temp_dir = TempDir::new().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(Duration::from_secs(100))
+        .segments_per_retention_period(100)
+        .clock(clock.clone());
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), options).unwrap();
+    for segment in 0..50 {
+        for i in 0..200 {
+            let content = Bytes::from(format!("segment {} record {}", segment, i));
+            wal.append_entry("spread_key", None, content, false)
+                .unwrap();
+        }
+        clock.advance(Duration::from_secs(1));
+    }
+
+    let mut group = c.benchmark_group("enumerate_50_segments");
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            let records: Vec<_> = wal
+                .enumerate_records(black_box("spread_key"))
+                .unwrap()
+                .collect();
+            black_box(records)
+        });
+    });
+    #[cfg(feature = "parallel")]
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            black_box(
+                wal.enumerate_records_parallel(black_box("spread_key"))
+                    .unwrap(),
+            )
+        });
+    });
+    group.finish();
+}
+
 fn bench_with_headers(c: &mut Criterion) {
     c.bench_function("append_with_small_header", |b| {
         b.iter_batched(
@@ -229,7 +434,7 @@ fn bench_compact(c: &mut Criterion) {
                 // Create some expired segments
                 for i in 0..10 {
                     let content = Bytes::from(format!("test data {}", i));
-                    wal.append_entry(&format!("key_{}", i), None, content, false)
+                    wal.append_entry(format!("key_{}", i), None, content, false)
                         .unwrap();
                     std::thread::sleep(Duration::from_millis(1));
                 }
@@ -247,6 +452,7 @@ criterion_group!(
     bench_append_entry,
     bench_batch_operations,
     bench_read_operations,
+    bench_enumerate_segments,
     bench_with_headers,
     bench_segment_rotation,
     bench_compact
@@ -9,7 +9,7 @@
 
 use bytes::Bytes;
 use nano_wal::{Wal, WalOptions};
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -32,6 +32,8 @@ fn main() {
     // Shared atomic counter to track successful writes
     let global_counter = Arc::new(AtomicU16::new(0));
     let counter_clone = Arc::clone(&global_counter);
+    let crashed = Arc::new(AtomicBool::new(false));
+    let crashed_clone = Arc::clone(&crashed);
 
     let wal_dir_clone = wal_dir.to_string();
 
@@ -44,7 +46,7 @@ fn main() {
         let mut wal = Wal::new(&wal_dir_clone, WalOptions::default()).unwrap();
         let mut rng_state = 42u32; // Simple PRNG for deterministic randomness
 
-        loop {
+        while !crashed_clone.load(Ordering::SeqCst) {
             // Random delay between 1-300ms as specified
             rng_state = rng_state.wrapping_mul(1664525).wrapping_add(1013904223);
             let delay = 1 + (rng_state % 300);
@@ -58,7 +60,7 @@ fn main() {
             // Write with durability enabled (fsync after write)
             match wal.append_entry("crash-test", None, content, true) {
                 Ok(_) => {
-                    if record_id % 10 == 0 {
+                    if record_id.is_multiple_of(10) {
                         println!("   ✅ Wrote record {}", record_id);
                     }
                 }
@@ -83,18 +85,17 @@ fn main() {
     thread::sleep(Duration::from_millis(wait_duration as u64));
     let actual_duration = start_time.elapsed();
 
-    // Get final counter value before "crash"
-    let final_counter = global_counter.load(Ordering::SeqCst);
-
     println!("💥 Simulating abrupt process termination!");
-    println!("   📊 Records written: {}", final_counter);
-    println!("   🕐 Actual runtime: {:?}", actual_duration);
 
-    // Abruptly terminate the writer thread (no graceful shutdown)
-    drop(writer_thread);
+    // Stop the writer thread without a graceful shutdown. The WAL is only
+    // dropped, which releases the directory lock just as process exit would.
+    crashed.store(true, Ordering::SeqCst);
+    writer_thread.join().unwrap();
 
-    // Small delay to ensure any pending filesystem operations complete
-    thread::sleep(Duration::from_millis(100));
+    // Get final counter value once no write is in flight
+    let final_counter = global_counter.load(Ordering::SeqCst);
+    println!("   📊 Records written: {}", final_counter);
+    println!("   🕐 Actual runtime: {:?}", actual_duration);
 
     println!("🔍 Verifying data integrity after crash...");
 
@@ -157,21 +158,17 @@ fn main() {
         "   💪 Durability: {}/{} records persisted ({}%)",
         recovered_count,
         final_counter,
-        if final_counter > 0 {
-            recovered_count * 100 / final_counter
-        } else {
-            0
-        }
+        (recovered_count * 100)
+            .checked_div(final_counter)
+            .unwrap_or(0)
     );
     println!(
         "   🔒 Integrity: {}/{} records valid ({}%)",
         recovered_count - corrupted_count,
         recovered_count,
-        if recovered_count > 0 {
-            (recovered_count - corrupted_count) * 100 / recovered_count
-        } else {
-            0
-        }
+        ((recovered_count - corrupted_count) * 100)
+            .checked_div(recovered_count)
+            .unwrap_or(0)
     );
 
     if recovered_count == final_counter && corrupted_count == 0 {
@@ -329,13 +329,11 @@ impl MessageBroker {
         let mut total_messages = 0;
         let mut partition_stats = Vec::new();
 
+        // One pass over the segments counts every partition at once
+        let counts = self.message_wal.key_record_counts()?;
         for partition in 0..partition_count {
             let topic_partition = format!("{}:partition:{}", topic, partition);
-            let records: Vec<Bytes> = self
-                .message_wal
-                .enumerate_records(topic_partition)?
-                .collect();
-            let message_count = records.len();
+            let message_count = counts.get(&topic_partition).copied().unwrap_or(0) as usize;
             total_messages += message_count;
 
             partition_stats.push(PartitionStats {
@@ -0,0 +1,99 @@
+//! Segment Inspector for nano-wal
+//!
+//! Prints the header, record counts and framing anomalies of segment files
+//! without opening the WAL they belong to, so a single `.log` or `.logz` file copied
+//! from a damaged directory can be examined on its own.
+//!
+//! Run with: cargo run --example inspect -- <segment.log>...
+
+use nano_wal::{SegmentAnomaly, Wal};
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: cargo run --example inspect -- <segment.log>...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut healthy = true;
+    for path in &paths {
+        println!("{}", path);
+        let inspection = match Wal::inspect_segment(Path::new(path)) {
+            Ok(inspection) => inspection,
+            Err(e) => {
+                println!("   ✗ unreadable: {}", e);
+                healthy = false;
+                continue;
+            }
+        };
+
+        println!("   key:          {}", inspection.key);
+        match (inspection.key_hash, inspection.sequence_number) {
+            (Some(key_hash), Some(sequence)) => {
+                println!("   key hash:     {}", key_hash);
+                println!("   sequence:     {}", sequence);
+            }
+            _ => println!("   sequence:     unknown (not a segment filename)"),
+        }
+        println!("   expires at:   {}", inspection.expiration_timestamp);
+        println!(
+            "   format:       v{}{}{}",
+            inspection.format_version,
+            if inspection.encrypted {
+                ", encrypted"
+            } else {
+                ""
+            },
+            if inspection.compressed {
+                ", compressed"
+            } else {
+                ""
+            }
+        );
+        println!("   records:      {}", inspection.record_count);
+        println!("   tombstones:   {}", inspection.tombstone_count);
+        println!(
+            "   body:         {} bytes, {} in intact records",
+            inspection.body_size, inspection.records_size
+        );
+
+        if inspection.anomalies.is_empty() {
+            println!("   ✓ no anomalies");
+        }
+        for anomaly in &inspection.anomalies {
+            healthy &= matches!(anomaly, SegmentAnomaly::ZeroFilledTail { .. });
+            match anomaly {
+                SegmentAnomaly::ZeroFilledTail { offset, len } => {
+                    println!(
+                        "   • {} zero bytes at offset {} (preallocated or unused)",
+                        len, offset
+                    )
+                }
+                SegmentAnomaly::TornRecord { offset, len } => {
+                    println!("   ✗ torn record at offset {} ({} bytes)", offset, len)
+                }
+                SegmentAnomaly::UnrecognizedBytes { offset, len } => {
+                    println!("   ✗ {} unrecognized bytes at offset {}", len, offset)
+                }
+                SegmentAnomaly::MalformedTombstone { offset } => {
+                    println!("   ✗ malformed tombstone at offset {}", offset)
+                }
+                SegmentAnomaly::UncommittedGroup { offset, len } => {
+                    println!(
+                        "   ✗ uncommitted record group at offset {} ({} bytes)",
+                        offset, len
+                    )
+                }
+            }
+        }
+        println!();
+    }
+
+    if healthy {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
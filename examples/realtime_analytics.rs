@@ -109,7 +109,6 @@ impl Default for Metrics {
 pub struct AnalyticsPipeline {
     events_wal: Wal,
     metrics_wal: Wal,
-    dedup_cache: HashMap<String, u64>,
     unique_users: HashMap<String, bool>,
     total_load_time: f64,
     load_time_count: u64,
@@ -122,7 +121,8 @@ impl AnalyticsPipeline {
         // High-frequency events with shorter retention
         let events_options = WalOptions::default()
             .retention(Duration::from_secs(60 * 60 * 24 * 7)) // 1 week
-            .segments_per_retention_period(168); // 1 hour per segment
+            .segments_per_retention_period(168) // 1 hour per segment
+            .dedup_window(Duration::from_secs(60 * 60)); // Skip repeats within 1 hour
 
         // Aggregated metrics with longer retention
         let metrics_options = WalOptions::default()
@@ -135,7 +135,6 @@ impl AnalyticsPipeline {
         Ok(Self {
             events_wal,
             metrics_wal,
-            dedup_cache: HashMap::new(),
             unique_users: HashMap::new(),
             total_load_time: 0.0,
             load_time_count: 0,
@@ -164,15 +163,6 @@ impl AnalyticsPipeline {
             _ => None,
         };
 
-        // Check for duplicates
-        if let Some(ref key) = dedup_key {
-            if self.dedup_cache.contains_key(key) {
-                println!("Duplicate event detected, skipping: {}", key);
-                return Ok(None);
-            }
-            self.dedup_cache.insert(key.clone(), now);
-        }
-
         let header = EventHeader {
             event_id: uuid::Uuid::new_v4().to_string(),
             source: source.to_string(),
@@ -182,7 +172,7 @@ impl AnalyticsPipeline {
             user_id: user_id.clone(),
             ip_address,
             user_agent,
-            dedup_key,
+            dedup_key: dedup_key.clone(),
         };
 
         // Determine stream based on event type
@@ -197,9 +187,27 @@ impl AnalyticsPipeline {
         let header_bytes = Some(Bytes::from(serde_json::to_string(&header)?));
         let event_bytes = Bytes::from(serde_json::to_string(&event)?);
 
-        let entry_ref = self
-            .events_wal
-            .log_entry(stream_key, header_bytes, event_bytes)?;
+        // The WAL skips events whose dedup key was seen recently
+        let entry_ref = match dedup_key {
+            Some(ref key) => {
+                match self.events_wal.append_entry_dedup(
+                    stream_key,
+                    key.as_bytes(),
+                    header_bytes,
+                    event_bytes,
+                    true,
+                )? {
+                    Some(entry_ref) => entry_ref,
+                    None => {
+                        println!("Duplicate event detected, skipping: {}", key);
+                        return Ok(None);
+                    }
+                }
+            }
+            None => self
+                .events_wal
+                .log_entry(stream_key, header_bytes, event_bytes)?,
+        };
 
         // Update real-time metrics
         self.update_metrics(&header, &event)?;
@@ -259,10 +267,15 @@ impl AnalyticsPipeline {
     pub fn calculate_metrics(&self) -> Result<Metrics, Box<dyn std::error::Error>> {
         let mut metrics = Metrics::default();
 
-        // Page views
-        let page_view_records: Vec<Bytes> =
-            self.events_wal.enumerate_records("page_views")?.collect();
-        metrics.page_views = page_view_records.len() as u64;
+        // Page views and top pages
+        metrics.page_views = self.events_wal.replay("page_views", |record| {
+            if let Ok(AnalyticsEvent::PageView { page_url, .. }) =
+                serde_json::from_slice::<AnalyticsEvent>(record)
+            {
+                *metrics.top_pages.entry(page_url).or_insert(0) += 1;
+            }
+            Ok(())
+        })?;
 
         // Unique users
         metrics.unique_users = self.unique_users.len() as u64;
@@ -275,38 +288,25 @@ impl AnalyticsPipeline {
         };
 
         // Calculate revenue from purchases
-        let purchase_records: Vec<Bytes> =
-            self.events_wal.enumerate_records("purchases")?.collect();
-        for record in &purchase_records {
-            if let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(&record) {
-                if let AnalyticsEvent::Purchase { total_amount, .. } = event {
-                    metrics.total_revenue += total_amount;
-                }
+        self.events_wal.replay("purchases", |record| {
+            if let Ok(AnalyticsEvent::Purchase { total_amount, .. }) =
+                serde_json::from_slice::<AnalyticsEvent>(record)
+            {
+                metrics.total_revenue += total_amount;
             }
-        }
-
-        // Error count
-        let error_records: Vec<Bytes> = self.events_wal.enumerate_records("errors")?.collect();
-        metrics.error_count = error_records.len() as u64;
-
-        // Top pages
-        for record in &page_view_records {
-            if let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(&record) {
-                if let AnalyticsEvent::PageView { page_url, .. } = event {
-                    *metrics.top_pages.entry(page_url).or_insert(0) += 1;
-                }
+            Ok(())
+        })?;
+
+        // Error count and errors by severity
+        metrics.error_count = self.events_wal.replay("errors", |record| {
+            if let Ok(AnalyticsEvent::Error { severity, .. }) =
+                serde_json::from_slice::<AnalyticsEvent>(record)
+            {
+                let severity_str = format!("{:?}", severity);
+                *metrics.error_by_severity.entry(severity_str).or_insert(0) += 1;
             }
-        }
-
-        // Error by severity
-        for record in &error_records {
-            if let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(&record) {
-                if let AnalyticsEvent::Error { severity, .. } = event {
-                    let severity_str = format!("{:?}", severity);
-                    *metrics.error_by_severity.entry(severity_str).or_insert(0) += 1;
-                }
-            }
-        }
+            Ok(())
+        })?;
 
         Ok(metrics)
     }
@@ -357,7 +357,7 @@ impl AnalyticsPipeline {
 
         let mut events = Vec::new();
         for record in records.iter().rev().take(limit) {
-            if let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(&record) {
+            if let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(record) {
                 // In a real implementation, you'd parse the header from the WAL entry
                 let header = EventHeader {
                     event_id: uuid::Uuid::new_v4().to_string(),
@@ -387,15 +387,6 @@ impl AnalyticsPipeline {
         }
     }
 
-    pub fn cleanup_old_dedup_cache(&mut self, max_age_seconds: u64) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        self.dedup_cache
-            .retain(|_, &mut timestamp| now - timestamp < max_age_seconds);
-    }
-
     pub fn compact_old_data(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.events_wal.compact()?;
         self.metrics_wal.compact()?;
@@ -422,7 +413,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("1. Simulating High-Frequency Events");
 
     // Simulate page view events
-    let pages = vec![
+    let pages = [
         "/home",
         "/products",
         "/product/123",
@@ -517,7 +508,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Simulate error events
     println!("\n3. Logging Error Events");
-    let errors = vec![
+    let errors = [
         ("AUTH001", "Invalid credentials", ErrorSeverity::Warning),
         ("DB002", "Connection timeout", ErrorSeverity::Error),
         ("SYS003", "Out of memory", ErrorSeverity::Critical),
@@ -612,11 +603,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         } = event
         {
             println!(
-                "   • [{}] {} - {} ({})",
-                header.timestamp,
-                error_code,
-                error_message,
-                format!("{:?}", severity)
+                "   • [{}] {} - {} ({:?})",
+                header.timestamp, error_code, error_message, severity
             );
         }
     }
@@ -643,7 +631,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Cleanup and final stats
     println!("\n10. Cleanup and Final Statistics");
-    analytics.cleanup_old_dedup_cache(3600); // Clean cache older than 1 hour
     analytics.compact_old_data()?;
 
     let _final_metrics = analytics.calculate_metrics()?;
@@ -40,13 +40,17 @@
 
 use bytes::Bytes;
 use chrono::Utc;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
 use std::fmt::{self, Debug, Display};
 use std::fs::{self, File, OpenOptions};
 use std::hash::{Hash, Hasher};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufReader, BufWriter, IoSlice, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// UTF-8 'NANO-LOG' signature for segment file headers.
 ///
@@ -63,12 +67,121 @@ const NANO_LOG_SIGNATURE: [u8; 8] = [b'N', b'A', b'N', b'O', b'-', b'L', b'O', b
 /// The 6-byte size is chosen to balance overhead with reliability.
 const NANO_REC_SIGNATURE: [u8; 6] = [b'N', b'A', b'N', b'O', b'R', b'C'];
 
-/// Maximum size for record headers in bytes (64KB).
+/// UTF-8 'NANO-ARC' signature for key export archives.
 ///
-/// Headers larger than this will be rejected to prevent memory exhaustion
-/// and ensure reasonable performance. This size is sufficient for most
-/// metadata use cases while preventing abuse.
-const MAX_HEADER_SIZE: usize = 65535;
+/// Written at the start of the portable archive produced by
+/// `Wal::export_key` so that `Wal::import_key` can reject foreign input.
+const NANO_ARCHIVE_SIGNATURE: [u8; 8] = [b'N', b'A', b'N', b'O', b'-', b'A', b'R', b'C'];
+
+/// Maximum size for record headers in bytes (4GB).
+///
+/// This is the ceiling imposed by the `u32` that follows an escaped header
+/// length field; `WalOptions::max_header_size` may not exceed it.
+const MAX_HEADER_SIZE: usize = u32::MAX as usize;
+
+/// Default for `WalOptions::max_header_size` (64KB), the largest header
+/// whose length fits the 2-byte field without escaping.
+const DEFAULT_MAX_HEADER_SIZE: usize = 65535;
+
+/// Header length field value meaning a `u32` length follows.
+const HEADER_LEN_ESCAPE: u16 = u16::MAX;
+
+/// Bytes added to every record by the default fixed-width framing.
+///
+/// A record frame is the 6-byte record signature, the 2-byte header
+/// length and the 8-byte content length, followed by the header and the
+/// content. A header of 65535 bytes or more adds a 4-byte length after the
+/// escaped 2-byte one. See [`Wal::record_overhead`] for the overhead under
+/// the configured framing.
+pub const RECORD_OVERHEAD: usize = NANO_REC_SIGNATURE.len() + 2 + 8;
+
+/// Name of the advisory lock file held by an open `Wal`.
+const LOCK_FILE_NAME: &str = ".nano-wal.lock";
+
+/// Name of the file recording the options a WAL directory was opened with.
+const MANIFEST_FILE_NAME: &str = ".nano-wal.manifest";
+
+/// Name of the file mapping each key to its latest durable record.
+const CHECKPOINT_FILE_NAME: &str = ".nano-wal.checkpoint";
+
+/// Signature at the start of the checkpoint file.
+const CHECKPOINT_SIGNATURE: [u8; 8] = *b"NANO-CKP";
+
+/// File written and deleted by [`WalBuilder::build`] to check writability.
+const PROBE_FILE_NAME: &str = ".nano-wal.probe";
+
+/// Maximum number of key characters kept in segment filenames.
+const SANITIZED_KEY_LEN: usize = 20;
+
+/// Most key hash bits `WalOptions::shard_dirs` may spread segments over.
+const MAX_SHARD_DIR_BITS: u8 = 16;
+
+/// Current segment file format version.
+///
+/// Stored in the first two bytes of the 8-byte format descriptor that
+/// follows the `NANO-LOG` signature. Files written by nano-wal 0.5 and
+/// earlier carry an all-zero descriptor and therefore read as version 0.
+/// Version 2 added the record framing byte; older files leave it zero,
+/// which is the fixed-width framing they were written with. Version 3
+/// added the header checksum. Version 4 added tombstone frames, which
+/// earlier readers would mistake for the end of the segment. Version 5
+/// added the group markers written by `Wal::append_group` and the padding
+/// frames written under `WalOptions::alignment`, likewise. Version 6
+/// added the producer markers written by `Wal::append_idempotent`.
+/// Version 7 reserved the fixed-width header length `0xFFFF` as an escape
+/// to a `u32` length, which earlier versions read as 65535.
+const FORMAT_VERSION: u16 = 7;
+
+/// First format version whose fixed-width header lengths can be escaped.
+const HEADER_LEN_ESCAPE_VERSION: u16 = 7;
+
+/// First format version whose headers carry a checksum.
+const HEADER_CHECKSUM_VERSION: u16 = 3;
+
+/// Bit of the stored format version marking a segment whose record
+/// content is encrypted.
+///
+/// Readers that predate encryption see an unknown version and refuse the
+/// segment instead of returning ciphertext.
+const ENCRYPTED_SEGMENT_FLAG: u16 = 0x8000;
+
+/// Bit of the stored format version marking a segment whose body is a
+/// zstd frame, written by `Wal::compress_sealed_segments`.
+///
+/// The header itself stays uncompressed, so it can be read without
+/// decompressing the file. Readers that predate compression see an
+/// unknown version and refuse the segment.
+const COMPRESSED_SEGMENT_FLAG: u16 = 0x4000;
+
+/// Number of decompressed segments a `Wal` keeps in memory for reads.
+const DECOMPRESSED_SEGMENT_CACHE: usize = 4;
+
+/// 64-bit FNV-1a offset basis.
+const FNV1A_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// 64-bit FNV-1a prime.
+const FNV1A_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Lookup table for CRC-32 (IEEE 802.3, reflected polynomial `0xEDB88320`).
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
 
 /// Custom error type for WAL operations.
 ///
@@ -85,6 +198,11 @@ pub enum WalError {
     CorruptedData(String),
     /// Header size exceeds maximum allowed
     HeaderTooLarge { size: usize, max: usize },
+    /// Entry offset lies outside the segment body
+    InvalidOffset { offset: u64, segment_len: u64 },
+    /// Segment or directory written in a format version newer than this
+    /// crate supports
+    UnsupportedFormat { found: u16, supported: u16 },
 }
 
 impl fmt::Display for WalError {
@@ -97,6 +215,21 @@ impl fmt::Display for WalError {
             WalError::HeaderTooLarge { size, max } => {
                 write!(f, "Header size {} exceeds maximum {}", size, max)
             }
+            WalError::InvalidOffset {
+                offset,
+                segment_len,
+            } => write!(
+                f,
+                "Offset {} is outside segment body of {} bytes",
+                offset, segment_len
+            ),
+            WalError::UnsupportedFormat { found, supported } => write!(
+                f,
+                "Format version {} was written by a newer nano-wal; nano-wal {} reads up to version {}",
+                found,
+                env!("CARGO_PKG_VERSION"),
+                supported
+            ),
         }
     }
 }
@@ -119,6 +252,44 @@ impl From<io::Error> for WalError {
 /// Custom Result type for WAL operations.
 pub type Result<T> = std::result::Result<T, WalError>;
 
+/// Error returned by `Wal::append_batch` when the batch stops part way.
+///
+/// Entries before `failed_index` are confirmed written and listed in
+/// `written`; the remaining entries must be treated as not written.
+#[derive(Debug)]
+pub struct BatchError {
+    /// References to the entries that were written, in batch order
+    pub written: Vec<EntryRef>,
+    /// Index of the first entry that was not written
+    pub failed_index: usize,
+    /// The error that stopped the batch
+    pub source: WalError,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Batch failed at entry {} after {} written: {}",
+            self.failed_index,
+            self.written.len(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<BatchError> for WalError {
+    fn from(e: BatchError) -> Self {
+        e.source
+    }
+}
+
 /// Reference to a specific entry location in the WAL.
 ///
 /// An `EntryRef` uniquely identifies an entry's location within the WAL,
@@ -129,299 +300,9482 @@ pub struct EntryRef {
     pub key_hash: u64,
     /// Sequence number of the segment file
     pub sequence_number: u64,
-    /// Byte offset within the segment file (after header)
+    /// Byte offset within the segment file (after header); see
+    /// [`EntryRef::absolute_offset`] for the offset from the file start
     pub offset: u64,
 }
 
-/// Configuration options for WAL behavior.
-///
-/// # Examples
+impl EntryRef {
+    /// Returns the offset of the record from the start of its segment
+    /// file, for a segment of a key `key_len` bytes long.
+    ///
+    /// The file header is `8 + 8 + 8 + 8 + key_len` bytes: the signature,
+    /// the format descriptor, the expiration timestamp, the key length and
+    /// the key. [`Wal::file_header_size`] computes the same size from the
+    /// key. Compressed segments store their body compressed, so this only
+    /// locates records in uncompressed segment files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::EntryRef;
+    ///
+    /// let entry_ref = EntryRef { key_hash: 0, sequence_number: 1, offset: 10 };
+    /// assert_eq!(entry_ref.absolute_offset("orders".len()), 32 + 6 + 10);
+    /// ```
+    pub fn absolute_offset(&self, key_len: usize) -> u64 {
+        file_header_size(key_len) + self.offset
+    }
+}
+
+/// Detailed outcome of [`Wal::append_entry_detailed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendResult {
+    /// Location of the record just written
+    pub entry_ref: EntryRef,
+    /// Offset at which the next record for the key will land, unless the
+    /// segment rotates first
+    pub next_offset: u64,
+    /// Sequence number of the segment the record was written to
+    pub segment_sequence: u64,
+    /// Whether this append opened a new segment for the key
+    pub rotated: bool,
+}
+
+/// A record read by [`Wal::read_entry_meta_at`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryWithMeta {
+    /// Record header, if the record has one
+    pub header: Option<Bytes>,
+    /// Record content
+    pub content: Bytes,
+    /// Whether the record lies in the key's active segment, which may
+    /// still be growing
+    pub read_from_active: bool,
+}
+
+/// Kind of a frame returned by [`Wal::enumerate_entries`].
 ///
-/// ```
-/// use nano_wal::WalOptions;
-/// use std::time::Duration;
+/// Stored as the frame type byte of the frame signature, the fifth byte,
+/// as in `NANORC` for data and `NANOTC` for tombstones. New kinds may be
+/// added, so matches need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RecordKind {
+    /// A record written by an append
+    Data,
+    /// A tombstone written by [`Wal::append_tombstone`]; its content
+    /// names the deleted record
+    Tombstone,
+    /// The marker opening a group written by [`Wal::append_group`]; its
+    /// content is the group's length in bytes, little endian
+    GroupBegin,
+    /// The marker committing a group, with empty content
+    GroupCommit,
+}
+
+/// A frame of any kind, returned by [`Wal::enumerate_entries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// What the frame holds
+    pub kind: RecordKind,
+    /// Frame header, if it has one
+    pub header: Option<Bytes>,
+    /// Frame content, decrypted for data records and tombstones
+    pub content: Bytes,
+    /// Location of the frame
+    pub entry_ref: EntryRef,
+}
+
+/// Description of a segment, passed to [`WalOptions::on_rotate`] when it
+/// is sealed and returned by [`Wal::segments_for_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Key the segment belongs to
+    pub key: String,
+    /// Hash of the key
+    pub key_hash: u64,
+    /// Sequence number of the segment file
+    pub sequence_number: u64,
+    /// Path of the segment file
+    pub path: PathBuf,
+    /// Size of the segment file in bytes, including its header
+    pub size: u64,
+    /// Unix timestamp when the segment expires
+    pub expiration_timestamp: u64,
+    /// Whether appends can still grow the segment; `false` once sealed
+    pub active: bool,
+}
+
+/// Callback invoked with each segment sealed by rotation.
+pub type RotateCallback = Arc<dyn Fn(&SegmentInfo) + Send + Sync>;
+
+/// Hash function used to derive `key_hash` from a key.
 ///
-/// let options = WalOptions::default()
-///     .retention(Duration::from_secs(3600))
-///     .segments_per_retention_period(5);
-/// ```
-#[derive(Debug, Clone)]
-pub struct WalOptions {
-    /// Duration for which entries are retained before expiration
-    pub entry_retention: Duration,
-    /// Number of segments per retention period for rotation
-    pub segments_per_retention_period: u32,
+/// The key hash is encoded in every segment filename and in every
+/// [`EntryRef`], so it must stay stable for the lifetime of a WAL directory.
+/// The chosen hasher is recorded in each segment file header and checked
+/// when the directory is reopened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HasherKind {
+    /// The standard library `DefaultHasher` (SipHash).
+    ///
+    /// This is what nano-wal 0.5 and earlier used. Its output is not
+    /// guaranteed to be stable across Rust releases, so it is only provided
+    /// for opening directories written by those versions.
+    StdDefault,
+    /// 64-bit FNV-1a, implemented in this crate and stable across toolchains.
+    #[default]
+    Fnv1a,
 }
 
-impl Default for WalOptions {
-    fn default() -> Self {
-        Self {
-            entry_retention: Duration::from_secs(60 * 60 * 24 * 7), // 1 week
-            segments_per_retention_period: 10,
+impl HasherKind {
+    /// Identifier stored in the segment file header.
+    fn id(self) -> u8 {
+        match self {
+            HasherKind::StdDefault => 0,
+            HasherKind::Fnv1a => 1,
         }
     }
-}
 
-impl WalOptions {
-    /// Creates options with custom retention duration.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use nano_wal::WalOptions;
-    /// use std::time::Duration;
-    ///
-    /// let options = WalOptions::with_retention(Duration::from_secs(3600));
-    /// ```
-    pub fn with_retention(retention: Duration) -> Self {
-        Self {
-            entry_retention: retention,
-            ..Default::default()
+    /// Maps a header identifier back to a hasher.
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(HasherKind::StdDefault),
+            1 => Some(HasherKind::Fnv1a),
+            _ => None,
         }
     }
 
-    /// Creates options with custom segment count.
+    /// Hashes the raw key bytes.
     ///
     /// # Examples
     ///
     /// ```
-    /// use nano_wal::WalOptions;
+    /// use nano_wal::HasherKind;
     ///
-    /// let options = WalOptions::with_segments_per_retention_period(20);
+    /// assert_eq!(HasherKind::Fnv1a.hash_key(b""), 0xcbf29ce484222325);
     /// ```
-    pub fn with_segments_per_retention_period(segments: u32) -> Self {
-        Self {
-            segments_per_retention_period: segments,
-            ..Default::default()
+    pub fn hash_key(self, key: &[u8]) -> u64 {
+        match self {
+            HasherKind::StdDefault => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish()
+            }
+            HasherKind::Fnv1a => key.iter().fold(FNV1A_OFFSET_BASIS, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(FNV1A_PRIME)
+            }),
         }
     }
+}
 
-    /// Sets retention period (chainable).
-    pub fn retention(mut self, retention: Duration) -> Self {
-        self.entry_retention = retention;
-        self
-    }
+/// How thoroughly durable writes are flushed to stable storage.
+///
+/// Applies to `durable` appends, [`Wal::sync`] and the creation of new
+/// segment files. Non-durable appends are never synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// `fdatasync` the file. Record data survives a power loss, but the
+    /// directory entry of a newly created segment may not, so the whole
+    /// segment can disappear.
+    #[default]
+    DataOnly,
+    /// `fsync` the file, including metadata such as its size and
+    /// modification time. The directory entry may still be lost.
+    FullFile,
+    /// `fsync` the file and, whenever a segment file is created or renamed
+    /// into place, the WAL directory. Both the data and the segment's
+    /// directory entry survive a power loss. The directory sync is skipped
+    /// on platforms that cannot open directories.
+    IncludeDir,
+}
 
-    /// Sets segments per retention period (chainable).
-    pub fn segments_per_retention_period(mut self, segments: u32) -> Self {
-        self.segments_per_retention_period = segments;
-        self
-    }
+/// When an active segment stops accepting appends.
+///
+/// A segment expires at its creation time plus one segment duration
+/// (retention divided by segments per retention period), and
+/// [`Wal::compact`] removes it once that moment has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotationPolicy {
+    /// Rotate as soon as the segment's expiration is reached, so no record
+    /// is written at or after it.
+    #[default]
+    Strict,
+    /// Keep appending through the expiration second and rotate only once it
+    /// has passed and the segment is eligible for compaction. Records
+    /// written at the boundary expire with the segment they land in.
+    Lazy,
+}
 
-    /// Validates the configuration.
-    ///
-    /// # Errors
-    ///
-    /// Returns `WalError::InvalidConfig` if:
-    /// - `entry_retention` is zero
-    /// - `segments_per_retention_period` is zero
-    pub fn validate(&self) -> Result<()> {
-        if self.entry_retention.as_secs() == 0 {
-            return Err(WalError::InvalidConfig(
-                "entry_retention must be greater than 0".to_string(),
-            ));
-        }
-        if self.segments_per_retention_period == 0 {
-            return Err(WalError::InvalidConfig(
-                "segments_per_retention_period must be greater than 0".to_string(),
-            ));
+impl RotationPolicy {
+    /// Returns whether a segment expiring at `expiration_timestamp` still
+    /// accepts appends at `now`.
+    fn accepts_appends(self, now: u64, expiration_timestamp: u64) -> bool {
+        match self {
+            RotationPolicy::Strict => now < expiration_timestamp,
+            RotationPolicy::Lazy => now <= expiration_timestamp,
         }
-        Ok(())
     }
 }
 
-/// Information about an active segment for a specific key.
-#[derive(Debug)]
-struct ActiveSegment {
-    /// Current active file handle
-    file: File,
-    /// Sequence number of this segment
-    sequence_number: u64,
-    /// Unix timestamp when this segment expires
-    expiration_timestamp: u64,
+impl Durability {
+    /// Flushes a file to stable storage at this level.
+    fn sync_file(self, file: &dyn VfsFile) -> io::Result<()> {
+        match self {
+            Durability::DataOnly => file.sync_data(),
+            Durability::FullFile | Durability::IncludeDir => file.sync_all(),
+        }
+    }
 }
 
-/// Write-Ahead Log with per-key segment sets.
+/// Source of the current time used for segment rotation and expiration.
 ///
-/// The `Wal` struct provides the main interface for WAL operations,
-/// managing segment files and ensuring durability guarantees.
-#[derive(Debug)]
-pub struct Wal {
-    dir: PathBuf,
-    options: WalOptions,
-    /// Map from key hash to active segment info
-    active_segments: HashMap<u64, ActiveSegment>,
-    /// Map from key hash to next sequence number
-    next_sequence: HashMap<u64, u64>,
+/// The WAL never reads the system time directly; it asks the clock in
+/// [`WalOptions::clock`]. Tests can inject a [`ManualClock`] to control
+/// expiration without sleeping.
+pub trait Clock: Send + Sync {
+    /// Returns the current time as seconds since the Unix epoch.
+    fn now_unix(&self) -> u64;
 }
 
-impl Wal {
-    /// Creates a new WAL instance.
-    ///
-    /// # Arguments
-    ///
-    /// * `filepath` - Directory path for WAL files
-    /// * `options` - Configuration options
+/// Encrypts record content for encryption at rest.
+///
+/// Configured with [`WalOptions::encryption`]. `aad` is associated data
+/// binding each ciphertext to the key, segment sequence and offset of its
+/// record; a cipher must authenticate it, so a record moved to another
+/// position, segment or key fails to decrypt. Record headers are not
+/// encrypted.
+pub trait RecordCipher: Send + Sync + Debug {
+    /// Encrypts `plaintext`, returning everything needed to decrypt it,
+    /// such as the nonce and authentication tag.
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8>;
+
+    /// Decrypts the output of [`RecordCipher::encrypt`].
     ///
     /// # Errors
     ///
-    /// Returns `WalError::InvalidConfig` if options are invalid.
-    /// Returns `WalError::Io` if directory creation fails.
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// use nano_wal::{Wal, WalOptions};
-    ///
-    /// let wal = Wal::new("./my_wal", WalOptions::default())?;
-    /// # Ok::<(), nano_wal::WalError>(())
-    /// ```
-    pub fn new(filepath: &str, options: WalOptions) -> Result<Self> {
-        options.validate()?;
+    /// Returns `WalError::CorruptedData` if the ciphertext or `aad` fails
+    /// authentication.
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>>;
 
-        let dir = Path::new(filepath);
-        if !dir.exists() {
-            fs::create_dir_all(dir)?;
-        }
+    /// Returns the length of the longest plaintext the cipher encrypts.
+    /// Appends of longer content are rejected before anything is written.
+    fn max_plaintext_len(&self) -> u64 {
+        u64::MAX
+    }
+}
 
-        let mut wal = Wal {
-            dir: dir.to_path_buf(),
-            options,
-            active_segments: HashMap::new(),
-            next_sequence: HashMap::new(),
-        };
+/// Clock backed by the system wall clock. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
 
-        wal.scan_existing_files()?;
-        Ok(wal)
+impl Clock for SystemClock {
+    fn now_unix(&self) -> u64 {
+        Utc::now().timestamp() as u64
     }
+}
 
-    /// Scans existing files to determine next sequence numbers.
-    fn scan_existing_files(&mut self) -> Result<()> {
-        if let Ok(entries) = fs::read_dir(&self.dir) {
-            for entry in entries.flatten() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if filename.ends_with(".log") {
-                        if let Some((key_hash, sequence)) = self.parse_filename(filename) {
-                            let current_max = *self.next_sequence.get(&key_hash).unwrap_or(&0);
-                            self.next_sequence
-                                .insert(key_hash, current_max.max(sequence + 1));
-                        }
-                    }
-                }
-            }
+/// Clock that only moves when told to.
+///
+/// # Examples
+///
+/// ```
+/// use nano_wal::{Clock, ManualClock};
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new(1_000);
+/// clock.advance(Duration::from_secs(5));
+/// assert_eq!(clock.now_unix(), 1_005);
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a clock reading `now` seconds since the Unix epoch.
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now),
         }
-        Ok(())
     }
 
-    /// Parses segment filename to extract key hash and sequence.
-    fn parse_filename(&self, filename: &str) -> Option<(u64, u64)> {
-        if let Some(name_part) = filename.strip_suffix(".log") {
-            let parts: Vec<&str> = name_part.split('-').collect();
-            if parts.len() >= 3 {
-                let len = parts.len();
-                if let (Ok(sequence), Ok(key_hash)) =
-                    (parts[len - 1].parse::<u64>(), parts[len - 2].parse::<u64>())
-                {
-                    return Some((key_hash, sequence));
-                }
-            }
-        }
-        None
+    /// Sets the current time.
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
     }
 
-    /// Generates a filename for a segment.
-    fn generate_filename<K: Display>(&self, key: &K, key_hash: u64, sequence: u64) -> String {
-        let key_str = format!("{}", key);
-        let sanitized_key = key_str
-            .chars()
-            .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
-            .take(20)
-            .collect::<String>();
+    /// Moves the clock forward by `duration`, truncated to whole seconds.
+    pub fn advance(&self, duration: Duration) {
+        self.now.fetch_add(duration.as_secs(), Ordering::SeqCst);
+    }
+}
 
-        format!("{}-{}-{:04}.log", sanitized_key, key_hash, sequence)
+impl Clock for ManualClock {
+    fn now_unix(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
     }
+}
 
-    /// Gets or creates an active segment for the given key.
-    fn get_or_create_active_segment<K: Hash + AsRef<[u8]> + Display>(
-        &mut self,
-        key: &K,
-    ) -> Result<u64> {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        key.as_ref().hash(&mut hasher);
-        let key_hash = hasher.finish();
+/// Filesystem a WAL keeps its directory on.
+///
+/// [`Wal::new`] uses [`StdVfs`]; [`Wal::with_vfs`] opens a WAL over any
+/// other implementation, such as the in-memory [`MemVfs`]. The WAL only
+/// touches paths below the directory it was opened with, and the only
+/// directories it creates there are shard directories.
+pub trait Vfs: Send + Sync + Debug {
+    /// Opens an existing file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
 
-        let now = Utc::now().timestamp() as u64;
+    /// Opens an existing file for reading and writing.
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
 
-        // Check if rotation is needed
-        if let Some(active) = self.active_segments.get(&key_hash) {
-            if now >= active.expiration_timestamp {
-                self.active_segments.remove(&key_hash);
-            }
-        }
+    /// Creates a file for reading and writing, truncating it if it exists.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
 
-        // Create new segment if needed
-        if !self.active_segments.contains_key(&key_hash) {
-            let sequence = *self.next_sequence.get(&key_hash).unwrap_or(&1);
-            self.next_sequence.insert(key_hash, sequence + 1);
+    /// Creates a file for reading and writing, failing with
+    /// `io::ErrorKind::AlreadyExists` if it exists.
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
 
-            let segment_duration = self.options.entry_retention.as_secs()
-                / self.options.segments_per_retention_period as u64;
-            let expiration_timestamp = now + segment_duration;
+    /// Lists the entries of a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>>;
 
-            let filename = self.generate_filename(key, key_hash, sequence);
-            let file_path = self.dir.join(&filename);
+    /// Removes a file.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
 
-            let mut file = OpenOptions::new()
-                .create(true)
-                
-                .append(true)
-                .open(&file_path)?;
+    /// Renames a file, replacing `to` if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
 
-            self.write_file_header(&mut file, key, expiration_timestamp)?;
+    /// Returns the metadata of a file or directory.
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
 
-            let active_segment = ActiveSegment {
-                file,
-                sequence_number: sequence,
-                expiration_timestamp,
-            };
+    /// Creates a directory along with any missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
 
-            self.active_segments.insert(key_hash, active_segment);
-        }
+    /// Removes a directory and everything in it.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
 
-        Ok(key_hash)
-    }
+    /// Flushes the entries of a directory to stable storage, for
+    /// [`Durability::IncludeDir`].
+    fn sync_dir(&self, path: &Path) -> io::Result<()>;
+}
 
-    /// Writes file header for new segment.
-    fn write_file_header<K: AsRef<[u8]>>(
-        &self,
-        file: &mut File,
-        key: &K,
-        expiration_timestamp: u64,
-    ) -> Result<()> {
-        file.write_all(&NANO_LOG_SIGNATURE)?;
-        file.write_all(&0u64.to_le_bytes())?; // Sequence placeholder
-        file.write_all(&expiration_timestamp.to_le_bytes())?;
+/// File opened through a [`Vfs`].
+pub trait VfsFile: Read + Write + Seek + Send + Sync + Debug {
+    /// Returns the length of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
 
-        let key_bytes = key.as_ref();
-        let key_len = key_bytes.len() as u64;
-        file.write_all(&key_len.to_le_bytes())?;
-        file.write_all(key_bytes)?;
+    /// Truncates or zero-extends the file to `len` bytes.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    /// Flushes the file's data to stable storage.
+    fn sync_data(&self) -> io::Result<()>;
+
+    /// Flushes the file's data and metadata to stable storage.
+    fn sync_all(&self) -> io::Result<()>;
+
+    /// Takes an exclusive lock on the file without blocking, returning
+    /// whether it was acquired. The lock is released when the file is
+    /// closed.
+    fn try_lock(&self) -> io::Result<bool>;
+}
+
+impl<F: VfsFile + ?Sized> VfsFile for Box<F> {
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        (**self).sync_all()
+    }
+
+    fn try_lock(&self) -> io::Result<bool> {
+        (**self).try_lock()
+    }
+}
+
+/// Entry of a directory listed by [`Vfs::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsDirEntry {
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl VfsDirEntry {
+    /// Creates an entry for `path`, a file or a directory.
+    pub fn new(path: PathBuf, is_dir: bool) -> Self {
+        Self { path, is_dir }
+    }
+
+    /// Returns the full path of the entry.
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    /// Returns the last component of the path.
+    pub fn file_name(&self) -> OsString {
+        self.path.file_name().unwrap_or_default().to_owned()
+    }
+
+    /// Returns whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
+
+/// Metadata of a file or directory, returned by [`Vfs::metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsMetadata {
+    /// Length in bytes, zero for a directory
+    pub len: u64,
+    /// Whether the path is a directory
+    pub is_dir: bool,
+    /// Time of the last modification, if the filesystem records one
+    pub modified: Option<SystemTime>,
+}
+
+/// [`Vfs`] over `std::fs`. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdVfs;
+
+impl Vfs for StdVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(OpenOptions::new().write(true).open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(
+            OpenOptions::new().write(true).create_new(true).open(path)?,
+        ))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(VfsDirEntry::new(entry.path(), entry.file_type()?.is_dir()))
+            })
+            .collect()
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(VfsMetadata {
+            len: metadata.len(),
+            is_dir: metadata.is_dir(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(path)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+}
+
+impl VfsFile for File {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn try_lock(&self) -> io::Result<bool> {
+        match File::try_lock(self) {
+            Ok(()) => Ok(true),
+            Err(fs::TryLockError::WouldBlock) => Ok(false),
+            Err(fs::TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
+
+/// In-memory [`Vfs`], for tests and for embedding without a filesystem.
+///
+/// Every handle shares the same files, so a `MemVfs` behind an `Arc` can
+/// be opened by a second `Wal` after the first is dropped to simulate a
+/// restart. Syncs do nothing, and the files are gone once the last
+/// reference is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use nano_wal::{MemVfs, Wal, WalOptions};
+/// use bytes::Bytes;
+/// use std::sync::Arc;
+///
+/// let vfs = Arc::new(MemVfs::new());
+/// let mut wal = Wal::with_vfs("/wal", WalOptions::default(), vfs.clone())?;
+/// let entry_ref = wal.append_entry("orders", None, Bytes::from("data"), false)?;
+/// drop(wal);
+///
+/// let wal = Wal::with_vfs("/wal", WalOptions::default(), vfs)?;
+/// assert_eq!(wal.read_entry_at(entry_ref)?, "data");
+/// # Ok::<(), nano_wal::WalError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct MemVfs {
+    nodes: Mutex<BTreeMap<PathBuf, MemNode>>,
+    /// Counter standing in for modification times
+    clock: Arc<AtomicU64>,
+}
+
+/// File or directory of a [`MemVfs`].
+#[derive(Debug, Clone)]
+enum MemNode {
+    Dir,
+    File(Arc<Mutex<MemFileData>>),
+}
+
+/// Contents of a [`MemVfs`] file, shared by its open handles.
+#[derive(Debug, Default)]
+struct MemFileData {
+    bytes: Vec<u8>,
+    /// Value of the `MemVfs` clock at the last change
+    modified: u64,
+    /// Whether a handle holds the lock
+    locked: bool,
+}
+
+/// Open handle to a [`MemVfs`] file.
+#[derive(Debug)]
+struct MemFile {
+    data: Arc<Mutex<MemFileData>>,
+    clock: Arc<AtomicU64>,
+    position: u64,
+    writable: bool,
+    /// Whether this handle holds the file's lock
+    holds_lock: AtomicBool,
+}
+
+/// Locks `mutex`, ignoring poisoning: the data a panicking thread left is
+/// still consistent, since no critical section panics halfway.
+fn lock_unpoisoned<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl MemVfs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a new handle to the contents of a file.
+    fn handle(&self, data: Arc<Mutex<MemFileData>>, writable: bool) -> Box<dyn VfsFile> {
+        Box::new(MemFile {
+            data,
+            clock: self.clock.clone(),
+            position: 0,
+            writable,
+            holds_lock: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the file at `path`, failing if it is missing or a directory.
+    fn file(&self, path: &Path) -> io::Result<Arc<Mutex<MemFileData>>> {
+        match lock_unpoisoned(&self.nodes).get(path) {
+            Some(MemNode::File(data)) => Ok(data.clone()),
+            Some(MemNode::Dir) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    /// Creates the file at `path`, or truncates it unless `new` is set.
+    fn create_file(&self, path: &Path, new: bool) -> io::Result<Box<dyn VfsFile>> {
+        let mut nodes = lock_unpoisoned(&self.nodes);
+        let parent = path.parent().unwrap_or(Path::new(""));
+        if !parent.as_os_str().is_empty() && !matches!(nodes.get(parent), Some(MemNode::Dir)) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let modified = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        let data = match nodes.get(path) {
+            Some(MemNode::Dir) => return Err(io::Error::from(io::ErrorKind::IsADirectory)),
+            Some(MemNode::File(_)) if new => {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            }
+            Some(MemNode::File(data)) => {
+                let mut file = lock_unpoisoned(data);
+                file.bytes.clear();
+                file.modified = modified;
+                data.clone()
+            }
+            None => {
+                let data = Arc::new(Mutex::new(MemFileData {
+                    modified,
+                    ..Default::default()
+                }));
+                nodes.insert(path.to_path_buf(), MemNode::File(data.clone()));
+                data
+            }
+        };
+        drop(nodes);
+        Ok(self.handle(data, true))
+    }
+}
+
+impl Vfs for MemVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.handle(self.file(path)?, false))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.handle(self.file(path)?, true))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.create_file(path, false)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.create_file(path, true)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        let nodes = lock_unpoisoned(&self.nodes);
+        match nodes.get(path) {
+            Some(MemNode::Dir) => {}
+            Some(MemNode::File(_)) => return Err(io::Error::from(io::ErrorKind::NotADirectory)),
+            None => return Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+        Ok(nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path))
+            .map(|(child, node)| VfsDirEntry::new(child.clone(), matches!(node, MemNode::Dir)))
+            .collect())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.file(path)?;
+        lock_unpoisoned(&self.nodes).remove(path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let data = self.file(from)?;
+        let mut nodes = lock_unpoisoned(&self.nodes);
+        if matches!(nodes.get(to), Some(MemNode::Dir)) {
+            return Err(io::Error::from(io::ErrorKind::IsADirectory));
+        }
+        let parent = to.parent().unwrap_or(Path::new(""));
+        if !parent.as_os_str().is_empty() && !matches!(nodes.get(parent), Some(MemNode::Dir)) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        nodes.remove(from);
+        nodes.insert(to.to_path_buf(), MemNode::File(data));
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        match lock_unpoisoned(&self.nodes).get(path) {
+            Some(MemNode::Dir) => Ok(VfsMetadata {
+                len: 0,
+                is_dir: true,
+                modified: None,
+            }),
+            Some(MemNode::File(data)) => {
+                let file = lock_unpoisoned(data);
+                Ok(VfsMetadata {
+                    len: file.bytes.len() as u64,
+                    is_dir: false,
+                    modified: Some(UNIX_EPOCH + Duration::from_nanos(file.modified)),
+                })
+            }
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = lock_unpoisoned(&self.nodes);
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            match nodes.get(dir) {
+                Some(MemNode::Dir) => {}
+                Some(MemNode::File(_)) => {
+                    return Err(io::Error::from(io::ErrorKind::NotADirectory))
+                }
+                None => {
+                    nodes.insert(dir.to_path_buf(), MemNode::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = lock_unpoisoned(&self.nodes);
+        if !matches!(nodes.get(path), Some(MemNode::Dir)) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        nodes.retain(|node_path, _| !node_path.starts_with(path));
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.metadata(path).map(|_| ())
+    }
+}
+
+impl MemFile {
+    /// Fails unless the handle was opened for writing.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file opened read-only",
+            ))
+        }
+    }
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let file = lock_unpoisoned(&self.data);
+        let start = (self.position as usize).min(file.bytes.len());
+        let n = buf.len().min(file.bytes.len() - start);
+        buf[..n].copy_from_slice(&file.bytes[start..start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        let mut file = lock_unpoisoned(&self.data);
+        let start = self.position as usize;
+        let end = start + buf.len();
+        if file.bytes.len() < end {
+            file.bytes.resize(end, 0);
+        }
+        file.bytes[start..end].copy_from_slice(buf);
+        file.modified = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(delta) => (lock_unpoisoned(&self.data).bytes.len() as u64, delta),
+            SeekFrom::Current(delta) => (self.position, delta),
+        };
+        self.position = base.checked_add_signed(delta).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl VfsFile for MemFile {
+    fn size(&self) -> io::Result<u64> {
+        Ok(lock_unpoisoned(&self.data).bytes.len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.check_writable()?;
+        let mut file = lock_unpoisoned(&self.data);
+        file.bytes.resize(len as usize, 0);
+        file.modified = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(())
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn try_lock(&self) -> io::Result<bool> {
+        let mut file = lock_unpoisoned(&self.data);
+        if file.locked && !self.holds_lock.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        file.locked = true;
+        self.holds_lock.store(true, Ordering::SeqCst);
+        Ok(true)
+    }
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        if self.holds_lock.load(Ordering::SeqCst) {
+            lock_unpoisoned(&self.data).locked = false;
+        }
+    }
+}
+
+/// Signature at the start of a WAL kept in a single file.
+const SINGLE_FILE_SIGNATURE: [u8; 8] = *b"NANO-ONE";
+
+/// Journal size, beyond twice the size of the files it holds, above
+/// which a single-file WAL is rewritten, on open or once files are
+/// removed or replaced.
+const SINGLE_FILE_COMPACT_SLACK: u64 = 64 * 1024;
+
+/// Largest `Write` operation written when a single-file WAL is rewritten.
+const SINGLE_FILE_WRITE_CHUNK: usize = 1024 * 1024;
+
+/// Change to the files of a [`SingleFileVfs`], as recorded in its journal.
+///
+/// Paths are relative to the WAL directory. Files are named by an id
+/// assigned when they are created, so writes through a handle follow the
+/// file across renames like they would on a real filesystem.
+#[derive(Debug, PartialEq, Eq)]
+enum JournalOp<'a> {
+    CreateDir(&'a str),
+    RemoveDir(&'a str),
+    Create {
+        id: u64,
+        path: &'a str,
+    },
+    Remove {
+        id: u64,
+    },
+    Rename {
+        id: u64,
+        to: &'a str,
+    },
+    Write {
+        id: u64,
+        offset: u64,
+        bytes: &'a [u8],
+    },
+    SetLen {
+        id: u64,
+        len: u64,
+    },
+}
+
+impl<'a> JournalOp<'a> {
+    /// Encodes the operation as `[crc32:4][op:1][length:8][payload]`, the
+    /// checksum covering everything after it.
+    fn encode(&self) -> Vec<u8> {
+        fn push_str(payload: &mut Vec<u8>, s: &str) {
+            payload.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            payload.extend_from_slice(s.as_bytes());
+        }
+
+        let mut payload = Vec::new();
+        let code = match self {
+            JournalOp::CreateDir(path) => {
+                push_str(&mut payload, path);
+                1
+            }
+            JournalOp::RemoveDir(path) => {
+                push_str(&mut payload, path);
+                2
+            }
+            JournalOp::Create { id, path } => {
+                payload.extend_from_slice(&id.to_le_bytes());
+                push_str(&mut payload, path);
+                3
+            }
+            JournalOp::Remove { id } => {
+                payload.extend_from_slice(&id.to_le_bytes());
+                4
+            }
+            JournalOp::Rename { id, to } => {
+                payload.extend_from_slice(&id.to_le_bytes());
+                push_str(&mut payload, to);
+                5
+            }
+            JournalOp::Write { id, offset, bytes } => {
+                payload.extend_from_slice(&id.to_le_bytes());
+                payload.extend_from_slice(&offset.to_le_bytes());
+                payload.extend_from_slice(bytes);
+                6
+            }
+            JournalOp::SetLen { id, len } => {
+                payload.extend_from_slice(&id.to_le_bytes());
+                payload.extend_from_slice(&len.to_le_bytes());
+                7
+            }
+        };
+
+        let mut frame = Vec::with_capacity(13 + payload.len());
+        frame.extend_from_slice(&[0; 4]);
+        frame.push(code);
+        frame.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        let checksum = crc32(&frame[4..]);
+        frame[..4].copy_from_slice(&checksum.to_le_bytes());
+        frame
+    }
+
+    /// Decodes the payload of an operation of type `code`, or `None` if it
+    /// is malformed.
+    fn decode(code: u8, payload: &'a [u8]) -> Option<Self> {
+        fn u64_at(payload: &[u8], at: usize) -> Option<u64> {
+            Some(u64::from_le_bytes(
+                payload.get(at..at + 8)?.try_into().ok()?,
+            ))
+        }
+        /// Reads a length-prefixed path that ends the payload.
+        fn str_at(payload: &[u8], at: usize) -> Option<&str> {
+            let len = u32::from_le_bytes(payload.get(at..at + 4)?.try_into().ok()?) as usize;
+            if at + 4 + len != payload.len() {
+                return None;
+            }
+            std::str::from_utf8(&payload[at + 4..]).ok()
+        }
+
+        match code {
+            1 => Some(JournalOp::CreateDir(str_at(payload, 0)?)),
+            2 => Some(JournalOp::RemoveDir(str_at(payload, 0)?)),
+            3 => Some(JournalOp::Create {
+                id: u64_at(payload, 0)?,
+                path: str_at(payload, 8)?,
+            }),
+            4 if payload.len() == 8 => Some(JournalOp::Remove {
+                id: u64_at(payload, 0)?,
+            }),
+            5 => Some(JournalOp::Rename {
+                id: u64_at(payload, 0)?,
+                to: str_at(payload, 8)?,
+            }),
+            6 => Some(JournalOp::Write {
+                id: u64_at(payload, 0)?,
+                offset: u64_at(payload, 8)?,
+                bytes: payload.get(16..)?,
+            }),
+            7 if payload.len() == 16 => Some(JournalOp::SetLen {
+                id: u64_at(payload, 0)?,
+                len: u64_at(payload, 8)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// [`Vfs`] keeping a whole WAL directory in one file, for
+/// [`Wal::open_single_file`].
+///
+/// The files are held in memory by a [`MemVfs`], and every change to them
+/// is appended to the container file as a [`JournalOp`]. Opening the file
+/// replays the journal up to the first operation that is torn or fails
+/// its checksum, and truncates the rest away. Syncing any file syncs the
+/// container. Once the journal is mostly dead data, whether on open or
+/// after a file is removed or replaced, it is rewritten with only the
+/// current files. The container is locked while open.
+#[derive(Debug)]
+struct SingleFileVfs {
+    /// Directory the WAL sees, the path of the container file
+    root: PathBuf,
+    files: MemVfs,
+    state: Arc<Mutex<JournalState>>,
+}
+
+/// Journal of a [`SingleFileVfs`] and the file ids it refers to.
+#[derive(Debug)]
+struct JournalState {
+    journal: File,
+    /// Length of the journal file
+    len: u64,
+    ids: HashMap<PathBuf, u64>,
+    paths: HashMap<u64, PathBuf>,
+    next_id: u64,
+}
+
+impl JournalState {
+    /// Appends `op` to the journal.
+    fn append(&mut self, op: &JournalOp) -> io::Result<()> {
+        let frame = op.encode();
+        self.journal.write_all(&frame)?;
+        self.len += frame.len() as u64;
+        Ok(())
+    }
+}
+
+impl SingleFileVfs {
+    /// Opens the container file at `path`, creating it if missing, and
+    /// loads the files it holds.
+    fn open(path: &Path) -> Result<Self> {
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if !VfsFile::try_lock(&journal)? {
+            return Err(WalError::InvalidConfig(format!(
+                "{} is already locked by another Wal",
+                path.display()
+            )));
+        }
+
+        let vfs = Self {
+            root: path.to_path_buf(),
+            files: MemVfs::new(),
+            state: Arc::new(Mutex::new(JournalState {
+                journal: journal.try_clone()?,
+                len: SINGLE_FILE_SIGNATURE.len() as u64,
+                ids: HashMap::new(),
+                paths: HashMap::new(),
+                next_id: 1,
+            })),
+        };
+        let len = journal.metadata()?.len();
+        if len == 0 {
+            journal.write_all(&SINGLE_FILE_SIGNATURE)?;
+            journal.sync_all()?;
+            return Ok(vfs);
+        }
+
+        let mut reader = BufReader::new(&journal);
+        let mut signature = [0u8; 8];
+        if reader.read_exact(&mut signature).is_err() || signature != SINGLE_FILE_SIGNATURE {
+            return Err(WalError::CorruptedData(format!(
+                "{} is not a single-file WAL",
+                path.display()
+            )));
+        }
+        let mut valid_len = SINGLE_FILE_SIGNATURE.len() as u64;
+        let mut state = lock_unpoisoned(&vfs.state);
+        {
+            let mut prefix = [0u8; 13];
+            while reader.read_exact(&mut prefix).is_ok() {
+                let payload_len = u64::from_le_bytes(prefix[5..].try_into().unwrap());
+                if payload_len > len - valid_len - 13 {
+                    break;
+                }
+                let mut frame = prefix[4..].to_vec();
+                frame.resize(9 + payload_len as usize, 0);
+                if reader.read_exact(&mut frame[9..]).is_err()
+                    || crc32(&frame) != u32::from_le_bytes(prefix[..4].try_into().unwrap())
+                {
+                    break;
+                }
+                let Some(op) = JournalOp::decode(frame[0], &frame[9..]) else {
+                    break;
+                };
+                vfs.apply(&mut state, &op)?;
+                valid_len += 13 + payload_len;
+            }
+        }
+        drop(reader);
+        if valid_len < len {
+            journal.set_len(valid_len)?;
+            journal.sync_all()?;
+        }
+        journal.seek(SeekFrom::End(0))?;
+        state.len = valid_len;
+
+        if vfs.mostly_dead(&state) {
+            vfs.rewrite(&mut state)?;
+        }
+        drop(state);
+        Ok(vfs)
+    }
+
+    /// Returns whether the journal has grown past twice the size of the
+    /// files it holds, plus some slack.
+    fn mostly_dead(&self, state: &JournalState) -> bool {
+        let live: u64 = lock_unpoisoned(&self.files.nodes)
+            .values()
+            .map(|node| match node {
+                MemNode::File(data) => lock_unpoisoned(data).bytes.len() as u64,
+                MemNode::Dir => 0,
+            })
+            .sum();
+        state.len > live.saturating_mul(2) + SINGLE_FILE_COMPACT_SLACK
+    }
+
+    /// Rewrites the journal if it is mostly dead data, after a change that
+    /// dropped file contents.
+    ///
+    /// Best effort: the change is already journaled, and a failed rewrite
+    /// leaves the journal as it was, so it is retried after the next one.
+    fn compact_if_mostly_dead(&self, state: &mut JournalState) {
+        if self.mostly_dead(state) {
+            let _ = self.rewrite(state);
+        }
+    }
+
+    /// Returns `path` relative to the WAL directory, as stored in the
+    /// journal.
+    fn relative<'p>(&self, path: &'p Path) -> io::Result<&'p str> {
+        path.strip_prefix(&self.root)
+            .ok()
+            .and_then(|relative| relative.to_str())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is outside the single-file WAL", path.display()),
+                )
+            })
+    }
+
+    /// Applies `op` to the files, as when it is replayed.
+    fn apply(&self, state: &mut JournalState, op: &JournalOp) -> io::Result<()> {
+        match *op {
+            JournalOp::CreateDir(path) => self.files.create_dir_all(&self.root.join(path)),
+            JournalOp::RemoveDir(path) => {
+                let path = self.root.join(path);
+                self.files.remove_dir_all(&path)?;
+                state.ids.retain(|file, id| {
+                    let removed = file.starts_with(&path);
+                    if removed {
+                        state.paths.remove(id);
+                    }
+                    !removed
+                });
+                Ok(())
+            }
+            JournalOp::Create { id, path } => {
+                let path = self.root.join(path);
+                self.files.create(&path)?;
+                if let Some(replaced) = state.ids.insert(path.clone(), id) {
+                    state.paths.remove(&replaced);
+                }
+                state.paths.insert(id, path);
+                state.next_id = state.next_id.max(id + 1);
+                Ok(())
+            }
+            JournalOp::Remove { id } => {
+                if let Some(path) = state.paths.remove(&id) {
+                    state.ids.remove(&path);
+                    self.files.remove_file(&path)?;
+                }
+                Ok(())
+            }
+            JournalOp::Rename { id, to } => {
+                let to = self.root.join(to);
+                if let Some(from) = state.paths.get(&id).cloned() {
+                    self.files.rename(&from, &to)?;
+                    state.ids.remove(&from);
+                    if let Some(replaced) = state.ids.insert(to.clone(), id) {
+                        state.paths.remove(&replaced);
+                    }
+                    state.paths.insert(id, to);
+                }
+                Ok(())
+            }
+            JournalOp::Write { id, offset, bytes } => match state.paths.get(&id) {
+                Some(path) => {
+                    let mut file = self.files.open_write(path)?;
+                    file.seek(SeekFrom::Start(offset))?;
+                    file.write_all(bytes)
+                }
+                None => Ok(()),
+            },
+            JournalOp::SetLen { id, len } => match state.paths.get(&id) {
+                Some(path) => self.files.open_write(path)?.set_len(len),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Applies `op` and records it in the journal.
+    fn record(&self, state: &mut JournalState, op: &JournalOp) -> io::Result<()> {
+        self.apply(state, op)?;
+        state.append(op)
+    }
+
+    /// Returns a writable handle to the file at `path`.
+    fn handle(&self, state: &JournalState, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let id = *state
+            .ids
+            .get(path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(Box::new(JournaledFile {
+            inner: self.files.open_write(path)?,
+            id,
+            state: self.state.clone(),
+        }))
+    }
+
+    /// Replaces the container with a journal holding only the current
+    /// files, written to a temporary file renamed over it.
+    ///
+    /// Files keep their ids, so open handles keep writing to them.
+    fn rewrite(&self, state: &mut JournalState) -> io::Result<()> {
+        let mut tmp_name = self.root.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.root.with_file_name(tmp_name);
+        let written = (|| {
+            // Locked before the rename, since the lock follows the file and
+            // no other Wal may take the new container once it is in place
+            let tmp = File::create(&tmp_path)?;
+            if !VfsFile::try_lock(&tmp)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("{} is already locked by another Wal", tmp_path.display()),
+                ));
+            }
+            let mut tmp = BufWriter::new(tmp);
+            tmp.write_all(&SINGLE_FILE_SIGNATURE)?;
+            let mut len = SINGLE_FILE_SIGNATURE.len() as u64;
+            let mut append = |tmp: &mut BufWriter<File>, op: JournalOp| {
+                let frame = op.encode();
+                len += frame.len() as u64;
+                tmp.write_all(&frame)
+            };
+            for (path, node) in lock_unpoisoned(&self.files.nodes).iter() {
+                let Ok(relative) = self.relative(path) else {
+                    continue;
+                };
+                match node {
+                    MemNode::Dir => append(&mut tmp, JournalOp::CreateDir(relative))?,
+                    MemNode::File(data) => {
+                        let Some(&id) = state.ids.get(path) else {
+                            continue;
+                        };
+                        append(&mut tmp, JournalOp::Create { id, path: relative })?;
+                        let data = lock_unpoisoned(data);
+                        for (i, bytes) in data.bytes.chunks(SINGLE_FILE_WRITE_CHUNK).enumerate() {
+                            let offset = (i * SINGLE_FILE_WRITE_CHUNK) as u64;
+                            append(&mut tmp, JournalOp::Write { id, offset, bytes })?;
+                        }
+                    }
+                }
+            }
+            let tmp = tmp.into_inner().map_err(|e| e.into_error())?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, &self.root)?;
+            Ok((tmp, len))
+        })();
+        let (mut journal, len) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+
+        // The old file is gone from the directory, so the new one, already
+        // locked, takes over before anything else can fail
+        journal.seek(SeekFrom::End(0))?;
+        state.journal = journal;
+        state.len = len;
+        if let Some(parent) = self.root.parent() {
+            StdVfs.sync_dir(parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl Vfs for SingleFileVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.files.open(path)
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let state = lock_unpoisoned(&self.state);
+        self.handle(&state, path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let relative = self.relative(path)?;
+        let mut state = lock_unpoisoned(&self.state);
+        match state.ids.get(path) {
+            Some(&id) => self.record(&mut state, &JournalOp::SetLen { id, len: 0 })?,
+            None => {
+                let id = state.next_id;
+                self.record(&mut state, &JournalOp::Create { id, path: relative })?;
+            }
+        }
+        self.handle(&state, path)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        if self.files.metadata(path).is_ok() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        self.create(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        self.files.read_dir(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = lock_unpoisoned(&self.state);
+        let id = *state
+            .ids
+            .get(path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        self.record(&mut state, &JournalOp::Remove { id })?;
+        self.compact_if_mostly_dead(&mut state);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let to_relative = self.relative(to)?;
+        let mut state = lock_unpoisoned(&self.state);
+        let id = *state
+            .ids
+            .get(from)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        self.record(
+            &mut state,
+            &JournalOp::Rename {
+                id,
+                to: to_relative,
+            },
+        )?;
+        self.compact_if_mostly_dead(&mut state);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        self.files.metadata(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let relative = self.relative(path)?;
+        let mut state = lock_unpoisoned(&self.state);
+        self.record(&mut state, &JournalOp::CreateDir(relative))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let relative = self.relative(path)?;
+        let mut state = lock_unpoisoned(&self.state);
+        self.record(&mut state, &JournalOp::RemoveDir(relative))?;
+        self.compact_if_mostly_dead(&mut state);
+        Ok(())
+    }
+
+    fn sync_dir(&self, _path: &Path) -> io::Result<()> {
+        lock_unpoisoned(&self.state).journal.sync_data()
+    }
+}
+
+/// Writable handle to a [`SingleFileVfs`] file, recording its changes in
+/// the journal before making them.
+#[derive(Debug)]
+struct JournaledFile {
+    inner: Box<dyn VfsFile>,
+    id: u64,
+    state: Arc<Mutex<JournalState>>,
+}
+
+impl Read for JournaledFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for JournaledFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = self.inner.stream_position()?;
+        lock_unpoisoned(&self.state).append(&JournalOp::Write {
+            id: self.id,
+            offset,
+            bytes: buf,
+        })?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for JournaledFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl VfsFile for JournaledFile {
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        lock_unpoisoned(&self.state).append(&JournalOp::SetLen { id: self.id, len })?;
+        self.inner.set_len(len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        lock_unpoisoned(&self.state).journal.sync_data()
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        lock_unpoisoned(&self.state).journal.sync_all()
+    }
+
+    fn try_lock(&self) -> io::Result<bool> {
+        self.inner.try_lock()
+    }
+}
+
+/// Configuration options for WAL behavior.
+///
+/// # Examples
+///
+/// ```
+/// use nano_wal::WalOptions;
+/// use std::time::Duration;
+///
+/// let options = WalOptions::default()
+///     .retention(Duration::from_secs(3600))
+///     .segments_per_retention_period(5);
+/// ```
+#[derive(Clone)]
+pub struct WalOptions {
+    /// Duration for which entries are retained before expiration
+    pub entry_retention: Duration,
+    /// Number of segments per retention period for rotation
+    pub segments_per_retention_period: u32,
+    /// Hash function used to derive key hashes
+    pub hasher: HasherKind,
+    /// Time source for rotation and expiration
+    pub clock: Arc<dyn Clock>,
+    /// Largest record header accepted by appends, at most `u32::MAX` bytes
+    pub max_header_size: usize,
+    /// How durable writes are flushed to stable storage
+    pub durability: Durability,
+    /// Magic bytes at the start of every segment file
+    pub signature: [u8; 8],
+    /// Read each segment into one shared buffer when enumerating records
+    pub zero_copy_reads: bool,
+    /// Encode record length fields as varints in new segments
+    pub compact_framing: bool,
+    /// How long a dedup id suppresses repeat appends
+    pub dedup_window: Duration,
+    /// Maximum number of dedup ids remembered per key
+    pub dedup_capacity: usize,
+    /// Called with each segment sealed by rotation
+    pub on_rotate: Option<RotateCallback>,
+    /// Bytes reserved ahead of the write position in active segments
+    pub preallocate: u64,
+    /// Cipher applied to record content in new segments
+    pub encryption: Option<Arc<dyn RecordCipher>>,
+    /// Accept options that differ from the directory's manifest
+    pub allow_option_change: bool,
+    /// When an expired active segment is rotated
+    pub rotation_policy: RotationPolicy,
+    /// Most segments kept open for appends at once
+    pub max_open_segments: usize,
+    /// Bits of the key hash selecting a segment's subdirectory, 0 for flat
+    pub shard_dirs: u8,
+    /// Power of two every frame in new writes is padded to a multiple of
+    pub alignment: usize,
+    /// Appends between automatic syncs, 0 to sync only on request
+    pub auto_sync_every: u64,
+    /// Refuse appends whose record content is empty
+    pub reject_empty_content: bool,
+    /// Keep the content hashes of looked-up keys in memory
+    pub content_index: bool,
+    /// Sync a segment sealed by rotation before moving to the next one
+    pub sync_on_rotate: bool,
+    /// Rewrite the checkpoint file on every sync and on close
+    pub checkpoint_on_sync: bool,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            entry_retention: Duration::from_secs(60 * 60 * 24 * 7), // 1 week
+            segments_per_retention_period: 10,
+            hasher: HasherKind::default(),
+            clock: Arc::new(SystemClock),
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            durability: Durability::default(),
+            signature: NANO_LOG_SIGNATURE,
+            zero_copy_reads: false,
+            compact_framing: false,
+            dedup_window: Duration::from_secs(60 * 60), // 1 hour
+            dedup_capacity: 10_000,
+            on_rotate: None,
+            preallocate: 0,
+            encryption: None,
+            allow_option_change: false,
+            rotation_policy: RotationPolicy::default(),
+            max_open_segments: usize::MAX,
+            shard_dirs: 0,
+            alignment: 1,
+            auto_sync_every: 0,
+            reject_empty_content: false,
+            content_index: false,
+            sync_on_rotate: true,
+            checkpoint_on_sync: true,
+        }
+    }
+}
+
+impl Debug for WalOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalOptions")
+            .field("entry_retention", &self.entry_retention)
+            .field(
+                "segments_per_retention_period",
+                &self.segments_per_retention_period,
+            )
+            .field("hasher", &self.hasher)
+            .field("max_header_size", &self.max_header_size)
+            .field("durability", &self.durability)
+            .field("signature", &String::from_utf8_lossy(&self.signature))
+            .field("zero_copy_reads", &self.zero_copy_reads)
+            .field("compact_framing", &self.compact_framing)
+            .field("dedup_window", &self.dedup_window)
+            .field("dedup_capacity", &self.dedup_capacity)
+            .field("on_rotate", &self.on_rotate.is_some())
+            .field("preallocate", &self.preallocate)
+            .field("encryption", &self.encryption)
+            .field("allow_option_change", &self.allow_option_change)
+            .field("rotation_policy", &self.rotation_policy)
+            .field("max_open_segments", &self.max_open_segments)
+            .field("shard_dirs", &self.shard_dirs)
+            .field("alignment", &self.alignment)
+            .field("auto_sync_every", &self.auto_sync_every)
+            .field("reject_empty_content", &self.reject_empty_content)
+            .field("content_index", &self.content_index)
+            .field("sync_on_rotate", &self.sync_on_rotate)
+            .field("checkpoint_on_sync", &self.checkpoint_on_sync)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WalOptions {
+    /// Creates options with custom retention duration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    /// use std::time::Duration;
+    ///
+    /// let options = WalOptions::with_retention(Duration::from_secs(3600));
+    /// ```
+    pub fn with_retention(retention: Duration) -> Self {
+        Self {
+            entry_retention: retention,
+            ..Default::default()
+        }
+    }
+
+    /// Creates options with custom segment count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::with_segments_per_retention_period(20);
+    /// ```
+    pub fn with_segments_per_retention_period(segments: u32) -> Self {
+        Self {
+            segments_per_retention_period: segments,
+            ..Default::default()
+        }
+    }
+
+    /// Sets retention period (chainable).
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.entry_retention = retention;
+        self
+    }
+
+    /// Sets segments per retention period (chainable).
+    pub fn segments_per_retention_period(mut self, segments: u32) -> Self {
+        self.segments_per_retention_period = segments;
+        self
+    }
+
+    /// Sets the key hash function (chainable).
+    ///
+    /// Use `HasherKind::StdDefault` to open directories created by
+    /// nano-wal 0.5 or earlier.
+    pub fn hasher(mut self, hasher: HasherKind) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Sets the time source (chainable).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::{ManualClock, WalOptions};
+    /// use std::sync::Arc;
+    ///
+    /// let clock = Arc::new(ManualClock::new(1_700_000_000));
+    /// let options = WalOptions::default().clock(clock.clone());
+    /// ```
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the maximum record header size in bytes (chainable).
+    ///
+    /// Defaults to 65535, the largest header whose length fits the 2-byte
+    /// length field. Larger headers, up to `u32::MAX` bytes, spend 4 more
+    /// bytes on their length.
+    pub fn max_header_size(mut self, max: usize) -> Self {
+        self.max_header_size = max;
+        self
+    }
+
+    /// Sets how durable writes are flushed (chainable).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::{Durability, WalOptions};
+    ///
+    /// let options = WalOptions::default().durability(Durability::IncludeDir);
+    /// ```
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets the segment file magic (chainable).
+    ///
+    /// Replaces `NANO-LOG` at the start of every segment file. Records are
+    /// tagged with the first four bytes followed by `RC`. Segments carrying
+    /// a different magic are left out of reads and appends, and reported by
+    /// [`Wal::enumerate_keys_checked`], so a host application can keep
+    /// stray files from other tools from being parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().signature(b"ACME-WAL");
+    /// ```
+    pub fn signature(mut self, signature: &[u8; 8]) -> Self {
+        self.signature = *signature;
+        self
+    }
+
+    /// Enables zero-copy record enumeration (chainable).
+    ///
+    /// When enabled, enumerating records reads each segment into a single
+    /// buffer and returns every record as a slice of it, replacing one
+    /// allocation and copy per record with one per segment. The trade-off
+    /// is memory: a segment's buffer stays alive until every record sliced
+    /// from it has been dropped, so holding on to one small record pins the
+    /// whole segment. Records of encrypted segments are decrypted into
+    /// buffers of their own.
+    pub fn zero_copy_reads(mut self, enabled: bool) -> Self {
+        self.zero_copy_reads = enabled;
+        self
+    }
+
+    /// Enables compact record framing for new segments (chainable).
+    ///
+    /// Compact framing stores the header and content lengths of each record
+    /// as LEB128 varints instead of a fixed `u16` and `u64`, saving up to
+    /// eight bytes per small record. The framing is recorded in each segment
+    /// header, so segments of both kinds can be read regardless of this
+    /// setting and a directory may mix them.
+    pub fn compact_framing(mut self, enabled: bool) -> Self {
+        self.compact_framing = enabled;
+        self
+    }
+
+    /// Sets how long [`Wal::append_entry_dedup`] suppresses a repeated
+    /// dedup id (chainable).
+    ///
+    /// The window has whole-second resolution, matching the clock.
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Sets how many dedup ids [`Wal::append_entry_dedup`] remembers per
+    /// key (chainable).
+    ///
+    /// When a key reaches the limit its oldest id is forgotten, even if it
+    /// is still inside the window.
+    pub fn dedup_capacity(mut self, capacity: usize) -> Self {
+        self.dedup_capacity = capacity;
+        self
+    }
+
+    /// Sets a callback notified whenever rotation seals a segment (chainable).
+    ///
+    /// The callback runs on the appending thread once the successor segment
+    /// has been created and the sealed segment flushed, so the sealed file
+    /// is complete and will not be written again; it can be shipped to
+    /// other storage straight away. The callback cannot fail the append, so
+    /// any error it encounters must be handled or logged inside it. Keep it
+    /// short or hand the work to another thread, as the append waits for it.
+    pub fn on_rotate(mut self, callback: RotateCallback) -> Self {
+        self.on_rotate = Some(callback);
+        self
+    }
+
+    /// Reserves space in active segments ahead of the write position
+    /// (chainable).
+    ///
+    /// When a segment becomes active, its file is extended with
+    /// `File::set_len` to `bytes` past the last record, so appends write
+    /// into existing file space instead of growing the file each time,
+    /// which saves a file size update on every durable append. Whether the
+    /// space is physically allocated or left sparse depends on the
+    /// filesystem. The unused tail is truncated when the segment is sealed
+    /// or the WAL is dropped; after a crash, readers stop at the zeroed
+    /// tail and the next append resumes after the last record. `0`, the
+    /// default, disables preallocation.
+    pub fn preallocate(mut self, bytes: u64) -> Self {
+        self.preallocate = bytes;
+        self
+    }
+
+    /// Encrypts record content with `cipher` (chainable).
+    ///
+    /// New segments are marked encrypted in their format descriptor and
+    /// each record's content is encrypted with its key and offset as
+    /// associated data. Record headers stay in plaintext so they can still
+    /// be filtered on. Existing plaintext segments remain readable, while
+    /// encrypted segments can only be read with a cipher configured. With
+    /// the `encryption` feature, [`Aes256GcmCipher`] provides AES-256-GCM.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "encryption")]
+    /// # {
+    /// use nano_wal::{Aes256GcmCipher, WalOptions};
+    ///
+    /// let key = [7u8; 32];
+    /// let options = WalOptions::default().encryption(Box::new(Aes256GcmCipher::new(&key)));
+    /// # }
+    /// ```
+    pub fn encryption(mut self, cipher: Box<dyn RecordCipher>) -> Self {
+        self.encryption = Some(Arc::from(cipher));
+        self
+    }
+
+    /// Accepts options that differ from those recorded for the directory
+    /// (chainable).
+    ///
+    /// [`Wal::new`] records the retention, segments per retention period
+    /// and hasher in a manifest file in the WAL directory, and by default
+    /// refuses to reopen the directory with different values, since
+    /// segments already on disk were sized and named with the old ones.
+    /// When enabled, the new values are accepted and replace the recorded
+    /// ones.
+    pub fn allow_option_change(mut self, allow: bool) -> Self {
+        self.allow_option_change = allow;
+        self
+    }
+
+    /// Sets when an expired active segment is rotated (chainable).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::{RotationPolicy, WalOptions};
+    ///
+    /// let options = WalOptions::default().rotation_policy(RotationPolicy::Lazy);
+    /// ```
+    pub fn rotation_policy(mut self, policy: RotationPolicy) -> Self {
+        self.rotation_policy = policy;
+        self
+    }
+
+    /// Limits how many segments are kept open for appends (chainable).
+    ///
+    /// Each key written to holds its active segment open. Once more than
+    /// `max` are open, the least recently written ones are flushed and
+    /// closed at the end of the append that went over the limit; a later
+    /// append to such a key reopens its segment from disk. A single
+    /// [`Wal::append_batch`] or [`WriteBatch::commit`] may exceed the limit
+    /// by the number of keys it touches until it completes. Unbounded by
+    /// default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().max_open_segments(1024);
+    /// ```
+    pub fn max_open_segments(mut self, max: usize) -> Self {
+        self.max_open_segments = max;
+        self
+    }
+
+    /// Spreads new segments over `2^bits` subdirectories (chainable).
+    ///
+    /// A segment goes to the subdirectory named by the low `bits` bits of
+    /// its key hash in lowercase hex, such as `ab/` with 8 bits, so no
+    /// single directory listing grows with the number of keys. Lookups for
+    /// one key list only the subdirectories that can hold it. Existing
+    /// segments are found wherever they are, so a flat directory can be
+    /// reopened with sharding, or with a different number of bits, and
+    /// only new segments move to the new layout. `0`, the default, keeps
+    /// every segment directly in the WAL directory; at most 16 bits are
+    /// allowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().shard_dirs(8);
+    /// ```
+    pub fn shard_dirs(mut self, bits: u8) -> Self {
+        self.shard_dirs = bits;
+        self
+    }
+
+    /// Aligns every frame written to segments to `bytes` (chainable).
+    ///
+    /// Each record, tombstone or group marker starts at a file offset that
+    /// is a multiple of `bytes` and is followed by a padding frame up to
+    /// the next multiple, so every write starts and ends on the boundary,
+    /// as `O_DIRECT` requires of the filesystem block size. Readers skip
+    /// padding frames by their `NANOPC` signature, so aligned and unaligned
+    /// segments can be mixed and read with any setting. Padding costs up to
+    /// `bytes` plus one frame overhead per record. `1`, the default, writes
+    /// no padding. Must be a power of two, and `preallocate` a multiple of
+    /// it so segment files keep an aligned length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().alignment(4096);
+    /// ```
+    pub fn alignment(mut self, bytes: usize) -> Self {
+        self.alignment = bytes;
+        self
+    }
+
+    /// Syncs the active segments after every `appends` records (chainable).
+    ///
+    /// The WAL counts the records appended through any method and, once
+    /// `appends` have accumulated since the last sync, syncs every active
+    /// segment with unsynced writes at the end of the append that crossed
+    /// the threshold, as [`Wal::flush_pending`] does. A `durable` append
+    /// still syncs its own segment immediately, and an explicit
+    /// [`Wal::sync`] or [`Wal::flush_pending`] restarts the count.
+    ///
+    /// After a crash, at most the last `appends - 1` non-durable records
+    /// of segments still open for appends can be lost. Segments sealed by
+    /// rotation are synced as they are sealed under
+    /// [`WalOptions::sync_on_rotate`]; records in a segment closed by
+    /// `max_open_segments` before the next automatic sync are flushed to
+    /// the OS but not synced. `0`, the default, never syncs automatically.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().auto_sync_every(100);
+    /// ```
+    pub fn auto_sync_every(mut self, appends: u64) -> Self {
+        self.auto_sync_every = appends;
+        self
+    }
+
+    /// Refuses records with empty content (chainable).
+    ///
+    /// When enabled, every append of a record whose content is zero bytes
+    /// long fails with `WalError::InvalidConfig` before anything is
+    /// written, so accidental empty writes surface at the call instead of
+    /// as empty records on replay. Empty or missing headers are still
+    /// accepted, and tombstones are unaffected. Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().reject_empty_content(true);
+    /// ```
+    pub fn reject_empty_content(mut self, reject: bool) -> Self {
+        self.reject_empty_content = reject;
+        self
+    }
+
+    /// Keeps an in-memory index from content hash to `EntryRef` for
+    /// [`Wal::contains_record`] (chainable).
+    ///
+    /// Without the index every lookup hashes all records of the key. With
+    /// it, a key's records are hashed once, on its first lookup, and
+    /// appends to the key are added as they are written, so later lookups
+    /// cost a hash table probe. The price is memory: every record of a
+    /// looked-up key takes roughly 80 bytes (a 32-byte SHA-256, a 24-byte
+    /// `EntryRef` and table overhead), a million records about 80 MB, and
+    /// the entries stay until the `Wal` is closed. Compaction, truncation,
+    /// merging, repair, imports and streamed appends drop the index of the
+    /// keys they touch, which is rebuilt on their next lookup. Requires the
+    /// `digest` feature. Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().content_index(true);
+    /// ```
+    pub fn content_index(mut self, enabled: bool) -> Self {
+        self.content_index = enabled;
+        self
+    }
+
+    /// Sets whether a segment sealed by rotation is synced before appends
+    /// move to the next one (chainable).
+    ///
+    /// Applies to time-based rotation and to [`Wal::force_rotate`], and
+    /// only to segments holding writes that were not synced yet, so a key
+    /// written durably pays nothing extra. This bounds what a crash can
+    /// lose to the records of segments still open for appends. Enabled by
+    /// default; disabling it leaves sealed segments to the OS to write
+    /// back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().sync_on_rotate(false);
+    /// ```
+    pub fn sync_on_rotate(mut self, enabled: bool) -> Self {
+        self.sync_on_rotate = enabled;
+        self
+    }
+
+    /// Sets whether [`Wal::sync`] and [`Wal::close`] rewrite the checkpoint
+    /// file, as [`Wal::write_checkpoint`] does (chainable).
+    ///
+    /// Only the first rewrite after opening, or after records were removed
+    /// or rewritten, walks the directory; the others are written from the
+    /// latest records kept in memory, at the cost of one more synced file
+    /// per sync. Enabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::WalOptions;
+    ///
+    /// let options = WalOptions::default().checkpoint_on_sync(false);
+    /// ```
+    pub fn checkpoint_on_sync(mut self, enabled: bool) -> Self {
+        self.checkpoint_on_sync = enabled;
+        self
+    }
+
+    /// Validates the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if:
+    /// - `entry_retention` is zero
+    /// - `segments_per_retention_period` is zero, or so large that a
+    ///   segment of `entry_retention` would last less than a second
+    /// - `max_header_size` exceeds `u32::MAX`
+    /// - `max_open_segments` is zero
+    /// - `shard_dirs` exceeds 16
+    /// - `alignment` is not a power of two, or `preallocate` is not a
+    ///   multiple of it
+    /// - `content_index` is set without the `digest` feature
+    pub fn validate(&self) -> Result<()> {
+        if self.entry_retention.as_secs() == 0 {
+            return Err(WalError::InvalidConfig(
+                "entry_retention must be greater than 0".to_string(),
+            ));
+        }
+        if self.segments_per_retention_period == 0 {
+            return Err(WalError::InvalidConfig(
+                "segments_per_retention_period must be greater than 0".to_string(),
+            ));
+        }
+        check_segment_duration(
+            "entry_retention",
+            self.entry_retention,
+            self.segments_per_retention_period,
+        )?;
+        if self.max_header_size > MAX_HEADER_SIZE {
+            return Err(WalError::InvalidConfig(format!(
+                "max_header_size must not exceed {}",
+                MAX_HEADER_SIZE
+            )));
+        }
+        if self.max_open_segments == 0 {
+            return Err(WalError::InvalidConfig(
+                "max_open_segments must be greater than 0".to_string(),
+            ));
+        }
+        if self.shard_dirs > MAX_SHARD_DIR_BITS {
+            return Err(WalError::InvalidConfig(format!(
+                "shard_dirs must not exceed {}",
+                MAX_SHARD_DIR_BITS
+            )));
+        }
+        if !self.alignment.is_power_of_two() {
+            return Err(WalError::InvalidConfig(
+                "alignment must be a power of two".to_string(),
+            ));
+        }
+        if !self.preallocate.is_multiple_of(self.alignment as u64) {
+            return Err(WalError::InvalidConfig(
+                "preallocate must be a multiple of alignment".to_string(),
+            ));
+        }
+        if self.content_index && !cfg!(feature = "digest") {
+            return Err(WalError::InvalidConfig(
+                "content_index requires the digest feature".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that splitting `retention` into `segments` leaves each segment
+/// at least a second.
+///
+/// Segment expirations have a resolution of one second, so a shorter
+/// segment would expire as it is created and every append would rotate.
+fn check_segment_duration(name: &str, retention: Duration, segments: u32) -> Result<()> {
+    if retention.as_secs() < segments as u64 {
+        return Err(WalError::InvalidConfig(format!(
+            "{} of {}s split into {} segments leaves less than a second per segment; \
+             use at most {} segments_per_retention_period",
+            name,
+            retention.as_secs(),
+            segments,
+            retention.as_secs()
+        )));
+    }
+    Ok(())
+}
+
+/// Builder for a [`Wal`] that checks up front that its directory is
+/// writable, returned by [`Wal::builder`].
+///
+/// The option setters mirror those of [`WalOptions`].
+#[derive(Debug, Clone)]
+pub struct WalBuilder {
+    path: PathBuf,
+    options: WalOptions,
+}
+
+impl WalBuilder {
+    /// Replaces all options at once (chainable).
+    pub fn options(mut self, options: WalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets [`WalOptions::retention`] (chainable).
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.options = self.options.retention(retention);
+        self
+    }
+
+    /// Sets [`WalOptions::segments_per_retention_period`] (chainable).
+    pub fn segments_per_retention_period(mut self, segments: u32) -> Self {
+        self.options = self.options.segments_per_retention_period(segments);
+        self
+    }
+
+    /// Sets [`WalOptions::hasher`] (chainable).
+    pub fn hasher(mut self, hasher: HasherKind) -> Self {
+        self.options = self.options.hasher(hasher);
+        self
+    }
+
+    /// Sets [`WalOptions::clock`] (chainable).
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.options = self.options.clock(clock);
+        self
+    }
+
+    /// Sets [`WalOptions::max_header_size`] (chainable).
+    pub fn max_header_size(mut self, max: usize) -> Self {
+        self.options = self.options.max_header_size(max);
+        self
+    }
+
+    /// Sets [`WalOptions::durability`] (chainable).
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.options = self.options.durability(durability);
+        self
+    }
+
+    /// Sets [`WalOptions::signature`] (chainable).
+    pub fn signature(mut self, signature: &[u8; 8]) -> Self {
+        self.options = self.options.signature(signature);
+        self
+    }
+
+    /// Sets [`WalOptions::zero_copy_reads`] (chainable).
+    pub fn zero_copy_reads(mut self, enabled: bool) -> Self {
+        self.options = self.options.zero_copy_reads(enabled);
+        self
+    }
+
+    /// Sets [`WalOptions::compact_framing`] (chainable).
+    pub fn compact_framing(mut self, enabled: bool) -> Self {
+        self.options = self.options.compact_framing(enabled);
+        self
+    }
+
+    /// Sets [`WalOptions::dedup_window`] (chainable).
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.options = self.options.dedup_window(window);
+        self
+    }
+
+    /// Sets [`WalOptions::dedup_capacity`] (chainable).
+    pub fn dedup_capacity(mut self, capacity: usize) -> Self {
+        self.options = self.options.dedup_capacity(capacity);
+        self
+    }
+
+    /// Sets [`WalOptions::on_rotate`] (chainable).
+    pub fn on_rotate(mut self, callback: RotateCallback) -> Self {
+        self.options = self.options.on_rotate(callback);
+        self
+    }
+
+    /// Sets [`WalOptions::preallocate`] (chainable).
+    pub fn preallocate(mut self, bytes: u64) -> Self {
+        self.options = self.options.preallocate(bytes);
+        self
+    }
+
+    /// Sets [`WalOptions::encryption`] (chainable).
+    pub fn encryption(mut self, cipher: Box<dyn RecordCipher>) -> Self {
+        self.options = self.options.encryption(cipher);
+        self
+    }
+
+    /// Sets [`WalOptions::allow_option_change`] (chainable).
+    pub fn allow_option_change(mut self, allow: bool) -> Self {
+        self.options = self.options.allow_option_change(allow);
+        self
+    }
+
+    /// Sets [`WalOptions::rotation_policy`] (chainable).
+    pub fn rotation_policy(mut self, policy: RotationPolicy) -> Self {
+        self.options = self.options.rotation_policy(policy);
+        self
+    }
+
+    /// Sets [`WalOptions::max_open_segments`] (chainable).
+    pub fn max_open_segments(mut self, max: usize) -> Self {
+        self.options = self.options.max_open_segments(max);
+        self
+    }
+
+    /// Sets [`WalOptions::shard_dirs`] (chainable).
+    pub fn shard_dirs(mut self, bits: u8) -> Self {
+        self.options = self.options.shard_dirs(bits);
+        self
+    }
+
+    /// Sets [`WalOptions::alignment`] (chainable).
+    pub fn alignment(mut self, bytes: usize) -> Self {
+        self.options = self.options.alignment(bytes);
+        self
+    }
+
+    /// Sets [`WalOptions::auto_sync_every`] (chainable).
+    pub fn auto_sync_every(mut self, appends: u64) -> Self {
+        self.options = self.options.auto_sync_every(appends);
+        self
+    }
+
+    /// Sets [`WalOptions::reject_empty_content`] (chainable).
+    pub fn reject_empty_content(mut self, reject: bool) -> Self {
+        self.options = self.options.reject_empty_content(reject);
+        self
+    }
+
+    /// Sets [`WalOptions::content_index`] (chainable).
+    pub fn content_index(mut self, enabled: bool) -> Self {
+        self.options = self.options.content_index(enabled);
+        self
+    }
+
+    /// Sets [`WalOptions::sync_on_rotate`] (chainable).
+    pub fn sync_on_rotate(mut self, enabled: bool) -> Self {
+        self.options = self.options.sync_on_rotate(enabled);
+        self
+    }
+
+    /// Sets [`WalOptions::checkpoint_on_sync`] (chainable).
+    pub fn checkpoint_on_sync(mut self, enabled: bool) -> Self {
+        self.options = self.options.checkpoint_on_sync(enabled);
+        self
+    }
+
+    /// Opens the WAL like [`Wal::new`], after checking that the directory
+    /// is writable.
+    ///
+    /// The check creates, writes and deletes a small `.nano-wal.probe`
+    /// file, so a read-only mount or missing permissions fail here instead
+    /// of on the first append.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be created or written.
+    /// Otherwise fails like [`Wal::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::Wal;
+    /// # use std::time::Duration;
+    /// let wal = Wal::builder("./wal")
+    ///     .retention(Duration::from_secs(3600))
+    ///     .build()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn build(self) -> Result<Wal> {
+        self.options.validate()?;
+        fs::create_dir_all(&self.path)?;
+
+        let probe_path = self.path.join(PROBE_FILE_NAME);
+        let probe = File::create(&probe_path).and_then(|mut probe| probe.write_all(b"probe"));
+        let removed = fs::remove_file(&probe_path);
+        probe?;
+        removed?;
+
+        Wal::open(&self.path, self.options, Arc::new(StdVfs))
+    }
+}
+
+/// Options recorded in a WAL directory's manifest file.
+///
+/// Stored as `name=value` lines; unknown names are ignored so later
+/// versions can add entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Manifest {
+    format_version: u16,
+    hasher_id: u8,
+    entry_retention_secs: u64,
+    segments_per_retention_period: u32,
+}
+
+impl Manifest {
+    /// Captures the recorded options of `options`.
+    fn from_options(options: &WalOptions) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            hasher_id: options.hasher.id(),
+            entry_retention_secs: options.entry_retention.as_secs(),
+            segments_per_retention_period: options.segments_per_retention_period,
+        }
+    }
+
+    /// Renders the manifest file contents.
+    fn render(&self) -> String {
+        format!(
+            "format_version={}\nhasher={}\nentry_retention_secs={}\nsegments_per_retention_period={}\n",
+            self.format_version,
+            self.hasher_id,
+            self.entry_retention_secs,
+            self.segments_per_retention_period
+        )
+    }
+
+    /// Parses manifest file contents.
+    fn parse(contents: &str) -> Result<Self> {
+        let mut fields = HashMap::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, value) = line.split_once('=').ok_or_else(|| {
+                WalError::CorruptedData(format!("Malformed manifest line {:?}", line))
+            })?;
+            fields.insert(name.trim(), value.trim());
+        }
+
+        fn field<T: std::str::FromStr>(fields: &HashMap<&str, &str>, name: &str) -> Result<T> {
+            fields
+                .get(name)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| WalError::CorruptedData(format!("Manifest has no valid {}", name)))
+        }
+
+        Ok(Self {
+            format_version: field(&fields, "format_version")?,
+            hasher_id: field(&fields, "hasher")?,
+            entry_retention_secs: field(&fields, "entry_retention_secs")?,
+            segments_per_retention_period: field(&fields, "segments_per_retention_period")?,
+        })
+    }
+
+    /// Describes how the `configured` options differ from this manifest.
+    fn differences(&self, configured: &Manifest) -> Vec<String> {
+        let hasher_name = |id| {
+            HasherKind::from_id(id)
+                .map(|hasher| format!("{:?}", hasher))
+                .unwrap_or_else(|| format!("unknown ({})", id))
+        };
+
+        let mut differences = Vec::new();
+        if self.hasher_id != configured.hasher_id {
+            differences.push(format!(
+                "hasher {} (configured {})",
+                hasher_name(self.hasher_id),
+                hasher_name(configured.hasher_id)
+            ));
+        }
+        if self.entry_retention_secs != configured.entry_retention_secs {
+            differences.push(format!(
+                "entry_retention {}s (configured {}s)",
+                self.entry_retention_secs, configured.entry_retention_secs
+            ));
+        }
+        if self.segments_per_retention_period != configured.segments_per_retention_period {
+            differences.push(format!(
+                "segments_per_retention_period {} (configured {})",
+                self.segments_per_retention_period, configured.segments_per_retention_period
+            ));
+        }
+        differences
+    }
+}
+
+/// Parsed segment file header.
+#[derive(Debug)]
+struct SegmentHeader {
+    /// Format version from the format descriptor
+    format_version: u16,
+    /// Hasher identifier from the format descriptor
+    hasher_id: u8,
+    /// Encoding of record length fields
+    framing: Framing,
+    /// Whether record content is encrypted
+    encrypted: bool,
+    /// Whether the body is zstd-compressed
+    compressed: bool,
+    /// Unix timestamp when this segment expires
+    expiration_timestamp: u64,
+    /// Key bytes stored in the header
+    key: Vec<u8>,
+}
+
+/// Information about an active segment for a specific key.
+#[derive(Debug)]
+struct ActiveSegment {
+    /// Buffered writer over the active file handle
+    writer: BufWriter<Box<dyn VfsFile>>,
+    /// Path of the segment file
+    path: PathBuf,
+    /// Key stored in the segment header
+    key: Vec<u8>,
+    /// Size of the file header
+    body_start: u64,
+    /// Bytes written after the file header, including buffered bytes
+    body_len: u64,
+    /// Value of `body_len` at the last successful flush, so always at a
+    /// record boundary
+    flushed_len: u64,
+    /// Cipher for record content, if the segment is encrypted
+    cipher: Option<KeyCipher>,
+    /// Sequence number of this segment
+    sequence_number: u64,
+    /// Unix timestamp when this segment expires
+    expiration_timestamp: u64,
+    /// Value of the WAL's write counter at the last write, for eviction
+    last_used: u64,
+    /// Whether the file changed since it was last synced
+    unsynced: bool,
+    /// Offset of the last data record written, for the checkpoint
+    last_record: Option<u64>,
+}
+
+/// Recently appended dedup ids of one key.
+#[derive(Debug, Default)]
+struct DedupCache {
+    /// Dedup id hash to the time it was appended
+    seen: HashMap<u64, u64>,
+    /// Appended ids in insertion order, for eviction
+    order: VecDeque<(u64, u64)>,
+}
+
+impl DedupCache {
+    /// Returns whether `id` was recorded less than `window` seconds ago.
+    fn contains(&mut self, id: u64, now: u64, window: u64) -> bool {
+        while let Some(&(oldest, seen_at)) = self.order.front() {
+            if seen_at.saturating_add(window) > now {
+                break;
+            }
+            self.order.pop_front();
+            self.forget(oldest, seen_at);
+        }
+        self.seen
+            .get(&id)
+            .is_some_and(|seen_at| seen_at.saturating_add(window) > now)
+    }
+
+    /// Records `id` as appended at `now`, evicting the oldest ids beyond
+    /// `capacity`.
+    fn insert(&mut self, id: u64, now: u64, capacity: usize) {
+        self.seen.insert(id, now);
+        self.order.push_back((id, now));
+        while self.seen.len() > capacity {
+            let Some((oldest, seen_at)) = self.order.pop_front() else {
+                break;
+            };
+            self.forget(oldest, seen_at);
+        }
+    }
+
+    /// Returns whether every recorded id is older than `window` seconds.
+    fn is_expired(&self, now: u64, window: u64) -> bool {
+        self.order
+            .back()
+            .is_none_or(|(_, seen_at)| seen_at.saturating_add(window) <= now)
+    }
+
+    /// Removes `id` unless it was re-recorded after `seen_at`.
+    fn forget(&mut self, id: u64, seen_at: u64) {
+        if self.seen.get(&id) == Some(&seen_at) {
+            self.seen.remove(&id);
+        }
+    }
+}
+
+impl ActiveSegment {
+    /// Flushes the segment and truncates any preallocated space past the
+    /// last record, leaving the file ready to be closed.
+    fn seal(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let end = self.body_start + self.body_len;
+        let file = self.writer.get_ref();
+        if file.size()? > end {
+            file.set_len(end)?;
+            self.unsynced = true;
+        }
+        Ok(())
+    }
+}
+
+/// Reduces a key to the filename-safe prefix used in segment names.
+///
+/// The prefix is only for humans browsing the directory; segments are
+/// matched to keys by the hash in the filename and the key stored in the
+/// header, so changing the length does not orphan existing files.
+fn sanitize_key<K: Display>(key: &K) -> String {
+    format!("{}", key)
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+        .take(SANITIZED_KEY_LEN)
+        .collect()
+}
+
+/// Computes the CRC-32 (IEEE) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Returns the size of a segment file header for a key of `key_len` bytes.
+fn file_header_size(key_len: usize) -> u64 {
+    (NANO_LOG_SIGNATURE.len() + 8 + 8 + 8 + key_len) as u64
+}
+
+/// Segment file and record signatures in effect for a WAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Signatures {
+    /// Magic at the start of every segment file
+    segment: [u8; 8],
+    /// Magic at the start of every record frame
+    record: [u8; 6],
+    /// Magic at the start of every tombstone frame
+    tombstone: [u8; 6],
+    /// Magic of the marker opening a record group
+    group_begin: [u8; 6],
+    /// Magic of the marker committing a record group
+    group_commit: [u8; 6],
+    /// Magic of the frames padding records to the alignment
+    padding: [u8; 6],
+    /// Magic of the marker naming the producer of the next record
+    producer: [u8; 6],
+}
+
+impl Signatures {
+    /// Derives the record signature from the first four bytes of the
+    /// segment signature followed by `RC`, so `NANO-LOG` yields `NANORC`.
+    ///
+    /// The fifth byte is the frame type: tombstones carry `T` in its place,
+    /// as in `NANOTC`, group markers `B` and `E`, padding `P` and producer
+    /// markers `I`.
+    fn new(segment: [u8; 8]) -> Self {
+        let mut record = [b'R'; 6];
+        record[..4].copy_from_slice(&segment[..4]);
+        record[5] = b'C';
+        let mut tombstone = record;
+        tombstone[4] = b'T';
+        let mut group_begin = record;
+        group_begin[4] = b'B';
+        let mut group_commit = record;
+        group_commit[4] = b'E';
+        let mut padding = record;
+        padding[4] = b'P';
+        let mut producer = record;
+        producer[4] = b'I';
+        Self {
+            segment,
+            record,
+            tombstone,
+            group_begin,
+            group_commit,
+            padding,
+            producer,
+        }
+    }
+}
+
+/// Encoding of the length fields in a record frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// `u16` header length and `u64` content length, little-endian, as
+    /// written before format version 7
+    Fixed,
+    /// Like `Fixed`, with a header length of `0xFFFF` followed by the
+    /// `u32` length
+    Extended,
+    /// LEB128 varint header and content lengths
+    Varint,
+}
+
+impl Framing {
+    /// Returns the framing identifier stored in the format descriptor.
+    fn id(self) -> u8 {
+        match self {
+            Framing::Fixed | Framing::Extended => 0,
+            Framing::Varint => 1,
+        }
+    }
+
+    /// Looks up a framing by its format descriptor identifier and the
+    /// format version of the segment.
+    fn from_id(id: u8, format_version: u16) -> Result<Self> {
+        match id {
+            0 if format_version >= HEADER_LEN_ESCAPE_VERSION => Ok(Framing::Extended),
+            0 => Ok(Framing::Fixed),
+            1 => Ok(Framing::Varint),
+            _ => Err(WalError::CorruptedData(format!(
+                "Unknown record framing {}",
+                id
+            ))),
+        }
+    }
+
+    /// Returns the smallest possible frame, an empty record.
+    fn min_frame_len(self) -> u64 {
+        self.frame_len(0, 0)
+    }
+
+    /// Returns the on-disk size of a record frame.
+    ///
+    /// Saturates at `u64::MAX` for a corrupt content length.
+    fn frame_len(self, header_len: usize, content_len: u64) -> u64 {
+        let lengths = match self {
+            Framing::Fixed => 2 + 8,
+            Framing::Extended if header_len >= HEADER_LEN_ESCAPE as usize => 2 + 4 + 8,
+            Framing::Extended => 2 + 8,
+            Framing::Varint => varint_len(header_len as u64) + varint_len(content_len),
+        };
+        ((NANO_REC_SIGNATURE.len() + lengths + header_len) as u64).saturating_add(content_len)
+    }
+
+    /// Writes a length field, using at most `fixed_width` bytes when fixed.
+    fn write_len<W: Write>(self, writer: &mut W, len: u64, fixed_width: usize) -> io::Result<()> {
+        match self {
+            Framing::Fixed | Framing::Extended => {
+                writer.write_all(&len.to_le_bytes()[..fixed_width])
+            }
+            Framing::Varint => {
+                let mut buf = [0u8; 10];
+                let mut value = len;
+                let mut n = 0;
+                loop {
+                    buf[n] = (value & 0x7f) as u8;
+                    value >>= 7;
+                    if value == 0 {
+                        break;
+                    }
+                    buf[n] |= 0x80;
+                    n += 1;
+                }
+                writer.write_all(&buf[..=n])
+            }
+        }
+    }
+
+    /// Reads a length field written by [`Framing::write_len`].
+    fn read_len<R: Read>(self, reader: &mut R, fixed_width: usize) -> io::Result<u64> {
+        match self {
+            Framing::Fixed | Framing::Extended => {
+                let mut buf = [0u8; 8];
+                reader.read_exact(&mut buf[..fixed_width])?;
+                Ok(u64::from_le_bytes(buf))
+            }
+            Framing::Varint => {
+                let mut value = 0u64;
+                for shift in (0..64).step_by(7) {
+                    let mut byte = [0u8; 1];
+                    reader.read_exact(&mut byte)?;
+                    value |= u64::from(byte[0] & 0x7f) << shift;
+                    if byte[0] & 0x80 == 0 {
+                        return Ok(value);
+                    }
+                }
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "varint length overflows u64",
+                ))
+            }
+        }
+    }
+
+    /// Writes a record header length, escaping one that does not fit the
+    /// 2-byte field under extended framing.
+    fn write_header_len<W: Write>(self, writer: &mut W, len: u64) -> io::Result<()> {
+        if self == Framing::Extended && len >= u64::from(HEADER_LEN_ESCAPE) {
+            writer.write_all(&HEADER_LEN_ESCAPE.to_le_bytes())?;
+            return self.write_len(writer, len, 4);
+        }
+        self.write_len(writer, len, 2)
+    }
+
+    /// Reads a record header length.
+    fn read_header_len<R: Read>(self, reader: &mut R) -> io::Result<usize> {
+        let mut len = self.read_len(reader, 2)?;
+        if self == Framing::Extended && len == u64::from(HEADER_LEN_ESCAPE) {
+            len = self.read_len(reader, 4)?;
+        }
+        if len > MAX_HEADER_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "record header length exceeds the maximum",
+            ));
+        }
+        Ok(len as usize)
+    }
+
+    /// Reads a record content length.
+    fn read_content_len<R: Read>(self, reader: &mut R) -> io::Result<u64> {
+        self.read_len(reader, 8)
+    }
+}
+
+/// Returns the number of bytes in the LEB128 encoding of `value`.
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+/// Writes one record frame, returning the number of bytes written.
+fn write_record<W: Write>(
+    writer: &mut W,
+    signature: &[u8; 6],
+    framing: Framing,
+    header: Option<&[u8]>,
+    content: &[u8],
+) -> io::Result<u64> {
+    let frame_len = write_frame_prefix(writer, signature, framing, header, content.len() as u64)?;
+    writer.write_all(content)?;
+    Ok(frame_len)
+}
+
+/// Writes the part of a record frame that precedes its content.
+///
+/// Returns the length of the whole frame, content included.
+fn write_frame_prefix<W: Write>(
+    writer: &mut W,
+    signature: &[u8; 6],
+    framing: Framing,
+    header: Option<&[u8]>,
+    content_len: u64,
+) -> io::Result<u64> {
+    let header = header.unwrap_or_default();
+    writer.write_all(signature)?;
+    framing.write_header_len(writer, header.len() as u64)?;
+    writer.write_all(header)?;
+    framing.write_len(writer, content_len, 8)?;
+    Ok(framing.frame_len(header.len(), content_len))
+}
+
+/// Encodes the record a tombstone deletes as the tombstone's content,
+/// `[sequence:8][offset:8]` little-endian.
+fn encode_tombstone(target: EntryRef) -> [u8; 16] {
+    let mut content = [0u8; 16];
+    content[..8].copy_from_slice(&target.sequence_number.to_le_bytes());
+    content[8..].copy_from_slice(&target.offset.to_le_bytes());
+    content
+}
+
+/// Decodes the `(sequence, offset)` a tombstone deletes.
+fn decode_tombstone(content: &[u8]) -> Option<(u64, u64)> {
+    let content: &[u8; 16] = content.try_into().ok()?;
+    Some((
+        u64::from_le_bytes(content[..8].try_into().unwrap()),
+        u64::from_le_bytes(content[8..].try_into().unwrap()),
+    ))
+}
+
+/// Encodes the producer of an idempotent record as the content of its
+/// marker, `[producer_id:8][producer_seq:8]` little-endian.
+fn encode_producer(producer_id: u64, producer_seq: u64) -> [u8; 16] {
+    let mut content = [0u8; 16];
+    content[..8].copy_from_slice(&producer_id.to_le_bytes());
+    content[8..].copy_from_slice(&producer_seq.to_le_bytes());
+    content
+}
+
+/// Decodes the `(producer_id, producer_seq)` of a producer marker.
+fn decode_producer(content: &[u8]) -> Option<(u64, u64)> {
+    let content: &[u8; 16] = content.try_into().ok()?;
+    Some((
+        u64::from_le_bytes(content[..8].try_into().unwrap()),
+        u64::from_le_bytes(content[8..].try_into().unwrap()),
+    ))
+}
+
+/// Encodes the marker frame that commits a record group. It has no header
+/// or content, so its bytes are fixed for a given framing.
+fn encode_group_commit(signatures: Signatures, framing: Framing) -> Vec<u8> {
+    let mut marker = Vec::new();
+    write_record(&mut marker, &signatures.group_commit, framing, None, &[]).unwrap();
+    marker
+}
+
+/// Encodes the padding frame that advances `position`, an absolute file
+/// offset, to the next multiple of `alignment`. Empty when `position` is
+/// already aligned.
+///
+/// A gap too small to hold a frame is widened by whole alignment units.
+fn encode_padding(
+    signatures: Signatures,
+    framing: Framing,
+    position: u64,
+    alignment: u64,
+) -> Vec<u8> {
+    let mut gap = position.next_multiple_of(alignment) - position;
+    if gap == 0 {
+        return Vec::new();
+    }
+    loop {
+        // Varint lengths can skip a size, so step down to a fitting one
+        let mut content_len = gap.saturating_sub(framing.min_frame_len());
+        while content_len > 0 && framing.frame_len(0, content_len) > gap {
+            content_len -= 1;
+        }
+        if framing.frame_len(0, content_len) == gap {
+            let mut padding = Vec::with_capacity(gap as usize);
+            write_frame_prefix(
+                &mut padding,
+                &signatures.padding,
+                framing,
+                None,
+                content_len,
+            )
+            .unwrap();
+            padding.resize(gap as usize, 0);
+            return padding;
+        }
+        gap += alignment;
+    }
+}
+
+/// Returns whether the group whose begin marker has `content` and ends at
+/// `frame_end` in `body` is followed by its commit marker.
+fn group_committed_in(
+    body: &[u8],
+    signatures: Signatures,
+    framing: Framing,
+    content: &[u8],
+    frame_end: usize,
+) -> bool {
+    let Ok(group_len) = <[u8; 8]>::try_from(content) else {
+        return false;
+    };
+    let commit = encode_group_commit(signatures, framing);
+    let commit_start = usize::try_from(u64::from_le_bytes(group_len))
+        .ok()
+        .and_then(|len| frame_end.checked_add(len));
+    commit_start
+        .and_then(|start| body.get(start..start.checked_add(commit.len())?))
+        .is_some_and(|marker| marker == commit)
+}
+
+/// Writes every buffer of `bufs` in order, with as few writes as possible.
+fn write_all_vectored<W: Write>(writer: &mut W, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Record cipher bound to the key of the segment it encrypts.
+#[derive(Debug, Clone)]
+struct KeyCipher {
+    cipher: Arc<dyn RecordCipher>,
+    key: Vec<u8>,
+    /// Sequence number of the segment the records belong to
+    sequence: u64,
+}
+
+impl KeyCipher {
+    /// Returns the cipher for the records of `header`'s segment, numbered
+    /// `sequence`, or `None` if the segment is not encrypted.
+    ///
+    /// Fails if the segment is encrypted and no cipher is configured.
+    fn for_segment(
+        header: &SegmentHeader,
+        sequence: u64,
+        cipher: Option<&Arc<dyn RecordCipher>>,
+    ) -> Result<Option<Self>> {
+        if !header.encrypted {
+            return Ok(None);
+        }
+        let cipher = cipher.ok_or_else(|| {
+            WalError::InvalidConfig(format!(
+                "segment of key {} is encrypted and no cipher is configured",
+                String::from_utf8_lossy(&header.key)
+            ))
+        })?;
+        Ok(Some(Self {
+            cipher: cipher.clone(),
+            key: header.key.clone(),
+            sequence,
+        }))
+    }
+
+    /// Associated data of the record at `offset`:
+    /// `[sequence:8][offset:8][key]`.
+    fn aad(&self, offset: u64) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(16 + self.key.len());
+        aad.extend_from_slice(&self.sequence.to_le_bytes());
+        aad.extend_from_slice(&offset.to_le_bytes());
+        aad.extend_from_slice(&self.key);
+        aad
+    }
+
+    /// Encrypts the content of the record at `offset`.
+    fn encrypt(&self, offset: u64, content: &[u8]) -> Vec<u8> {
+        self.cipher.encrypt(content, &self.aad(offset))
+    }
+
+    /// Decrypts the content of the record at `offset`.
+    fn decrypt(&self, offset: u64, content: &[u8]) -> Result<Bytes> {
+        Ok(Bytes::from(
+            self.cipher.decrypt(content, &self.aad(offset))?,
+        ))
+    }
+}
+
+/// Framing information for one record.
+#[derive(Debug)]
+struct RecordFrame {
+    /// Offset of the record, relative to the end of the file header
+    offset: u64,
+    /// Record header, if requested and present
+    header: Option<Bytes>,
+    /// Frame type
+    kind: RecordKind,
+    /// `(producer_id, producer_seq)` of the marker preceding the record
+    producer: Option<(u64, u64)>,
+}
+
+/// Sequential reader over the record frames of a segment file.
+///
+/// The frame chain ends at end of file, at a record whose signature does
+/// not match, or at a record that extends past the end of the file (a torn
+/// write). Content is only read when requested; otherwise it is skipped.
+/// Tombstone frames are skipped unless requested with `with_tombstones`,
+/// and group markers unless requested with `with_markers`.
+#[derive(Debug)]
+struct SegmentCursor {
+    reader: BufReader<SegmentFile>,
+    /// Absolute position of the first record
+    body_start: u64,
+    /// File length when the cursor was opened
+    file_len: u64,
+    /// Offset of the next frame, relative to `body_start`
+    offset: u64,
+    /// Content bytes of the current frame not yet consumed
+    pending_content: u64,
+    /// Signatures of the frame types
+    signatures: Signatures,
+    /// Whether tombstone frames are returned rather than skipped
+    tombstones: bool,
+    /// Whether group markers are returned rather than consumed
+    markers: bool,
+    /// Encoding of the frame length fields
+    framing: Framing,
+    /// Offset of the current frame, relative to `body_start`
+    frame_offset: u64,
+    /// Type of the current frame
+    frame_kind: RecordKind,
+    /// Cipher for record content, if the segment is encrypted
+    cipher: Option<KeyCipher>,
+    /// Counters to report content reads to
+    metrics: Option<Arc<WalCounters>>,
+    /// Offset and contents of a producer marker awaiting its record
+    producer_marker: Option<(u64, (u64, u64))>,
+}
+
+impl SegmentCursor {
+    /// Wraps a file already positioned at the first record.
+    fn new(
+        mut file: SegmentFile,
+        signatures: Signatures,
+        framing: Framing,
+        cipher: Option<KeyCipher>,
+    ) -> Result<Self> {
+        let body_start = file.stream_position()?;
+        let file_len = file.len()?;
+        Ok(Self {
+            signatures,
+            tombstones: false,
+            markers: false,
+            framing,
+            reader: BufReader::new(file),
+            body_start,
+            file_len,
+            offset: 0,
+            pending_content: 0,
+            frame_offset: 0,
+            frame_kind: RecordKind::Data,
+            cipher,
+            metrics: None,
+            producer_marker: None,
+        })
+    }
+
+    /// Returns tombstone frames from `next_frame` along with data records.
+    fn with_tombstones(mut self) -> Self {
+        self.tombstones = true;
+        self
+    }
+
+    /// Returns the begin and commit markers of committed groups from
+    /// `next_frame` around their records.
+    fn with_markers(mut self) -> Self {
+        self.markers = true;
+        self
+    }
+
+    /// Reports the opening of the segment and each content read to
+    /// `metrics`.
+    fn counted(mut self, metrics: &Arc<WalCounters>) -> Self {
+        WalCounters::add(&metrics.segments_opened, 1);
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Advances to the next record frame, reading its header if requested.
+    ///
+    /// Any unread content of the previous frame is skipped. Read errors end
+    /// the frame chain like a torn write.
+    fn next_frame(&mut self, read_header: bool) -> Option<RecordFrame> {
+        self.try_next_frame(read_header).ok().flatten()
+    }
+
+    /// Advances to the next record frame, reporting read errors.
+    ///
+    /// The end of the frame chain, including a torn or malformed frame, is
+    /// `Ok(None)`; only failures of the underlying file are errors.
+    fn try_next_frame(&mut self, read_header: bool) -> io::Result<Option<RecordFrame>> {
+        loop {
+            match self.try_next_any_frame(read_header)? {
+                Some(frame) if frame.kind == RecordKind::Tombstone && !self.tombstones => continue,
+                frame => return Ok(frame),
+            }
+        }
+    }
+
+    /// Advances to the next frame of either type.
+    ///
+    /// Group markers, padding and producer markers are consumed here: the
+    /// records of a committed group are returned like any others, and a
+    /// group missing its commit marker ends the frame chain at its begin
+    /// marker. Under `with_markers` a committed group's markers are
+    /// returned as well. A producer marker is attached to the frame that follows it,
+    /// and one that no frame follows ends the chain at the marker.
+    fn try_next_any_frame(&mut self, read_header: bool) -> io::Result<Option<RecordFrame>> {
+        let frame = self.read_any_frame(read_header)?;
+        if frame.is_none() {
+            if let Some((marker_offset, _)) = self.producer_marker.take() {
+                self.offset = marker_offset;
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Reads frames up to the next record or tombstone, consuming markers.
+    fn read_any_frame(&mut self, read_header: bool) -> io::Result<Option<RecordFrame>> {
+        loop {
+            if self.pending_content > 0 {
+                self.reader.seek_relative(self.pending_content as i64)?;
+                self.pending_content = 0;
+            }
+
+            let mut signature_buf = [0u8; 6];
+            if end_of_chain(self.reader.read_exact(&mut signature_buf))?.is_none() {
+                return Ok(None);
+            }
+            let tombstone = signature_buf == self.signatures.tombstone;
+            let group_begin = signature_buf == self.signatures.group_begin;
+            let group_commit = signature_buf == self.signatures.group_commit;
+            let padding = signature_buf == self.signatures.padding;
+            let producer = signature_buf == self.signatures.producer;
+            if signature_buf != self.signatures.record
+                && !tombstone
+                && !group_begin
+                && !group_commit
+                && !padding
+                && !producer
+            {
+                return Ok(None);
+            }
+
+            let Some(header_len) = end_of_chain(self.framing.read_header_len(&mut self.reader))?
+            else {
+                return Ok(None);
+            };
+
+            // A corrupt length must not force a huge allocation
+            if self.body_start.saturating_add(self.offset) + header_len as u64 > self.file_len {
+                return Ok(None);
+            }
+            let header = if read_header && header_len > 0 {
+                let mut header = vec![0u8; header_len];
+                if end_of_chain(self.reader.read_exact(&mut header))?.is_none() {
+                    return Ok(None);
+                }
+                Some(Bytes::from(header))
+            } else {
+                self.reader.seek_relative(header_len as i64)?;
+                None
+            };
+
+            let Some(content_len) = end_of_chain(self.framing.read_content_len(&mut self.reader))?
+            else {
+                return Ok(None);
+            };
+
+            let Some(frame_end) = self
+                .offset
+                .checked_add(self.framing.frame_len(header_len, content_len))
+            else {
+                return Ok(None);
+            };
+            if self.body_start.saturating_add(frame_end) > self.file_len {
+                return Ok(None);
+            }
+
+            if group_begin {
+                self.producer_marker = None;
+                if !self.group_committed(frame_end, content_len)? {
+                    return Ok(None);
+                }
+                if !self.markers {
+                    self.offset = frame_end;
+                    continue;
+                }
+                // Leave the group length to be read as the marker's content
+                self.reader.seek_relative(-(content_len as i64))?;
+            }
+            if producer && content_len == 16 {
+                let mut content = [0u8; 16];
+                self.reader.read_exact(&mut content)?;
+                self.producer_marker = decode_producer(&content).map(|p| (self.offset, p));
+                self.offset = frame_end;
+                continue;
+            }
+            if (group_commit && !self.markers) || padding || producer {
+                self.offset = frame_end;
+                self.pending_content = content_len;
+                continue;
+            }
+
+            let kind = if tombstone {
+                RecordKind::Tombstone
+            } else if group_begin {
+                RecordKind::GroupBegin
+            } else if group_commit {
+                RecordKind::GroupCommit
+            } else {
+                RecordKind::Data
+            };
+            let frame = RecordFrame {
+                offset: self.offset,
+                header,
+                kind,
+                producer: self.producer_marker.take().map(|(_, producer)| producer),
+            };
+            self.frame_offset = self.offset;
+            self.frame_kind = kind;
+            self.offset = frame_end;
+            self.pending_content = content_len;
+            return Ok(Some(frame));
+        }
+    }
+
+    /// Consumes the content of a group begin marker ending at `frame_end`
+    /// and checks that its commit marker follows the group's records.
+    ///
+    /// On success the reader is left at the first record of the group.
+    fn group_committed(&mut self, frame_end: u64, content_len: u64) -> io::Result<bool> {
+        if content_len != 8 {
+            return Ok(false);
+        }
+        let mut group_len = [0u8; 8];
+        self.reader.read_exact(&mut group_len)?;
+        let group_len = u64::from_le_bytes(group_len);
+
+        let commit = encode_group_commit(self.signatures, self.framing);
+        let Some(commit_end) = frame_end
+            .checked_add(group_len)
+            .and_then(|start| start.checked_add(commit.len() as u64))
+        else {
+            return Ok(false);
+        };
+        if self.body_start.saturating_add(commit_end) > self.file_len {
+            return Ok(false);
+        }
+
+        let mut marker = vec![0u8; commit.len()];
+        self.reader.seek_relative(group_len as i64)?;
+        self.reader.read_exact(&mut marker)?;
+        self.reader
+            .seek_relative(-((group_len + commit.len() as u64) as i64))?;
+        Ok(marker == commit)
+    }
+
+    /// Reads the content of the current frame.
+    fn read_content(&mut self) -> Option<Bytes> {
+        self.try_read_content().ok().flatten()
+    }
+
+    /// Reads the content of the current frame, reporting read errors.
+    ///
+    /// Records and tombstones of an encrypted segment are decrypted, and
+    /// one that fails to decrypt is an error. Returns `Ok(None)` if the
+    /// file ends before the content does.
+    fn try_read_content(&mut self) -> Result<Option<Bytes>> {
+        let mut content = vec![0u8; content_buffer_len(self.pending_content)?];
+        if end_of_chain(self.reader.read_exact(&mut content))?.is_none() {
+            return Ok(None);
+        }
+        self.pending_content = 0;
+        if let Some(metrics) = &self.metrics {
+            WalCounters::add(&metrics.records_read, 1);
+        }
+        match (&self.cipher, self.frame_kind) {
+            (Some(cipher), RecordKind::Data | RecordKind::Tombstone) => {
+                cipher.decrypt(self.frame_offset, &content).map(Some)
+            }
+            _ => Ok(Some(Bytes::from(content))),
+        }
+    }
+
+    /// Ignores any bytes at or past `file_len`.
+    fn limit(mut self, file_len: u64) -> Self {
+        self.file_len = self.file_len.min(file_len);
+        self
+    }
+
+    /// Repositions the cursor so the next frame is read from `offset`.
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(self.body_start.saturating_add(offset)))?;
+        self.offset = offset;
+        self.pending_content = 0;
+        self.producer_marker = None;
+        Ok(())
+    }
+
+    /// Returns the number of record bytes visible to the cursor.
+    fn body_len(&self) -> u64 {
+        self.file_len.saturating_sub(self.body_start)
+    }
+}
+
+/// Maps the read errors that mark the end of a frame chain to `None`.
+///
+/// A frame cut short by end of file or with a malformed length field ends
+/// the chain like a torn write; any other error is a real I/O failure.
+fn end_of_chain<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Opens a record cursor over `file`, segment `sequence` of its key,
+/// positioned after the segment file header.
+///
+/// Fails if the segment is encrypted and `cipher` is `None`.
+fn open_cursor(
+    mut file: SegmentFile,
+    sequence: u64,
+    signatures: Signatures,
+    cipher: Option<&Arc<dyn RecordCipher>>,
+) -> Result<SegmentCursor> {
+    let header = parse_segment_header(&mut file, signatures)?;
+    let cipher = KeyCipher::for_segment(&header, sequence, cipher)?;
+    SegmentCursor::new(file, signatures, header.framing, cipher)
+}
+
+/// A segment file opened for reading.
+///
+/// A compressed segment is read whole and decompressed into memory. Its
+/// image is the segment as it was before compression, header included,
+/// so offsets into it are those of the original `.log` file.
+#[derive(Debug)]
+enum SegmentFile {
+    Plain(Box<dyn VfsFile>),
+    Image(io::Cursor<Bytes>),
+}
+
+impl SegmentFile {
+    /// Opens the segment at `path`, decompressing it if it is a `.logz`
+    /// file.
+    ///
+    /// A `.log` path that no longer exists is looked up as `.logz` too,
+    /// since the segment may have been compressed after it was listed.
+    fn open(vfs: &dyn Vfs, path: &Path, signatures: Signatures) -> Result<Self> {
+        if is_compressed_segment(path) {
+            let image = read_compressed_segment(vfs, path, signatures)?;
+            return Ok(Self::Image(io::Cursor::new(image)));
+        }
+        match vfs.open(path) {
+            Ok(file) => Ok(Self::Plain(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let compressed_path = path.with_extension("logz");
+                if vfs.metadata(&compressed_path).is_ok() {
+                    Self::open(vfs, &compressed_path, signatures)
+                } else {
+                    Err(e.into())
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the length of the file, or of the image of a compressed one.
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            Self::Plain(file) => file.size(),
+            Self::Image(image) => Ok(image.get_ref().len() as u64),
+        }
+    }
+}
+
+impl Read for SegmentFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.read(buf),
+            Self::Image(image) => image.read(buf),
+        }
+    }
+}
+
+impl Seek for SegmentFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(file) => file.seek(pos),
+            Self::Image(image) => image.seek(pos),
+        }
+    }
+}
+
+/// Reads the whole file at `path`.
+fn read_file(vfs: &dyn Vfs, path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = vfs.open(path)?;
+    let mut contents = Vec::with_capacity(file.size().unwrap_or(0) as usize);
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Returns whether `path` names a compressed segment.
+fn is_compressed_segment(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "logz")
+}
+
+/// Returns whether `filename` names a segment file, plain or compressed.
+fn is_segment_file_name(filename: &str) -> bool {
+    filename.ends_with(".log") || filename.ends_with(".logz")
+}
+
+/// Returns whether `filename` is a temporary copy of a segment, of the
+/// manifest or of the checkpoint, written before being renamed into place.
+fn is_temp_file_name(filename: &str) -> bool {
+    filename.strip_suffix(".tmp").is_some_and(|name| {
+        is_segment_file_name(name) || name == MANIFEST_FILE_NAME || name == CHECKPOINT_FILE_NAME
+    })
+}
+
+/// Copies the directory `from` to `to`, with everything in it, syncing
+/// every file copied.
+fn copy_dir_all(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+            File::open(&dest)?.sync_all()?;
+        }
+    }
+    StdVfs.sync_dir(to)
+}
+
+/// Sets a key's checkpoint entry to `entry_ref`, unless the entry
+/// already points at a later record.
+fn advance_checkpoint(entries: &mut HashMap<String, EntryRef>, key: &[u8], entry_ref: EntryRef) {
+    let key = String::from_utf8_lossy(key).into_owned();
+    let later = entries.get(&key).is_none_or(|current| {
+        (entry_ref.sequence_number, entry_ref.offset) >= (current.sequence_number, current.offset)
+    });
+    if later {
+        entries.insert(key, entry_ref);
+    }
+}
+
+/// Encodes checkpoint entries: the signature, a CRC32 of the rest, the
+/// entry count, then per entry the three `EntryRef` fields and the
+/// length-prefixed key.
+fn encode_checkpoint(entries: &HashMap<String, EntryRef>) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, entry_ref) in entries {
+        body.extend_from_slice(&entry_ref.key_hash.to_le_bytes());
+        body.extend_from_slice(&entry_ref.sequence_number.to_le_bytes());
+        body.extend_from_slice(&entry_ref.offset.to_le_bytes());
+        body.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        body.extend_from_slice(key.as_bytes());
+    }
+
+    let mut contents = Vec::with_capacity(12 + body.len());
+    contents.extend_from_slice(&CHECKPOINT_SIGNATURE);
+    contents.extend_from_slice(&crc32(&body).to_le_bytes());
+    contents.extend_from_slice(&body);
+    contents
+}
+
+/// Decodes the contents of a checkpoint file.
+fn decode_checkpoint(contents: &[u8]) -> Result<HashMap<String, EntryRef>> {
+    let corrupted = || WalError::CorruptedData("Malformed checkpoint file".to_string());
+    if contents.len() < 16 || contents[..8] != CHECKPOINT_SIGNATURE {
+        return Err(corrupted());
+    }
+    let body = &contents[12..];
+    if u32::from_le_bytes(contents[8..12].try_into().unwrap()) != crc32(body) {
+        return Err(WalError::CorruptedData(
+            "Checkpoint file checksum mismatch".to_string(),
+        ));
+    }
+
+    let mut rest = body;
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if rest.len() < len {
+            return Err(corrupted());
+        }
+        let (taken, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(taken)
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let key_hash = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let sequence_number = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let offset = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let key_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let key = String::from_utf8(take(key_len)?.to_vec()).map_err(|_| corrupted())?;
+        entries.insert(
+            key,
+            EntryRef {
+                key_hash,
+                sequence_number,
+                offset,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+/// Returns whether `filename` is a segment moved aside by quarantine.
+fn is_quarantined_file_name(filename: &str) -> bool {
+    filename
+        .strip_suffix(".corrupt")
+        .is_some_and(is_segment_file_name)
+}
+
+/// Reads a compressed segment and returns its decompressed image.
+///
+/// Fails with `WalError::InvalidConfig` if the `compression` feature is
+/// not enabled.
+fn read_compressed_segment(vfs: &dyn Vfs, path: &Path, signatures: Signatures) -> Result<Bytes> {
+    let compressed = read_file(vfs, path)?;
+    let mut body = &compressed[..];
+    let header = parse_segment_header(&mut body, signatures)?;
+    if !header.compressed {
+        return Err(WalError::CorruptedData(format!(
+            "Segment {} is not marked as compressed",
+            path.display()
+        )));
+    }
+
+    let mut image = compressed[..compressed.len() - body.len()].to_vec();
+    set_compressed_flag(&mut image, false);
+    #[cfg(feature = "compression")]
+    {
+        zstd::stream::copy_decode(body, &mut image)?;
+        Ok(Bytes::from(image))
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        Err(WalError::InvalidConfig(format!(
+            "segment {} is compressed; reading it requires the `compression` feature",
+            path.display()
+        )))
+    }
+}
+
+/// Sets or clears the compression flag in a serialized segment header,
+/// updating its checksum.
+///
+/// `header` is the complete header of a segment of at least
+/// `HEADER_CHECKSUM_VERSION`.
+fn set_compressed_flag(header: &mut [u8], compressed: bool) {
+    let mut version = u16::from_le_bytes([header[8], header[9]]);
+    if compressed {
+        version |= COMPRESSED_SEGMENT_FLAG;
+    } else {
+        version &= !COMPRESSED_SEGMENT_FLAG;
+    }
+    header[8..10].copy_from_slice(&version.to_le_bytes());
+    header[12..16].fill(0);
+    let checksum = crc32(header);
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Images of the compressed segments a `Wal` read most recently.
+///
+/// Images are keyed by path together with the file's length and
+/// modification time, so a segment removed and written again under the
+/// same name is not served from a stale image.
+#[derive(Debug, Default)]
+struct DecompressedSegments {
+    images: Mutex<VecDeque<SegmentImage>>,
+}
+
+/// Decompressed image of a segment file.
+#[derive(Debug)]
+struct SegmentImage {
+    path: PathBuf,
+    /// Length and modification time of the file the image was read from
+    stamp: (u64, Option<SystemTime>),
+    image: Bytes,
+}
+
+impl DecompressedSegments {
+    /// Returns the image of the compressed segment at `path`,
+    /// decompressing it unless it is cached.
+    fn get(&self, vfs: &dyn Vfs, path: &Path, signatures: Signatures) -> Result<Bytes> {
+        let metadata = vfs.metadata(path)?;
+        let stamp = (metadata.len, metadata.modified);
+        {
+            let mut images = lock_unpoisoned(&self.images);
+            let cached = images
+                .iter()
+                .position(|cached| cached.path == path && cached.stamp == stamp);
+            if let Some(index) = cached {
+                let cached = images.remove(index).unwrap();
+                let image = cached.image.clone();
+                images.push_back(cached);
+                return Ok(image);
+            }
+        }
+
+        let image = read_compressed_segment(vfs, path, signatures)?;
+        let mut images = lock_unpoisoned(&self.images);
+        images.retain(|cached| cached.path != path);
+        if images.len() == DECOMPRESSED_SEGMENT_CACHE {
+            images.pop_front();
+        }
+        images.push_back(SegmentImage {
+            path: path.to_path_buf(),
+            stamp,
+            image: image.clone(),
+        });
+        Ok(image)
+    }
+}
+
+/// Content hashes of the records of looked-up keys, kept under
+/// `WalOptions::content_index`.
+///
+/// A key is only present once [`Wal::contains_record`] has hashed its
+/// records; from then on appends to it are added as they are written.
+#[derive(Debug, Default)]
+struct ContentIndex {
+    /// Map from key hash to the first record with each content hash
+    keys: Mutex<HashMap<u64, HashMap<[u8; 32], EntryRef>>>,
+}
+
+impl ContentIndex {
+    /// Adds appended records to the index of their key, if it is indexed.
+    #[cfg(feature = "digest")]
+    fn insert<'a>(&self, records: impl IntoIterator<Item = (EntryRef, &'a [u8])>) {
+        let mut keys = lock_unpoisoned(&self.keys);
+        for (entry_ref, content) in records {
+            if let Some(hashes) = keys.get_mut(&entry_ref.key_hash) {
+                hashes.entry(sha256(content)).or_insert(entry_ref);
+            }
+        }
+    }
+
+    /// Drops the index of a key whose records were removed or rewritten,
+    /// so that its next lookup hashes the records again.
+    fn forget(&self, key_hash: u64) {
+        lock_unpoisoned(&self.keys).remove(&key_hash);
+    }
+}
+
+/// Returns the SHA-256 hash of `data`.
+#[cfg(feature = "digest")]
+fn sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(data).into()
+}
+
+/// Parses a segment filename, plain or compressed, into its key hash and
+/// sequence number.
+fn parse_segment_filename(filename: &str) -> Option<(u64, u64)> {
+    let name_part = filename
+        .strip_suffix(".log")
+        .or_else(|| filename.strip_suffix(".logz"));
+    if let Some(name_part) = name_part {
+        let parts: Vec<&str> = name_part.split('-').collect();
+        if parts.len() >= 3 {
+            let len = parts.len();
+            if let (Ok(sequence), Ok(key_hash)) =
+                (parts[len - 1].parse::<u64>(), parts[len - 2].parse::<u64>())
+            {
+                return Some((key_hash, sequence));
+            }
+        }
+    }
+    None
+}
+
+/// Returns the sequence number in the name of the segment file at `path`,
+/// or 0, which no segment has, if the name is not a segment name.
+fn segment_sequence(path: &Path) -> u64 {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_segment_filename)
+        .map_or(0, |(_, sequence)| sequence)
+}
+
+/// Parses the name of a shard subdirectory into the key hash bits it
+/// holds and its width in hex digits.
+fn parse_shard_dir_name(name: &str) -> Option<(u64, usize)> {
+    let width = name.len();
+    let valid = (1..=(MAX_SHARD_DIR_BITS as usize).div_ceil(4)).contains(&width)
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    valid.then(|| (u64::from_str_radix(name, 16).unwrap(), width))
+}
+
+/// Returns whether a shard subdirectory can hold segments of `key_hash`.
+///
+/// The width of the name leaves up to four possible bit counts; the
+/// directory matches if the key hash agrees with it under any of them.
+fn shard_dir_holds(shard: u64, width: usize, key_hash: u64) -> bool {
+    (width * 4 - 3..=width * 4).any(|bits| key_hash & ((1u64 << bits) - 1) == shard)
+}
+
+/// Describes the bytes between the end of a segment's frame chain and the
+/// end of the file.
+///
+/// `reader` is positioned at `offset`, the end of the last intact frame,
+/// and `len` bytes follow it.
+fn classify_segment_tail<R: Read>(
+    reader: &mut R,
+    signatures: Signatures,
+    offset: u64,
+    len: u64,
+) -> io::Result<SegmentAnomaly> {
+    let mut reader = reader.take(len);
+    let mut buf = [0u8; 8192];
+    let mut zero_filled = true;
+    let mut first = true;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if first {
+            first = false;
+            if buf[..n].starts_with(&signatures.group_begin) {
+                return Ok(SegmentAnomaly::UncommittedGroup { offset, len });
+            }
+            let prefix = &buf[..n.min(6)];
+            if prefix == &signatures.record[..prefix.len()]
+                || prefix == &signatures.tombstone[..prefix.len()]
+                || prefix == &signatures.producer[..prefix.len()]
+            {
+                return Ok(SegmentAnomaly::TornRecord { offset, len });
+            }
+        }
+        zero_filled &= buf[..n].iter().all(|byte| *byte == 0);
+    }
+
+    Ok(if zero_filled {
+        SegmentAnomaly::ZeroFilledTail { offset, len }
+    } else {
+        SegmentAnomaly::UnrecognizedBytes { offset, len }
+    })
+}
+
+/// Reads and parses the headers of segment files, in the order of `paths`.
+///
+/// With the `parallel` feature the files are read on the rayon thread
+/// pool, which speeds up directories with many segments.
+fn read_segment_headers(
+    vfs: &dyn Vfs,
+    paths: &[PathBuf],
+    signatures: Signatures,
+) -> Vec<Result<SegmentHeader>> {
+    let read = |path: &PathBuf| {
+        let mut file = vfs.open(path)?;
+        parse_segment_header(&mut file, signatures)
+    };
+
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        paths.par_iter().map(read).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        paths.iter().map(read).collect()
+    }
+}
+
+/// Parses a segment file header, leaving `reader` at the first record.
+fn parse_segment_header<R: Read>(reader: &mut R, signatures: Signatures) -> Result<SegmentHeader> {
+    let mut signature_buf = [0u8; 8];
+    reader.read_exact(&mut signature_buf)?;
+    if signature_buf != signatures.segment {
+        return Err(WalError::CorruptedData(format!(
+            "Segment signature {:?} does not match the configured {:?}",
+            String::from_utf8_lossy(&signature_buf),
+            String::from_utf8_lossy(&signatures.segment)
+        )));
+    }
+
+    let mut descriptor = [0u8; 8];
+    reader.read_exact(&mut descriptor)?;
+    let stored_version = u16::from_le_bytes([descriptor[0], descriptor[1]]);
+    let encrypted = stored_version & ENCRYPTED_SEGMENT_FLAG != 0;
+    let compressed = stored_version & COMPRESSED_SEGMENT_FLAG != 0;
+    let format_version = stored_version & !(ENCRYPTED_SEGMENT_FLAG | COMPRESSED_SEGMENT_FLAG);
+    if format_version > FORMAT_VERSION {
+        return Err(WalError::UnsupportedFormat {
+            found: format_version,
+            supported: FORMAT_VERSION,
+        });
+    }
+
+    let mut expiration_bytes = [0u8; 8];
+    reader.read_exact(&mut expiration_bytes)?;
+
+    let mut key_len_bytes = [0u8; 8];
+    reader.read_exact(&mut key_len_bytes)?;
+    let key_len = u64::from_le_bytes(key_len_bytes);
+
+    // Read at most what the file holds, so a corrupt length cannot force a
+    // huge allocation
+    let mut key = Vec::new();
+    reader.take(key_len).read_to_end(&mut key)?;
+    if key.len() as u64 != key_len {
+        return Err(WalError::CorruptedData(format!(
+            "Segment key length {} exceeds the file",
+            key_len
+        )));
+    }
+
+    if format_version >= HEADER_CHECKSUM_VERSION {
+        let stored = u32::from_le_bytes(descriptor[4..8].try_into().unwrap());
+        descriptor[4..8].fill(0);
+        let mut header = Vec::with_capacity(32 + key.len());
+        header.extend_from_slice(&signature_buf);
+        header.extend_from_slice(&descriptor);
+        header.extend_from_slice(&expiration_bytes);
+        header.extend_from_slice(&key_len_bytes);
+        header.extend_from_slice(&key);
+        if crc32(&header) != stored {
+            return Err(WalError::CorruptedData(
+                "Segment header checksum mismatch".to_string(),
+            ));
+        }
+    }
+
+    Ok(SegmentHeader {
+        format_version,
+        hasher_id: descriptor[2],
+        framing: Framing::from_id(descriptor[3], format_version)?,
+        encrypted,
+        compressed,
+        expiration_timestamp: u64::from_le_bytes(expiration_bytes),
+        key,
+    })
+}
+
+/// Returns whether the segment at `file_path` has a readable header for `key`.
+///
+/// A damaged or short header is not a match. Failing to open or read the
+/// file is an error, so an unreadable segment is not silently skipped.
+fn segment_has_key(
+    vfs: &dyn Vfs,
+    file_path: &Path,
+    key: &[u8],
+    signatures: Signatures,
+) -> Result<bool> {
+    let mut file = vfs.open(file_path)?;
+    match parse_segment_header(&mut file, signatures) {
+        Ok(header) => Ok(header.key == key),
+        Err(WalError::Io(e)) if e.kind() != io::ErrorKind::UnexpectedEof => Err(WalError::Io(e)),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Reads specific entry from segment file, segment `sequence` of its key.
+///
+/// Bytes at or past `file_len` are treated as absent.
+fn read_entry_from_file(
+    file: SegmentFile,
+    sequence: u64,
+    offset: u64,
+    file_len: u64,
+    signatures: Signatures,
+    cipher: Option<&Arc<dyn RecordCipher>>,
+) -> Result<Bytes> {
+    let (mut file, content_len, header) = seek_to_content(file, offset, file_len, signatures)?;
+    let cipher = KeyCipher::for_segment(&header, sequence, cipher)?;
+
+    let mut content = vec![0u8; content_buffer_len(content_len)?];
+    file.read_exact(&mut content)?;
+
+    match cipher {
+        Some(cipher) => cipher.decrypt(offset, &content),
+        None => Ok(Bytes::from(content)),
+    }
+}
+
+/// Converts a stored content length to a buffer size.
+///
+/// Callers check the length against the file first; this only guards
+/// targets whose `usize` is narrower than a segment can be.
+fn content_buffer_len(content_len: u64) -> Result<usize> {
+    usize::try_from(content_len).map_err(|_| {
+        WalError::CorruptedData(format!(
+            "Record content length {} does not fit in memory",
+            content_len
+        ))
+    })
+}
+
+/// Positions a segment file at the content of the record at `offset`,
+/// returning the file, the stored content length and the segment header.
+///
+/// Bytes at or past `file_len` are treated as absent. A record whose
+/// stored lengths run past the end of the file itself is corrupt.
+fn seek_to_content(
+    mut file: SegmentFile,
+    offset: u64,
+    file_len: u64,
+    signatures: Signatures,
+) -> Result<(SegmentFile, u64, SegmentHeader)> {
+    let physical_len = file.len()?;
+    let file_len = physical_len.min(file_len);
+
+    let header = parse_segment_header(&mut file, signatures)?;
+    let framing = header.framing;
+    let body_start = file.stream_position()?;
+    let segment_len = file_len.saturating_sub(body_start);
+    if offset.saturating_add(framing.min_frame_len()) > segment_len {
+        return Err(WalError::InvalidOffset {
+            offset,
+            segment_len,
+        });
+    }
+    file.seek(SeekFrom::Current(offset as i64))?;
+
+    let mut signature_buf = [0u8; 6];
+    file.read_exact(&mut signature_buf)?;
+    if signature_buf != signatures.record {
+        return Err(WalError::CorruptedData(
+            "Record signature not found".to_string(),
+        ));
+    }
+
+    let header_len = framing.read_header_len(&mut file)?;
+    file.seek(SeekFrom::Current(header_len as i64))?;
+    let content_len = framing.read_content_len(&mut file)?;
+
+    let frame_end = offset.saturating_add(framing.frame_len(header_len, content_len));
+    if frame_end > physical_len.saturating_sub(body_start) {
+        return Err(WalError::CorruptedData(format!(
+            "Record content length {} exceeds the segment",
+            content_len
+        )));
+    }
+    if frame_end > segment_len {
+        return Err(WalError::InvalidOffset {
+            offset,
+            segment_len,
+        });
+    }
+
+    Ok((file, content_len, header))
+}
+
+/// Write-Ahead Log with per-key segment sets.
+///
+/// The `Wal` struct provides the main interface for WAL operations,
+/// managing segment files and ensuring durability guarantees.
+///
+/// Writes are read-your-writes: every write method hands what it wrote to
+/// the OS before returning, whether or not it was `durable`, so the records
+/// are visible to the reads of the same `Wal`, such as
+/// [`Wal::read_entry_at`] and [`Wal::enumerate_records`], as soon as the
+/// call returns. Reads open segment files on their own and never see the
+/// write buffers of active segments, so no write leaves data in them.
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    options: WalOptions,
+    /// Map from key hash to active segment info
+    active_segments: HashMap<u64, ActiveSegment>,
+    /// Map from key hash to next sequence number
+    next_sequence: HashMap<u64, u64>,
+    /// Map from key hash to retention overriding `entry_retention`
+    key_retention: HashMap<u64, Duration>,
+    /// Exclusive lock on the directory, released when closed
+    lock: Option<Box<dyn VfsFile>>,
+    /// Map from key hash to recently appended dedup ids
+    dedup: HashMap<u64, DedupCache>,
+    /// Segment files moved aside by `scan_existing_files`
+    quarantined: Vec<PathBuf>,
+    /// Segments sealed or closed with writes not synced yet, synced
+    /// before a checkpoint can point into them
+    unsynced_sealed: HashSet<PathBuf>,
+    /// Latest record of each key as of the last checkpoint and the
+    /// segments sealed since, once a checkpoint has walked the directory
+    checkpoint: Option<HashMap<String, EntryRef>>,
+    /// Counters reported by `metrics`
+    metrics: Arc<WalCounters>,
+    /// Number of writes so far, used to order active segments by last use
+    write_clock: u64,
+    /// Last segment examined by `compact_incremental`, if a pass is under way
+    compact_cursor: Option<PathBuf>,
+    /// Map from key hash to the last-seen sequence and entry of each
+    /// producer, loaded on the key's first idempotent append
+    producers: HashMap<u64, HashMap<u64, (u64, EntryRef)>>,
+    /// Value of the appends counter when the active segments were last
+    /// synced, for `auto_sync_every`
+    synced_appends: u64,
+    /// Images of recently read compressed segments
+    decompressed: DecompressedSegments,
+    /// Content hashes of looked-up keys, for `content_index`
+    content_index: ContentIndex,
+    /// Filesystem the directory is on
+    vfs: Arc<dyn Vfs>,
+}
+
+impl Wal {
+    /// Creates a new WAL instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `filepath` - Directory path for WAL files
+    /// * `options` - Configuration options
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if options are invalid or another
+    /// `Wal`, in this or any other process, already has the directory open.
+    /// Returns `WalError::Io` if directory creation fails.
+    /// Returns `WalError::UnsupportedFormat` if the directory or one of its
+    /// segments was written by a newer nano-wal.
+    ///
+    /// The directory is locked through an advisory lock on
+    /// `.nano-wal.lock` until the `Wal` is dropped or shut down.
+    ///
+    /// A record cut short at the end of a key's latest segment, as a crash
+    /// during a non-durable append leaves it, is truncated away on open.
+    /// Segments whose header is damaged or carries a foreign signature are
+    /// left in place but take no part in reads or appends;
+    /// [`Wal::enumerate_keys_checked`] reports them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nano_wal::{Wal, WalOptions};
+    ///
+    /// let wal = Wal::new("./my_wal", WalOptions::default())?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn new(filepath: &str, options: WalOptions) -> Result<Self> {
+        Self::open(Path::new(filepath), options, Arc::new(StdVfs))
+    }
+
+    /// Creates a WAL instance whose files live on `vfs` instead of the
+    /// local filesystem.
+    ///
+    /// Behaves like [`Wal::new`] in every other respect. With [`MemVfs`],
+    /// tests run without touching the disk, and reopening the same
+    /// `MemVfs` after dropping the `Wal` simulates a restart.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nano_wal::{MemVfs, Wal, WalOptions};
+    /// use std::sync::Arc;
+    ///
+    /// let wal = Wal::with_vfs("/wal", WalOptions::default(), Arc::new(MemVfs::new()))?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn with_vfs(
+        path: impl AsRef<Path>,
+        options: WalOptions,
+        vfs: Arc<dyn Vfs>,
+    ) -> Result<Self> {
+        Self::open(path.as_ref(), options, vfs)
+    }
+
+    /// Opens or creates a WAL kept entirely in the file at `path`, instead
+    /// of a directory.
+    ///
+    /// The file holds every segment of every key, so a WAL can be handed
+    /// off or archived as one artifact; the rest of the API, `EntryRef`s
+    /// included, works as with [`Wal::new`]. The file is a journal of
+    /// changes to the WAL's files, replayed into memory on open, and the
+    /// contents of every segment stay in memory while the WAL is open, so
+    /// memory use grows with the data the WAL holds; this mode suits WALs
+    /// that fit in memory. Appends are added to the end of the file and
+    /// syncs sync it, with the same durability as the directory mode. An
+    /// operation torn by a crash is truncated away on the next open. Once
+    /// the journal grows to more than twice the data it holds, it is
+    /// rewritten compactly through a temporary file renamed over it, when
+    /// opened or as soon as segments are removed or replaced, for instance
+    /// by [`Wal::compact`]. The file is locked while open.
+    /// [`Wal::shutdown`] removes the WAL's data but leaves the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if the file is already open.
+    /// Returns `WalError::CorruptedData` if the file is not a single-file
+    /// WAL.
+    /// Otherwise the same as [`Wal::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nano_wal::{Wal, WalOptions};
+    /// use bytes::Bytes;
+    ///
+    /// let mut wal = Wal::open_single_file("./events.nwal", WalOptions::default())?;
+    /// wal.append_entry("orders", None, Bytes::from("order 1"), true)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn open_single_file(path: impl AsRef<Path>, options: WalOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let vfs = SingleFileVfs::open(path)?;
+        Self::open(path, options, Arc::new(vfs))
+    }
+
+    /// Starts a [`WalBuilder`] for the WAL in `path`.
+    ///
+    /// Unlike [`Wal::new`], the builder checks that the directory is
+    /// writable before opening it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::Wal;
+    /// let wal = Wal::builder("./wal").segments_per_retention_period(24).build()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn builder(path: impl AsRef<Path>) -> WalBuilder {
+        WalBuilder {
+            path: path.as_ref().to_path_buf(),
+            options: WalOptions::default(),
+        }
+    }
+
+    /// Replaces the WAL in `target` with the one built in `staging`, and
+    /// opens it.
+    ///
+    /// `staging` is first opened with `options`, so a directory that is
+    /// locked, written by a newer nano-wal or recorded with different
+    /// options is refused before anything moves. An existing `target` is
+    /// renamed to `<target>.backup`, then `staging` is renamed to
+    /// `target`; on one filesystem each step is atomic. When `staging` is
+    /// on another filesystem, it is copied to `<target>.promoting` next to
+    /// the target, which is renamed into place once complete, and
+    /// `staging` is removed afterwards if it can be. The promoted directory is then
+    /// scanned and returned open like [`Wal::new`].
+    ///
+    /// A `Wal` open on `target` must be closed first; its lock makes this
+    /// fail rather than move the directory from under it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if `staging` is not a directory,
+    /// either directory is locked by another `Wal`, or a
+    /// `<target>.backup` or `<target>.promoting` left by an earlier
+    /// promotion exists; a leftover `.promoting` copy means that promotion
+    /// was interrupted, and the target was not replaced.
+    /// Returns `WalError::Io` if a rename or the copy fails, after moving
+    /// the backup back into place.
+    /// Otherwise the same as [`Wal::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nano_wal::{Wal, WalOptions};
+    /// use std::path::Path;
+    ///
+    /// let wal = Wal::promote(
+    ///     Path::new("./wal.rebuilt"),
+    ///     Path::new("./wal"),
+    ///     WalOptions::default(),
+    /// )?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn promote(staging: &Path, target: &Path, options: WalOptions) -> Result<Wal> {
+        if !fs::metadata(staging)?.is_dir() {
+            return Err(WalError::InvalidConfig(format!(
+                "{} is not a directory",
+                staging.display()
+            )));
+        }
+        Self::open(staging, options.clone(), Arc::new(StdVfs))?.close()?;
+
+        let sibling = |suffix: &str| {
+            let mut name = target.file_name().unwrap_or_default().to_os_string();
+            name.push(suffix);
+            target.with_file_name(name)
+        };
+        let backup = sibling(".backup");
+        let promoting = sibling(".promoting");
+        if promoting.exists() {
+            return Err(WalError::InvalidConfig(format!(
+                "{} was left by an interrupted promotion; remove it to promote again",
+                promoting.display()
+            )));
+        }
+        if backup.exists() {
+            return Err(WalError::InvalidConfig(format!(
+                "{} from an earlier promotion exists; remove it to promote again",
+                backup.display()
+            )));
+        }
+
+        let backed_up = if target.exists() {
+            let lock = File::create(target.join(LOCK_FILE_NAME))?;
+            if !VfsFile::try_lock(&lock)? {
+                return Err(WalError::InvalidConfig(format!(
+                    "{} is already locked by another Wal",
+                    target.display()
+                )));
+            }
+            fs::rename(target, &backup)?;
+            true
+        } else {
+            false
+        };
+
+        let moved = match fs::rename(staging, target) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let copied =
+                    copy_dir_all(staging, &promoting).and_then(|_| fs::rename(&promoting, target));
+                if copied.is_err() {
+                    let _ = fs::remove_dir_all(&promoting);
+                } else {
+                    // The promoted copy is complete either way
+                    let _ = fs::remove_dir_all(staging);
+                }
+                copied
+            }
+            moved => moved,
+        };
+        if let Err(e) = moved {
+            if backed_up && !target.exists() {
+                let _ = fs::rename(&backup, target);
+            }
+            return Err(e.into());
+        }
+        if let Some(parent) = target.parent() {
+            StdVfs.sync_dir(parent)?;
+        }
+
+        Self::open(target, options, Arc::new(StdVfs))
+    }
+
+    /// Opens or creates the WAL in `dir`.
+    fn open(dir: &Path, options: WalOptions, vfs: Arc<dyn Vfs>) -> Result<Self> {
+        options.validate()?;
+
+        if vfs.metadata(dir).is_err() {
+            vfs.create_dir_all(dir)?;
+        }
+
+        let lock = vfs.create(&dir.join(LOCK_FILE_NAME))?;
+        if !lock.try_lock()? {
+            return Err(WalError::InvalidConfig(format!(
+                "{} is already locked by another Wal",
+                dir.display()
+            )));
+        }
+
+        let mut wal = Wal {
+            dir: dir.to_path_buf(),
+            options,
+            active_segments: HashMap::new(),
+            next_sequence: HashMap::new(),
+            key_retention: HashMap::new(),
+            lock: Some(lock),
+            dedup: HashMap::new(),
+            quarantined: Vec::new(),
+            unsynced_sealed: HashSet::new(),
+            checkpoint: None,
+            metrics: Arc::default(),
+            write_clock: 0,
+            compact_cursor: None,
+            producers: HashMap::new(),
+            synced_appends: 0,
+            decompressed: DecompressedSegments::default(),
+            content_index: ContentIndex::default(),
+            vfs,
+        };
+
+        wal.check_manifest()?;
+        wal.scan_existing_files()?;
+        Ok(wal)
+    }
+
+    /// Compares the options with the directory's manifest, then records
+    /// them.
+    ///
+    /// A directory without a manifest, new or written by an older version,
+    /// gets one. A manifest from a newer format version is refused.
+    fn check_manifest(&self) -> Result<()> {
+        let path = self.dir.join(MANIFEST_FILE_NAME);
+        let configured = Manifest::from_options(&self.options);
+
+        match read_file(&*self.vfs, &path) {
+            Ok(contents) => {
+                let contents = String::from_utf8(contents).map_err(|_| {
+                    WalError::CorruptedData("Manifest is not valid UTF-8".to_string())
+                })?;
+                let recorded = Manifest::parse(&contents)?;
+                if recorded.format_version > FORMAT_VERSION {
+                    return Err(WalError::UnsupportedFormat {
+                        found: recorded.format_version,
+                        supported: FORMAT_VERSION,
+                    });
+                }
+                let differences = recorded.differences(&configured);
+                if !differences.is_empty() && !self.options.allow_option_change {
+                    return Err(WalError::InvalidConfig(format!(
+                        "{} was created with {}; reopen with the same options or set allow_option_change",
+                        self.dir.display(),
+                        differences.join(", ")
+                    )));
+                }
+                if recorded == configured {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        self.replace_file(&path, configured.render().as_bytes())
+    }
+
+    /// Replaces the file at `path` with `contents` through a synced
+    /// temporary file renamed over it, so a crash leaves either the old or
+    /// the new contents.
+    fn replace_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let result = self
+            .vfs
+            .create(&tmp_path)
+            .and_then(|mut tmp| {
+                tmp.write_all(contents)?;
+                self.sync_file(&tmp)
+            })
+            .and_then(|_| self.vfs.rename(&tmp_path, path));
+        if let Err(e) = result {
+            let _ = self.vfs.remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        self.sync_dir(&self.dir)
+    }
+
+    /// Scans existing files to determine next sequence numbers.
+    ///
+    /// Also verifies that existing segments were written with the
+    /// configured hasher, since key hashes are baked into filenames, and
+    /// removes temporary files left behind by an interrupted segment
+    /// creation or rewrite, on a best-effort basis: a leftover that cannot
+    /// be removed stays listed by [`Wal::list_orphans`]. Segment files too
+    /// short to hold a header, such as empty files left by a crash, are
+    /// quarantined.
+    ///
+    /// Headers are read with [`read_segment_headers`], in parallel with the
+    /// `parallel` feature, and checked in filename order, so the outcome
+    /// does not depend on directory order or thread scheduling.
+    fn scan_existing_files(&mut self) -> Result<()> {
+        for path in self.list_orphans().unwrap_or_default() {
+            let filename = path.file_name().and_then(|name| name.to_str());
+            if filename.is_some_and(is_temp_file_name) {
+                let _ = self.vfs.remove_file(&path);
+            }
+        }
+        let mut segment_paths = Vec::new();
+        if let Ok(entries) = self.segment_dir_entries(None) {
+            for entry in entries {
+                if let Some(filename) = entry.file_name().to_str() {
+                    if is_segment_file_name(filename) {
+                        let too_short = self
+                            .vfs
+                            .metadata(&entry.path())
+                            .is_ok_and(|metadata| metadata.len < file_header_size(0));
+                        if too_short {
+                            self.quarantine_segment(&entry.path())?;
+                        } else {
+                            segment_paths.push(entry.path());
+                        }
+                    }
+                }
+            }
+        }
+        // Replacing a segment with its compressed or decompressed form can
+        // leave both behind; the compressed one is complete either way
+        segment_paths.retain(|path| {
+            let superseded = !is_compressed_segment(path)
+                && self.vfs.metadata(&path.with_extension("logz")).is_ok();
+            if superseded {
+                let _ = self.vfs.remove_file(path);
+            }
+            !superseded
+        });
+        segment_paths.sort();
+
+        let headers = read_segment_headers(&*self.vfs, &segment_paths, self.signatures());
+        let mut latest: HashMap<u64, (u64, &PathBuf)> = HashMap::new();
+        for (segment_path, header) in segment_paths.iter().zip(headers) {
+            let filename = segment_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let parsed = self.parse_filename(filename);
+            if let Some((key_hash, sequence)) = parsed {
+                let current_max = *self.next_sequence.get(&key_hash).unwrap_or(&0);
+                self.next_sequence
+                    .insert(key_hash, current_max.max(sequence + 1));
+            }
+            let header = match header {
+                Err(e @ WalError::UnsupportedFormat { .. }) => return Err(e),
+                // A damaged or foreign header leaves the segment out of
+                // reads and appends, for `enumerate_keys_checked` to report
+                Err(_) => continue,
+                Ok(header) => header,
+            };
+            if header.hasher_id != self.options.hasher.id() {
+                let found = HasherKind::from_id(header.hasher_id)
+                    .map(|h| format!("{:?}", h))
+                    .unwrap_or_else(|| format!("unknown ({})", header.hasher_id));
+                return Err(WalError::InvalidConfig(format!(
+                    "segment {} was written with hasher {} but {:?} is configured",
+                    filename, found, self.options.hasher
+                )));
+            }
+            if let Some((key_hash, sequence)) = parsed {
+                if latest
+                    .get(&key_hash)
+                    .is_none_or(|(newest, _)| sequence > *newest)
+                {
+                    latest.insert(key_hash, (sequence, segment_path));
+                }
+            }
+        }
+
+        // Only a key's latest segment can have been written when a crash
+        // cut a record short
+        for (_, segment_path) in latest.into_values() {
+            self.truncate_torn_tail(segment_path)?;
+        }
+        Ok(())
+    }
+
+    /// Cuts a torn record or an uncommitted group off the end of a segment,
+    /// so appends that resume it start right after its last intact frame.
+    ///
+    /// A zero-filled tail is left alone, since it is preallocated space, and
+    /// so are unrecognized bytes, which are not a write this crate started.
+    /// Segments that cannot be read, such as encrypted ones without a
+    /// cipher configured, are skipped, and so are compressed ones, which
+    /// were sealed when they were compressed. Returns whether the file was
+    /// cut.
+    fn truncate_torn_tail(&self, file_path: &Path) -> Result<bool> {
+        if is_compressed_segment(file_path) {
+            return Ok(false);
+        }
+        let Ok(mut cursor) = self.open_cursor(file_path) else {
+            return Ok(false);
+        };
+        while cursor.try_next_frame(false)?.is_some() {}
+        let records_size = cursor.offset;
+        let body_size = cursor.body_len();
+        if records_size >= body_size {
+            return Ok(false);
+        }
+
+        cursor.seek(records_size)?;
+        let tail = classify_segment_tail(
+            &mut cursor.reader,
+            self.signatures(),
+            records_size,
+            body_size - records_size,
+        )?;
+        if !matches!(
+            tail,
+            SegmentAnomaly::TornRecord { .. } | SegmentAnomaly::UncommittedGroup { .. }
+        ) {
+            return Ok(false);
+        }
+
+        let end = cursor.body_start + records_size;
+        drop(cursor);
+        let file = self.vfs.open_write(file_path)?;
+        file.set_len(end)?;
+        self.sync_file(&file)?;
+        Ok(true)
+    }
+
+    /// Lists the paths of the `.log` and `.logz` files in the WAL directory.
+    fn segment_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut segment_paths = Vec::new();
+        for entry in self.segment_dir_entries(None)? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if is_segment_file_name(filename) {
+                    segment_paths.push(entry.path());
+                }
+            }
+        }
+        Ok(segment_paths)
+    }
+
+    /// Moves a segment file aside to a `.corrupt` sidecar so it no longer
+    /// takes part in reads, and records it for
+    /// [`Wal::quarantined_segments`].
+    fn quarantine_segment(&mut self, file_path: &Path) -> Result<()> {
+        let mut sidecar = file_path.as_os_str().to_owned();
+        sidecar.push(".corrupt");
+        let sidecar = PathBuf::from(sidecar);
+        self.vfs.rename(file_path, &sidecar)?;
+        self.quarantined.push(sidecar);
+        Ok(())
+    }
+
+    /// Parses segment filename to extract key hash and sequence.
+    fn parse_filename(&self, filename: &str) -> Option<(u64, u64)> {
+        parse_segment_filename(filename)
+    }
+
+    /// Generates a filename for a segment.
+    fn generate_filename<K: Display>(&self, key: &K, key_hash: u64, sequence: u64) -> String {
+        format!("{}-{}-{:04}.log", sanitize_key(key), key_hash, sequence)
+    }
+
+    /// Returns the directory new segments of `key_hash` are created in.
+    fn shard_dir(&self, key_hash: u64) -> PathBuf {
+        let bits = self.options.shard_dirs as usize;
+        if bits == 0 {
+            return self.dir.clone();
+        }
+        let shard = key_hash & ((1u64 << bits) - 1);
+        self.dir
+            .join(format!("{:0width$x}", shard, width = bits.div_ceil(4)))
+    }
+
+    /// Returns the path of a new segment, creating its shard directory if
+    /// needed.
+    fn segment_path<K: Display>(&self, key: &K, key_hash: u64, sequence: u64) -> Result<PathBuf> {
+        let dir = self.shard_dir(key_hash);
+        if self.vfs.metadata(&dir).is_err() {
+            self.vfs.create_dir_all(&dir)?;
+            self.sync_dir(&self.dir)?;
+        }
+        Ok(dir.join(self.generate_filename(key, key_hash, sequence)))
+    }
+
+    /// Lists the WAL directory with the contents of its shard directories
+    /// in place of the shard directories themselves.
+    ///
+    /// With `key_hash`, only the shard directories that can hold segments
+    /// of that key are listed.
+    fn segment_dir_entries(&self, key_hash: Option<u64>) -> io::Result<Vec<VfsDirEntry>> {
+        let mut entries = Vec::new();
+        for entry in self.vfs.read_dir(&self.dir)? {
+            let name = entry.file_name();
+            let shard = entry
+                .is_dir()
+                .then(|| parse_shard_dir_name(name.to_str()?))
+                .flatten();
+            match shard {
+                Some((shard, width)) => {
+                    if key_hash.is_none_or(|key_hash| shard_dir_holds(shard, width, key_hash)) {
+                        entries.extend(self.vfs.read_dir(&entry.path())?);
+                    }
+                }
+                None => entries.push(entry),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the bytes each record adds on top of its header and content.
+    ///
+    /// With the default fixed-width framing this is [`RECORD_OVERHEAD`].
+    /// With compact framing the length fields grow with the lengths they
+    /// encode, so this is the smallest overhead, reached when both the
+    /// header and the content are under 128 bytes; use
+    /// [`Wal::record_size`] for the exact size of a given record.
+    pub fn record_overhead(&self) -> usize {
+        self.framing().min_frame_len() as usize
+    }
+
+    /// Returns the exact on-disk size of a record under the configured
+    /// framing, including its header and content. With encryption,
+    /// `content_len` is the length after encryption.
+    pub fn record_size(&self, header_len: usize, content_len: u64) -> u64 {
+        self.framing().frame_len(header_len, content_len)
+    }
+
+    /// Returns the size of the file header of a segment for `key`.
+    ///
+    /// A segment's size is this plus the [`Wal::record_size`] of each of
+    /// its records.
+    pub fn file_header_size(key: &[u8]) -> usize {
+        file_header_size(key.len()) as usize
+    }
+
+    /// Returns the segment and record signatures for the configured magic.
+    fn signatures(&self) -> Signatures {
+        Signatures::new(self.options.signature)
+    }
+
+    /// Returns the record framing used for new segments.
+    fn framing(&self) -> Framing {
+        if self.options.compact_framing {
+            Framing::Varint
+        } else {
+            Framing::Extended
+        }
+    }
+
+    /// Returns the configured cipher bound to `key`, if encryption is on.
+    fn key_cipher(&self, key: &[u8], sequence: u64) -> Option<KeyCipher> {
+        self.options.encryption.clone().map(|cipher| KeyCipher {
+            cipher,
+            key: key.to_vec(),
+            sequence,
+        })
+    }
+
+    /// Computes the hash of a key with the configured hasher.
+    fn key_hash(&self, key: &[u8]) -> u64 {
+        self.options.hasher.hash_key(key)
+    }
+
+    /// Gets or creates an active segment for the given key.
+    fn get_or_create_active_segment<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: &K,
+    ) -> Result<u64> {
+        let key_hash = self.key_hash(key.as_ref());
+
+        let now = self.options.clock.now_unix();
+
+        // Check if rotation is needed
+        let mut sealed_info = None;
+        if let Some(active) = self.active_segments.get(&key_hash) {
+            if !self
+                .options
+                .rotation_policy
+                .accepts_appends(now, active.expiration_timestamp)
+            {
+                sealed_info = self.seal_active_segment(key.as_ref(), key_hash)?;
+            }
+        }
+
+        // Resume the latest on-disk segment if it still accepts writes; a
+        // key without a sequence number has no segments to resume
+        if !self.active_segments.contains_key(&key_hash)
+            && self.next_sequence.contains_key(&key_hash)
+        {
+            if let Some(active_segment) = self.reopen_latest_segment(key, now)? {
+                self.active_segments.insert(key_hash, active_segment);
+            }
+        }
+
+        // Create new segment if needed
+        if !self.active_segments.contains_key(&key_hash) {
+            let expiration_timestamp = now + self.segment_duration(key_hash);
+            self.create_active_segment(key, key_hash, expiration_timestamp)?;
+        }
+
+        if let (Some(info), Some(on_rotate)) = (sealed_info, &self.options.on_rotate) {
+            on_rotate(&info);
+        }
+
+        Ok(key_hash)
+    }
+
+    /// Seals the key's active segment, if any, and describes it for
+    /// `on_rotate`.
+    ///
+    /// The segment is synced first if it has unsynced writes and
+    /// `sync_on_rotate` is set.
+    fn seal_active_segment(&mut self, key: &[u8], key_hash: u64) -> Result<Option<SegmentInfo>> {
+        let Some(mut sealed) = self.active_segments.remove(&key_hash) else {
+            return Ok(None);
+        };
+        sealed.seal()?;
+        self.note_latest_record(&sealed);
+        if self.options.sync_on_rotate && sealed.unsynced {
+            self.sync_file(sealed.writer.get_ref())?;
+        } else if sealed.unsynced {
+            self.unsynced_sealed.insert(sealed.path.clone());
+        }
+        WalCounters::add(&self.metrics.rotations, 1);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            key_hash,
+            sequence_number = sealed.sequence_number,
+            size = sealed.body_start + sealed.body_len,
+            "segment rotated"
+        );
+        Ok(Some(SegmentInfo {
+            key: String::from_utf8_lossy(key).into_owned(),
+            key_hash,
+            sequence_number: sealed.sequence_number,
+            size: sealed.body_start + sealed.body_len,
+            path: sealed.path,
+            expiration_timestamp: sealed.expiration_timestamp,
+            active: false,
+        }))
+    }
+
+    /// Starts a new active segment for the key with its next sequence
+    /// number.
+    fn create_active_segment<K: AsRef<[u8]> + Display>(
+        &mut self,
+        key: &K,
+        key_hash: u64,
+        expiration_timestamp: u64,
+    ) -> Result<()> {
+        let sequence = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+        self.next_sequence.insert(key_hash, sequence + 1);
+
+        let file_path = self.segment_path(key, key_hash, sequence)?;
+        let file = self.create_segment_file(&file_path, key, expiration_timestamp)?;
+
+        let active_segment = ActiveSegment {
+            writer: BufWriter::new(file),
+            path: file_path,
+            key: key.as_ref().to_vec(),
+            body_start: file_header_size(key.as_ref().len()),
+            body_len: 0,
+            flushed_len: 0,
+            cipher: self.key_cipher(key.as_ref(), sequence),
+            sequence_number: sequence,
+            expiration_timestamp,
+            last_used: 0,
+            unsynced: false,
+            last_record: None,
+        };
+
+        self.active_segments.insert(key_hash, active_segment);
+        Ok(())
+    }
+
+    /// Returns the lifetime in seconds of a new segment for the key.
+    fn segment_duration(&self, key_hash: u64) -> u64 {
+        let retention = self
+            .key_retention
+            .get(&key_hash)
+            .unwrap_or(&self.options.entry_retention);
+        retention.as_secs() / self.options.segments_per_retention_period as u64
+    }
+
+    /// Reopens the key's highest-sequence segment for appending.
+    ///
+    /// Returns `None` if the key has no segments, or if the latest one no
+    /// longer accepts appends under the rotation policy, is unreadable, is
+    /// compressed, was written in a different format, framing or
+    /// encryption setting, or has unrecognized bytes after its records.
+    fn reopen_latest_segment<K: AsRef<[u8]> + Display>(
+        &self,
+        key: &K,
+        now: u64,
+    ) -> Result<Option<ActiveSegment>> {
+        let Some((sequence, file_path)) = self.segment_files_for_key(key)?.pop() else {
+            return Ok(None);
+        };
+        let Ok(header) = self.read_segment_header(&file_path) else {
+            return Ok(None);
+        };
+        if header.key != key.as_ref()
+            || header.compressed
+            || header.format_version != FORMAT_VERSION
+            || header.framing != self.framing()
+            || header.encrypted != self.options.encryption.is_some()
+            || !self
+                .options
+                .rotation_policy
+                .accepts_appends(now, header.expiration_timestamp)
+        {
+            return Ok(None);
+        }
+
+        self.open_for_append(file_path, sequence, &header)
+    }
+
+    /// Creates a segment file with a complete header.
+    ///
+    /// The header is written to a `.log.tmp` sibling, synced and renamed
+    /// into place, so a segment file either has a valid header or does not
+    /// exist at all. Returns the file opened for appending.
+    fn create_segment_file<K: AsRef<[u8]>>(
+        &self,
+        file_path: &Path,
+        key: &K,
+        expiration_timestamp: u64,
+    ) -> Result<Box<dyn VfsFile>> {
+        if self.vfs.metadata(file_path).is_ok() {
+            return Err(WalError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("segment {} already exists", file_path.display()),
+            )));
+        }
+
+        let tmp_path = file_path.with_extension("log.tmp");
+        let result = self
+            .vfs
+            .create(&tmp_path)
+            .map_err(WalError::from)
+            .and_then(|mut tmp| {
+                self.write_file_header(&mut tmp, key, expiration_timestamp)?;
+                self.sync_file(&tmp)?;
+                Ok(())
+            })
+            .and_then(|_| {
+                self.vfs
+                    .rename(&tmp_path, file_path)
+                    .map_err(WalError::from)
+            });
+        if let Err(e) = result {
+            let _ = self.vfs.remove_file(&tmp_path);
+            return Err(e);
+        }
+        self.sync_dir(file_path.parent().unwrap_or(&self.dir))?;
+
+        self.open_segment_writer(file_path, file_header_size(key.as_ref().len()))
+    }
+
+    /// Opens a segment for writing at `end`, the end of its last record.
+    ///
+    /// Anything past `end` is discarded, then the file is extended by the
+    /// configured preallocation.
+    fn open_segment_writer(&self, file_path: &Path, end: u64) -> Result<Box<dyn VfsFile>> {
+        let mut file = self.vfs.open_write(file_path)?;
+        let target_len = end + self.options.preallocate;
+        if file.size()? != target_len {
+            file.set_len(end)?;
+            file.set_len(target_len)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        Ok(file)
+    }
+
+    /// Syncs a directory of the WAL when `Durability::IncludeDir` is
+    /// configured.
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        if self.options.durability == Durability::IncludeDir {
+            self.vfs.sync_dir(dir)?;
+            WalCounters::add(&self.metrics.fsyncs, 1);
+        }
+        Ok(())
+    }
+
+    /// Syncs a file at the configured durability level.
+    fn sync_file(&self, file: &dyn VfsFile) -> io::Result<()> {
+        self.options.durability.sync_file(file)?;
+        WalCounters::add(&self.metrics.fsyncs, 1);
+        Ok(())
+    }
+
+    /// Writes file header for new segment.
+    fn write_file_header<K: AsRef<[u8]>, W: Write>(
+        &self,
+        file: &mut W,
+        key: &K,
+        expiration_timestamp: u64,
+    ) -> Result<()> {
+        let key_bytes = key.as_ref();
+        let mut header = Vec::with_capacity(file_header_size(key_bytes.len()) as usize);
+        header.extend_from_slice(&self.signatures().segment);
+
+        // Format descriptor: [version:2][hasher:1][framing:1][header crc32:4]
+        let mut version = FORMAT_VERSION;
+        if self.options.encryption.is_some() {
+            version |= ENCRYPTED_SEGMENT_FLAG;
+        }
+        let mut descriptor = [0u8; 8];
+        descriptor[0..2].copy_from_slice(&version.to_le_bytes());
+        descriptor[2] = self.options.hasher.id();
+        descriptor[3] = self.framing().id();
+        header.extend_from_slice(&descriptor);
+
+        header.extend_from_slice(&expiration_timestamp.to_le_bytes());
+        header.extend_from_slice(&(key_bytes.len() as u64).to_le_bytes());
+        header.extend_from_slice(key_bytes);
+
+        // The checksum covers the whole header with its own bytes zeroed
+        let checksum = crc32(&header);
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(())
+    }
+
+    /// Overrides the retention period for a single key.
+    ///
+    /// The key's segments are sized as `retention / segments_per_retention_period`
+    /// instead of using the global `entry_retention`. The override only
+    /// affects segments created after this call; existing segments keep the
+    /// expiration stored in their headers. Overrides are held in memory and
+    /// must be set again after reopening the WAL.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if `retention` is zero or shorter
+    /// than a second per segment.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use std::time::Duration;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.set_key_retention("metrics", Duration::from_secs(90 * 24 * 3600))?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn set_key_retention<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        retention: Duration,
+    ) -> Result<()> {
+        if retention.as_secs() == 0 {
+            return Err(WalError::InvalidConfig(
+                "key retention must be greater than 0".to_string(),
+            ));
+        }
+        check_segment_duration(
+            "key retention",
+            retention,
+            self.options.segments_per_retention_period,
+        )?;
+        let key_hash = self.key_hash(key.as_ref());
+        self.key_retention.insert(key_hash, retention);
+        Ok(())
+    }
+
+    /// Appends an entry to the WAL.
+    ///
+    /// The record is handed to the OS in a single write before returning,
+    /// so it is immediately visible to reads and survives a process crash.
+    /// Only `durable` appends also survive a power loss.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Entry key for segment selection
+    /// * `header` - Optional metadata header (at most `max_header_size`)
+    /// * `content` - Entry content
+    /// * `durable` - If true, syncs to disk before returning
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::HeaderTooLarge` if the header exceeds `max_header_size`.
+    /// Returns `WalError::InvalidConfig` if `content` is empty and
+    /// `WalOptions::reject_empty_content` is set, or is longer than the
+    /// configured cipher encrypts.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let entry_ref = wal.append_entry(
+    ///     "user_123",
+    ///     Some(Bytes::from("metadata")),
+    ///     Bytes::from("data"),
+    ///     true
+    /// )?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_entry<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<EntryRef> {
+        Ok(self
+            .append_entry_detailed(key, header, content, durable)?
+            .entry_ref)
+    }
+
+    /// Appends an entry and reports where the next record will land.
+    ///
+    /// Behaves exactly like [`Wal::append_entry`], but also returns the
+    /// offset following the written record and whether the append opened a
+    /// new segment. Once `rotated` is true, any `next_offset` predicted by an
+    /// earlier append for the same key no longer holds.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::append_entry`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let result = wal.append_entry_detailed("user_123", None, Bytes::from("data"), true)?;
+    /// if result.rotated {
+    ///     println!("Started segment {}", result.segment_sequence);
+    /// }
+    /// println!("Next record at offset {}", result.next_offset);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_entry_detailed<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<AppendResult> {
+        let key_hash = self.key_hash(key.as_ref());
+        let sequence_before = self.next_sequence.get(&key_hash).copied();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "append_entry",
+            key_hash,
+            bytes = content.len(),
+            durable,
+            rotated = tracing::field::Empty,
+        )
+        .entered();
+
+        let entry_ref = self.append_record(&key, header, &content)?;
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+
+        // A new segment always consumes a sequence number
+        let rotated = self.next_sequence.get(&key_hash).copied() != sequence_before;
+        #[cfg(feature = "tracing")]
+        span.record("rotated", rotated);
+        Ok(AppendResult {
+            entry_ref,
+            next_offset: self.active_segments[&key_hash].body_len,
+            segment_sequence: entry_ref.sequence_number,
+            rotated,
+        })
+    }
+
+    /// Appends an entry unless its dedup id was appended recently.
+    ///
+    /// The WAL remembers the hash of each `dedup_id` appended for a key for
+    /// `WalOptions::dedup_window`, keeping at most
+    /// `WalOptions::dedup_capacity` ids per key. A repeated id within the
+    /// window is skipped without writing anything. The ids are held in
+    /// memory only, so they are forgotten when the WAL is reopened. Since
+    /// ids are compared by 64-bit hash, a hash collision can skip a
+    /// distinct entry.
+    ///
+    /// # Returns
+    ///
+    /// The entry reference, or `None` if the entry was a duplicate.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::append_entry`]. A failed append does not record the id.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let first = wal.append_entry_dedup("payments", b"txn-42", None, Bytes::from("paid"), true)?;
+    /// let retry = wal.append_entry_dedup("payments", b"txn-42", None, Bytes::from("paid"), true)?;
+    /// assert!(first.is_some() && retry.is_none());
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_entry_dedup<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        dedup_id: &[u8],
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<Option<EntryRef>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let id = self.key_hash(dedup_id);
+        let now = self.options.clock.now_unix();
+        let window = self.options.dedup_window.as_secs();
+
+        let cache = self.dedup.entry(key_hash).or_default();
+        if cache.contains(id, now, window) {
+            return Ok(None);
+        }
+
+        let entry_ref = self.append_entry(key, header, content, durable)?;
+        self.dedup
+            .entry(key_hash)
+            .or_default()
+            .insert(id, now, self.options.dedup_capacity);
+        Ok(Some(entry_ref))
+    }
+
+    /// Appends an entry on behalf of a producer that numbers its writes.
+    ///
+    /// The record is preceded by a marker holding `producer_id` and
+    /// `producer_seq`, and the WAL remembers the last sequence written by
+    /// each producer of a key. Retrying the last write of a producer, with
+    /// the same `producer_seq`, writes nothing and returns the reference of
+    /// the original record, so a producer that retries after an ambiguous
+    /// failure appends each record exactly once.
+    ///
+    /// The last-seen sequences are kept in memory. After a restart they are
+    /// rebuilt from the markers in the key's segments on its first
+    /// idempotent append, so they survive as long as those segments do.
+    /// A marker whose record was never written is ignored.
+    ///
+    /// # Returns
+    ///
+    /// The entry reference and `true` if the write was a retry and nothing
+    /// was appended.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if `producer_seq` is lower than
+    /// the last sequence seen from the producer on this key.
+    /// Otherwise the same as [`Wal::append_entry`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let (first, _) = wal.append_idempotent("orders", 7, 1, None, Bytes::from("paid"), true)?;
+    /// let (retry, duplicate) = wal.append_idempotent("orders", 7, 1, None, Bytes::from("paid"), true)?;
+    /// assert!(duplicate && retry == first);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_idempotent<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        producer_id: u64,
+        producer_seq: u64,
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<(EntryRef, bool)> {
+        self.check_header_size(header.as_deref())?;
+        self.check_content_len(content.len() as u64)?;
+        let key_hash = self.key_hash(key.as_ref());
+        self.load_producers(&key, key_hash)?;
+        if let Some(&(last_seq, entry_ref)) = self.producers[&key_hash].get(&producer_id) {
+            if producer_seq == last_seq {
+                return Ok((entry_ref, true));
+            }
+            if producer_seq < last_seq {
+                return Err(WalError::InvalidConfig(format!(
+                    "producer {} sequence {} is behind its last-seen sequence {} on key {}",
+                    producer_id, producer_seq, last_seq, key
+                )));
+            }
+        }
+
+        let key_hash = self.get_or_create_active_segment(&key)?;
+        let marker_offset = self.active_segments[&key_hash].body_len;
+        let written = self
+            .write_producer_marker(key_hash, producer_id, producer_seq)
+            .map_err(WalError::from)
+            .and_then(|_| self.write_to_active(key_hash, header.as_deref(), &content));
+        let entry_ref = match written {
+            Ok(entry_ref) => entry_ref,
+            Err(e) => {
+                self.active_segments.get_mut(&key_hash).unwrap().body_len = marker_offset;
+                self.discard_unfinished_frame(key_hash)?;
+                return Err(e);
+            }
+        };
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+
+        if let Some(producers) = self.producers.get_mut(&key_hash) {
+            producers.insert(producer_id, (producer_seq, entry_ref));
+        }
+        Ok((entry_ref, false))
+    }
+
+    /// Loads the last-seen sequence of each producer of a key from the
+    /// markers in its segments, unless they are already loaded.
+    fn load_producers<K: AsRef<[u8]>>(&mut self, key: &K, key_hash: u64) -> Result<()> {
+        if self.producers.contains_key(&key_hash) {
+            return Ok(());
+        }
+        let mut producers = HashMap::new();
+        for (sequence_number, file_path) in self.segment_files_for_key(key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while let Some(frame) = cursor.try_next_frame(false)? {
+                if let Some((producer_id, producer_seq)) = frame.producer {
+                    let entry_ref = EntryRef {
+                        key_hash,
+                        sequence_number,
+                        offset: frame.offset,
+                    };
+                    producers.insert(producer_id, (producer_seq, entry_ref));
+                }
+            }
+        }
+        self.producers.insert(key_hash, producers);
+        Ok(())
+    }
+
+    /// Appends an entry whose content is copied from a reader.
+    ///
+    /// Exactly `content_len` bytes are read from `content` and copied into
+    /// the segment through its fixed-size write buffer, so a large payload
+    /// is never held in memory as a whole. Bytes after the first
+    /// `content_len` are left unread.
+    ///
+    /// If the reader fails or ends early, the partially written record is
+    /// truncated from the segment and nothing is appended.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if `content` ends before
+    /// `content_len` bytes.
+    /// Returns `WalError::InvalidConfig` if encryption is configured, since
+    /// records are encrypted as a whole.
+    /// Returns `WalError::HeaderTooLarge` if the header exceeds `max_header_size`.
+    /// Returns `WalError::Io` for I/O failures, including those of `content`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use std::fs::File;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let artifact = File::open("build.tar")?;
+    /// let len = artifact.metadata()?.len();
+    /// wal.append_stream("artifacts", None, artifact, len, true)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_stream<K: Hash + AsRef<[u8]> + Display, R: Read>(
+        &mut self,
+        key: K,
+        header: Option<Bytes>,
+        content: R,
+        content_len: u64,
+        durable: bool,
+    ) -> Result<EntryRef> {
+        if self.options.encryption.is_some() {
+            return Err(WalError::InvalidConfig(
+                "streamed content cannot be encrypted".to_string(),
+            ));
+        }
+        self.check_header_size(header.as_deref())?;
+        self.check_content_len(content_len)?;
+
+        let key_hash = self.get_or_create_active_segment(&key)?;
+        self.align_active(key_hash)?;
+        let signature = self.signatures().record;
+        let framing = self.framing();
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        self.write_clock += 1;
+        active_segment.last_used = self.write_clock;
+        active_segment.unsynced = true;
+
+        let entry_offset = active_segment.body_len;
+        let written = write_frame_prefix(
+            &mut active_segment.writer,
+            &signature,
+            framing,
+            header.as_deref(),
+            content_len,
+        )
+        .and_then(|frame_len| {
+            let copied = io::copy(&mut content.take(content_len), &mut active_segment.writer)?;
+            Ok((frame_len, copied))
+        });
+        let frame_len = match written {
+            Ok((frame_len, copied)) if copied == content_len => frame_len,
+            Ok((_, copied)) => {
+                self.discard_unfinished_frame(key_hash)?;
+                return Err(WalError::CorruptedData(format!(
+                    "content stream ended after {} of {} bytes",
+                    copied, content_len
+                )));
+            }
+            Err(e) => {
+                self.discard_unfinished_frame(key_hash)?;
+                return Err(e.into());
+            }
+        };
+
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        active_segment.body_len += frame_len;
+        active_segment.last_record = Some(entry_offset);
+        let sequence_number = active_segment.sequence_number;
+        WalCounters::add(&self.metrics.appends, 1);
+        WalCounters::add(&self.metrics.bytes_written, frame_len);
+        self.align_active(key_hash)?;
+        // The content was never held in memory to hash
+        self.content_index.forget(key_hash);
+
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+        Ok(EntryRef {
+            key_hash,
+            sequence_number,
+            offset: entry_offset,
+        })
+    }
+
+    /// Drops whatever was written to the key's active segment after its
+    /// last complete record, buffered or already in the file.
+    ///
+    /// Complete records still in the buffer, such as the earlier records
+    /// of a batch, are buffered again rather than dropped. The space past
+    /// the file's end is zeroed up to the configured preallocation again.
+    /// If that fails the segment is closed, and the next append reopens it
+    /// after its last intact record.
+    fn discard_unfinished_frame(&mut self, key_hash: u64) -> Result<()> {
+        let mut active_segment = self.active_segments.remove(&key_hash).unwrap();
+        let (mut file, buffered) = active_segment.writer.into_parts();
+        let buffered = buffered.unwrap_or_default();
+        let end = active_segment.body_start + active_segment.body_len;
+        // The buffer holds the bytes that follow what reached the file
+        let kept_len = end.saturating_sub(file.stream_position()?).min(buffered.len() as u64);
+        let kept = &buffered[..kept_len as usize];
+        let written_end = end - kept_len;
+        active_segment.flushed_len = active_segment.flushed_len.min(active_segment.body_len);
+        let body_len = active_segment.body_len;
+        active_segment.last_record = active_segment.last_record.filter(|offset| *offset < body_len);
+        file.set_len(written_end)?;
+        file.set_len(end + self.options.preallocate)?;
+        file.seek(SeekFrom::Start(written_end))?;
+        let mut writer = BufWriter::with_capacity(kept.len().max(8 * 1024), file);
+        writer.write_all(kept)?;
+        active_segment.writer = writer;
+        self.active_segments.insert(key_hash, active_segment);
+        Ok(())
+    }
+
+    /// Appends an entry timestamped with an event time rather than now.
+    ///
+    /// Meant for backfilling historical events: the record goes to a
+    /// segment whose expiration is computed from `event_time_unix_ms`, so
+    /// retention runs from the event time and data that is already past
+    /// its retention is removed by the next [`Wal::compact`]. Records carry
+    /// no timestamp of their own; the event time only decides the segment.
+    ///
+    /// The active segment is reused while the event time falls within its
+    /// window, the segment duration before its expiration. An event time
+    /// outside that window seals it and starts a new segment, so backfill
+    /// in time order fills segments as live appends do, while out-of-order
+    /// times split the key into many small segments. Either way records are
+    /// read back in append order, not event-time order. The next
+    /// [`Wal::append_entry`] rotates away from a backfilled segment once
+    /// the clock is past its expiration.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::append_entry`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let event_time_ms = 1_700_000_000_000;
+    /// wal.append_entry_at_time("orders", event_time_ms, None, Bytes::from("data"), false)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_entry_at_time<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        event_time_unix_ms: u64,
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<EntryRef> {
+        self.check_header_size(header.as_deref())?;
+        self.check_content_len(content.len() as u64)?;
+        let key_hash = self.key_hash(key.as_ref());
+        let event_time = event_time_unix_ms / 1000;
+        let duration = self.segment_duration(key_hash);
+        let covers = |active: &ActiveSegment| {
+            (active.expiration_timestamp.saturating_sub(duration)..active.expiration_timestamp)
+                .contains(&event_time)
+        };
+
+        if !self.active_segments.contains_key(&key_hash) {
+            let now = self.options.clock.now_unix();
+            if let Some(active_segment) = self.reopen_latest_segment(&key, now)? {
+                self.active_segments.insert(key_hash, active_segment);
+            }
+        }
+
+        let mut sealed_info = None;
+        if !self.active_segments.get(&key_hash).is_some_and(covers) {
+            sealed_info = self.seal_active_segment(key.as_ref(), key_hash)?;
+            self.create_active_segment(&key, key_hash, event_time + duration)?;
+        }
+        if let (Some(info), Some(on_rotate)) = (sealed_info, &self.options.on_rotate) {
+            on_rotate(&info);
+        }
+
+        let entry_ref = self.write_to_active(key_hash, header.as_deref(), &content)?;
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+        Ok(entry_ref)
+    }
+
+    /// Marks an earlier record of a key as logically deleted.
+    ///
+    /// Writes a tombstone frame, distinguished from data records by the
+    /// type byte of its signature, that refers to `target`. The target
+    /// record stays on disk: [`Wal::enumerate_records`] and the other
+    /// readers keep returning it and never return tombstones, while
+    /// [`Wal::enumerate_live_records`] leaves it out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if `target` is not a record of
+    /// `key`.
+    /// Returns `WalError::InvalidOffset` or `WalError::CorruptedData` if no
+    /// record starts at the target offset.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let created = wal.append_entry("order:42", None, Bytes::from("created"), true)?;
+    /// wal.append_tombstone("order:42", created, true)?;
+    /// assert_eq!(wal.enumerate_live_records("order:42")?.count(), 0);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_tombstone<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        target: EntryRef,
+        durable: bool,
+    ) -> Result<EntryRef> {
+        let key_hash = self.key_hash(key.as_ref());
+        if target.key_hash != key_hash {
+            return Err(WalError::EntryNotFound(format!(
+                "key_hash {} of the target does not belong to key {}",
+                target.key_hash, key
+            )));
+        }
+        self.entry_size_at(target)?;
+
+        self.get_or_create_active_segment(&key)?;
+        let signature = self.signatures().tombstone;
+        let entry_ref =
+            self.write_frame_to_active(key_hash, &signature, None, &encode_tombstone(target))?;
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+        Ok(entry_ref)
+    }
+
+    /// Appends an entry to an explicit segment sequence of the key.
+    ///
+    /// Instead of the key's current segment, the record goes to segment
+    /// `sequence`: appended if the file exists, or written to a new segment
+    /// file otherwise. That segment becomes the key's active segment, so
+    /// later [`Wal::append_entry`] calls continue in it until it expires,
+    /// and the key's next sequence number moves past it. This lets a
+    /// replica apply a primary's records segment by segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if `sequence` is zero, is lower
+    /// than the key's active segment, or names an existing segment of
+    /// another key or format.
+    /// Returns `WalError::HeaderTooLarge` if the header exceeds `max_header_size`.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let entry_ref = wal.append_entry_to_sequence("orders", 7, None, Bytes::from("data"), true)?;
+    /// assert_eq!(entry_ref.sequence_number, 7);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_entry_to_sequence<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        sequence: u64,
+        header: Option<Bytes>,
+        content: Bytes,
+        durable: bool,
+    ) -> Result<EntryRef> {
+        if sequence == 0 {
+            return Err(WalError::InvalidConfig(
+                "segment sequence numbers start at 1".to_string(),
+            ));
+        }
+        self.check_header_size(header.as_deref())?;
+        self.check_content_len(content.len() as u64)?;
+
+        let key_hash = self.key_hash(key.as_ref());
+        let active_sequence = self
+            .active_segments
+            .get(&key_hash)
+            .map(|active| active.sequence_number);
+        if let Some(active_sequence) = active_sequence.filter(|active| sequence < *active) {
+            return Err(WalError::InvalidConfig(format!(
+                "sequence {} is behind the active segment {} of key {}",
+                sequence, active_sequence, key
+            )));
+        }
+
+        if active_sequence != Some(sequence) {
+            let segment = self.open_segment_at(&key, key_hash, sequence)?;
+            if let Some(previous) = self.active_segments.insert(key_hash, segment) {
+                self.retire_segment(previous)?;
+                WalCounters::add(&self.metrics.rotations, 1);
+            }
+            let next = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+            self.next_sequence.insert(key_hash, next.max(sequence + 1));
+        }
+
+        let entry_ref = self.write_to_active(key_hash, header.as_deref(), &content)?;
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+        Ok(entry_ref)
+    }
+
+    /// Opens segment `sequence` of a key for appending, creating it if absent.
+    fn open_segment_at<K: AsRef<[u8]> + Display>(
+        &self,
+        key: &K,
+        key_hash: u64,
+        sequence: u64,
+    ) -> Result<ActiveSegment> {
+        let file_path = match self.find_segment(key_hash, sequence) {
+            Ok(file_path) => file_path,
+            Err(WalError::EntryNotFound(_)) => {
+                let now = self.options.clock.now_unix();
+                let expiration_timestamp = now + self.segment_duration(key_hash);
+                let file_path = self.segment_path(key, key_hash, sequence)?;
+                let file = self.create_segment_file(&file_path, key, expiration_timestamp)?;
+                return Ok(ActiveSegment {
+                    writer: BufWriter::new(file),
+                    path: file_path,
+                    key: key.as_ref().to_vec(),
+                    body_start: file_header_size(key.as_ref().len()),
+                    body_len: 0,
+                    flushed_len: 0,
+                    cipher: self.key_cipher(key.as_ref(), sequence),
+                    sequence_number: sequence,
+                    expiration_timestamp,
+                    last_used: 0,
+                    unsynced: false,
+                    last_record: None,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+
+        let header = self.read_segment_header(&file_path)?;
+        if header.key != key.as_ref() {
+            return Err(WalError::InvalidConfig(format!(
+                "segment {} belongs to a different key",
+                file_path.display()
+            )));
+        }
+        if header.compressed {
+            return Err(WalError::InvalidConfig(format!(
+                "segment {} is compressed and accepts no appends",
+                file_path.display()
+            )));
+        }
+        if header.format_version != FORMAT_VERSION
+            || header.framing != self.framing()
+            || header.encrypted != self.options.encryption.is_some()
+        {
+            return Err(WalError::InvalidConfig(format!(
+                "segment {} was written in a different format",
+                file_path.display()
+            )));
+        }
+        self.open_for_append(file_path.clone(), sequence, &header)?
+            .ok_or_else(|| {
+                WalError::CorruptedData(format!(
+                    "segment {} has unrecognized bytes after its last record",
+                    file_path.display()
+                ))
+            })
+    }
+
+    /// Opens an existing segment with a parsed header for appending.
+    ///
+    /// Appends resume after the last intact record, so a zeroed
+    /// preallocated tail or a torn write left by a crash is overwritten.
+    /// Returns `None` if unrecognized bytes follow the last intact record,
+    /// since cutting them could delete valid records behind a corrupted
+    /// frame.
+    fn open_for_append(
+        &self,
+        file_path: PathBuf,
+        sequence: u64,
+        header: &SegmentHeader,
+    ) -> Result<Option<ActiveSegment>> {
+        let mut cursor = self.open_cursor(&file_path)?;
+        while cursor.try_next_frame(false)?.is_some() {}
+        let body_len = cursor.offset;
+        let body_size = cursor.body_len();
+        if body_len < body_size {
+            cursor.seek(body_len)?;
+            let tail = classify_segment_tail(
+                &mut cursor.reader,
+                self.signatures(),
+                body_len,
+                body_size - body_len,
+            )?;
+            if matches!(tail, SegmentAnomaly::UnrecognizedBytes { .. }) {
+                return Ok(None);
+            }
+        }
+        drop(cursor);
+
+        let body_start = file_header_size(header.key.len());
+        let file = self.open_segment_writer(&file_path, body_start + body_len)?;
+
+        Ok(Some(ActiveSegment {
+            writer: BufWriter::new(file),
+            path: file_path,
+            key: header.key.clone(),
+            body_start,
+            body_len,
+            flushed_len: body_len,
+            cipher: self.key_cipher(&header.key, sequence),
+            sequence_number: sequence,
+            expiration_timestamp: header.expiration_timestamp,
+            last_used: 0,
+            unsynced: false,
+            last_record: None,
+        }))
+    }
+
+    /// Flushes the key's active segment after an append, syncing it if
+    /// `durable`.
+    fn finish_append(&mut self, key_hash: u64, durable: bool) -> Result<()> {
+        self.flush_active(key_hash)?;
+        if durable {
+            self.sync_file(self.active_segments[&key_hash].writer.get_ref())?;
+            self.active_segments.get_mut(&key_hash).unwrap().unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Ends a write operation: syncs the active segments if
+    /// `auto_sync_every` appends have accumulated, then closes idle ones.
+    fn finish_write(&mut self) -> Result<()> {
+        let every = self.options.auto_sync_every;
+        let appends = self.metrics.appends.load(Ordering::Relaxed);
+        if every > 0 && appends - self.synced_appends >= every {
+            self.flush_pending()?;
+        }
+        self.evict_idle_segments()
+    }
+
+    /// Drops what is kept in memory about a key's records after they were
+    /// removed or rewritten: its content index, and the in-memory
+    /// checkpoint, which the next checkpoint rebuilds from the directory.
+    fn forget_records(&mut self, key_hash: u64) {
+        self.content_index.forget(key_hash);
+        self.checkpoint = None;
+    }
+
+    /// Seals a segment taken out of `active_segments`, remembering it
+    /// for the next checkpoint if it has writes not synced yet.
+    fn retire_segment(&mut self, mut segment: ActiveSegment) -> io::Result<()> {
+        segment.seal()?;
+        self.note_latest_record(&segment);
+        if segment.unsynced {
+            self.unsynced_sealed.insert(segment.path);
+        }
+        Ok(())
+    }
+
+    /// Records the last record of a segment in the in-memory checkpoint,
+    /// unless the key already has a later one there.
+    fn note_latest_record(&mut self, segment: &ActiveSegment) {
+        let Some(offset) = segment.last_record else {
+            return;
+        };
+        let entry_ref = EntryRef {
+            key_hash: self.key_hash(&segment.key),
+            sequence_number: segment.sequence_number,
+            offset,
+        };
+        if let Some(entries) = &mut self.checkpoint {
+            advance_checkpoint(entries, &segment.key, entry_ref);
+        }
+    }
+
+    /// Syncs the segments sealed or closed with unsynced writes.
+    ///
+    /// Segments removed or compressed since are skipped.
+    fn sync_sealed_segments(&mut self) -> Result<()> {
+        for path in &self.unsynced_sealed {
+            match self.vfs.open_write(path) {
+                Ok(file) => self.sync_file(&*file)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.unsynced_sealed.clear();
+        Ok(())
+    }
+
+    /// Closes the least recently written active segments beyond
+    /// `max_open_segments`.
+    ///
+    /// Only called at the end of a write operation, so no segment the
+    /// operation still has to flush or sync is closed under it.
+    fn evict_idle_segments(&mut self) -> Result<()> {
+        let excess = (self.active_segments.len()).saturating_sub(self.options.max_open_segments);
+        if excess == 0 {
+            return Ok(());
+        }
+
+        let mut idle: Vec<(u64, u64)> = (self.active_segments.iter())
+            .map(|(key_hash, active)| (active.last_used, *key_hash))
+            .collect();
+        idle.select_nth_unstable(excess - 1);
+        for (_, key_hash) in &idle[..excess] {
+            if let Some(active_segment) = self.active_segments.remove(key_hash) {
+                self.retire_segment(active_segment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a record into the key's active segment buffer without flushing.
+    fn append_record<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: &K,
+        header: Option<Bytes>,
+        content: &[u8],
+    ) -> Result<EntryRef> {
+        self.check_header_size(header.as_deref())?;
+        self.check_content_len(content.len() as u64)?;
+        let key_hash = self.get_or_create_active_segment(key)?;
+        self.write_to_active(key_hash, header.as_deref(), content)
+    }
+
+    /// Rejects empty record content under `reject_empty_content`, and
+    /// content longer than the configured cipher encrypts.
+    fn check_content_len(&self, content_len: u64) -> Result<()> {
+        if content_len == 0 && self.options.reject_empty_content {
+            return Err(WalError::InvalidConfig(
+                "empty content rejected".to_string(),
+            ));
+        }
+        if let Some(cipher) = &self.options.encryption {
+            if content_len > cipher.max_plaintext_len() {
+                return Err(WalError::InvalidConfig(format!(
+                    "content of {} bytes exceeds the {} bytes the cipher encrypts",
+                    content_len,
+                    cipher.max_plaintext_len()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects a record header larger than `max_header_size`.
+    fn check_header_size(&self, header: Option<&[u8]>) -> Result<()> {
+        if let Some(h) = header {
+            if h.len() > self.options.max_header_size {
+                return Err(WalError::HeaderTooLarge {
+                    size: h.len(),
+                    max: self.options.max_header_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a record into the existing active segment of `key_hash`.
+    fn write_to_active(
+        &mut self,
+        key_hash: u64,
+        header: Option<&[u8]>,
+        content: &[u8],
+    ) -> Result<EntryRef> {
+        let signature = self.signatures().record;
+        let entry_ref = self.write_frame_to_active(key_hash, &signature, header, content)?;
+        self.index_contents([(entry_ref, content)]);
+        Ok(entry_ref)
+    }
+
+    /// Adds appended records to the content index under `content_index`.
+    fn index_contents<'a>(&self, records: impl IntoIterator<Item = (EntryRef, &'a [u8])>) {
+        #[cfg(feature = "digest")]
+        if self.options.content_index {
+            self.content_index.insert(records);
+        }
+        #[cfg(not(feature = "digest"))]
+        let _ = records;
+    }
+
+    /// Writes a frame with the given signature into the existing active
+    /// segment of `key_hash`.
+    fn write_frame_to_active(
+        &mut self,
+        key_hash: u64,
+        signature: &[u8; 6],
+        header: Option<&[u8]>,
+        content: &[u8],
+    ) -> Result<EntryRef> {
+        let start = self.active_segments[&key_hash].body_len;
+        match self.append_frame(key_hash, signature, header, content) {
+            Ok(entry_ref) => Ok(entry_ref),
+            Err(e) => {
+                self.active_segments.get_mut(&key_hash).unwrap().body_len = start;
+                self.discard_unfinished_frame(key_hash)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes a frame and its alignment padding into the key's active
+    /// segment. A failed write is left for the caller to discard.
+    fn append_frame(
+        &mut self,
+        key_hash: u64,
+        signature: &[u8; 6],
+        header: Option<&[u8]>,
+        content: &[u8],
+    ) -> Result<EntryRef> {
+        self.align_active(key_hash)?;
+        let framing = self.framing();
+        let is_record = *signature == self.signatures().record;
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        self.write_clock += 1;
+        active_segment.last_used = self.write_clock;
+        active_segment.unsynced = true;
+
+        let entry_offset = active_segment.body_len;
+        let encrypted = (active_segment.cipher.as_ref()).map(|c| c.encrypt(entry_offset, content));
+        let frame_len = write_record(
+            &mut active_segment.writer,
+            signature,
+            framing,
+            header,
+            encrypted.as_deref().unwrap_or(content),
+        )?;
+        active_segment.body_len += frame_len;
+        if is_record {
+            active_segment.last_record = Some(entry_offset);
+        }
+        let sequence_number = active_segment.sequence_number;
+        WalCounters::add(&self.metrics.appends, 1);
+        WalCounters::add(&self.metrics.bytes_written, frame_len);
+        self.align_active(key_hash)?;
+
+        Ok(EntryRef {
+            key_hash,
+            sequence_number,
+            offset: entry_offset,
+        })
+    }
+
+    /// Returns the padding frame that advances the absolute file offset
+    /// `position` to `WalOptions::alignment`.
+    fn padding_at(&self, position: u64) -> Vec<u8> {
+        encode_padding(
+            self.signatures(),
+            self.framing(),
+            position,
+            self.options.alignment as u64,
+        )
+    }
+
+    /// Pads the key's active segment to `WalOptions::alignment`.
+    ///
+    /// Called before and after each frame: the padding after a frame keeps
+    /// every write aligned, and the one before covers a segment that was
+    /// just created or resumed after an unaligned end.
+    fn align_active(&mut self, key_hash: u64) -> io::Result<()> {
+        let active_segment = &self.active_segments[&key_hash];
+        let padding = self.padding_at(active_segment.body_start + active_segment.body_len);
+        if padding.is_empty() {
+            return Ok(());
+        }
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        active_segment.writer.write_all(&padding)?;
+        active_segment.body_len += padding.len() as u64;
+        active_segment.unsynced = true;
+        WalCounters::add(&self.metrics.bytes_written, padding.len() as u64);
+        Ok(())
+    }
+
+    /// Writes the marker naming the producer of the next record into the
+    /// key's active segment. The marker is not encrypted.
+    fn write_producer_marker(
+        &mut self,
+        key_hash: u64,
+        producer_id: u64,
+        producer_seq: u64,
+    ) -> io::Result<()> {
+        self.align_active(key_hash)?;
+        let mut marker = Vec::new();
+        write_record(
+            &mut marker,
+            &self.signatures().producer,
+            self.framing(),
+            None,
+            &encode_producer(producer_id, producer_seq),
+        )?;
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        active_segment.writer.write_all(&marker)?;
+        active_segment.body_len += marker.len() as u64;
+        active_segment.unsynced = true;
+        WalCounters::add(&self.metrics.bytes_written, marker.len() as u64);
+        Ok(())
+    }
+
+    /// Flushes the buffers of all active segments to the OS.
+    fn flush_active_segments(&mut self) -> Result<()> {
+        let key_hashes: Vec<u64> = self.active_segments.keys().copied().collect();
+        for key_hash in key_hashes {
+            self.flush_active(key_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffer of the key's active segment to the OS.
+    ///
+    /// If the flush fails, the frames written since the last successful
+    /// flush are dropped along with whatever part of them reached the
+    /// file, so the next append starts at the end of the last flushed
+    /// frame.
+    fn flush_active(&mut self, key_hash: u64) -> Result<()> {
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        if let Err(e) = active_segment.writer.flush() {
+            active_segment.body_len = active_segment.flushed_len;
+            self.discard_unfinished_frame(key_hash)?;
+            return Err(e.into());
+        }
+        active_segment.flushed_len = active_segment.body_len;
+        Ok(())
+    }
+
+    /// Appends multiple entries in a batch.
+    ///
+    /// Batch operations provide better throughput by reducing I/O overhead.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Iterator of (key, header, content) tuples
+    /// * `durable` - If true, syncs after all entries are written
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first error and returns a `BatchError` listing the
+    /// entries written before it. Those entries are flushed, and synced when
+    /// `durable` is set, just as on success. If that final flush or sync
+    /// fails, `written` is empty because none of the entries are confirmed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let entries = vec![
+    ///     ("key1", None, Bytes::from("data1")),
+    ///     ("key2", Some(Bytes::from("meta")), Bytes::from("data2")),
+    /// ];
+    /// let refs = wal.append_batch(entries, true)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_batch<K, I>(
+        &mut self,
+        entries: I,
+        durable: bool,
+    ) -> std::result::Result<Vec<EntryRef>, BatchError>
+    where
+        K: Hash + AsRef<[u8]> + Display,
+        I: IntoIterator<Item = (K, Option<Bytes>, Bytes)>,
+    {
+        let mut refs = Vec::new();
+        let mut failure = None;
+
+        for (key, header, content) in entries {
+            match self.append_record(&key, header, &content) {
+                Ok(entry_ref) => refs.push(entry_ref),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let finished = if durable {
+            self.sync()
+        } else {
+            self.flush_active_segments()
+        }
+        .and_then(|_| self.finish_write());
+        if let Err(source) = finished {
+            return Err(BatchError {
+                written: Vec::new(),
+                failed_index: 0,
+                source,
+            });
+        }
+
+        match failure {
+            Some(source) => Err(BatchError {
+                failed_index: refs.len(),
+                written: refs,
+                source,
+            }),
+            None => Ok(refs),
+        }
+    }
+
+    /// Appends records of one key as a group that is read back all or
+    /// nothing.
+    ///
+    /// The records are framed by a begin marker, which stores their total
+    /// length, and a commit marker. Readers only return the records of a
+    /// group whose commit marker is present, so a crash part way through
+    /// leaves none of them visible, and the next append overwrites the
+    /// unfinished group. With `durable` set, the records are synced before
+    /// the commit marker is written and synced, so the marker can never
+    /// reach the disk ahead of them.
+    ///
+    /// A group is written to the key's active segment as a whole and never
+    /// spans a rotation. Truncating into the middle of a group with
+    /// [`Wal::truncate_after`] drops the rest of the group along with its
+    /// commit marker, and therefore the whole group.
+    ///
+    /// # Returns
+    ///
+    /// The entry references of the records, in order. An empty `records`
+    /// writes nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::HeaderTooLarge` if any header exceeds
+    /// `max_header_size`; nothing is written.
+    /// Returns `WalError::Io` for I/O failures. The partially written group
+    /// is then truncated from the segment.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let refs = wal.append_group(
+    ///     "account-7",
+    ///     &[
+    ///         (None, Bytes::from("debit 10")),
+    ///         (None, Bytes::from("credit 10")),
+    ///     ],
+    ///     true,
+    /// )?;
+    /// assert_eq!(refs.len(), 2);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn append_group<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        records: &[(Option<Bytes>, Bytes)],
+        durable: bool,
+    ) -> Result<Vec<EntryRef>> {
+        for (header, content) in records {
+            self.check_header_size(header.as_deref())?;
+            self.check_content_len(content.len() as u64)?;
+        }
+        if records.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let key_hash = self.get_or_create_active_segment(&key)?;
+        self.align_active(key_hash)?;
+        let signatures = self.signatures();
+        let framing = self.framing();
+        let alignment = self.options.alignment as u64;
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        self.write_clock += 1;
+        active_segment.last_used = self.write_clock;
+        active_segment.unsynced = true;
+
+        // The records are encoded first, since the begin marker holds their
+        // length, padding included
+        let group_offset = active_segment.body_len;
+        let records_offset = group_offset + framing.frame_len(0, 8);
+        let pad = |offset: u64| {
+            encode_padding(
+                signatures,
+                framing,
+                active_segment.body_start + offset,
+                alignment,
+            )
+        };
+        let mut group = pad(records_offset);
+        let mut offsets = Vec::with_capacity(records.len());
+        for (header, content) in records {
+            let offset = records_offset + group.len() as u64;
+            let encrypted = (active_segment.cipher.as_ref()).map(|c| c.encrypt(offset, content));
+            write_record(
+                &mut group,
+                &signatures.record,
+                framing,
+                header.as_deref(),
+                encrypted.as_deref().unwrap_or(content),
+            )?;
+            group.extend(pad(records_offset + group.len() as u64));
+            offsets.push(offset);
+        }
+        let group_len = group.len() as u64;
+
+        let writer = &mut active_segment.writer;
+        let mut written = write_record(
+            writer,
+            &signatures.group_begin,
+            framing,
+            None,
+            &group_len.to_le_bytes(),
+        )
+        .and_then(|_| writer.write_all(&group))
+        .and_then(|_| writer.flush());
+        if durable && written.is_ok() {
+            written = self.sync_file(self.active_segments[&key_hash].writer.get_ref());
+        }
+        let commit = encode_group_commit(signatures, framing);
+        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        if let Err(e) = written.and_then(|_| active_segment.writer.write_all(&commit)) {
+            self.discard_unfinished_frame(key_hash)?;
+            return Err(e.into());
+        }
+
+        let frame_len = records_offset - group_offset + group_len + commit.len() as u64;
+        active_segment.body_len += frame_len;
+        active_segment.last_record = offsets.last().copied().or(active_segment.last_record);
+        let sequence_number = active_segment.sequence_number;
+        WalCounters::add(&self.metrics.appends, records.len() as u64);
+        WalCounters::add(&self.metrics.bytes_written, frame_len);
+        self.align_active(key_hash)?;
+
+        self.finish_append(key_hash, durable)?;
+        self.finish_write()?;
+        let refs: Vec<EntryRef> = offsets
+            .into_iter()
+            .map(|offset| EntryRef {
+                key_hash,
+                sequence_number,
+                offset,
+            })
+            .collect();
+        let contents = records.iter().map(|(_, content)| &content[..]);
+        self.index_contents(refs.iter().copied().zip(contents));
+        Ok(refs)
+    }
+
+    /// Starts a batch of records that is written to disk on commit.
+    ///
+    /// Unlike [`Wal::append_batch`], which writes each record as it goes,
+    /// the batch encodes its records in memory and writes all records of a
+    /// key with a single write on [`WriteBatch::commit`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let mut batch = wal.begin_batch();
+    /// batch.add("key1", None, Bytes::from("data1"))?;
+    /// batch.add("key2", Some(Bytes::from("meta")), Bytes::from("data2"))?;
+    /// let refs = batch.commit(true)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn begin_batch(&mut self) -> WriteBatch<'_> {
+        WriteBatch {
+            wal: self,
+            groups: Vec::new(),
+            group_index: HashMap::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Logs an entry with durability guarantee.
+    ///
+    /// Convenience method equivalent to `append_entry(key, header, content, true)`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.log_entry("key", None, Bytes::from("data"))?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn log_entry<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        header: Option<Bytes>,
+        content: Bytes,
+    ) -> Result<EntryRef> {
+        self.append_entry(key, header, content, true)
+    }
+
+    /// Returns the segment files moved aside when the WAL was opened.
+    ///
+    /// A `.log` file too short to hold a segment header, such as an empty
+    /// file left by a crash during segment creation, is renamed with a
+    /// `.corrupt` suffix instead of failing [`Wal::new`]. The crate does not
+    /// log, so callers that want a record of the cleanup read it here.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for path in wal.quarantined_segments() {
+    ///     eprintln!("quarantined {}", path.display());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn quarantined_segments(&self) -> &[PathBuf] {
+        &self.quarantined
+    }
+
+    /// Checks every key's next sequence number against its segment files.
+    ///
+    /// New segments are named after the key's next sequence number, which
+    /// is derived from the segment files when the WAL is opened and then
+    /// kept in memory. Segment files that appear later, copied in or
+    /// written by a process that ignored the directory lock, can leave it
+    /// at or below a sequence already on disk, so the next rotation would
+    /// collide with an existing file or sort before it. Each such key is
+    /// reported, ordered by key hash; [`Wal::repair_sequences`] fixes
+    /// them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for mismatch in wal.validate_sequences()? {
+    ///     eprintln!(
+    ///         "key {} would reuse sequence {} (on disk up to {})",
+    ///         mismatch.key_hash, mismatch.next_sequence, mismatch.max_on_disk
+    ///     );
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn validate_sequences(&self) -> Result<Vec<SequenceMismatch>> {
+        let mut max_on_disk: BTreeMap<u64, u64> = BTreeMap::new();
+        for path in self.segment_paths()? {
+            let filename = path.file_name().and_then(|name| name.to_str());
+            if let Some((key_hash, sequence)) = filename.and_then(|name| self.parse_filename(name))
+            {
+                let max = max_on_disk.entry(key_hash).or_default();
+                *max = (*max).max(sequence);
+            }
+        }
+
+        Ok(max_on_disk
+            .into_iter()
+            .filter_map(|(key_hash, max_on_disk)| {
+                let next_sequence = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+                (next_sequence <= max_on_disk).then_some(SequenceMismatch {
+                    key_hash,
+                    max_on_disk,
+                    next_sequence,
+                })
+            })
+            .collect())
+    }
+
+    /// Moves the next sequence number of every key reported by
+    /// [`Wal::validate_sequences`] past its highest segment on disk,
+    /// returning how many keys were fixed.
+    ///
+    /// A fixed key's active segment is sealed if it is older than that
+    /// segment, so later appends land after the records already on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or an
+    /// active segment cannot be flushed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let fixed = wal.repair_sequences()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn repair_sequences(&mut self) -> Result<usize> {
+        let mismatches = self.validate_sequences()?;
+        for mismatch in &mismatches {
+            let stale = (self.active_segments.get(&mismatch.key_hash))
+                .is_some_and(|active| active.sequence_number < mismatch.max_on_disk);
+            if stale {
+                let active = self.active_segments.remove(&mismatch.key_hash).unwrap();
+                self.retire_segment(active)?;
+            }
+            self.next_sequence
+                .insert(mismatch.key_hash, mismatch.max_on_disk + 1);
+        }
+        Ok(mismatches.len())
+    }
+
+    /// Lists the leftover files of interrupted or failed operations.
+    ///
+    /// These are the `.tmp` copies of segments and of the manifest that a
+    /// crash left before they were renamed into place, and the `.corrupt`
+    /// segments moved aside when the WAL was opened, as listed by
+    /// [`Wal::quarantined_segments`]. Temporary files are already removed
+    /// when the WAL is opened, so outside of tests this usually lists
+    /// quarantined segments only. Paths are sorted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for path in wal.list_orphans()? {
+    ///     println!("leftover {}", path.display());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn list_orphans(&self) -> Result<Vec<PathBuf>> {
+        let mut orphans = Vec::new();
+        for entry in self.segment_dir_entries(None)? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if is_temp_file_name(filename) || is_quarantined_file_name(filename) {
+                    orphans.push(entry.path());
+                }
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Deletes the files listed by [`Wal::list_orphans`], returning how many
+    /// were removed.
+    ///
+    /// Quarantined segments are deleted along with temporary files, and
+    /// their data with them, so copy out anything worth salvaging first.
+    /// [`Wal::quarantined_segments`] no longer lists the deleted segments.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a file
+    /// cannot be removed. Files removed before the failure stay removed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let removed = wal.purge_orphans()?;
+    /// println!("removed {} leftover files", removed);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn purge_orphans(&mut self) -> Result<usize> {
+        let mut removed = 0;
+        for path in self.list_orphans()? {
+            self.vfs.remove_file(&path)?;
+            self.quarantined.retain(|sidecar| *sidecar != path);
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Enumerates all keys in the WAL.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` for filesystem errors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for key in wal.enumerate_keys()? {
+    ///     println!("Found key: {}", key);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_keys(&self) -> Result<impl Iterator<Item = String>> {
+        let mut keys = std::collections::HashSet::new();
+
+        let segment_paths = self.segment_paths().unwrap_or_default();
+        for header in read_segment_headers(&*self.vfs, &segment_paths, self.signatures())
+            .into_iter()
+            .flatten()
+        {
+            keys.insert(String::from_utf8_lossy(&header.key).to_string());
+        }
+
+        Ok(keys.into_iter())
+    }
+
+    /// Enumerates the keys that start with `prefix`.
+    ///
+    /// Keys are matched against the key stored in each segment header, not
+    /// the filename, whose key prefix is sanitized and truncated. Segments
+    /// whose header cannot be read are skipped, like [`Wal::enumerate_keys`].
+    ///
+    /// # Returns
+    ///
+    /// The distinct matching keys in sorted order.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for partition in wal.enumerate_keys_with_prefix("orders:")? {
+    ///     println!("Found partition: {}", partition);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_keys_with_prefix(&self, prefix: &str) -> Result<impl Iterator<Item = String>> {
+        let mut keys = HashSet::new();
+
+        let segment_paths = self.segment_paths()?;
+        for header in read_segment_headers(&*self.vfs, &segment_paths, self.signatures())
+            .into_iter()
+            .flatten()
+        {
+            if header.key.starts_with(prefix.as_bytes()) {
+                keys.insert(String::from_utf8_lossy(&header.key).to_string());
+            }
+        }
+
+        let mut keys: Vec<String> = keys.into_iter().collect();
+        keys.sort();
+        Ok(keys.into_iter())
+    }
+
+    /// Checks whether any segment exists for a key.
+    ///
+    /// Only filenames are inspected: the check stops at the first `.log`
+    /// file carrying the key's sanitized prefix and hash, without opening
+    /// it. Two keys with the same sanitized prefix and a colliding hash are
+    /// indistinguishable here; [`Wal::enumerate_records`] compares the full
+    /// key stored in each segment header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// if wal.contains_key("user_123")? {
+    ///     println!("stream exists");
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn contains_key<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<bool> {
+        let key_hash = self.key_hash(key.as_ref());
+        let prefix = format!("{}-{}-", sanitize_key(&key), key_hash);
+
+        for entry in self.segment_dir_entries(Some(key_hash))? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if filename.starts_with(&prefix)
+                    && self.parse_filename(filename).map(|(hash, _)| hash) == Some(key_hash)
+                {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Enumerates all keys in the WAL and reports unreadable segments.
+    ///
+    /// Unlike [`Wal::enumerate_keys`], segments whose header cannot be
+    /// parsed are not silently skipped but returned with their error, so
+    /// diagnostic tooling can report the damage.
+    ///
+    /// # Returns
+    ///
+    /// The distinct keys in sorted order, and the path and error of every
+    /// segment file that failed to parse.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let (keys, damaged) = wal.enumerate_keys_checked()?;
+    /// for (path, error) in damaged {
+    ///     eprintln!("{}: {}", path.display(), error);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn enumerate_keys_checked(&self) -> Result<(Vec<String>, Vec<(PathBuf, WalError)>)> {
+        let mut keys = HashSet::new();
+        let mut failures = Vec::new();
+
+        let segment_paths = self.segment_paths()?;
+        let headers = read_segment_headers(&*self.vfs, &segment_paths, self.signatures());
+        for (segment_path, header) in segment_paths.into_iter().zip(headers) {
+            match header {
+                Ok(header) => {
+                    keys.insert(String::from_utf8_lossy(&header.key).to_string());
+                }
+                Err(e) => failures.push((segment_path, e)),
+            }
+        }
+
+        let mut keys: Vec<String> = keys.into_iter().collect();
+        keys.sort();
+        failures.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok((keys, failures))
+    }
+
+    /// Reads key from segment file header.
+    fn read_key_from_file(&self, file_path: &Path) -> Result<String> {
+        let header = self.read_segment_header(file_path)?;
+        Ok(String::from_utf8_lossy(&header.key).to_string())
+    }
+
+    /// Reads and parses the header of a segment file.
+    fn read_segment_header(&self, file_path: &Path) -> Result<SegmentHeader> {
+        let mut file = self.vfs.open(file_path)?;
+        parse_segment_header(&mut file, self.signatures())
+    }
+
+    /// Iterates over every record in the WAL in a single pass.
+    ///
+    /// Each segment file is visited once, ordered by key and then by
+    /// sequence number. Every item carries the owning key and an `EntryRef`
+    /// that can be passed to [`Wal::read_entry_at`]. Segments that cannot
+    /// be read are reported as `Err` items after all readable records.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the WAL directory cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for item in wal.iter_all_records()? {
+    ///     let (key, entry_ref, content) = item?;
+    ///     println!("{} @ {:?}: {} bytes", key, entry_ref, content.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn iter_all_records(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(String, EntryRef, Bytes)>> + '_> {
+        let mut segments = Vec::new();
+        let mut failures = Vec::new();
+
+        for entry in self.segment_dir_entries(None)? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if let Some((key_hash, sequence)) = self.parse_filename(filename) {
+                    let segment_path = entry.path();
+                    match self.read_key_from_file(&segment_path) {
+                        Ok(key) => segments.push((key, sequence, key_hash, segment_path)),
+                        Err(e) => failures.push(Err(e)),
+                    }
+                }
+            }
+        }
+
+        segments.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+        let records =
+            segments
+                .into_iter()
+                .flat_map(move |(key, sequence, key_hash, segment_path)| {
+                    let items: Vec<Result<(String, EntryRef, Bytes)>> =
+                        match self.read_records_with_offsets(&segment_path) {
+                            Ok(records) => records
+                                .into_iter()
+                                .map(|(offset, content)| {
+                                    let entry_ref = EntryRef {
+                                        key_hash,
+                                        sequence_number: sequence,
+                                        offset,
+                                    };
+                                    Ok((key.clone(), entry_ref, content))
+                                })
+                                .collect(),
+                            Err(e) => vec![Err(e)],
+                        };
+                    items
+                });
+
+        Ok(records.chain(failures))
+    }
+
+    /// Enumerates records for a specific key.
+    ///
+    /// Segments whose records cannot be read are skipped; use
+    /// [`Wal::try_enumerate_records`] to observe those errors instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key to enumerate records for
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for record in wal.enumerate_records("my_key")? {
+    ///     println!("Record size: {}", record.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_records<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = Bytes>> {
+        let mut records = Vec::new();
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            if let Ok(file_records) = self.read_records_from_segment(&file_path) {
+                records.extend(file_records);
+            }
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Reads the records of a key like [`Wal::enumerate_records`], reading
+    /// its segments in parallel.
+    ///
+    /// Available with the `parallel` feature. Each segment is read on the
+    /// rayon thread pool and the results are joined in sequence order, so
+    /// records come back in the same order as from
+    /// [`Wal::enumerate_records`]. Worth it for keys spread over many
+    /// segments; a key with one segment gains nothing. All records are
+    /// held in memory at once, and unreadable segments are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let records = wal.enumerate_records_parallel("sensor-7")?;
+    /// println!("{} records", records.len());
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[cfg(feature = "parallel")]
+    pub fn enumerate_records_parallel<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<Vec<Bytes>> {
+        use rayon::prelude::*;
+
+        let segments: Vec<Vec<Bytes>> = self
+            .segment_files_for_key(&key)?
+            .par_iter()
+            .map(|(_, file_path)| {
+                self.read_records_from_segment(file_path)
+                    .unwrap_or_default()
+            })
+            .collect();
+        Ok(segments.into_iter().flatten().collect())
+    }
+
+    /// Reads the records of several keys as one stream ordered by time,
+    /// tagging each record with its key.
+    ///
+    /// Records carry no timestamp of their own, so the order is by the
+    /// time window of the segment holding each record: the keys' segment
+    /// lists are merged by the start of that window, the expiration less
+    /// the key's segment duration, each list staying in sequence order.
+    /// Keys with different retentions thus merge by when their segments
+    /// were opened rather than when they expire. Records of segments whose
+    /// windows overlap are not interleaved, and segments starting at the
+    /// same time come back in the order of `keys`. Within a key
+    /// records keep their append order. Segments are read one at a time
+    /// as the iterator advances, and unreadable segments are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for (key, record) in wal.merge_records(&["clicks", "views"])? {
+    ///     println!("{}: {} bytes", key, record.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn merge_records(
+        &self,
+        keys: &[&str],
+    ) -> Result<impl Iterator<Item = (String, Bytes)> + '_> {
+        let mut segments: Vec<VecDeque<(u64, PathBuf)>> = Vec::with_capacity(keys.len());
+        for key in keys {
+            let duration = self.segment_duration(self.key_hash(key.as_bytes()));
+            let mut key_segments = VecDeque::new();
+            for (_, file_path) in self.segment_files_for_key(key)? {
+                if let Ok(header) = self.read_segment_header(&file_path) {
+                    let start = header.expiration_timestamp.saturating_sub(duration);
+                    key_segments.push_back((start, file_path));
+                }
+            }
+            segments.push(key_segments);
+        }
+
+        // k-way merge on the head segment of each key; ties go to the key
+        // listed first.
+        let mut heads: BinaryHeap<Reverse<(u64, usize)>> = segments
+            .iter()
+            .enumerate()
+            .filter_map(|(index, key_segments)| {
+                key_segments
+                    .front()
+                    .map(|(start, _)| Reverse((*start, index)))
+            })
+            .collect();
+        let mut order = Vec::new();
+        while let Some(Reverse((_, index))) = heads.pop() {
+            if let Some((_, file_path)) = segments[index].pop_front() {
+                order.push((index, file_path));
+            }
+            if let Some((start, _)) = segments[index].front() {
+                heads.push(Reverse((*start, index)));
+            }
+        }
+
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        Ok(order.into_iter().flat_map(move |(index, file_path)| {
+            let key = keys[index].clone();
+            self.read_records_from_segment(&file_path)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |record| (key.clone(), record))
+        }))
+    }
+
+    /// Calls `f` with each record of a key in append order, returning how
+    /// many records it was called with.
+    ///
+    /// Records are read one at a time and dropped after the call, so a
+    /// fold over a large key needs no more memory than its largest record,
+    /// unlike collecting [`Wal::enumerate_records`]. Unreadable segments
+    /// are skipped the same way.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error `f` returns, without reading further.
+    /// Returns `WalError::Io` if the directory cannot be listed; segments
+    /// that cannot be opened are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let mut total_bytes = 0;
+    /// let count = wal.replay("page_views", |record| {
+    ///     total_bytes += record.len();
+    ///     Ok(())
+    /// })?;
+    /// println!("{} records, {} bytes", count, total_bytes);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn replay<K, F>(&self, key: K, mut f: F) -> Result<u64>
+    where
+        K: Hash + AsRef<[u8]> + Display,
+        F: FnMut(&Bytes) -> Result<()>,
+    {
+        let mut count = 0;
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let Ok(mut cursor) = self.open_cursor(&file_path) else {
+                continue;
+            };
+            while cursor.next_frame(false).is_some() {
+                let Some(content) = cursor.read_content() else {
+                    break;
+                };
+                f(&content)?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Enumerates the records of a key appended after `after`.
+    ///
+    /// A consumer that checkpoints the `EntryRef` of the last record it
+    /// processed can resume from there instead of from the start of the
+    /// key: records of `after`'s segment past its offset are returned,
+    /// followed by those of every later segment. Frames before the
+    /// checkpoint are skipped without reading their content. The
+    /// checkpointed record itself need not exist anymore, so a checkpoint
+    /// into a segment removed by retention resumes with the next one.
+    /// Unreadable segments are skipped, like [`Wal::enumerate_records`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if `after` belongs to another key.
+    /// Otherwise the same as [`Wal::enumerate_records`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let checkpoint = wal.append_entry("orders", None, Bytes::from("processed"), true)?;
+    /// wal.append_entry("orders", None, Bytes::from("pending"), true)?;
+    /// for record in wal.enumerate_records_from("orders", checkpoint)? {
+    ///     println!("Record size: {}", record.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_records_from<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+        after: EntryRef,
+    ) -> Result<impl Iterator<Item = Bytes>> {
+        let key_hash = self.key_hash(key.as_ref());
+        if after.key_hash != key_hash {
+            return Err(WalError::EntryNotFound(format!(
+                "key_hash {} of the checkpoint does not belong to key {}",
+                after.key_hash, key
+            )));
+        }
+
+        let mut records = Vec::new();
+        for (sequence, file_path) in self.segment_files_for_key(&key)? {
+            if sequence < after.sequence_number {
+                continue;
+            }
+            let Ok(mut cursor) = self.open_cursor(&file_path) else {
+                continue;
+            };
+            while let Some(frame) = cursor.next_frame(false) {
+                if sequence == after.sequence_number && frame.offset <= after.offset {
+                    continue;
+                }
+                match cursor.read_content() {
+                    Some(content) => records.push(content),
+                    None => break,
+                }
+            }
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Enumerates records for a specific key along with their references.
+    ///
+    /// Each record comes with the same `EntryRef` that appending it
+    /// returned, so it can be read again later with [`Wal::read_entry_at`].
+    /// Unreadable segments are skipped, like [`Wal::enumerate_records`].
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::enumerate_records`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let index: Vec<_> = wal
+    ///     .enumerate_records_with_refs("orders")?
+    ///     .map(|(entry_ref, _)| entry_ref)
+    ///     .collect();
+    /// let first = wal.read_entry_at(index[0])?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_records_with_refs<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = (EntryRef, Bytes)>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let mut records = Vec::new();
+
+        for (sequence_number, file_path) in self.segment_files_for_key(&key)? {
+            if let Ok(file_records) = self.read_records_with_offsets(&file_path) {
+                records.extend(file_records.into_iter().map(|(offset, content)| {
+                    let entry_ref = EntryRef {
+                        key_hash,
+                        sequence_number,
+                        offset,
+                    };
+                    (entry_ref, content)
+                }));
+            }
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Lists the `EntryRef` of every record of a key, in append order.
+    ///
+    /// Each reference is the one `append_entry` returned for the record, so
+    /// an index lost in a crash, or never built for imported segments, can
+    /// be rebuilt from the segments alone. Unlike
+    /// [`Wal::enumerate_records_with_refs`], content is skipped rather than
+    /// read, and a segment that cannot be read is an error instead of being
+    /// left out. Each segment is walked up to its first incomplete or
+    /// invalid frame; tombstones are not listed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if a segment header is invalid.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for entry_ref in wal.find_refs_for_key("orders")? {
+    ///     println!("record at {}:{}", entry_ref.sequence_number, entry_ref.offset);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn find_refs_for_key<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<Vec<EntryRef>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let mut refs = Vec::new();
+
+        for (sequence_number, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while let Some(frame) = cursor.try_next_frame(false)? {
+                refs.push(EntryRef {
+                    key_hash,
+                    sequence_number,
+                    offset: frame.offset,
+                });
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Enumerates the records of a key that no tombstone deletes.
+    ///
+    /// Like [`Wal::enumerate_records`], but records targeted by a tombstone
+    /// written with [`Wal::append_tombstone`] are left out.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::enumerate_records`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for record in wal.enumerate_live_records("order:42")? {
+    ///     println!("Record size: {}", record.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_live_records<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = Bytes>> {
+        let mut records = Vec::new();
+        let mut deleted = HashSet::new();
+
+        for (sequence, file_path) in self.segment_files_for_key(&key)? {
+            let Ok(cursor) = self.open_cursor(&file_path) else {
+                continue;
+            };
+            let mut cursor = cursor.with_tombstones();
+            while let Some(frame) = cursor.next_frame(false) {
+                let Some(content) = cursor.read_content() else {
+                    break;
+                };
+                if frame.kind == RecordKind::Tombstone {
+                    deleted.extend(decode_tombstone(&content));
+                } else {
+                    records.push((sequence, frame.offset, content));
+                }
+            }
+        }
+
+        Ok(records
+            .into_iter()
+            .filter(move |(sequence, offset, _)| !deleted.contains(&(*sequence, *offset)))
+            .map(|(_, _, content)| content))
+    }
+
+    /// Collects the `(sequence, offset)` of every record deleted by a
+    /// tombstone in `segments`.
+    fn tombstone_targets(&self, segments: &[(u64, PathBuf)]) -> Result<HashSet<(u64, u64)>> {
+        let mut targets = HashSet::new();
+        for (_, file_path) in segments {
+            let mut cursor = self.open_cursor(file_path)?.with_tombstones();
+            while let Some(frame) = cursor.try_next_frame(false)? {
+                if frame.kind == RecordKind::Tombstone {
+                    let Some(content) = cursor.try_read_content()? else {
+                        break;
+                    };
+                    targets.extend(decode_tombstone(&content));
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Enumerates the records of a key deserialized from JSON.
+    ///
+    /// Available with the `serde-json` feature. Wraps
+    /// [`Wal::enumerate_records`] and parses each record's content as `T`,
+    /// so a record that fails to parse is reported without ending the
+    /// iteration.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::enumerate_records`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for event in wal.enumerate_typed::<_, serde_json::Value>("events")? {
+    ///     println!("{}", event?);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "serde-json")]
+    pub fn enumerate_typed<K, T>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = std::result::Result<T, serde_json::Error>>>
+    where
+        K: Hash + AsRef<[u8]> + Display,
+        T: serde::de::DeserializeOwned,
+    {
+        Ok(self
+            .enumerate_records(key)?
+            .map(|record| serde_json::from_slice(&record)))
+    }
+
+    /// Enumerates the records of a key whose header satisfies `predicate`.
+    ///
+    /// `predicate` receives the raw header bytes of each record, empty for
+    /// records without a header. Headers are stored before content, so the
+    /// content of a rejected record is skipped without being read, making
+    /// a filtered scan cheaper than reading every record. Unreadable
+    /// segments are skipped, like [`Wal::enumerate_records`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let live = wal.enumerate_records_where("events", |header| header != b"deleted")?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_records_where<K, F>(
+        &self,
+        key: K,
+        predicate: F,
+    ) -> Result<impl Iterator<Item = Bytes>>
+    where
+        K: Hash + AsRef<[u8]> + Display,
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut records = Vec::new();
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let Ok(mut cursor) = self.open_cursor(&file_path) else {
+                continue;
+            };
+            while let Some(frame) = cursor.next_frame(true) {
+                if !predicate(frame.header.as_deref().unwrap_or_default()) {
+                    continue;
+                }
+                match cursor.read_content() {
+                    Some(content) => records.push(content),
+                    None => break,
+                }
+            }
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Enumerates records for a specific key, reporting read errors.
+    ///
+    /// Like [`Wal::enumerate_records`], but a segment that fails to read
+    /// yields an `Err` item in place of its records, so an I/O error is
+    /// never mistaken for the end of the key. A torn write
+    /// at the end of a segment still just ends that segment's records.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let records: Vec<Bytes> = wal.try_enumerate_records("my_key")?.collect::<Result<_, _>>()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn try_enumerate_records<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = Result<Bytes>> + '_> {
+        let segment_files = self.segment_files_for_key(&key)?;
+
+        Ok(segment_files.into_iter().flat_map(move |(_, file_path)| {
+            let items: Vec<Result<Bytes>> = match self.read_records_from_segment(&file_path) {
+                Ok(records) => records.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            items
+        }))
+    }
+
+    /// Enumerates every frame of a key with its kind, header and location.
+    ///
+    /// Where [`Wal::enumerate_records`] returns the content of data
+    /// records only, this also returns tombstones and the begin and commit
+    /// markers around each committed group, tagged with their
+    /// [`RecordKind`]. Padding and producer markers are left out, and a
+    /// record preceded by a producer marker is returned as plain data.
+    /// Like [`Wal::try_enumerate_records`], a segment that fails to read
+    /// yields an `Err` item, while a torn write just ends its segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{RecordKind, Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for entry in wal.enumerate_entries("orders")? {
+    ///     let entry = entry?;
+    ///     match entry.kind {
+    ///         RecordKind::Data => println!("record of {} bytes", entry.content.len()),
+    ///         RecordKind::Tombstone => println!("deletion at {}", entry.entry_ref.offset),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn enumerate_entries<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<impl Iterator<Item = Result<Entry>> + '_> {
+        let key_hash = self.key_hash(key.as_ref());
+        let segment_files = self.segment_files_for_key(&key)?;
+
+        Ok(segment_files
+            .into_iter()
+            .flat_map(move |(sequence_number, file_path)| {
+                let items: Vec<Result<Entry>> =
+                    match self.read_entries_from_segment(key_hash, sequence_number, &file_path) {
+                        Ok(entries) => entries.into_iter().map(Ok).collect(),
+                        Err(e) => vec![Err(e)],
+                    };
+                items
+            }))
+    }
+
+    /// Reads the frames of every kind from a segment file.
+    fn read_entries_from_segment(
+        &self,
+        key_hash: u64,
+        sequence_number: u64,
+        file_path: &Path,
+    ) -> Result<Vec<Entry>> {
+        let mut cursor = self
+            .open_cursor(file_path)?
+            .with_tombstones()
+            .with_markers();
+        let mut entries = Vec::new();
+        while let Some(frame) = cursor.try_next_frame(true)? {
+            let Some(content) = cursor.try_read_content()? else {
+                break;
+            };
+            entries.push(Entry {
+                kind: frame.kind,
+                header: frame.header,
+                content,
+                entry_ref: EntryRef {
+                    key_hash,
+                    sequence_number,
+                    offset: frame.offset,
+                },
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Counts the records of a key, for sizing progress reporting.
+    ///
+    /// The count is exact: it walks the frame headers of each segment,
+    /// seeking past record content without reading it, and stops each
+    /// segment at the same torn or damaged frame that record enumeration
+    /// stops at. It is an estimate only in that appends made after the call
+    /// are not included.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let total = wal.record_count_estimate("events")?;
+    /// let mut reader = wal.reader_for("events")?;
+    /// let mut done = 0;
+    /// while let Some(_record) = reader.next()? {
+    ///     done += 1;
+    ///     println!("{}/{}", done, total);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn record_count_estimate<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<u64> {
+        let mut count = 0;
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while cursor.try_next_frame(false)?.is_some() {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the latest record of every key, read in one directory walk.
+    ///
+    /// Segments are grouped by the key in their header, and for each key
+    /// only its highest-sequence segment is read, falling back to earlier
+    /// ones while the latest holds no complete record. Frames are walked
+    /// without reading content up to the last record, whose content alone
+    /// is read. Tombstones are not applied, so a key whose last record was
+    /// deleted still maps to it. Segments whose header cannot be read are
+    /// skipped, like in [`Wal::enumerate_keys`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a segment cannot be read.
+    /// Returns `WalError::CorruptedData` if a record fails to decrypt.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use std::collections::HashMap;
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let cache: HashMap<String, bytes::Bytes> = wal.latest_records()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn latest_records(&self) -> Result<HashMap<String, Bytes>> {
+        let mut latest = HashMap::new();
+        for (key, key_segments) in self.segments_by_key_name() {
+            for (_, _, file_path) in key_segments {
+                let mut cursor = self.open_cursor(&file_path)?;
+                let mut last = None;
+                while let Some(frame) = cursor.try_next_frame(false)? {
+                    last = Some(frame.offset);
+                }
+                let Some(offset) = last else {
+                    continue;
+                };
+                cursor.seek(offset)?;
+                cursor.try_next_frame(false)?;
+                if let Some(content) = cursor.try_read_content()? {
+                    latest.insert(String::from_utf8_lossy(&key).into_owned(), content);
+                    break;
+                }
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Groups the readable segments by the key in their header, each
+    /// group as `(key_hash, sequence, path)` from the highest sequence
+    /// down.
+    fn segments_by_key_name(&self) -> HashMap<Vec<u8>, Vec<(u64, u64, PathBuf)>> {
+        let segment_paths = self.segment_paths().unwrap_or_default();
+        let mut segments: HashMap<Vec<u8>, Vec<(u64, u64, PathBuf)>> = HashMap::new();
+        let headers = read_segment_headers(&*self.vfs, &segment_paths, self.signatures());
+        for (file_path, header) in segment_paths.into_iter().zip(headers) {
+            let parsed = (file_path.file_name().and_then(|name| name.to_str()))
+                .and_then(|filename| self.parse_filename(filename));
+            if let (Ok(header), Some((key_hash, sequence))) = (header, parsed) {
+                segments
+                    .entry(header.key)
+                    .or_default()
+                    .push((key_hash, sequence, file_path));
+            }
+        }
+        for key_segments in segments.values_mut() {
+            key_segments.sort_by_key(|(_, sequence, _)| Reverse(*sequence));
+        }
+        segments
+    }
+
+    /// Writes the checkpoint file, mapping each key to its latest durable
+    /// record.
+    ///
+    /// Appends not synced yet are synced first, as by
+    /// [`Wal::flush_pending`], along with segments sealed by rotation
+    /// without [`WalOptions::sync_on_rotate`] or closed by
+    /// [`WalOptions::max_open_segments`], so every `EntryRef` in the
+    /// checkpoint points at a record on disk. The latest record of each
+    /// key is kept in memory as it is appended; the first checkpoint after
+    /// opening, or after [`Wal::compact`] or another operation removed or
+    /// rewrote records, finds it instead by walking the frames of the key's
+    /// latest segment without reading content, skipping segments that
+    /// cannot be read. Like [`Wal::latest_records`], tombstones are not
+    /// applied. The file,
+    /// `.nano-wal.checkpoint`, is written to a temporary file that is
+    /// synced and renamed over the previous one, so a crash leaves either
+    /// checkpoint intact. With [`WalOptions::checkpoint_on_sync`] this
+    /// happens on every [`Wal::sync`] and [`Wal::close`] as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if syncing or writing the file fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.append_entry("orders", None, Bytes::from("order 1"), false)?;
+    /// wal.write_checkpoint()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn write_checkpoint(&mut self) -> Result<()> {
+        self.flush_pending()?;
+        self.store_checkpoint()
+    }
+
+    /// Writes the checkpoint file from the segments as they are on disk,
+    /// once the sealed segments are synced.
+    fn store_checkpoint(&mut self) -> Result<()> {
+        self.sync_sealed_segments()?;
+        let mut entries = match self.checkpoint.take() {
+            Some(entries) => entries,
+            None => self.scan_checkpoint(),
+        };
+        for (&key_hash, active_segment) in &self.active_segments {
+            if let Some(offset) = active_segment.last_record {
+                let entry_ref = EntryRef {
+                    key_hash,
+                    sequence_number: active_segment.sequence_number,
+                    offset,
+                };
+                advance_checkpoint(&mut entries, &active_segment.key, entry_ref);
+            }
+        }
+
+        let path = self.dir.join(CHECKPOINT_FILE_NAME);
+        let contents = encode_checkpoint(&entries);
+        self.checkpoint = Some(entries);
+        self.replace_file(&path, &contents)
+    }
+
+    /// Finds the latest record of every key by walking the frames of its
+    /// latest readable segment.
+    fn scan_checkpoint(&self) -> HashMap<String, EntryRef> {
+        let mut entries = HashMap::new();
+        for (key, key_segments) in self.segments_by_key_name() {
+            for (key_hash, sequence_number, file_path) in key_segments {
+                if let Ok(Some(offset)) = self.last_frame_offset(&file_path) {
+                    let entry_ref = EntryRef {
+                        key_hash,
+                        sequence_number,
+                        offset,
+                    };
+                    entries.insert(String::from_utf8_lossy(&key).into_owned(), entry_ref);
+                    break;
+                }
+            }
+        }
+        entries
+    }
+
+    /// Returns the offset of the last complete record of a segment, walking
+    /// its frames without reading content.
+    fn last_frame_offset(&self, file_path: &Path) -> Result<Option<u64>> {
+        let mut cursor = self.open_cursor(file_path)?;
+        let mut last = None;
+        while let Some(frame) = cursor.try_next_frame(false)? {
+            last = Some(frame.offset);
+        }
+        Ok(last)
+    }
+
+    /// Reads the checkpoint file written by [`Wal::write_checkpoint`].
+    ///
+    /// Each key maps to the `EntryRef` of its latest record at the time
+    /// of the checkpoint, ready to resume from with
+    /// [`Wal::enumerate_records_from`]. A directory without a checkpoint
+    /// gives an empty map.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if the file is malformed or fails
+    /// its checksum.
+    /// Returns `WalError::Io` if the file cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let checkpoint = wal.read_checkpoint()?;
+    /// if let Some(&last) = checkpoint.get("orders") {
+    ///     for record in wal.enumerate_records_from("orders", last)? {
+    ///         println!("New since checkpoint: {} bytes", record.len());
+    ///     }
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_checkpoint(&self) -> Result<HashMap<String, EntryRef>> {
+        match read_file(&*self.vfs, &self.dir.join(CHECKPOINT_FILE_NAME)) {
+            Ok(contents) => decode_checkpoint(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the earliest record of a key.
+    ///
+    /// Only the first record of the lowest-sequence segment is read, so this
+    /// is cheap regardless of how many records the key holds. Segments
+    /// without a complete record are skipped.
+    ///
+    /// # Returns
+    ///
+    /// The record content, or `None` if the key has no records.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a segment cannot be opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// if let Some(first) = wal.first_record("events")? {
+    ///     println!("Stream starts with {} bytes", first.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn first_record<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<Option<Bytes>> {
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            if cursor.next_frame(false).is_some() {
+                if let Some(content) = cursor.read_content() {
+                    return Ok(Some(content));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the record at position `index` of a key, counting from zero.
+    ///
+    /// Segments are walked in sequence order, counting frames like
+    /// [`Wal::record_count_estimate`] and seeking past the content of
+    /// earlier records, so only the requested record is read. Positions are
+    /// those [`Wal::enumerate_records`] yields, and shift when retention
+    /// removes old segments.
+    ///
+    /// # Returns
+    ///
+    /// The record content, or `None` if the key has `index` records or fewer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// if let Some(event) = wal.read_nth_record("user_123", 499)? {
+    ///     println!("500th event has {} bytes", event.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_nth_record<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+        index: u64,
+    ) -> Result<Option<Bytes>> {
+        let mut remaining = index;
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while cursor.try_next_frame(false)?.is_some() {
+                if remaining == 0 {
+                    return cursor.try_read_content();
+                }
+                remaining -= 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads up to `limit` records of a key after skipping the first `skip`.
+    ///
+    /// Skipped records are passed over by seeking past their content, as in
+    /// [`Wal::read_nth_record`], and reading stops as soon as the page is
+    /// full. Positions are those [`Wal::enumerate_records`] yields.
+    ///
+    /// # Returns
+    ///
+    /// The records of the page, and whether any record follows it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let (page, has_more) = wal.read_records_page("events", 200, 100)?;
+    /// println!("{} records, more: {}", page.len(), has_more);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_records_page<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+        skip: u64,
+        limit: u64,
+    ) -> Result<(Vec<Bytes>, bool)> {
+        let mut to_skip = skip;
+        let mut records = Vec::new();
+
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while cursor.try_next_frame(false)?.is_some() {
+                if to_skip > 0 {
+                    to_skip -= 1;
+                    continue;
+                }
+                if records.len() as u64 == limit {
+                    return Ok((records, true));
+                }
+                match cursor.try_read_content()? {
+                    Some(content) => records.push(content),
+                    None => break,
+                }
+            }
+        }
+
+        Ok((records, false))
+    }
+
+    /// Lists the segment files of a key, sorted by sequence number.
+    ///
+    /// Candidates are selected by the key hash in the filename and then
+    /// confirmed against the key stored in each header, so neither the
+    /// human-readable filename prefix nor a hash collision can mix keys.
+    ///
+    /// Errors listing the directory or opening a candidate are returned
+    /// rather than dropping the segment from the result.
+    fn segment_files_for_key<K: AsRef<[u8]>>(&self, key: &K) -> Result<Vec<(u64, PathBuf)>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let mut segment_files = Vec::new();
+
+        for entry in self.segment_dir_entries(Some(key_hash))? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if let Some((hash, sequence)) = self.parse_filename(filename) {
+                    let file_path = entry.path();
+                    if hash == key_hash
+                        && segment_has_key(&*self.vfs, &file_path, key.as_ref(), self.signatures())?
+                    {
+                        segment_files.push((sequence, file_path));
+                    }
+                }
+            }
+        }
+
+        segment_files.sort_by_key(|(seq, _)| *seq);
+        Ok(segment_files)
+    }
+
+    /// Exports all segments of a key into a single portable archive.
+    ///
+    /// The archive is `[NANO-ARC:8][key_length:8][key:N][segment_count:8]`
+    /// followed by `[sequence:8][length:8][segment:L]` for each segment in
+    /// sequence order, where each segment is the raw segment file.
+    /// Restore it with [`Wal::import_key`].
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a segment cannot be read or the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let archive = std::fs::File::create("user_123.narc")?;
+    /// let bytes = wal.export_key("user_123", archive)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn export_key<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+        mut writer: impl Write,
+    ) -> Result<u64> {
+        let segment_files = self.segment_files_for_key(&key)?;
+        let key_bytes = key.as_ref();
+
+        writer.write_all(&NANO_ARCHIVE_SIGNATURE)?;
+        writer.write_all(&(key_bytes.len() as u64).to_le_bytes())?;
+        writer.write_all(key_bytes)?;
+        writer.write_all(&(segment_files.len() as u64).to_le_bytes())?;
+        let mut written = 8 + 8 + key_bytes.len() as u64 + 8;
+
+        for (sequence, file_path) in segment_files {
+            let mut file = self.open_segment_file(&file_path)?;
+            let len = file.len()?;
+
+            writer.write_all(&sequence.to_le_bytes())?;
+            writer.write_all(&len.to_le_bytes())?;
+            let copied = io::copy(&mut (&mut file).take(len), &mut writer)?;
+            if copied != len {
+                return Err(WalError::CorruptedData(format!(
+                    "Segment {} shrank during export",
+                    file_path.display()
+                )));
+            }
+            written += 16 + len;
+        }
+
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Imports a key archive produced by [`Wal::export_key`].
+    ///
+    /// If the key has no segments in this WAL, the archived segments are
+    /// restored with their original sequence numbers, so `EntryRef`s issued
+    /// by the source WAL remain valid. If the key already exists and `merge`
+    /// is true, the archived segments are appended after the existing ones
+    /// with fresh sequence numbers; encrypted ones are then encrypted again
+    /// for their new sequence, which takes the cipher they were written
+    /// with.
+    ///
+    /// The segments are first written to `.log.tmp` files and checked, and
+    /// only renamed into place once the whole archive has been read, so a
+    /// truncated or malformed archive imports nothing and can be retried.
+    ///
+    /// # Returns
+    ///
+    /// The imported key.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidConfig` if the key already exists and `merge`
+    /// is false, or if the archive was written with a different hasher.
+    /// Returns `WalError::CorruptedData` if the archive is malformed or truncated.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let archive = std::fs::File::open("user_123.narc")?;
+    /// let key = wal.import_key(archive, false)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn import_key(&mut self, mut reader: impl Read, merge: bool) -> Result<String> {
+        let mut signature_buf = [0u8; 8];
+        reader.read_exact(&mut signature_buf)?;
+        if signature_buf != NANO_ARCHIVE_SIGNATURE {
+            return Err(WalError::CorruptedData(
+                "Invalid NANO-ARC signature".to_string(),
+            ));
+        }
+
+        let mut len_bytes = [0u8; 8];
+        reader.read_exact(&mut len_bytes)?;
+        let key_len = u64::from_le_bytes(len_bytes);
+        // Read at most what the archive holds, so a corrupt length cannot
+        // force a huge allocation
+        let mut key_bytes = Vec::new();
+        (&mut reader).take(key_len).read_to_end(&mut key_bytes)?;
+        if key_bytes.len() as u64 != key_len {
+            return Err(WalError::CorruptedData("Archive truncated".to_string()));
+        }
+        let key = String::from_utf8_lossy(&key_bytes).to_string();
+        let key_hash = self.key_hash(&key_bytes);
+
+        let existing = !self.segment_files_for_key(&key)?.is_empty();
+        if existing && !merge {
+            return Err(WalError::InvalidConfig(format!(
+                "key {} already exists",
+                key
+            )));
+        }
+
+        // Every segment is staged and checked before any is put in place,
+        // so a bad archive leaves the key as it was
+        let mut staged = Vec::new();
+        let result = self.stage_imported_segments(
+            &mut reader,
+            &key,
+            &key_bytes,
+            existing,
+            &mut staged,
+        );
+        if let Err(e) = result {
+            for (tmp_path, _, _) in &staged {
+                let _ = self.vfs.remove_file(tmp_path);
+            }
+            return Err(e);
+        }
+
+        // Seal the current segment so new appends land after the imported data
+        if let Some(active) = self.active_segments.remove(&key_hash) {
+            self.retire_segment(active)?;
+        }
+        // Imported segments may carry producer markers of their own
+        self.producers.remove(&key_hash);
+        self.forget_records(key_hash);
+
+        for (index, (tmp_path, file_path, sequence)) in staged.iter().enumerate() {
+            if let Err(e) = self.vfs.rename(tmp_path, file_path) {
+                for (tmp_path, _, _) in &staged[index..] {
+                    let _ = self.vfs.remove_file(tmp_path);
+                }
+                return Err(e.into());
+            }
+            let next = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+            self.next_sequence.insert(key_hash, next.max(sequence + 1));
+        }
+        self.sync_dir(&self.shard_dir(key_hash))?;
+
+        Ok(key)
+    }
+
+    /// Copies each segment of an archive to a `.log.tmp` file next to the
+    /// segment it becomes, and checks its header.
+    ///
+    /// Pushes `(tmp_path, file_path, sequence)` to `staged` for every file
+    /// created, including one that failed its checks, so the caller can
+    /// remove them all if the archive turns out to be bad.
+    fn stage_imported_segments(
+        &self,
+        reader: &mut impl Read,
+        key: &str,
+        key_bytes: &[u8],
+        existing: bool,
+        staged: &mut Vec<(PathBuf, PathBuf, u64)>,
+    ) -> Result<()> {
+        let key_hash = self.key_hash(key_bytes);
+        let mut len_bytes = [0u8; 8];
+        reader.read_exact(&mut len_bytes)?;
+        let segment_count = u64::from_le_bytes(len_bytes);
+        let mut next_sequence = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+
+        for _ in 0..segment_count {
+            let mut sequence_bytes = [0u8; 8];
+            reader.read_exact(&mut sequence_bytes)?;
+            reader.read_exact(&mut len_bytes)?;
+            let len = u64::from_le_bytes(len_bytes);
+
+            let archived_sequence = u64::from_le_bytes(sequence_bytes);
+            let sequence = if existing {
+                next_sequence
+            } else {
+                archived_sequence
+            };
+            if staged.iter().any(|(_, _, staged)| *staged == sequence) {
+                return Err(WalError::CorruptedData(format!(
+                    "Archive holds segment {} twice",
+                    sequence
+                )));
+            }
+            next_sequence = next_sequence.max(sequence + 1);
+
+            let file_path = self.segment_path(&key, key_hash, sequence)?;
+            if self.vfs.metadata(&file_path).is_ok() {
+                return Err(WalError::Io(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("segment {} already exists", file_path.display()),
+                )));
+            }
+            let tmp_path = file_path.with_extension("log.tmp");
+            staged.push((tmp_path.clone(), file_path, sequence));
+            if sequence == archived_sequence {
+                self.copy_imported_segment(reader, len, &tmp_path, key_bytes)?;
+                continue;
+            }
+
+            // Encrypted records are bound to their segment's sequence, so a
+            // renumbered segment is copied aside under sequence 0, which no
+            // segment has, and encrypted again for its new sequence
+            let scratch_path = self
+                .segment_path(&key, key_hash, 0)?
+                .with_extension("log.tmp");
+            let result = self
+                .copy_imported_segment(reader, len, &scratch_path, key_bytes)
+                .and_then(|header| {
+                    if !header.encrypted {
+                        return Ok(self.vfs.rename(&scratch_path, &tmp_path)?);
+                    }
+                    self.write_merged_segment(
+                        &tmp_path,
+                        sequence,
+                        &key_bytes,
+                        header.expiration_timestamp,
+                        &[(archived_sequence, scratch_path.clone())],
+                        false,
+                    )
+                    .map(|_| ())
+                });
+            let _ = self.vfs.remove_file(&scratch_path);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Copies one `len`-byte segment of an archive to `file_path` and
+    /// checks its header, which it returns.
+    fn copy_imported_segment(
+        &self,
+        reader: &mut impl Read,
+        len: u64,
+        file_path: &Path,
+        key_bytes: &[u8],
+    ) -> Result<SegmentHeader> {
+        let mut file = self.vfs.create(file_path)?;
+        let copied = io::copy(&mut reader.take(len), &mut file)?;
+        if copied != len {
+            return Err(WalError::CorruptedData("Archive truncated".to_string()));
+        }
+        self.sync_file(&file)?;
+        drop(file);
+
+        let header = self.read_segment_header(file_path)?;
+        self.validate_imported_header(&header, key_bytes)?;
+        Ok(header)
+    }
+
+    /// Lists the segments of a key in sequence order.
+    ///
+    /// Meant for replicating one key at a time: a follower copies each
+    /// segment once it is no longer `active`, and keeps re-reading only the
+    /// active one. The active segment is the one open for appends, or the
+    /// key's latest segment while it still accepts appends under the
+    /// rotation policy, since the next append resumes it. Sealed segments
+    /// only change through maintenance that rewrites records, such as
+    /// [`Wal::compact_and_merge`], [`Wal::truncate_after`] or
+    /// [`Wal::append_entry_to_sequence`] naming an old sequence.
+    ///
+    /// The size of the active segment covers its records only, not any
+    /// preallocated space after them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if a segment header is invalid.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for segment in wal.segments_for_key("orders")? {
+    ///     if !segment.active {
+    ///         println!("sealed: {} ({} bytes)", segment.path.display(), segment.size);
+    ///     }
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn segments_for_key<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<Vec<SegmentInfo>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let now = self.options.clock.now_unix();
+        let open = self.active_segments.get(&key_hash);
+        let segment_files = self.segment_files_for_key(&key)?;
+        let latest = segment_files.last().map(|(sequence, _)| *sequence);
+
+        segment_files
+            .into_iter()
+            .map(|(sequence, path)| {
+                let header = self.read_segment_header(&path)?;
+                let (size, active) = match open.filter(|open| open.sequence_number == sequence) {
+                    Some(open) => (open.body_start + open.body_len, true),
+                    None => (
+                        self.vfs.metadata(&path)?.len,
+                        Some(sequence) == latest
+                            && (self.options.rotation_policy)
+                                .accepts_appends(now, header.expiration_timestamp),
+                    ),
+                };
+                Ok(SegmentInfo {
+                    key: String::from_utf8_lossy(&header.key).into_owned(),
+                    key_hash,
+                    sequence_number: sequence,
+                    path,
+                    size,
+                    expiration_timestamp: header.expiration_timestamp,
+                    active,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the length of the key's active segment body, which is the
+    /// offset the next record for the key will be written at.
+    ///
+    /// Together with [`Wal::force_rotate`], this lets callers rotate on
+    /// their own triggers, such as a size or an event count. Padding
+    /// written under [`WalOptions::alignment`] is included.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the key has no active segment, either because it was
+    /// never written or because its segment was sealed or closed. The next
+    /// append may then resume the latest segment rather than start at 0.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.append_entry("events", None, Bytes::from("e1"), false)?;
+    /// if wal.current_offset("events")?.is_some_and(|len| len > 64 * 1024 * 1024) {
+    ///     wal.force_rotate("events")?;
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn current_offset<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<Option<u64>> {
+        let key_hash = self.key_hash(key.as_ref());
+        Ok(self
+            .active_segments
+            .get(&key_hash)
+            .map(|active| active.body_len))
+    }
+
+    /// Seals the key's active segment and starts a new one, regardless of
+    /// its expiration.
+    ///
+    /// The new segment is created right away with a fresh expiration, so
+    /// the next append lands in it even if the sealed segment would still
+    /// accept appends after a reopen. [`WalOptions::on_rotate`] is called
+    /// with the sealed segment, as for a time-based rotation. A key without
+    /// an active segment just gets a new one.
+    ///
+    /// # Returns
+    ///
+    /// The sealed segment, or `None` if the key had no active segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the sealed segment cannot be flushed or
+    /// the new one cannot be created.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// // Start a new segment at midnight
+    /// if let Some(sealed) = wal.force_rotate("orders")? {
+    ///     println!("sealed {}", sealed.path.display());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn force_rotate<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<SegmentInfo>> {
+        let key_hash = self.key_hash(key.as_ref());
+        let sealed = self.seal_active_segment(key.as_ref(), key_hash)?;
+        let now = self.options.clock.now_unix();
+        let expiration_timestamp = now + self.segment_duration(key_hash);
+        self.create_active_segment(&key, key_hash, expiration_timestamp)?;
+        self.finish_write()?;
+
+        if let (Some(info), Some(on_rotate)) = (&sealed, &self.options.on_rotate) {
+            on_rotate(info);
+        }
+        Ok(sealed)
+    }
+
+    /// Computes a SHA-256 digest of a key's records.
+    ///
+    /// Available with the `digest` feature. The digest covers the header
+    /// and content of every record in append order, and nothing about how
+    /// they are stored: segment boundaries, offsets, framing and encryption
+    /// do not change it. Two WALs holding the same records for a key, such
+    /// as a backup or a replica, produce the same digest however the key is
+    /// segmented. A record without a header digests like one with an empty
+    /// header, and tombstones are left out like in
+    /// [`Wal::enumerate_records`]. A key without records has the digest of
+    /// no input.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a
+    /// segment cannot be read. Unlike enumeration, unreadable segments are
+    /// not skipped, since that would hide a difference.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let primary = Wal::new("./wal", WalOptions::default())?;
+    /// # let replica = Wal::new("./replica", WalOptions::default())?;
+    /// assert_eq!(primary.digest_key("orders")?, replica.digest_key("orders")?);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[cfg(feature = "digest")]
+    pub fn digest_key<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<[u8; 32]> {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        for (_, file_path) in self.segment_files_for_key(&key)? {
+            let mut cursor = self.open_cursor(&file_path)?;
+            while let Some(frame) = cursor.try_next_frame(true)? {
+                let Some(content) = cursor.try_read_content()? else {
+                    break;
+                };
+                let header = frame.header.as_deref().unwrap_or_default();
+                hasher.update((header.len() as u64).to_le_bytes());
+                hasher.update(header);
+                hasher.update((content.len() as u64).to_le_bytes());
+                hasher.update(&content);
+            }
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Computes [`Wal::digest_key`] for every key, ordered by key.
+    ///
+    /// Available with the `digest` feature. Comparing the maps of two WALs
+    /// also reveals keys present in only one of them.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::digest_key`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let primary = Wal::new("./wal", WalOptions::default())?;
+    /// # let replica = Wal::new("./replica", WalOptions::default())?;
+    /// assert_eq!(primary.digest_all()?, replica.digest_all()?);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[cfg(feature = "digest")]
+    pub fn digest_all(&self) -> Result<std::collections::BTreeMap<String, [u8; 32]>> {
+        self.enumerate_keys()?
+            .map(|key| Ok((key.clone(), self.digest_key(key)?)))
+            .collect()
+    }
+
+    /// Looks for a record of `key` whose content has the SHA-256 hash
+    /// `content_sha256`.
+    ///
+    /// Available with the `digest` feature. Returns the first such record
+    /// in append order, or `None`. Headers are not part of the hash, and
+    /// encrypted records are compared by their plaintext. Without
+    /// [`WalOptions::content_index`] every call reads and hashes all
+    /// records of the key; with it the key is hashed once and later calls
+    /// are answered from memory. Segments that cannot be read are skipped,
+    /// like [`Wal::enumerate_records`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// use sha2::{Digest, Sha256};
+    ///
+    /// # let mut wal = Wal::new("./wal", WalOptions::default().content_index(true))?;
+    /// let content = Bytes::from("invoice-17");
+    /// let hash: [u8; 32] = Sha256::digest(&content).into();
+    /// if wal.contains_record("invoices", &hash)?.is_none() {
+    ///     wal.append_entry("invoices", None, content, true)?;
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[cfg(feature = "digest")]
+    pub fn contains_record<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+        content_sha256: &[u8; 32],
+    ) -> Result<Option<EntryRef>> {
+        if !self.options.content_index {
+            return Ok(self
+                .enumerate_records_with_refs(key)?
+                .find(|(_, content)| sha256(content) == *content_sha256)
+                .map(|(entry_ref, _)| entry_ref));
+        }
+
+        let key_hash = self.key_hash(key.as_ref());
+        let mut keys = lock_unpoisoned(&self.content_index.keys);
+        let hashes = match keys.entry(key_hash) {
+            std::collections::hash_map::Entry::Occupied(indexed) => indexed.into_mut(),
+            std::collections::hash_map::Entry::Vacant(unindexed) => {
+                let mut hashes = HashMap::new();
+                for (entry_ref, content) in self.enumerate_records_with_refs(&key)? {
+                    hashes.entry(sha256(&content)).or_insert(entry_ref);
+                }
+                unindexed.insert(hashes)
+            }
+        };
+        Ok(hashes.get(content_sha256).copied())
+    }
+
+    /// Checks that an imported segment header belongs to this WAL and key.
+    fn validate_imported_header(&self, header: &SegmentHeader, key: &[u8]) -> Result<()> {
+        if header.hasher_id != self.options.hasher.id() {
+            return Err(WalError::InvalidConfig(
+                "archive was written with a different hasher".to_string(),
+            ));
+        }
+        if header.key != key {
+            return Err(WalError::CorruptedData(
+                "Archived segment belongs to a different key".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads all records from a segment file.
+    fn read_records_from_segment(&self, file_path: &Path) -> Result<Vec<Bytes>> {
+        Ok(self
+            .read_records_with_offsets(file_path)?
+            .into_iter()
+            .map(|(_, content)| content)
+            .collect())
+    }
+
+    /// Reads all records from a segment file along with their offsets.
+    ///
+    /// Offsets are relative to the end of the file header, matching the
+    /// `offset` of the `EntryRef` returned when the record was appended.
+    fn read_records_with_offsets(&self, file_path: &Path) -> Result<Vec<(u64, Bytes)>> {
+        if self.options.zero_copy_reads {
+            return self.read_records_zerocopy(file_path);
+        }
+
+        let mut cursor = self.open_cursor(file_path)?;
+        let mut records = Vec::new();
+
+        while let Some(frame) = cursor.try_next_frame(false)? {
+            match cursor.try_read_content()? {
+                Some(content) => records.push((frame.offset, content)),
+                None => break,
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Reads all records from a segment file as slices of one buffer.
+    ///
+    /// Skips tombstones and stops at the first invalid or incomplete frame,
+    /// like `SegmentCursor`.
+    fn read_records_zerocopy(&self, file_path: &Path) -> Result<Vec<(u64, Bytes)>> {
+        self.debug_assert_flushed(file_path);
+        let buffer = if is_compressed_segment(file_path) {
+            self.decompressed
+                .get(&*self.vfs, file_path, self.signatures())?
+        } else {
+            Bytes::from(read_file(&*self.vfs, file_path)?)
+        };
+        WalCounters::add(&self.metrics.segments_opened, 1);
+        let mut reader = &buffer[..];
+        let header = parse_segment_header(&mut reader, self.signatures())?;
+        let framing = header.framing;
+        let cipher = KeyCipher::for_segment(
+            &header,
+            segment_sequence(file_path),
+            self.options.encryption.as_ref(),
+        )?;
+        let body = buffer.slice(buffer.len() - reader.len()..);
+
+        let signatures = self.signatures();
+        let mut records = Vec::new();
+        let mut offset = 0usize;
+        loop {
+            let frame = &body[offset..];
+            let tombstone = frame.starts_with(&signatures.tombstone);
+            let group_begin = frame.starts_with(&signatures.group_begin);
+            let group_commit = frame.starts_with(&signatures.group_commit);
+            let padding = frame.starts_with(&signatures.padding);
+            let producer = frame.starts_with(&signatures.producer);
+            if !frame.starts_with(&signatures.record)
+                && !tombstone
+                && !group_begin
+                && !group_commit
+                && !padding
+                && !producer
+            {
+                break;
+            }
+            let mut reader = &body[offset + signatures.record.len()..];
+            let Ok(header_len) = framing.read_header_len(&mut reader) else {
+                break;
+            };
+            let Some(rest) = reader.get(header_len..) else {
+                break;
+            };
+            reader = rest;
+            let Ok(content_len) = framing.read_content_len(&mut reader) else {
+                break;
+            };
+            let content_start = body.len() - reader.len();
+            let Some(content_end) = usize::try_from(content_len)
+                .ok()
+                .and_then(|len| content_start.checked_add(len))
+                .filter(|end| *end <= body.len())
+            else {
+                break;
+            };
+
+            if group_begin {
+                let content = &body[content_start..content_end];
+                if !group_committed_in(&body, signatures, framing, content, content_end) {
+                    break;
+                }
+            } else if !tombstone && !group_commit && !padding && !producer {
+                let content = body.slice(content_start..content_end);
+                let content = match &cipher {
+                    Some(cipher) => cipher.decrypt(offset as u64, &content)?,
+                    None => content,
+                };
+                records.push((offset as u64, content));
+            }
+            offset = content_end;
+        }
+
+        WalCounters::add(&self.metrics.records_read, records.len() as u64);
+        Ok(records)
+    }
+
+    /// Checks, in debug builds, that the active segment at `file_path` has
+    /// no buffered writes, which a read of the file would miss.
+    fn debug_assert_flushed(&self, file_path: &Path) {
+        debug_assert!(
+            (self.active_segments.values())
+                .all(|active| active.path != file_path || active.writer.buffer().is_empty()),
+            "{} is read while writes to it are buffered",
+            file_path.display()
+        );
+    }
+
+    /// Opens a record cursor positioned after the segment file header.
+    fn open_cursor(&self, file_path: &Path) -> Result<SegmentCursor> {
+        self.open_cursor_at(file_path, segment_sequence(file_path))
+    }
+
+    /// Opens a record cursor over a segment file whose name need not
+    /// carry its sequence number, such as a staged `.tmp` file.
+    fn open_cursor_at(&self, file_path: &Path, sequence: u64) -> Result<SegmentCursor> {
+        self.debug_assert_flushed(file_path);
+        Ok(open_cursor(
+            self.open_segment_file(file_path)?,
+            sequence,
+            self.signatures(),
+            self.options.encryption.as_ref(),
+        )?
+        .counted(&self.metrics))
+    }
+
+    /// Opens a segment file for reading, taking the image of a compressed
+    /// segment from the cache of recently decompressed ones.
+    fn open_segment_file(&self, file_path: &Path) -> Result<SegmentFile> {
+        if is_compressed_segment(file_path) {
+            let image = self
+                .decompressed
+                .get(&*self.vfs, file_path, self.signatures())?;
+            Ok(SegmentFile::Image(io::Cursor::new(image)))
+        } else {
+            SegmentFile::open(&*self.vfs, file_path, self.signatures())
+        }
+    }
+
+    /// Reads entry at specified location.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry_ref` - Reference to the entry location
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if segment doesn't exist.
+    /// Returns `WalError::InvalidOffset` if the offset is past the last record.
+    /// Returns `WalError::CorruptedData` if signature is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
+    /// let data = wal.read_entry_at(entry_ref)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_entry_at(&self, entry_ref: EntryRef) -> Result<Bytes> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        WalCounters::add(&self.metrics.segments_opened, 1);
+        let content = read_entry_from_file(
+            self.open_segment_file(&file_path)?,
+            entry_ref.sequence_number,
+            entry_ref.offset,
+            u64::MAX,
+            self.signatures(),
+            self.options.encryption.as_ref(),
+        )?;
+        WalCounters::add(&self.metrics.records_read, 1);
+        Ok(content)
+    }
+
+    /// Reads the entry starting `file_offset` bytes from the start of its
+    /// segment file, as given by [`EntryRef::absolute_offset`].
+    ///
+    /// The segment's header is read for the key length and the offset is
+    /// converted back to the post-header one of an `EntryRef`; otherwise
+    /// this behaves like [`Wal::read_entry_at`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::InvalidOffset` if `file_offset` falls inside the
+    /// file header or past the last record.
+    /// Otherwise the same as [`Wal::read_entry_at`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
+    /// let file_offset = entry_ref.absolute_offset("key".len());
+    /// let data = wal.read_entry_at_absolute(entry_ref.key_hash, entry_ref.sequence_number, file_offset)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_entry_at_absolute(
+        &self,
+        key_hash: u64,
+        sequence_number: u64,
+        file_offset: u64,
+    ) -> Result<Bytes> {
+        let file_path = self.find_segment(key_hash, sequence_number)?;
+        let header = self.read_segment_header(&file_path)?;
+        let Some(offset) = file_offset.checked_sub(file_header_size(header.key.len())) else {
+            return Err(WalError::InvalidOffset {
+                offset: file_offset,
+                segment_len: self.vfs.metadata(&file_path)?.len,
+            });
+        };
+        self.read_entry_at(EntryRef {
+            key_hash,
+            sequence_number,
+            offset,
+        })
+    }
+
+    /// Reads the entry at the specified location with its header, and
+    /// reports whether it came from the key's active segment.
+    ///
+    /// A tailing consumer that reads from the active segment knows more
+    /// records may follow in the same segment and can come back for them,
+    /// while a sealed segment no longer changes. The flag reflects the
+    /// segment the WAL is appending to at the time of the call.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if segment doesn't exist.
+    /// Returns `WalError::InvalidOffset` if the offset is past the end of
+    /// the segment.
+    /// Returns `WalError::CorruptedData` if no intact record starts at the
+    /// offset.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
+    /// let entry = wal.read_entry_meta_at(entry_ref)?;
+    /// if entry.read_from_active {
+    ///     println!("at the live tail");
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_entry_meta_at(&self, entry_ref: EntryRef) -> Result<EntryWithMeta> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        let mut cursor = self.open_cursor(&file_path)?;
+        let segment_len = cursor.body_len();
+        if entry_ref.offset >= segment_len {
+            return Err(WalError::InvalidOffset {
+                offset: entry_ref.offset,
+                segment_len,
+            });
+        }
+
+        cursor.seek(entry_ref.offset)?;
+        let frame = (cursor.try_next_frame(true)?).filter(|frame| frame.offset == entry_ref.offset);
+        let content = match frame {
+            Some(_) => cursor.try_read_content()?,
+            None => None,
+        };
+        let (Some(frame), Some(content)) = (frame, content) else {
+            return Err(WalError::CorruptedData(format!(
+                "No intact record at offset {}",
+                entry_ref.offset
+            )));
+        };
+
+        Ok(EntryWithMeta {
+            header: frame.header,
+            content,
+            read_from_active: self
+                .active_segments
+                .get(&entry_ref.key_hash)
+                .is_some_and(|active| active.sequence_number == entry_ref.sequence_number),
+        })
+    }
+
+    /// Returns the key of the segment that `entry_ref` points into.
+    ///
+    /// An `EntryRef` only carries the key's hash; the key itself is read
+    /// from the segment header, so tools holding refs from an external
+    /// index can show which key they belong to. Only the header is read:
+    /// the record at `entry_ref.offset` is not checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if the segment doesn't exist.
+    /// Returns `WalError::CorruptedData` if the segment header is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let entry_ref = wal.append_entry("orders", None, Bytes::from("data"), true)?;
+    /// assert_eq!(wal.key_for_ref(entry_ref)?, "orders");
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn key_for_ref(&self, entry_ref: EntryRef) -> Result<Bytes> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        let header = self.read_segment_header(&file_path)?;
+        Ok(Bytes::from(header.key))
+    }
+
+    /// Returns the content length of the entry at the specified location.
+    ///
+    /// Only the record framing is read; the content itself is not loaded,
+    /// which makes this a cheap check before deciding whether to read a
+    /// large entry. For an encrypted segment this is the stored length,
+    /// including what the cipher adds.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if segment doesn't exist.
+    /// Returns `WalError::InvalidOffset` if the offset is past the last record.
+    /// Returns `WalError::CorruptedData` if signature is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
+    /// if wal.entry_size_at(entry_ref)? < 1024 * 1024 {
+    ///     let data = wal.read_entry_at(entry_ref)?;
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn entry_size_at(&self, entry_ref: EntryRef) -> Result<u64> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        WalCounters::add(&self.metrics.segments_opened, 1);
+        let (_, content_len, _) = seek_to_content(
+            self.open_segment_file(&file_path)?,
+            entry_ref.offset,
+            u64::MAX,
+            self.signatures(),
+        )?;
+        Ok(content_len)
+    }
+
+    /// Returns a reader over the content of the entry at the specified
+    /// location, without reading it into memory.
+    ///
+    /// The reader is a segment file positioned at the start of the
+    /// record's content and limited to its length, so a large record can be
+    /// copied to a socket or file with [`io::copy`] in fixed-size pieces.
+    /// It is the read-side counterpart of [`Wal::append_stream`]. The file
+    /// is not buffered; wrap it in a `BufReader` for small reads. A
+    /// compressed segment is served from its decompressed image, so only
+    /// plain segments avoid holding the record in memory.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Wal::read_entry_at`]. Also returns
+    /// `WalError::InvalidConfig` if the segment is encrypted, since a
+    /// record can only be decrypted whole.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("blobs", None, Bytes::from("data"), true)?;
+    /// let mut socket = std::net::TcpStream::connect("127.0.0.1:9000")?;
+    /// let mut content = wal.read_entry_reader(entry_ref)?;
+    /// std::io::copy(&mut content, &mut socket)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn read_entry_reader(&self, entry_ref: EntryRef) -> Result<impl Read + Send> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        WalCounters::add(&self.metrics.segments_opened, 1);
+        let (file, content_len, header) = seek_to_content(
+            self.open_segment_file(&file_path)?,
+            entry_ref.offset,
+            u64::MAX,
+            self.signatures(),
+        )?;
+        if header.encrypted {
+            return Err(WalError::InvalidConfig(
+                "encrypted content cannot be streamed".to_string(),
+            ));
+        }
+        WalCounters::add(&self.metrics.records_read, 1);
+        Ok(file.take(content_len))
+    }
+
+    /// Returns a reader over the records of a key.
+    ///
+    /// The reader keeps one segment file open at a time and moves through
+    /// the key's segments in sequence order, so sequential reads and
+    /// [`RecordReader::read_at`] calls within a segment reuse the same
+    /// handle. It covers the segments that exist when it is created.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a segment cannot be opened while reading.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// let mut reader = wal.reader_for("orders")?;
+    /// while let Some(record) = reader.next()? {
+    ///     println!("Record size: {}", record.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn reader_for<K: Hash + AsRef<[u8]> + Display>(&self, key: K) -> Result<RecordReader> {
+        Ok(RecordReader {
+            vfs: self.vfs.clone(),
+            key_hash: self.key_hash(key.as_ref()),
+            signatures: self.signatures(),
+            cipher: self.options.encryption.clone(),
+            metrics: self.metrics.clone(),
+            segments: self.segment_files_for_key(&key)?,
+            index: 0,
+            cursor: None,
+        })
+    }
+
+    /// Opens one segment file for parsing its records directly.
+    ///
+    /// The handle yields each record's offset, header and content as
+    /// stored, for callers that frame their own data inside the content and
+    /// want to index it by [`EntryRef`]. Content of an encrypted segment is
+    /// decrypted. Records end at the first damaged or incomplete frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if the segment doesn't exist.
+    /// Returns `WalError::CorruptedData` if the segment header is invalid.
+    /// Returns `WalError::InvalidConfig` if the segment is encrypted and no
+    /// cipher is configured.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("orders", None, Bytes::from("data"), true)?;
+    /// let mut segment = wal.open_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+    /// while let Some((offset, header, content)) = segment.next()? {
+    ///     let entry_ref = segment.entry_ref(offset);
+    ///     println!("{:?}: {} + {} bytes", entry_ref, header.len(), content.len());
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn open_segment(&self, key_hash: u64, sequence_number: u64) -> Result<SegmentHandle> {
+        let file_path = self.find_segment(key_hash, sequence_number)?;
+        let mut file = self.open_segment_file(&file_path)?;
+        let header = parse_segment_header(&mut file, self.signatures())?;
+        let cipher = KeyCipher::for_segment(
+            &header,
+            sequence_number,
+            self.options.encryption.as_ref(),
+        )?;
+        let cursor = SegmentCursor::new(file, self.signatures(), header.framing, cipher)?
+            .counted(&self.metrics);
+
+        Ok(SegmentHandle {
+            key_hash,
+            sequence_number,
+            key: Bytes::from(header.key),
+            expiration_timestamp: header.expiration_timestamp,
+            cursor,
+        })
+    }
+
+    /// Captures a read-only view of the WAL as it is now.
+    ///
+    /// The snapshot records the path and length of every segment file.
+    /// Reads through it stop at those lengths and ignore segments created
+    /// afterwards, so repeated reads return the same records while appends
+    /// continue. No file handles are kept open; segments are reopened on
+    /// each read. A segment removed by compaction after the snapshot is
+    /// taken reads as empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let snapshot = wal.freeze()?;
+    /// wal.append_entry("orders", None, Bytes::from("late"), false)?;
+    /// let records: Vec<Bytes> = snapshot.enumerate_records("orders")?.collect();
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn freeze(&self) -> Result<WalSnapshot> {
+        let mut segments = Vec::new();
+
+        for entry in self.segment_dir_entries(None)? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if let Some((key_hash, sequence_number)) = self.parse_filename(filename) {
+                    // A compressed segment is sealed, and its length on
+                    // disk is not that of its image
+                    let file_len = if is_compressed_segment(&entry.path()) {
+                        u64::MAX
+                    } else {
+                        self.vfs.metadata(&entry.path())?.len
+                    };
+                    segments.push(SnapshotSegment {
+                        key_hash,
+                        sequence_number,
+                        path: entry.path(),
+                        file_len,
+                    });
+                }
+            }
+        }
+
+        segments.sort_by_key(|segment| (segment.key_hash, segment.sequence_number));
+
+        Ok(WalSnapshot {
+            vfs: self.vfs.clone(),
+            hasher: self.options.hasher,
+            signatures: self.signatures(),
+            cipher: self.options.encryption.clone(),
+            segments,
+        })
+    }
+
+    /// Locates the segment file for a key hash and sequence number.
+    fn find_segment(&self, key_hash: u64, sequence_number: u64) -> Result<PathBuf> {
+        if let Ok(entries) = self.segment_dir_entries(Some(key_hash)) {
+            for entry in entries {
+                if let Some(filename) = entry.file_name().to_str() {
+                    if self.parse_filename(filename) == Some((key_hash, sequence_number)) {
+                        self.debug_assert_flushed(&entry.path());
+                        return Ok(entry.path());
+                    }
+                }
+            }
+        }
+
+        Err(WalError::EntryNotFound(format!(
+            "Segment for key_hash {} sequence {} not found",
+            key_hash, sequence_number
+        )))
+    }
+
+    /// Removes expired segments from disk.
+    ///
+    /// Also forgets the in-memory state kept for keys that no longer have
+    /// any segment, so a key written again afterwards starts over at
+    /// sequence 1, and drops dedup ids that are all past the dedup window.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` for filesystem errors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.compact()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn compact(&mut self) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("compact", removed = tracing::field::Empty).entered();
+        let now = self.options.clock.now_unix();
+        let _removed =
+            self.remove_segments_where(|expiration_timestamp| now > expiration_timestamp);
+        #[cfg(feature = "tracing")]
+        span.record("removed", _removed);
+        let window = self.options.dedup_window.as_secs();
+        self.dedup.retain(|_, cache| !cache.is_expired(now, window));
 
         Ok(())
     }
 
-    /// Appends an entry to the WAL.
+    /// Removes the segments whose records all predate `cutoff_unix`,
+    /// regardless of the retention they were created with.
     ///
-    /// # Arguments
+    /// Records carry no timestamp of their own, so a segment's newest
+    /// record is bounded by its header expiration: under the configured
+    /// [`RotationPolicy`] no record lands in a segment once it stops
+    /// accepting appends. A segment is removed when it would no longer
+    /// accept appends at `cutoff_unix`. For segments written with
+    /// [`Wal::append_entry_at_time`], that is by event time. Active
+    /// segments are removed like any other, and the next append starts a
+    /// new one. In-memory state of keys left without segments is forgotten
+    /// as in [`Wal::compact`].
     ///
-    /// * `key` - Entry key for segment selection
-    /// * `header` - Optional metadata header (max 64KB)
-    /// * `content` - Entry content
-    /// * `durable` - If true, syncs to disk before returning
+    /// # Returns
+    ///
+    /// The number of segment files removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` for filesystem errors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// // Keep only the last day, whatever retention the segments had
+    /// let cutoff = chrono::Utc::now().timestamp() as u64 - 24 * 60 * 60;
+    /// let removed = wal.compact_before(cutoff)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn compact_before(&mut self, cutoff_unix: u64) -> Result<usize> {
+        let policy = self.options.rotation_policy;
+        Ok(self.remove_segments_where(|expiration_timestamp| {
+            !policy.accepts_appends(cutoff_unix, expiration_timestamp)
+        }))
+    }
+
+    /// Removes the segments of one key whose records all predate
+    /// `cutoff_unix`.
+    ///
+    /// The single-key form of [`Wal::compact_before`], which only lists
+    /// and reads the key's own segments.
+    ///
+    /// # Returns
+    ///
+    /// The number of segment files removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if a segment header is invalid.
+    /// Returns `WalError::Io` for filesystem errors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let removed = wal.compact_key_before("audit", 1_700_000_000)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn compact_key_before<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        cutoff_unix: u64,
+    ) -> Result<usize> {
+        let key_hash = self.key_hash(key.as_ref());
+        let segments = self.segment_files_for_key(&key)?;
+        let mut removed = 0;
+        for (_, file_path) in &segments {
+            let header = self.read_segment_header(file_path)?;
+            if !(self.options.rotation_policy)
+                .accepts_appends(cutoff_unix, header.expiration_timestamp)
+            {
+                self.vfs.remove_file(file_path)?;
+                self.active_segments
+                    .retain(|_, active| active.path != *file_path);
+                self.forget_records(key_hash);
+                removed += 1;
+            }
+        }
+
+        if removed == segments.len() && !self.active_segments.contains_key(&key_hash) {
+            self.next_sequence.remove(&key_hash);
+        }
+        Ok(removed)
+    }
+
+    /// Removes every segment whose header expiration satisfies `expired`,
+    /// returning how many were removed.
+    ///
+    /// Also forgets the next sequence of keys that no longer have any
+    /// segment.
+    fn remove_segments_where(&mut self, expired: impl Fn(u64) -> bool) -> usize {
+        let mut remaining = HashSet::new();
+        let mut removed_count = 0;
+
+        if let Ok(entries) = self.segment_dir_entries(None) {
+            for entry in entries {
+                if let Some(filename) = entry.file_name().to_str() {
+                    if is_segment_file_name(filename) {
+                        if self.remove_segment_if(&entry.path(), &expired) {
+                            removed_count += 1;
+                        } else if let Some((key_hash, _)) = self.parse_filename(filename) {
+                            remaining.insert(key_hash);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Forget per-key state of keys whose segments are all gone
+        let active_segments = &self.active_segments;
+        self.next_sequence.retain(|key_hash, _| {
+            remaining.contains(key_hash) || active_segments.contains_key(key_hash)
+        });
+
+        removed_count
+    }
+
+    /// Removes one segment file if its header expiration satisfies
+    /// `expired`, returning whether it was removed.
+    ///
+    /// Files that cannot be read, carry a foreign signature or fail the
+    /// header checksum are kept, so a damaged expiration never removes a
+    /// live segment.
+    fn remove_segment_if(&mut self, file_path: &Path, expired: &impl Fn(u64) -> bool) -> bool {
+        let Ok(header) = self.read_segment_header(file_path) else {
+            return false;
+        };
+        if !expired(header.expiration_timestamp) {
+            return false;
+        }
+
+        let removed = self.vfs.remove_file(file_path).is_ok();
+        let name = file_path.file_name().and_then(|name| name.to_str());
+        if let Some((key_hash, _)) = name.and_then(|name| self.parse_filename(name)) {
+            self.forget_records(key_hash);
+        }
+        // A backfilled segment can be active yet expired
+        self.active_segments
+            .retain(|_, active| active.path != file_path);
+        removed
+    }
+
+    /// Removes expired segments a bounded number at a time.
+    ///
+    /// Each call examines at most `max_files` segment files, in path
+    /// order, starting after the last one examined by the previous call,
+    /// and removes those that [`Wal::compact`] would. The position is kept
+    /// in memory, so a background loop can call this until
+    /// [`CompactProgress::has_more`] is false to finish a pass with short
+    /// pauses; the next call then starts a new pass. The directory listing
+    /// is still read on every call, but only the examined files are
+    /// opened. Per-key state is forgotten as in [`Wal::compact`], for the
+    /// keys whose segments the call removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// while wal.compact_incremental(64)?.has_more {
+    ///     std::thread::yield_now();
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn compact_incremental(&mut self, max_files: usize) -> Result<CompactProgress> {
+        let now = self.options.clock.now_unix();
+        let mut paths = Vec::new();
+        for entry in self.segment_dir_entries(None)? {
+            let path = entry.path();
+            let is_segment =
+                (path.file_name().and_then(|name| name.to_str())).is_some_and(is_segment_file_name);
+            if is_segment
+                && self
+                    .compact_cursor
+                    .as_ref()
+                    .is_none_or(|cursor| path > *cursor)
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let has_more = paths.len() > max_files;
+        paths.truncate(max_files);
+
+        let mut removed = 0;
+        let mut emptied = HashSet::new();
+        for path in &paths {
+            if self.remove_segment_if(path, &|expiration_timestamp| now > expiration_timestamp) {
+                removed += 1;
+                let filename = path.file_name().and_then(|name| name.to_str());
+                emptied.extend(
+                    filename
+                        .and_then(|name| self.parse_filename(name))
+                        .map(|(key_hash, _)| key_hash),
+                );
+            }
+        }
+        for key_hash in emptied {
+            if !self.active_segments.contains_key(&key_hash) && !self.has_segment_files(key_hash)? {
+                self.next_sequence.remove(&key_hash);
+            }
+        }
+
+        if !has_more {
+            self.compact_cursor = None;
+            let window = self.options.dedup_window.as_secs();
+            self.dedup.retain(|_, cache| !cache.is_expired(now, window));
+        } else if let Some(last) = paths.last() {
+            self.compact_cursor = Some(last.clone());
+        }
+
+        Ok(CompactProgress {
+            examined: paths.len(),
+            removed,
+            has_more,
+        })
+    }
+
+    /// Returns whether any segment file is named for `key_hash`.
+    fn has_segment_files(&self, key_hash: u64) -> Result<bool> {
+        for entry in self.segment_dir_entries(Some(key_hash))? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if self.parse_filename(filename).map(|(hash, _)| hash) == Some(key_hash) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Compresses every sealed segment with zstd, returning the bytes saved.
+    ///
+    /// Each segment that is not a key's active segment is rewritten as a
+    /// `.logz` file: the header stays uncompressed, with a flag marking the
+    /// body as a zstd frame, and the body follows compressed. The file is
+    /// written to a temporary sibling, synced and renamed into place before
+    /// the `.log` file is removed. Segments that would not shrink, and those
+    /// written before format version 3, are left as they are.
+    ///
+    /// Reads decompress transparently, but a compressed segment can only
+    /// be decompressed whole, so reading one record at an [`EntryRef`]
+    /// reads the entire segment. The `Wal` keeps the images of the last few
+    /// compressed segments it read in memory: random reads clustered on a
+    /// few segments decompress each once, while reads spread over many
+    /// compressed segments decompress one for nearly every read. Snapshots
+    /// and [`RecordReader`]s do not share that cache. A compressed segment
+    /// accepts no appends, so a key whose latest segment is compressed
+    /// continues in a new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let saved = wal.compress_sealed_segments()?;
+    /// println!("compression saved {} bytes", saved);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    #[cfg(feature = "compression")]
+    pub fn compress_sealed_segments(&mut self) -> Result<u64> {
+        let active: HashSet<&Path> = self
+            .active_segments
+            .values()
+            .map(|active| active.path.as_path())
+            .collect();
+
+        let mut saved = 0;
+        for file_path in self.segment_paths()? {
+            if is_compressed_segment(&file_path) || active.contains(file_path.as_path()) {
+                continue;
+            }
+            saved += self.compress_segment(&file_path)?;
+        }
+        Ok(saved)
+    }
+
+    /// Replaces a sealed segment with its compressed form, returning the
+    /// bytes saved.
+    ///
+    /// Segments with an unreadable header are left alone.
+    #[cfg(feature = "compression")]
+    fn compress_segment(&self, file_path: &Path) -> Result<u64> {
+        let plain = read_file(&*self.vfs, file_path)?;
+        let mut body = &plain[..];
+        let Ok(header) = parse_segment_header(&mut body, self.signatures()) else {
+            return Ok(0);
+        };
+        if header.format_version < HEADER_CHECKSUM_VERSION {
+            return Ok(0);
+        }
+
+        let mut compressed = plain[..plain.len() - body.len()].to_vec();
+        set_compressed_flag(&mut compressed, true);
+        zstd::stream::copy_encode(body, &mut compressed, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        if compressed.len() >= plain.len() {
+            return Ok(0);
+        }
+
+        self.replace_segment_file(file_path, &file_path.with_extension("logz"), &compressed)?;
+        Ok((plain.len() - compressed.len()) as u64)
+    }
+
+    /// Writes a compressed segment back as a plain `.log` file, returning
+    /// its path.
+    fn decompress_segment(&self, file_path: &Path) -> Result<PathBuf> {
+        let image = self
+            .decompressed
+            .get(&*self.vfs, file_path, self.signatures())?;
+        let plain_path = file_path.with_extension("log");
+        self.replace_segment_file(file_path, &plain_path, &image)?;
+        Ok(plain_path)
+    }
+
+    /// Replaces the segment file at `old_path` with `contents` at
+    /// `new_path`.
+    ///
+    /// The contents are written to a `.tmp` sibling, synced and renamed
+    /// into place before `old_path` is removed, so a crash leaves at least
+    /// one complete copy of the segment.
+    fn replace_segment_file(
+        &self,
+        old_path: &Path,
+        new_path: &Path,
+        contents: &[u8],
+    ) -> Result<()> {
+        let mut tmp_path = new_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let result = self
+            .vfs
+            .create(&tmp_path)
+            .map_err(WalError::from)
+            .and_then(|mut tmp| {
+                tmp.write_all(contents)?;
+                self.sync_file(&tmp)?;
+                Ok(())
+            })
+            .and_then(|_| self.vfs.rename(&tmp_path, new_path).map_err(WalError::from));
+        if let Err(e) = result {
+            let _ = self.vfs.remove_file(&tmp_path);
+            return Err(e);
+        }
+        self.vfs.remove_file(old_path)?;
+        self.sync_dir(new_path.parent().unwrap_or(&self.dir))
+    }
+
+    /// Merges the non-expired segments of a key into a single segment.
+    ///
+    /// Records are copied in order into a new segment whose expiration is
+    /// the latest of the merged segments, so nothing expires earlier than
+    /// it would have. The merged file is written under a temporary name,
+    /// synced and renamed into place before the originals are deleted; a
+    /// crash between those steps can leave records duplicated but never
+    /// lost. The key's active segment is sealed, so the next append starts
+    /// a new segment after the merged one. Expired segments are left for
+    /// [`Wal::compact`].
+    ///
+    /// Record offsets change, so `EntryRef`s previously issued for the
+    /// merged segments become invalid. For the same reason, records deleted
+    /// by a tombstone within the merged segments are dropped along with the
+    /// tombstone.
+    ///
+    /// # Returns
+    ///
+    /// The number of segments merged, or 0 if there were fewer than two.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if a segment header is invalid.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let merged = wal.compact_and_merge("metrics")?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn compact_and_merge<K: Hash + AsRef<[u8]> + Display>(&mut self, key: K) -> Result<usize> {
+        let now = self.options.clock.now_unix();
+        let key_hash = self.key_hash(key.as_ref());
+
+        let mut live_segments = Vec::new();
+        let mut expiration_timestamp = 0;
+        for (sequence, file_path) in self.segment_files_for_key(&key)? {
+            let header = self.read_segment_header(&file_path)?;
+            if now > header.expiration_timestamp {
+                continue;
+            }
+            expiration_timestamp = expiration_timestamp.max(header.expiration_timestamp);
+            live_segments.push((sequence, file_path));
+        }
+
+        if live_segments.len() < 2 {
+            return Ok(0);
+        }
+
+        if let Some(active) = self.active_segments.remove(&key_hash) {
+            self.retire_segment(active)?;
+        }
+        self.producers.remove(&key_hash);
+        self.forget_records(key_hash);
+
+        let sequence = *self.next_sequence.get(&key_hash).unwrap_or(&1);
+        self.next_sequence.insert(key_hash, sequence + 1);
+
+        let file_path = self.segment_path(&key, key_hash, sequence)?;
+        let tmp_path = file_path.with_extension("log.tmp");
+
+        if let Err(e) = self.write_merged_segment(
+            &tmp_path,
+            sequence,
+            &key,
+            expiration_timestamp,
+            &live_segments,
+            true,
+        ) {
+            let _ = self.vfs.remove_file(&tmp_path);
+            return Err(e);
+        }
+        self.vfs.rename(&tmp_path, &file_path)?;
+        self.sync_dir(&self.shard_dir(key_hash))?;
+
+        for (_, segment_path) in &live_segments {
+            self.vfs.remove_file(segment_path)?;
+        }
+
+        Ok(live_segments.len())
+    }
+
+    /// Writes the records of several segments into one new segment file,
+    /// which becomes segment `sequence` of the key.
+    ///
+    /// Each segment is copied up to its first incomplete or invalid frame.
+    /// With `apply_tombstones`, a record deleted by a tombstone in
+    /// `segments` is dropped together with the tombstone, whose reference
+    /// would not survive the move; tombstones for records in other segments
+    /// are kept. Otherwise tombstones are copied like records. Returns the
+    /// number of data records written.
+    fn write_merged_segment<K: AsRef<[u8]>>(
+        &self,
+        file_path: &Path,
+        sequence: u64,
+        key: &K,
+        expiration_timestamp: u64,
+        segments: &[(u64, PathBuf)],
+        apply_tombstones: bool,
+    ) -> Result<usize> {
+        let mut writer = BufWriter::new(self.vfs.create(file_path)?);
+        self.write_file_header(&mut writer, key, expiration_timestamp)?;
+        let cipher = self.key_cipher(key.as_ref(), sequence);
+        let signatures = self.signatures();
+
+        let deleted = if apply_tombstones {
+            self.tombstone_targets(segments)?
+        } else {
+            HashSet::new()
+        };
+        let merged: HashSet<u64> = segments.iter().map(|(sequence, _)| *sequence).collect();
+
+        let mut records = 0;
+        let body_start = file_header_size(key.as_ref().len());
+        let mut body_len = 0;
+        for (source, segment_path) in segments {
+            let mut cursor = self
+                .open_cursor_at(segment_path, *source)?
+                .with_tombstones();
+            while let Some(frame) = cursor.next_frame(true) {
+                let Some(content) = cursor.read_content() else {
+                    break;
+                };
+                let signature = if frame.kind == RecordKind::Tombstone {
+                    let target = decode_tombstone(&content);
+                    if apply_tombstones && target.is_some_and(|(seq, _)| merged.contains(&seq)) {
+                        continue;
+                    }
+                    &signatures.tombstone
+                } else {
+                    if deleted.contains(&(*source, frame.offset)) {
+                        continue;
+                    }
+                    records += 1;
+                    &signatures.record
+                };
+                if let Some((producer_id, producer_seq)) = frame.producer {
+                    let mut marker = self.padding_at(body_start + body_len);
+                    write_record(
+                        &mut marker,
+                        &signatures.producer,
+                        self.framing(),
+                        None,
+                        &encode_producer(producer_id, producer_seq),
+                    )?;
+                    writer.write_all(&marker)?;
+                    body_len += marker.len() as u64;
+                }
+                let padding = self.padding_at(body_start + body_len);
+                writer.write_all(&padding)?;
+                body_len += padding.len() as u64;
+                // Records move, so they are encrypted again for their new
+                // sequence and offset
+                let encrypted = cipher.as_ref().map(|c| c.encrypt(body_len, &content));
+                body_len += write_record(
+                    &mut writer,
+                    signature,
+                    self.framing(),
+                    frame.header.as_deref(),
+                    encrypted.as_deref().unwrap_or(&content),
+                )?;
+            }
+        }
+        writer.write_all(&self.padding_at(body_start + body_len))?;
+
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        self.sync_file(&file)?;
+        Ok(records)
+    }
+
+    /// Rewrites a damaged segment, keeping the records before the damage.
+    ///
+    /// Records are read up to the first frame with a bad signature or one
+    /// that runs past the end of the file, such as a torn write. That intact
+    /// prefix is written under a fresh header to a temporary file, synced
+    /// and renamed over the original, so a crash mid-repair leaves either
+    /// the old or the repaired segment. The key and expiration are kept. If
+    /// the segment is a key's active segment it is sealed first, so later
+    /// appends resume from the repaired file. A compressed segment is
+    /// written back uncompressed first and stays so.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if the segment doesn't exist.
+    /// Returns `WalError::CorruptedData` if the segment header itself is
+    /// unreadable, since there is no key to rebuild it under.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// # let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
+    /// let outcome = wal.repair_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+    /// println!("kept {} records", outcome.records_kept);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn repair_segment(&mut self, key_hash: u64, sequence_number: u64) -> Result<RepairOutcome> {
+        let mut file_path = self.find_segment(key_hash, sequence_number)?;
+        if is_compressed_segment(&file_path) {
+            file_path = self.decompress_segment(&file_path)?;
+        }
+        let header = self.read_segment_header(&file_path)?;
+
+        if self
+            .active_segments
+            .get(&key_hash)
+            .is_some_and(|active| active.sequence_number == sequence_number)
+        {
+            let active = self.active_segments.remove(&key_hash).unwrap();
+            self.retire_segment(active)?;
+        }
+        self.producers.remove(&key_hash);
+        self.forget_records(key_hash);
+
+        let original_len = self.vfs.metadata(&file_path)?.len;
+        let tmp_path = file_path.with_extension("log.tmp");
+        let records_kept = match self.write_merged_segment(
+            &tmp_path,
+            sequence_number,
+            &header.key,
+            header.expiration_timestamp,
+            &[(sequence_number, file_path.clone())],
+            false,
+        ) {
+            Ok(records) => records,
+            Err(e) => {
+                let _ = self.vfs.remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+        let repaired_len = self.vfs.metadata(&tmp_path)?.len;
+        self.vfs.rename(&tmp_path, &file_path)?;
+        self.sync_dir(file_path.parent().unwrap_or(&self.dir))?;
+
+        Ok(RepairOutcome {
+            records_kept,
+            bytes_truncated: original_len.saturating_sub(repaired_len),
+        })
+    }
+
+    /// Inspects a single segment file without opening its WAL.
+    ///
+    /// A read-only diagnostic for a segment copied out of its directory:
+    /// nothing but `path` is read. The header is parsed with the signature
+    /// found at the start of the file, so segments of WALs with a custom
+    /// [`WalOptions::signature`] can be inspected too. The frame chain is
+    /// then walked like enumeration does, without reading record content,
+    /// so encrypted segments are inspected without their cipher; only
+    /// their tombstones are not checked. Anything after the last intact
+    /// frame is reported as an anomaly.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if the segment header is invalid.
+    /// Returns `WalError::Io` for I/O failures, including a file too short
+    /// to hold a header.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::Wal;
+    /// # use std::path::Path;
+    /// let inspection = Wal::inspect_segment(Path::new("./wal/orders-42-0001.log"))?;
+    /// println!("{} records, {:?}", inspection.record_count, inspection.anomalies);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn inspect_segment(path: &Path) -> Result<SegmentInspection> {
+        let mut file = File::open(path)?;
+        let mut segment_signature = [0u8; 8];
+        file.read_exact(&mut segment_signature)?;
+        file.rewind()?;
+        let signatures = Signatures::new(segment_signature);
+        let compressed = parse_segment_header(&mut file, signatures)?.compressed;
+        let mut file = SegmentFile::open(&StdVfs, path, signatures)?;
+        let header = parse_segment_header(&mut file, signatures)?;
+        let (key_hash, sequence_number) = (path.file_name().and_then(|name| name.to_str()))
+            .and_then(parse_segment_filename)
+            .unzip();
+
+        let mut cursor =
+            SegmentCursor::new(file, signatures, header.framing, None)?.with_tombstones();
+        let mut record_count = 0;
+        let mut tombstone_count = 0;
+        let mut anomalies = Vec::new();
+        while let Some(frame) = cursor.try_next_frame(false)? {
+            if frame.kind != RecordKind::Tombstone {
+                record_count += 1;
+                continue;
+            }
+            tombstone_count += 1;
+            if !header.encrypted {
+                let target = cursor.try_read_content()?;
+                if target.as_deref().and_then(decode_tombstone).is_none() {
+                    anomalies.push(SegmentAnomaly::MalformedTombstone {
+                        offset: frame.offset,
+                    });
+                }
+            }
+        }
+
+        let records_size = cursor.offset;
+        let body_size = cursor.body_len();
+        if records_size < body_size {
+            cursor.seek(records_size)?;
+            anomalies.push(classify_segment_tail(
+                &mut cursor.reader,
+                signatures,
+                records_size,
+                body_size - records_size,
+            )?);
+        }
+
+        Ok(SegmentInspection {
+            key: String::from_utf8_lossy(&header.key).into_owned(),
+            key_hash,
+            sequence_number,
+            expiration_timestamp: header.expiration_timestamp,
+            format_version: header.format_version,
+            encrypted: header.encrypted,
+            compressed,
+            record_count,
+            tombstone_count,
+            body_size,
+            records_size,
+            anomalies,
+        })
+    }
+
+    /// Discards every record of a key written after `entry_ref`.
+    ///
+    /// The segment holding `entry_ref` is truncated right after that record
+    /// with `File::set_len`, and all later segments of the same key are
+    /// deleted, so the key ends with the referenced record. The key's active
+    /// segment is sealed; the next append resumes the truncated segment if
+    /// it has not expired. A compressed segment is written back
+    /// uncompressed before it is truncated.
     ///
     /// # Errors
     ///
-    /// Returns `WalError::HeaderTooLarge` if header exceeds 64KB.
+    /// Returns `WalError::EntryNotFound` if the segment doesn't exist or no
+    /// record starts at `entry_ref.offset`.
     /// Returns `WalError::Io` for I/O failures.
     ///
     /// # Examples
@@ -430,78 +9784,165 @@ impl Wal {
     /// # use nano_wal::{Wal, WalOptions};
     /// # use bytes::Bytes;
     /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// let entry_ref = wal.append_entry(
-    ///     "user_123",
-    ///     Some(Bytes::from("metadata")),
-    ///     Bytes::from("data"),
-    ///     true
-    /// )?;
+    /// let checkpoint = wal.append_entry("txn", None, Bytes::from("begin"), true)?;
+    /// wal.append_entry("txn", None, Bytes::from("partial"), true)?;
+    /// wal.truncate_after(checkpoint)?;
     /// # Ok::<(), nano_wal::WalError>(())
     /// ```
-    pub fn append_entry<K: Hash + AsRef<[u8]> + Display>(
-        &mut self,
-        key: K,
-        header: Option<Bytes>,
-        content: Bytes,
-        durable: bool,
-    ) -> Result<EntryRef> {
-        // Validate header size
-        if let Some(ref h) = header {
-            if h.len() > MAX_HEADER_SIZE {
-                return Err(WalError::HeaderTooLarge {
-                    size: h.len(),
-                    max: MAX_HEADER_SIZE,
-                });
+    pub fn truncate_after(&mut self, entry_ref: EntryRef) -> Result<()> {
+        let file_path = self.find_segment(entry_ref.key_hash, entry_ref.sequence_number)?;
+        let header = self.read_segment_header(&file_path)?;
+
+        let mut cursor = self.open_cursor(&file_path)?;
+        let record_end = loop {
+            match cursor.next_frame(false) {
+                Some(frame) if frame.offset == entry_ref.offset => break cursor.offset,
+                Some(frame) if frame.offset < entry_ref.offset => continue,
+                _ => {
+                    return Err(WalError::EntryNotFound(format!(
+                        "No record starts at offset {} of segment {}",
+                        entry_ref.offset,
+                        file_path.display()
+                    )));
+                }
             }
+        };
+        drop(cursor);
+
+        if let Some(active) = self.active_segments.remove(&entry_ref.key_hash) {
+            self.retire_segment(active)?;
         }
+        self.producers.remove(&entry_ref.key_hash);
+        self.forget_records(entry_ref.key_hash);
 
-        let key_hash = self.get_or_create_active_segment(&key)?;
-        let active_segment = self.active_segments.get_mut(&key_hash).unwrap();
+        let file_path = if is_compressed_segment(&file_path) {
+            self.decompress_segment(&file_path)?
+        } else {
+            file_path
+        };
+        let file = self.vfs.open_write(&file_path)?;
+        file.set_len(file_header_size(header.key.len()) + record_end)?;
+        self.sync_file(&file)?;
+
+        let mut dirs = HashSet::new();
+        for (sequence, segment_path) in self.segment_files_for_key(&header.key)? {
+            if sequence > entry_ref.sequence_number {
+                self.vfs.remove_file(&segment_path)?;
+                dirs.insert(segment_path.parent().unwrap_or(&self.dir).to_path_buf());
+            }
+        }
+        for dir in dirs {
+            self.sync_dir(&dir)?;
+        }
 
-        let current_position = active_segment.file.stream_position()?;
-        let file_header_size = 8 + 8 + 8 + 8 + key.as_ref().len() as u64;
-        let entry_offset = current_position - file_header_size;
+        Ok(())
+    }
 
-        // Write record
-        active_segment.file.write_all(&NANO_REC_SIGNATURE)?;
+    /// Deletes the oldest segments of each key beyond the newest `per_key_max`.
+    ///
+    /// This is count-based retention to complement the time-based
+    /// [`Wal::compact`]. A key's active segment is never deleted, even when
+    /// `per_key_max` is zero. Only the lowest sequence numbers are removed,
+    /// so new segments keep numbering after the existing ones.
+    ///
+    /// # Returns
+    ///
+    /// The number of segment files removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed or a segment
+    /// cannot be removed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// let removed = wal.prune_to_segment_count(5)?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn prune_to_segment_count(&mut self, per_key_max: u32) -> Result<usize> {
+        let mut segments_by_key: HashMap<u64, Vec<(u64, PathBuf)>> = HashMap::new();
 
-        let header_len = header.as_ref().map(|h| h.len()).unwrap_or(0);
-        active_segment
-            .file
-            .write_all(&(header_len as u16).to_le_bytes())?;
-        if let Some(header_bytes) = &header {
-            active_segment.file.write_all(header_bytes.as_ref())?;
+        for entry in self.segment_dir_entries(None)? {
+            if let Some(filename) = entry.file_name().to_str() {
+                if let Some((key_hash, sequence)) = self.parse_filename(filename) {
+                    segments_by_key
+                        .entry(key_hash)
+                        .or_default()
+                        .push((sequence, entry.path()));
+                }
+            }
         }
 
-        let content_len = content.len() as u64;
-        active_segment.file.write_all(&content_len.to_le_bytes())?;
-        active_segment.file.write_all(content.as_ref())?;
+        let mut removed = 0;
+        for (key_hash, mut segments) in segments_by_key {
+            segments.sort_by_key(|(sequence, _)| *sequence);
+            let active_sequence = self
+                .active_segments
+                .get(&key_hash)
+                .map(|active| active.sequence_number);
+            let excess = segments.len().saturating_sub(per_key_max as usize);
 
-        if durable {
-            active_segment.file.sync_data()?;
-        } else {
-            active_segment.file.flush()?;
+            for (sequence, file_path) in segments.into_iter().take(excess) {
+                if Some(sequence) != active_sequence {
+                    self.vfs.remove_file(&file_path)?;
+                    self.forget_records(key_hash);
+                    removed += 1;
+                }
+            }
         }
 
-        Ok(EntryRef {
-            key_hash,
-            sequence_number: active_segment.sequence_number,
-            offset: entry_offset,
-        })
+        Ok(removed)
     }
 
-    /// Appends multiple entries in a batch.
+    /// Syncs all active segments to disk.
     ///
-    /// Batch operations provide better throughput by reducing I/O overhead.
+    /// # Errors
     ///
-    /// # Arguments
+    /// Returns `WalError::Io` if sync fails.
     ///
-    /// * `entries` - Iterator of (key, header, content) tuples
-    /// * `durable` - If true, syncs after all entries are written
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.sync()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn sync(&mut self) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("sync", segments = self.active_segments.len()).entered();
+        self.flush_active_segments()?;
+        for active_segment in self.active_segments.values() {
+            self.sync_file(active_segment.writer.get_ref())?;
+        }
+        for active_segment in self.active_segments.values_mut() {
+            active_segment.unsynced = false;
+        }
+        self.synced_appends = self.metrics.appends.load(Ordering::Relaxed);
+        if self.options.checkpoint_on_sync {
+            self.store_checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the active segments written since they were last synced, and
+    /// returns once they are on disk.
+    ///
+    /// The point to drain non-durable appends, for instance when shutting
+    /// down: every record appended with `durable` set to `false` to a
+    /// segment that is still active is synced, while segments with nothing
+    /// new are skipped, unlike [`Wal::sync`]. Segments sealed by rotation
+    /// or closed by [`WalOptions::max_open_segments`] were flushed to the
+    /// OS but are not synced here. [`Wal::close`] calls this after sealing
+    /// the active segments.
     ///
     /// # Errors
     ///
-    /// Returns first error encountered; partial writes may occur.
+    /// Returns `WalError::Io` if flushing or syncing a segment fails; all
+    /// segments then stay pending.
     ///
     /// # Examples
     ///
@@ -509,169 +9950,512 @@ impl Wal {
     /// # use nano_wal::{Wal, WalOptions};
     /// # use bytes::Bytes;
     /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// let entries = vec![
-    ///     ("key1", None, Bytes::from("data1")),
-    ///     ("key2", Some(Bytes::from("meta")), Bytes::from("data2")),
-    /// ];
-    /// let refs = wal.append_batch(entries, true)?;
+    /// for i in 0..100 {
+    ///     wal.append_entry("events", None, Bytes::from(format!("event {}", i)), false)?;
+    /// }
+    /// wal.flush_pending()?;
     /// # Ok::<(), nano_wal::WalError>(())
     /// ```
-    pub fn append_batch<K, I>(&mut self, entries: I, durable: bool) -> Result<Vec<EntryRef>>
-    where
-        K: Hash + AsRef<[u8]> + Display,
-        I: IntoIterator<Item = (K, Option<Bytes>, Bytes)>,
-    {
-        let mut refs = Vec::new();
+    pub fn flush_pending(&mut self) -> Result<()> {
+        self.flush_active_segments()?;
+        for active_segment in self.active_segments.values() {
+            if active_segment.unsynced {
+                self.sync_file(active_segment.writer.get_ref())?;
+            }
+        }
+        for active_segment in self.active_segments.values_mut() {
+            active_segment.unsynced = false;
+        }
+        self.synced_appends = self.metrics.appends.load(Ordering::Relaxed);
+        Ok(())
+    }
 
-        for (key, header, content) in entries {
-            refs.push(self.append_entry(key, header, content, false)?);
+    /// Returns when the key's current segment expires.
+    ///
+    /// Uses the in-memory active segment if there is one, otherwise the
+    /// header of the key's latest segment on disk. Once this time passes,
+    /// the next append for the key starts a new segment.
+    ///
+    /// # Returns
+    ///
+    /// The expiration as a Unix timestamp in seconds, or `None` if the key
+    /// has no segments.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::CorruptedData` if the latest segment header is invalid.
+    /// Returns `WalError::Io` for I/O failures.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// if let Some(expires_at) = wal.segment_expiration("user_123")? {
+    ///     println!("Segment rotates at {}", expires_at);
+    /// }
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn segment_expiration<K: Hash + AsRef<[u8]> + Display>(
+        &self,
+        key: K,
+    ) -> Result<Option<u64>> {
+        let key_hash = self.key_hash(key.as_ref());
+        if let Some(active) = self.active_segments.get(&key_hash) {
+            return Ok(Some(active.expiration_timestamp));
         }
 
-        if durable {
-            self.sync()?;
+        match self.segment_files_for_key(&key)?.pop() {
+            Some((_, file_path)) => Ok(Some(
+                self.read_segment_header(&file_path)?.expiration_timestamp,
+            )),
+            None => Ok(None),
         }
+    }
 
-        Ok(refs)
+    /// Returns count of active segments.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// println!("Active segments: {}", wal.active_segment_count());
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn active_segment_count(&self) -> usize {
+        self.active_segments.len()
     }
 
-    /// Logs an entry with durability guarantee.
+    /// Counts the records of every key in one pass over the directory.
     ///
-    /// Convenience method equivalent to `append_entry(key, header, content, true)`.
+    /// Each segment file is opened once, grouped under the key in its
+    /// header, and its frames are walked without reading content, so the
+    /// cost grows with the number of segments rather than keys times
+    /// segments as with a [`Wal::record_count_estimate`] per key. Tombstones
+    /// are not counted, but the records they delete still are. Segments
+    /// whose header cannot be read are skipped, like in [`Wal::stats`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the directory cannot be listed.
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # use nano_wal::{Wal, WalOptions};
-    /// # use bytes::Bytes;
-    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// wal.log_entry("key", None, Bytes::from("data"))?;
+    /// # let wal = Wal::new("./wal", WalOptions::default())?;
+    /// for (key, count) in wal.key_record_counts()? {
+    ///     println!("{}: {} records", key, count);
+    /// }
     /// # Ok::<(), nano_wal::WalError>(())
     /// ```
-    pub fn log_entry<K: Hash + AsRef<[u8]> + Display>(
-        &mut self,
-        key: K,
-        header: Option<Bytes>,
-        content: Bytes,
-    ) -> Result<EntryRef> {
-        self.append_entry(key, header, content, true)
+    pub fn key_record_counts(&self) -> Result<HashMap<String, u64>> {
+        let mut counts = HashMap::new();
+
+        for entry in self.segment_dir_entries(None)? {
+            let is_segment = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|filename| self.parse_filename(filename).is_some());
+            if !is_segment {
+                continue;
+            }
+
+            let Ok(mut file) = self.open_segment_file(&entry.path()) else {
+                continue;
+            };
+            let Ok(header) = parse_segment_header(&mut file, self.signatures()) else {
+                continue;
+            };
+            let count = counts
+                .entry(String::from_utf8_lossy(&header.key).into_owned())
+                .or_insert(0);
+
+            // Only frames are walked, so encrypted content needs no cipher
+            let mut cursor = SegmentCursor::new(file, self.signatures(), header.framing, None)?;
+            while cursor.next_frame(false).is_some() {
+                *count += 1;
+            }
+        }
+
+        Ok(counts)
     }
 
-    /// Enumerates all keys in the WAL.
+    /// Summarizes the whole WAL in a single directory walk.
+    ///
+    /// Each segment's header is read for its key and expiration, and its
+    /// records are counted from the framing alone; record content is never
+    /// read. Segments whose header cannot be parsed still count towards
+    /// `total_segments` and `total_bytes` but contribute nothing else.
     ///
     /// # Errors
     ///
-    /// Returns `WalError::Io` for filesystem errors.
+    /// Returns `WalError::Io` if the directory cannot be listed.
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # use nano_wal::{Wal, WalOptions};
     /// # let wal = Wal::new("./wal", WalOptions::default())?;
-    /// for key in wal.enumerate_keys()? {
-    ///     println!("Found key: {}", key);
-    /// }
+    /// let stats = wal.stats()?;
+    /// println!("{} records in {} bytes", stats.total_records, stats.total_bytes);
     /// # Ok::<(), nano_wal::WalError>(())
     /// ```
-    pub fn enumerate_keys(&self) -> Result<impl Iterator<Item = String>> {
-        let mut keys = std::collections::HashSet::new();
+    pub fn stats(&self) -> Result<WalStats> {
+        let mut keys = HashSet::new();
+        let mut stats = WalStats {
+            active_segments: self.active_segments.len(),
+            ..Default::default()
+        };
 
-        if let Ok(entries) = fs::read_dir(&self.dir) {
-            for entry in entries.flatten() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if filename.ends_with(".log") {
-                        let segment_path = entry.path();
-                        if let Ok(key) = self.read_key_from_file(&segment_path) {
-                            keys.insert(key);
-                        }
-                    }
-                }
+        for entry in self.segment_dir_entries(None)? {
+            let is_segment = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|filename| self.parse_filename(filename).is_some());
+            if !is_segment {
+                continue;
+            }
+
+            stats.total_segments += 1;
+            stats.total_bytes += self.vfs.metadata(&entry.path())?.len;
+            let Ok(mut file) = self.open_segment_file(&entry.path()) else {
+                continue;
+            };
+
+            let Ok(header) = parse_segment_header(&mut file, self.signatures()) else {
+                continue;
+            };
+            let expiration = header.expiration_timestamp;
+            stats.oldest_expiration = Some(
+                stats
+                    .oldest_expiration
+                    .map_or(expiration, |e| e.min(expiration)),
+            );
+            stats.newest_expiration = Some(
+                stats
+                    .newest_expiration
+                    .map_or(expiration, |e| e.max(expiration)),
+            );
+            keys.insert(header.key);
+
+            // Only frames are walked, so encrypted content needs no cipher
+            let mut cursor = SegmentCursor::new(file, self.signatures(), header.framing, None)?;
+            while cursor.next_frame(false).is_some() {
+                stats.total_records += 1;
             }
         }
 
-        Ok(keys.into_iter())
+        stats.key_count = keys.len();
+        Ok(stats)
     }
 
-    /// Reads key from segment file header.
-    fn read_key_from_file(&self, file_path: &Path) -> Result<String> {
-        let mut file = File::open(file_path)?;
+    /// Returns the counters of the work done since the WAL was opened.
+    ///
+    /// Useful to check what a durability setting costs, for example that
+    /// grouping appends into batches reduced the number of syncs. Reads
+    /// through a [`WalSnapshot`] are not counted, and neither are the
+    /// frames [`Wal::stats`] walks.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # use bytes::Bytes;
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.append_entry("orders", None, Bytes::from("data"), true)?;
+    /// let metrics = wal.metrics();
+    /// println!("{} syncs for {} appends", metrics.fsyncs, metrics.appends);
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn metrics(&self) -> WalMetrics {
+        self.metrics.snapshot()
+    }
 
-        let mut signature_buf = [0u8; 8];
-        file.read_exact(&mut signature_buf)?;
-        if signature_buf != NANO_LOG_SIGNATURE {
-            return Err(WalError::CorruptedData(
-                "Invalid NANO-LOG signature".to_string(),
-            ));
+    /// Closes the WAL, keeping its data on disk.
+    ///
+    /// Every active segment is flushed, trimmed of preallocated space and,
+    /// through [`Wal::flush_pending`], synced at the configured durability
+    /// if it changed since its last sync. Then its file handle is
+    /// released along with the directory lock, so the directory can be
+    /// reopened by another `Wal`. This is the recommended way to stop using
+    /// a WAL; unlike dropping it, errors are reported. The `Wal` should not
+    /// be used afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if flushing or syncing a segment fails. The
+    /// lock is still held in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.close()?;
+    /// let reopened = Wal::new("./wal", WalOptions::default())?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn close(&mut self) -> Result<()> {
+        for active_segment in self.active_segments.values_mut() {
+            active_segment.seal()?;
+        }
+        self.flush_pending()?;
+        if self.options.checkpoint_on_sync {
+            self.store_checkpoint()?;
+        }
+        self.active_segments.clear();
+        self.lock = None;
+        Ok(())
+    }
+
+    /// Shuts down WAL and removes all storage.
+    ///
+    /// Meant for tearing down temporary WALs, such as in tests; use
+    /// [`Wal::close`] to stop using a WAL and keep its data.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if removal fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use nano_wal::{Wal, WalOptions};
+    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
+    /// wal.shutdown()?;
+    /// # Ok::<(), nano_wal::WalError>(())
+    /// ```
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.active_segments.clear();
+        self.lock = None;
+        self.vfs.remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+impl Drop for Wal {
+    /// Flushes active segments and releases their preallocated space on a
+    /// best-effort basis.
+    ///
+    /// Errors are ignored; call [`Wal::sync`] before dropping when the
+    /// outcome matters.
+    fn drop(&mut self) {
+        for active_segment in self.active_segments.values_mut() {
+            let _ = active_segment.seal();
         }
+    }
+}
+
+/// A key whose next segment would not sort after its existing ones,
+/// reported by [`Wal::validate_sequences`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceMismatch {
+    /// Hash of the key
+    pub key_hash: u64,
+    /// Highest sequence number among the key's segment files
+    pub max_on_disk: u64,
+    /// Sequence number the key's next segment would be created with
+    pub next_sequence: u64,
+}
+
+/// Result of one [`Wal::compact_incremental`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactProgress {
+    /// Number of segment files examined by the call
+    pub examined: usize,
+    /// Number of expired segment files removed by the call
+    pub removed: usize,
+    /// Whether segments remain to be examined in the current pass
+    pub has_more: bool,
+}
+
+/// Result of [`Wal::repair_segment`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairOutcome {
+    /// Number of intact records kept in the repaired segment
+    pub records_kept: usize,
+    /// Number of bytes dropped from the end of the segment
+    pub bytes_truncated: u64,
+}
+
+/// Diagnostic report on a single segment file from [`Wal::inspect_segment`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInspection {
+    /// Key stored in the segment header
+    pub key: String,
+    /// Key hash from the filename, if it follows the segment naming scheme
+    pub key_hash: Option<u64>,
+    /// Sequence number from the filename, if it follows the naming scheme
+    pub sequence_number: Option<u64>,
+    /// Unix timestamp when the segment expires
+    pub expiration_timestamp: u64,
+    /// Segment format version
+    pub format_version: u16,
+    /// Whether record content is encrypted
+    pub encrypted: bool,
+    /// Whether the segment body is zstd-compressed
+    pub compressed: bool,
+    /// Number of intact data records
+    pub record_count: u64,
+    /// Number of intact tombstones
+    pub tombstone_count: u64,
+    /// Bytes after the file header
+    pub body_size: u64,
+    /// Bytes of the body covered by intact frames
+    pub records_size: u64,
+    /// Framing problems found, in file order
+    pub anomalies: Vec<SegmentAnomaly>,
+}
+
+/// A framing problem reported by [`Wal::inspect_segment`].
+///
+/// Offsets are relative to the end of the file header, like
+/// [`EntryRef::offset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentAnomaly {
+    /// Zero bytes after the last record. Normal for an active segment with
+    /// preallocation, otherwise left by a crash before the space was used.
+    ZeroFilledTail { offset: u64, len: u64 },
+    /// A frame that starts with a record signature but is cut short by the
+    /// end of the file or has a malformed length, typically a torn write
+    TornRecord { offset: u64, len: u64 },
+    /// Bytes after the last record that do not start a frame
+    UnrecognizedBytes { offset: u64, len: u64 },
+    /// A tombstone whose content is not a record reference
+    MalformedTombstone { offset: u64 },
+    /// A record group without its commit marker, left by a crash during
+    /// [`Wal::append_group`]; its records are never read
+    UncommittedGroup { offset: u64, len: u64 },
+}
 
-        file.seek(SeekFrom::Current(16))?; // Skip sequence and expiration
+/// Summary of a WAL returned by [`Wal::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalStats {
+    /// Number of distinct keys with at least one readable segment
+    pub key_count: usize,
+    /// Number of segment files on disk
+    pub total_segments: usize,
+    /// Number of segments currently open for appends
+    pub active_segments: usize,
+    /// Number of complete records across all segments
+    pub total_records: u64,
+    /// Combined size of all segment files in bytes
+    pub total_bytes: u64,
+    /// Earliest segment expiration as a Unix timestamp, if any
+    pub oldest_expiration: Option<u64>,
+    /// Latest segment expiration as a Unix timestamp, if any
+    pub newest_expiration: Option<u64>,
+}
 
-        let mut key_len_bytes = [0u8; 8];
-        file.read_exact(&mut key_len_bytes)?;
-        let key_len = u64::from_le_bytes(key_len_bytes);
+/// Counters of the work a WAL has done, returned by [`Wal::metrics`].
+///
+/// Counts start at zero when the `Wal` is opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalMetrics {
+    /// Records appended
+    pub appends: u64,
+    /// File and directory syncs issued
+    pub fsyncs: u64,
+    /// Active segments sealed to move appends to another segment
+    pub rotations: u64,
+    /// Bytes of appended records, including their framing
+    pub bytes_written: u64,
+    /// Record contents read from segments, including by compaction
+    pub records_read: u64,
+    /// Segment files opened for reading or to resume appending
+    pub segments_opened: u64,
+}
 
-        let mut key_bytes = vec![0u8; key_len as usize];
-        file.read_exact(&mut key_bytes)?;
+/// Live counters behind [`WalMetrics`], shared with the readers a `Wal`
+/// creates.
+#[derive(Debug, Default)]
+struct WalCounters {
+    appends: AtomicU64,
+    fsyncs: AtomicU64,
+    rotations: AtomicU64,
+    bytes_written: AtomicU64,
+    records_read: AtomicU64,
+    segments_opened: AtomicU64,
+}
 
-        Ok(String::from_utf8_lossy(&key_bytes).to_string())
+impl WalCounters {
+    /// Adds `n` to `counter`.
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
     }
 
-    /// Enumerates records for a specific key.
-    ///
-    /// # Arguments
-    ///
-    /// * `key` - Key to enumerate records for
-    ///
-    /// # Errors
-    ///
-    /// Returns `WalError::Io` for filesystem errors.
-    ///
-    /// # Examples
+    /// Reads the current values.
+    fn snapshot(&self) -> WalMetrics {
+        WalMetrics {
+            appends: self.appends.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            records_read: self.records_read.load(Ordering::Relaxed),
+            segments_opened: self.segments_opened.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A segment file captured by [`Wal::freeze`].
+#[derive(Debug, Clone)]
+struct SnapshotSegment {
+    key_hash: u64,
+    sequence_number: u64,
+    path: PathBuf,
+    /// File length when the snapshot was taken
+    file_len: u64,
+}
+
+/// Immutable view of a WAL created by [`Wal::freeze`].
+///
+/// Only records present when the snapshot was taken are visible.
+#[derive(Debug, Clone)]
+pub struct WalSnapshot {
+    vfs: Arc<dyn Vfs>,
+    hasher: HasherKind,
+    signatures: Signatures,
+    cipher: Option<Arc<dyn RecordCipher>>,
+    segments: Vec<SnapshotSegment>,
+}
+
+impl WalSnapshot {
+    /// Enumerates the records of a key as of the snapshot.
     ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # let wal = Wal::new("./wal", WalOptions::default())?;
-    /// for record in wal.enumerate_records("my_key")? {
-    ///     println!("Record size: {}", record.len());
-    /// }
-    /// # Ok::<(), nano_wal::WalError>(())
-    /// ```
+    /// Segments that can no longer be read are skipped, like
+    /// [`Wal::enumerate_records`].
     pub fn enumerate_records<K: Hash + AsRef<[u8]> + Display>(
         &self,
         key: K,
     ) -> Result<impl Iterator<Item = Bytes>> {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        key.as_ref().hash(&mut hasher);
-        let key_hash = hasher.finish();
-
+        let key_hash = self.hasher.hash_key(key.as_ref());
         let mut records = Vec::new();
 
-        let key_str = format!("{}", key);
-        let sanitized_key = key_str
-            .chars()
-            .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
-            .take(20)
-            .collect::<String>();
-
-        if let Ok(entries) = fs::read_dir(&self.dir) {
-            let mut segment_files = Vec::new();
-
-            for entry in entries.flatten() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if filename.starts_with(&format!("{}-{}-", sanitized_key, key_hash))
-                        && filename.ends_with(".log")
-                    {
-                        if let Some((_, sequence)) = self.parse_filename(filename) {
-                            segment_files.push((sequence, entry.path()));
-                        }
-                    }
-                }
-            }
-
-            segment_files.sort_by_key(|(seq, _)| *seq);
-
-            for (_, file_path) in segment_files {
-                if let Ok(file_records) = self.read_records_from_segment(&file_path) {
-                    records.extend(file_records);
+        for segment in self
+            .segments
+            .iter()
+            .filter(|segment| segment.key_hash == key_hash)
+        {
+            let Ok(mut file) = SegmentFile::open(&*self.vfs, &segment.path, self.signatures) else {
+                continue;
+            };
+            let header = match parse_segment_header(&mut file, self.signatures) {
+                Ok(header) if header.key == key.as_ref() => header,
+                _ => continue,
+            };
+            let Ok(cipher) =
+                KeyCipher::for_segment(&header, segment.sequence_number, self.cipher.as_ref())
+            else {
+                continue;
+            };
+            let mut cursor = SegmentCursor::new(file, self.signatures, header.framing, cipher)?
+                .limit(segment.file_len);
+            while cursor.next_frame(false).is_some() {
+                match cursor.read_content() {
+                    Some(content) => records.push(content),
+                    None => break,
                 }
             }
         }
@@ -679,237 +10463,513 @@ impl Wal {
         Ok(records.into_iter())
     }
 
-    /// Reads all records from a segment file.
-    fn read_records_from_segment(&self, file_path: &Path) -> Result<Vec<Bytes>> {
-        let mut file = File::open(file_path)?;
-        let mut records = Vec::new();
-
-        self.skip_file_header(&mut file)?;
-
-        loop {
-            let mut signature_buf = [0u8; 6];
-            match file.read_exact(&mut signature_buf) {
-                Ok(_) => {
-                    if signature_buf != NANO_REC_SIGNATURE {
-                        break;
-                    }
-                }
-                Err(_) => break,
-            }
+    /// Reads the entry at `entry_ref` as of the snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if the segment is not in the snapshot.
+    /// Returns `WalError::InvalidOffset` if the entry was written after the
+    /// snapshot was taken.
+    /// Returns `WalError::CorruptedData` if signature is invalid.
+    pub fn read_entry_at(&self, entry_ref: EntryRef) -> Result<Bytes> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| {
+                segment.key_hash == entry_ref.key_hash
+                    && segment.sequence_number == entry_ref.sequence_number
+            })
+            .ok_or_else(|| {
+                WalError::EntryNotFound(format!(
+                    "Segment for key_hash {} sequence {} not in snapshot",
+                    entry_ref.key_hash, entry_ref.sequence_number
+                ))
+            })?;
 
-            let mut header_len_bytes = [0u8; 2];
-            if file.read_exact(&mut header_len_bytes).is_err() {
-                break;
-            }
-            let header_len = u16::from_le_bytes(header_len_bytes);
+        read_entry_from_file(
+            SegmentFile::open(&*self.vfs, &segment.path, self.signatures)?,
+            entry_ref.sequence_number,
+            entry_ref.offset,
+            segment.file_len,
+            self.signatures,
+            self.cipher.as_ref(),
+        )
+    }
+}
 
-            if file.seek(SeekFrom::Current(header_len as i64)).is_err() {
-                break;
-            }
+/// Sequential reader over the records of one key, created by
+/// [`Wal::reader_for`].
+///
+/// Like [`Wal::enumerate_records`], a segment's records end at its first
+/// damaged or incomplete frame, after which reading continues with the
+/// next segment.
+#[derive(Debug)]
+pub struct RecordReader {
+    vfs: Arc<dyn Vfs>,
+    key_hash: u64,
+    signatures: Signatures,
+    cipher: Option<Arc<dyn RecordCipher>>,
+    metrics: Arc<WalCounters>,
+    /// Segments of the key in sequence order
+    segments: Vec<(u64, PathBuf)>,
+    /// Index into `segments` of the segment being read
+    index: usize,
+    /// Cursor over `segments[index]`, opened on first use
+    cursor: Option<SegmentCursor>,
+}
 
-            let mut content_len_bytes = [0u8; 8];
-            if file.read_exact(&mut content_len_bytes).is_err() {
-                break;
+impl RecordReader {
+    /// Reads the next record, moving on to the next segment as needed.
+    ///
+    /// Returns `None` once every segment has been read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a segment cannot be opened.
+    // Fallible, so it cannot be `Iterator::next`
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Bytes>> {
+        while self.index < self.segments.len() {
+            let cursor = match self.cursor.as_mut() {
+                Some(cursor) => cursor,
+                None => self.open_current()?,
+            };
+            if cursor.next_frame(false).is_some() {
+                if let Some(content) = cursor.read_content() {
+                    return Ok(Some(content));
+                }
             }
-            let content_len = u64::from_le_bytes(content_len_bytes);
+            self.index += 1;
+            self.cursor = None;
+        }
+        Ok(None)
+    }
 
-            let mut content = vec![0u8; content_len as usize];
-            if file.read_exact(&mut content).is_err() {
-                break;
-            }
+    /// Positions the reader so that [`RecordReader::next`] returns the
+    /// record at `entry_ref`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if `entry_ref` does not belong to
+    /// one of the reader's segments.
+    /// Returns `WalError::Io` if the segment cannot be opened.
+    pub fn seek_to(&mut self, entry_ref: EntryRef) -> Result<()> {
+        let index = self
+            .segments
+            .iter()
+            .position(|(sequence, _)| *sequence == entry_ref.sequence_number)
+            .filter(|_| entry_ref.key_hash == self.key_hash)
+            .ok_or_else(|| {
+                WalError::EntryNotFound(format!(
+                    "Segment for key_hash {} sequence {} not in reader",
+                    entry_ref.key_hash, entry_ref.sequence_number
+                ))
+            })?;
 
-            records.push(Bytes::from(content));
+        if index != self.index || self.cursor.is_none() {
+            self.index = index;
+            self.open_current()?;
         }
-
-        Ok(records)
+        self.cursor.as_mut().unwrap().seek(entry_ref.offset)
     }
 
-    /// Skips file header to position at first record.
-    fn skip_file_header(&self, file: &mut File) -> Result<()> {
-        file.seek(SeekFrom::Current(24))?; // Skip signature, sequence, expiration
+    /// Reads the record at `entry_ref`, leaving the reader positioned after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::EntryNotFound` if `entry_ref` does not belong to
+    /// one of the reader's segments.
+    /// Returns `WalError::InvalidOffset` if the offset is past the end of
+    /// the segment.
+    /// Returns `WalError::CorruptedData` if no intact record starts at the
+    /// offset.
+    pub fn read_at(&mut self, entry_ref: EntryRef) -> Result<Bytes> {
+        self.seek_to(entry_ref)?;
+        let cursor = self.cursor.as_mut().unwrap();
+        let segment_len = cursor.body_len();
+        if entry_ref.offset >= segment_len {
+            return Err(WalError::InvalidOffset {
+                offset: entry_ref.offset,
+                segment_len,
+            });
+        }
 
-        let mut key_len_bytes = [0u8; 8];
-        file.read_exact(&mut key_len_bytes)?;
-        let key_len = u64::from_le_bytes(key_len_bytes);
-        file.seek(SeekFrom::Current(key_len as i64))?;
+        cursor
+            .next_frame(false)
+            .filter(|frame| frame.offset == entry_ref.offset)
+            .and_then(|_| cursor.read_content())
+            .ok_or_else(|| {
+                WalError::CorruptedData(format!("No intact record at offset {}", entry_ref.offset))
+            })
+    }
 
-        Ok(())
+    /// Opens the cursor for the segment at `index`.
+    fn open_current(&mut self) -> Result<&mut SegmentCursor> {
+        let (sequence, file_path) = &self.segments[self.index];
+        let cursor = open_cursor(
+            SegmentFile::open(&*self.vfs, file_path, self.signatures)?,
+            *sequence,
+            self.signatures,
+            self.cipher.as_ref(),
+        )?
+        .counted(&self.metrics);
+        Ok(self.cursor.insert(cursor))
     }
+}
 
-    /// Reads entry at specified location.
-    ///
-    /// # Arguments
+/// Raw record reader over one segment file, created by
+/// [`Wal::open_segment`].
+#[derive(Debug)]
+pub struct SegmentHandle {
+    key_hash: u64,
+    sequence_number: u64,
+    /// Key bytes stored in the segment header
+    key: Bytes,
+    /// Unix timestamp when the segment expires
+    expiration_timestamp: u64,
+    cursor: SegmentCursor,
+}
+
+impl SegmentHandle {
+    /// Reads the next record as `(offset, header, content)`.
     ///
-    /// * `entry_ref` - Reference to the entry location
+    /// The offset is relative to the end of the file header, as in
+    /// [`EntryRef::offset`]. A record appended without a header has an
+    /// empty one. Returns `None` at the end of the frame chain.
     ///
     /// # Errors
     ///
-    /// Returns `WalError::EntryNotFound` if segment doesn't exist.
-    /// Returns `WalError::CorruptedData` if signature is invalid.
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # use bytes::Bytes;
-    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// # let entry_ref = wal.append_entry("key", None, Bytes::from("data"), true)?;
-    /// let data = wal.read_entry_at(entry_ref)?;
-    /// # Ok::<(), nano_wal::WalError>(())
-    /// ```
-    pub fn read_entry_at(&self, entry_ref: EntryRef) -> Result<Bytes> {
-        if let Ok(entries) = fs::read_dir(&self.dir) {
-            for entry in entries.flatten() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if let Some((key_hash, sequence)) = self.parse_filename(filename) {
-                        if key_hash == entry_ref.key_hash && sequence == entry_ref.sequence_number {
-                            let file_path = entry.path();
-                            return self.read_entry_from_file(&file_path, entry_ref.offset);
-                        }
-                    }
-                }
-            }
+    /// Returns `WalError::Io` if the segment cannot be read.
+    /// Returns `WalError::CorruptedData` if a record fails to decrypt.
+    // Fallible, so it cannot be `Iterator::next`
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<(u64, Bytes, Bytes)>> {
+        let Some(frame) = self.cursor.try_next_frame(true)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .cursor
+            .try_read_content()?
+            .map(|content| (frame.offset, frame.header.unwrap_or_default(), content)))
+    }
+
+    /// Returns the location of the record at `offset` in this segment.
+    pub fn entry_ref(&self, offset: u64) -> EntryRef {
+        EntryRef {
+            key_hash: self.key_hash,
+            sequence_number: self.sequence_number,
+            offset,
         }
+    }
 
-        Err(WalError::EntryNotFound(format!(
-            "Segment for key_hash {} sequence {} not found",
-            entry_ref.key_hash, entry_ref.sequence_number
-        )))
+    /// Returns the key stored in the segment header.
+    pub fn key(&self) -> &[u8] {
+        &self.key
     }
 
-    /// Reads specific entry from segment file.
-    fn read_entry_from_file(&self, file_path: &Path, offset: u64) -> Result<Bytes> {
-        let mut file = File::open(file_path)?;
+    /// Returns the Unix timestamp when the segment expires.
+    pub fn expiration_timestamp(&self) -> u64 {
+        self.expiration_timestamp
+    }
 
-        self.skip_file_header(&mut file)?;
-        file.seek(SeekFrom::Current(offset as i64))?;
+    /// Returns the hash of the segment's key.
+    pub fn key_hash(&self) -> u64 {
+        self.key_hash
+    }
 
-        let mut signature_buf = [0u8; 6];
-        file.read_exact(&mut signature_buf)?;
-        if signature_buf != NANO_REC_SIGNATURE {
-            return Err(WalError::CorruptedData(
-                "NANORC signature not found".to_string(),
-            ));
-        }
+    /// Returns the sequence number of the segment.
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+}
 
-        let mut header_len_bytes = [0u8; 2];
-        file.read_exact(&mut header_len_bytes)?;
-        let header_len = u16::from_le_bytes(header_len_bytes);
+/// Records staged in memory and written together, created by
+/// [`Wal::begin_batch`].
+///
+/// Records are grouped by key and hold on to their content buffers rather
+/// than copying them. Nothing is written until [`WriteBatch::commit`];
+/// dropping the batch discards it.
+#[derive(Debug)]
+pub struct WriteBatch<'a> {
+    wal: &'a mut Wal,
+    /// Records of each key, in order of first use
+    groups: Vec<BatchGroup>,
+    /// Map from key to its index in `groups`
+    group_index: HashMap<Vec<u8>, usize>,
+    /// Group index and index within the group of each record, in add order
+    records: Vec<(usize, usize)>,
+}
 
-        file.seek(SeekFrom::Current(header_len as i64))?;
+/// Records of one key in a [`WriteBatch`].
+#[derive(Debug)]
+struct BatchGroup {
+    key: BatchKey,
+    /// Header and content of each record
+    records: Vec<(Option<Bytes>, Bytes)>,
+}
 
-        let mut content_len_bytes = [0u8; 8];
-        file.read_exact(&mut content_len_bytes)?;
-        let content_len = u64::from_le_bytes(content_len_bytes);
+/// Owned copy of a batch key, keeping both its bytes and its display form.
+#[derive(Debug, Hash)]
+struct BatchKey {
+    bytes: Vec<u8>,
+    name: String,
+}
 
-        let mut content = vec![0u8; content_len as usize];
-        file.read_exact(&mut content)?;
+impl AsRef<[u8]> for BatchKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
 
-        Ok(Bytes::from(content))
+impl Display for BatchKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
     }
+}
 
-    /// Removes expired segments from disk.
+impl WriteBatch<'_> {
+    /// Stages a record for `key`.
     ///
     /// # Errors
     ///
-    /// Returns `WalError::Io` for filesystem errors.
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// wal.compact()?;
-    /// # Ok::<(), nano_wal::WalError>(())
-    /// ```
-    pub fn compact(&mut self) -> Result<()> {
-        let now = Utc::now().timestamp() as u64;
+    /// Returns `WalError::HeaderTooLarge` if the header exceeds the
+    /// configured `max_header_size`; the record is not staged.
+    pub fn add<K: Hash + AsRef<[u8]> + Display>(
+        &mut self,
+        key: K,
+        header: Option<Bytes>,
+        content: Bytes,
+    ) -> Result<()> {
+        self.wal.check_header_size(header.as_deref())?;
+        self.wal.check_content_len(content.len() as u64)?;
 
-        if let Ok(entries) = fs::read_dir(&self.dir) {
-            for entry in entries.flatten() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if filename.ends_with(".log") {
-                        let file_path = entry.path();
-
-                        if let Ok(mut file) = File::open(&file_path) {
-                            let mut signature = [0u8; 8];
-                            if file.read_exact(&mut signature).is_ok()
-                                && signature == NANO_LOG_SIGNATURE
-                            {
-                                let mut sequence_bytes = [0u8; 8];
-                                let mut expiration_bytes = [0u8; 8];
-
-                                if file.read_exact(&mut sequence_bytes).is_ok()
-                                    && file.read_exact(&mut expiration_bytes).is_ok()
-                                {
-                                    let expiration_timestamp = u64::from_le_bytes(expiration_bytes);
-
-                                    if now > expiration_timestamp {
-                                        let _ = fs::remove_file(&file_path);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        let index = match self.group_index.get(key.as_ref()) {
+            Some(index) => *index,
+            None => {
+                let bytes = key.as_ref().to_vec();
+                self.group_index.insert(bytes.clone(), self.groups.len());
+                self.groups.push(BatchGroup {
+                    key: BatchKey {
+                        bytes,
+                        name: key.to_string(),
+                    },
+                    records: Vec::new(),
+                });
+                self.groups.len() - 1
             }
-        }
+        };
 
+        let group = &mut self.groups[index];
+        self.records.push((index, group.records.len()));
+        group.records.push((header, content));
         Ok(())
     }
 
-    /// Syncs all active segments to disk.
+    /// Returns the number of staged records.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if no records are staged.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Writes the staged records and flushes the touched segments.
     ///
-    /// # Errors
+    /// Each key's records go to its active segment, rotating it first if it
+    /// has expired, in one vectored write. With `durable` set, each touched
+    /// segment is then synced once.
     ///
-    /// Returns `WalError::Io` if sync fails.
+    /// # Returns
     ///
-    /// # Examples
+    /// The entry references of the records, in the order they were added.
     ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// wal.sync()?;
-    /// # Ok::<(), nano_wal::WalError>(())
-    /// ```
-    pub fn sync(&mut self) -> Result<()> {
-        for active_segment in self.active_segments.values_mut() {
-            active_segment.file.sync_data()?;
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if a write, flush or sync fails. Keys written
+    /// before the failure keep their records, as with
+    /// [`Wal::append_batch`], but no references are returned.
+    pub fn commit(self, durable: bool) -> Result<Vec<EntryRef>> {
+        let signatures = self.wal.signatures();
+        let framing = self.wal.framing();
+        let alignment = self.wal.options.alignment as u64;
+        let mut placed = Vec::with_capacity(self.groups.len());
+
+        for group in &self.groups {
+            let key_hash = self.wal.get_or_create_active_segment(&group.key)?;
+            if let Err(e) = self.wal.align_active(key_hash) {
+                self.wal.discard_unfinished_frame(key_hash)?;
+                return Err(e.into());
+            }
+            let active_segment = self.wal.active_segments.get_mut(&key_hash).unwrap();
+            self.wal.write_clock += 1;
+            active_segment.last_used = self.wal.write_clock;
+            active_segment.unsynced = true;
+
+            // Frame prefix and content of each record, alternating
+            let mut pieces = Vec::with_capacity(group.records.len() * 2);
+            let mut offsets = Vec::with_capacity(group.records.len());
+            let mut offset = active_segment.body_len;
+            for (header, content) in &group.records {
+                let content = match &active_segment.cipher {
+                    Some(cipher) => Bytes::from(cipher.encrypt(offset, content)),
+                    None => content.clone(),
+                };
+                let mut prefix = Vec::new();
+                let frame_len = write_frame_prefix(
+                    &mut prefix,
+                    &signatures.record,
+                    framing,
+                    header.as_deref(),
+                    content.len() as u64,
+                )?;
+                pieces.push(Bytes::from(prefix));
+                pieces.push(content);
+                offsets.push(offset);
+                offset += frame_len;
+
+                let padding = encode_padding(
+                    signatures,
+                    framing,
+                    active_segment.body_start + offset,
+                    alignment,
+                );
+                offset += padding.len() as u64;
+                pieces.push(Bytes::from(padding));
+            }
+
+            let mut bufs: Vec<IoSlice<'_>> = pieces.iter().map(|p| IoSlice::new(p)).collect();
+            if let Err(e) = write_all_vectored(&mut active_segment.writer, &mut bufs) {
+                self.wal.discard_unfinished_frame(key_hash)?;
+                return Err(e.into());
+            }
+            let metrics = &self.wal.metrics;
+            WalCounters::add(&metrics.appends, group.records.len() as u64);
+            WalCounters::add(&metrics.bytes_written, offset - active_segment.body_len);
+            active_segment.body_len = offset;
+            active_segment.last_record = offsets.last().copied().or(active_segment.last_record);
+            placed.push((key_hash, active_segment.sequence_number, offsets));
         }
-        Ok(())
+
+        let mut finished = HashSet::new();
+        for (key_hash, _, _) in &placed {
+            if finished.insert(*key_hash) {
+                self.wal.finish_append(*key_hash, durable)?;
+            }
+        }
+        self.wal.finish_write()?;
+
+        let refs: Vec<EntryRef> = self
+            .records
+            .iter()
+            .map(|(group, index)| {
+                let (key_hash, sequence_number, offsets) = &placed[*group];
+                EntryRef {
+                    key_hash: *key_hash,
+                    sequence_number: *sequence_number,
+                    offset: offsets[*index],
+                }
+            })
+            .collect();
+        let contents =
+            (self.records.iter()).map(|(group, index)| &self.groups[*group].records[*index].1[..]);
+        self.wal.index_contents(refs.iter().copied().zip(contents));
+        Ok(refs)
     }
+}
 
-    /// Returns count of active segments.
+/// AES-256-GCM [`RecordCipher`], available with the `encryption` feature.
+///
+/// Each record is encrypted under a fresh random 96-bit nonce, stored in
+/// front of the ciphertext and followed by the 16-byte authentication tag,
+/// so encryption adds 28 bytes to every record's content. Random nonces
+/// stay safe for about 2^32 records per key.
+#[cfg(feature = "encryption")]
+pub struct Aes256GcmCipher {
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "encryption")]
+impl Aes256GcmCipher {
+    /// Length of the nonce stored in front of each ciphertext.
+    const NONCE_LEN: usize = 12;
+
+    /// Longest plaintext AES-GCM encrypts under one nonce, 64 GiB.
+    const MAX_PLAINTEXT_LEN: u64 = 1 << 36;
+
+    /// Creates a cipher from a 256-bit key.
     ///
     /// # Examples
     ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # let wal = Wal::new("./wal", WalOptions::default())?;
-    /// println!("Active segments: {}", wal.active_segment_count());
-    /// # Ok::<(), nano_wal::WalError>(())
     /// ```
-    pub fn active_segment_count(&self) -> usize {
-        self.active_segments.len()
-    }
-
-    /// Shuts down WAL and removes all storage.
-    ///
-    /// # Errors
+    /// use nano_wal::{Aes256GcmCipher, WalOptions};
     ///
-    /// Returns `WalError::Io` if removal fails.
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// # use nano_wal::{Wal, WalOptions};
-    /// # let mut wal = Wal::new("./wal", WalOptions::default())?;
-    /// wal.shutdown()?;
-    /// # Ok::<(), nano_wal::WalError>(())
+    /// let key = [7u8; 32];
+    /// let options = WalOptions::default().encryption(Box::new(Aes256GcmCipher::new(&key)));
     /// ```
-    pub fn shutdown(&mut self) -> Result<()> {
-        self.active_segments.clear();
-        fs::remove_dir_all(&self.dir)?;
-        Ok(())
+    pub fn new(key: &[u8; 32]) -> Self {
+        use aes_gcm::KeyInit;
+
+        Self {
+            cipher: aes_gcm::Aes256Gcm::new(key.into()),
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Debug for Aes256GcmCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aes256GcmCipher").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl RecordCipher for Aes256GcmCipher {
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("content length is checked against max_plaintext_len");
+
+        let mut sealed = Vec::with_capacity(Self::NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        use aes_gcm::aead::{Aead, Payload};
+
+        if ciphertext.len() < Self::NONCE_LEN {
+            return Err(WalError::CorruptedData(
+                "Encrypted record is shorter than its nonce".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(Self::NONCE_LEN);
+        self.cipher
+            .decrypt(
+                nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| {
+                WalError::CorruptedData("Encrypted record failed authentication".to_string())
+            })
+    }
+
+    fn max_plaintext_len(&self) -> u64 {
+        Self::MAX_PLAINTEXT_LEN
     }
 }
@@ -1,6 +1,9 @@
 use bytes::Bytes;
-use nano_wal::{Wal, WalOptions};
+use nano_wal::{
+    EntryRef, ManualClock, RotationPolicy, SegmentInfo, Wal, WalError, WalOptions, RECORD_OVERHEAD,
+};
 use std::fs;
+use std::sync::{Arc, Mutex};
 
 use std::thread;
 use std::time::Duration;
@@ -11,32 +14,38 @@ fn test_segment_rotation_time_based() {
     let temp_dir = TempDir::new().unwrap();
     let wal_dir = temp_dir.path().to_str().unwrap();
 
+    // 10 second retention over 10 segments rotates every second
+    let clock = Arc::new(ManualClock::new(1_000));
     let mut wal = Wal::new(
         wal_dir,
-        WalOptions {
-            entry_retention: Duration::from_secs(10),
-            segments_per_retention_period: 10,
-        },
+        WalOptions::default()
+            .retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
     )
     .unwrap();
 
-    // Write some entries to trigger size-based rotation
-    let _ref1 = wal
-        .append_entry(
-            "key1",
-            None,
-            Bytes::from("data1 with enough content to trigger rotation when combined"),
-            false,
-        )
+    let ref1 = wal
+        .append_entry("key1", None, Bytes::from("data1"), false)
         .unwrap();
+    assert_eq!(wal.segment_expiration("key1").unwrap(), Some(1_001));
 
-    let _ref2 = wal
-        .append_entry("key2", None, Bytes::from("data2"), false)
+    // Still inside the segment window
+    let ref2 = wal
+        .append_entry("key1", None, Bytes::from("data2"), false)
         .unwrap();
+    assert_eq!(ref2.sequence_number, ref1.sequence_number);
 
-    // Should have created segment files for different keys
-    let entries = fs::read_dir(wal_dir).unwrap();
-    let log_files: Vec<_> = entries
+    // Exactly at the expiration boundary the segment rotates
+    clock.advance(Duration::from_secs(1));
+    let ref3 = wal
+        .append_entry("key1", None, Bytes::from("data3"), false)
+        .unwrap();
+    assert_eq!(ref3.sequence_number, ref1.sequence_number + 1);
+    assert_eq!(wal.segment_expiration("key1").unwrap(), Some(1_002));
+
+    let log_files = fs::read_dir(wal_dir)
+        .unwrap()
         .filter_map(|e| e.ok())
         .filter(|e| {
             e.file_name()
@@ -44,9 +53,11 @@ fn test_segment_rotation_time_based() {
                 .map(|s| s.ends_with(".log"))
                 .unwrap_or(false)
         })
-        .collect();
+        .count();
+    assert_eq!(log_files, 2);
 
-    assert!(!log_files.is_empty(), "Should have at least one .log file");
+    let records: Vec<Bytes> = wal.enumerate_records("key1").unwrap().collect();
+    assert_eq!(records.len(), 3);
 
     wal.shutdown().unwrap();
 }
@@ -56,11 +67,14 @@ fn test_compaction() {
     let temp_dir = TempDir::new().unwrap();
     let wal_dir = temp_dir.path().to_str().unwrap();
 
+    let clock = Arc::new(ManualClock::new(1_000));
     let mut wal = Wal::new(
         wal_dir,
         WalOptions {
-            entry_retention: Duration::from_secs(5),
+            entry_retention: Duration::from_secs(10),
             segments_per_retention_period: 10,
+            clock: clock.clone(),
+            ..Default::default()
         },
     )
     .unwrap();
@@ -68,39 +82,24 @@ fn test_compaction() {
     let _ref1 = wal
         .append_entry("key1", None, Bytes::from("data1"), true)
         .unwrap();
-    thread::sleep(Duration::from_secs(3));
+    clock.advance(Duration::from_secs(3));
     let _ref2 = wal
         .append_entry("key2", None, Bytes::from("data2"), true)
         .unwrap();
 
-    // Count files before compaction
-    let _files_before = fs::read_dir(wal_dir)
-        .unwrap()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.file_name()
-                .to_str()
-                .map(|s| s.ends_with(".log"))
-                .unwrap_or(false)
-        })
-        .count();
-
+    // key1 expired at 1_001; key2 expires at 1_004
     wal.compact().unwrap();
+    assert_eq!(wal.enumerate_records("key1").unwrap().count(), 0);
+    assert_eq!(wal.enumerate_records("key2").unwrap().count(), 1);
 
-    // Files should still exist or be manageable
-    let files_after = fs::read_dir(wal_dir)
-        .unwrap()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.file_name()
-                .to_str()
-                .map(|s| s.ends_with(".log"))
-                .unwrap_or(false)
-        })
-        .count();
+    // Nothing is removed until the clock passes the expiration
+    clock.set(1_004);
+    wal.compact().unwrap();
+    assert_eq!(wal.enumerate_records("key2").unwrap().count(), 1);
 
-    // Compaction should have run without error
-    assert!(files_after < 100); // Just check it's reasonable
+    clock.advance(Duration::from_secs(1));
+    wal.compact().unwrap();
+    assert_eq!(wal.enumerate_records("key2").unwrap().count(), 0);
 
     wal.shutdown().unwrap();
 }
@@ -179,6 +178,7 @@ fn test_error_handling_invalid_config() {
         WalOptions {
             entry_retention: Duration::from_secs(0), // Invalid
             segments_per_retention_period: 10,
+            ..Default::default()
         },
     );
     assert!(result.is_err());
@@ -189,9 +189,45 @@ fn test_error_handling_invalid_config() {
         WalOptions {
             entry_retention: Duration::from_secs(60 * 60 * 24), // 1 day
             segments_per_retention_period: 0,                   // Invalid
+            ..Default::default()
         },
     );
     assert!(result.is_err());
+
+    // Header limit above the escaped u32 length field
+    let result = Wal::new(
+        wal_dir,
+        WalOptions::default().max_header_size(u32::MAX as usize + 1),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_sub_second_segments_are_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    // 100s split into 168 segments would rotate on every append
+    let options =
+        WalOptions::with_retention(Duration::from_secs(100)).segments_per_retention_period(168);
+    match Wal::new(wal_dir, options) {
+        Err(WalError::InvalidConfig(msg)) => assert!(msg.contains("at most 100")),
+        other => panic!("expected InvalidConfig, got {:?}", other.map(|_| ())),
+    }
+
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(100)
+        .clock(Arc::new(ManualClock::new(1_000)));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert!(matches!(
+        wal.set_key_retention("metrics", Duration::from_secs(50)),
+        Err(WalError::InvalidConfig(_))
+    ));
+    for i in 0..3 {
+        wal.append_entry("orders", None, Bytes::from(format!("{}", i)), false)
+            .unwrap();
+    }
+    assert_eq!(wal.stats().unwrap().total_segments, 1);
 }
 
 #[test]
@@ -202,7 +238,7 @@ fn test_special_characters_in_keys() {
     let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
 
     // Test various key formats
-    let test_keys = vec![
+    let test_keys = [
         "simple_key",
         "key-with-dashes",
         "key.with.dots",
@@ -260,6 +296,46 @@ fn test_empty_and_large_content() {
     wal.shutdown().unwrap();
 }
 
+#[test]
+fn test_reject_empty_content() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let options = WalOptions::default().reject_empty_content(true);
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    fn rejected<T>(result: Result<T, WalError>) -> bool {
+        matches!(result, Err(WalError::InvalidConfig(msg)) if msg == "empty content rejected")
+    }
+    assert!(rejected(wal.append_entry("key", None, Bytes::new(), true)));
+    assert!(rejected(wal.append_entry(
+        "key",
+        Some(Bytes::from("h")),
+        Bytes::new(),
+        true
+    )));
+    assert!(rejected(wal.append_stream("key", None, &b""[..], 0, true)));
+    assert!(rejected(wal.append_group(
+        "key",
+        &[(None, Bytes::from("x")), (None, Bytes::new())],
+        true
+    )));
+    let mut batch = wal.begin_batch();
+    assert!(rejected(batch.add("key", None, Bytes::new())));
+    drop(batch);
+    let err = wal
+        .append_batch(vec![("key", None, Bytes::new())], true)
+        .unwrap_err();
+    assert_eq!(err.failed_index, 0);
+    assert!(wal.enumerate_records("key").unwrap().next().is_none());
+
+    // Empty headers are still fine
+    let entry_ref = wal
+        .append_entry("key", Some(Bytes::new()), Bytes::from("x"), true)
+        .unwrap();
+    wal.append_tombstone("key", entry_ref, true).unwrap();
+}
+
 #[test]
 fn test_wal_options_builder_methods() {
     let temp_dir = TempDir::new().unwrap();
@@ -274,6 +350,7 @@ fn test_wal_options_builder_methods() {
     assert_eq!(wal.active_segment_count(), 0);
 
     // Drop wal to free the directory
+    drop(wal);
 
     // Test with_segments_per_retention_period method
     let options = WalOptions::with_segments_per_retention_period(20);
@@ -283,7 +360,8 @@ fn test_wal_options_builder_methods() {
         Duration::from_secs(60 * 60 * 24 * 7)
     ); // Default
 
-    let mut wal = Wal::new(wal_dir, options).unwrap();
+    // The directory was created with other options
+    let mut wal = Wal::new(wal_dir, options.allow_option_change(true)).unwrap();
     wal.shutdown().unwrap();
 }
 
@@ -295,8 +373,9 @@ fn test_segment_id_progression() {
     let mut wal = Wal::new(
         wal_dir,
         WalOptions {
-            entry_retention: Duration::from_secs(6),
+            entry_retention: Duration::from_secs(60),
             segments_per_retention_period: 10,
+            ..Default::default()
         },
     )
     .unwrap();
@@ -329,3 +408,1269 @@ fn test_segment_id_progression() {
 
     wal.shutdown().unwrap();
 }
+
+#[test]
+fn test_compact_and_merge_consolidates_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let source_dir = TempDir::new().unwrap();
+
+    // Build an archive to merge in, which adds one segment per import
+    let mut source = Wal::new(source_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    source
+        .append_entry(
+            "metrics",
+            Some(Bytes::from("h")),
+            Bytes::from("imported"),
+            true,
+        )
+        .unwrap();
+    let mut archive = Vec::new();
+    source.export_key("metrics", &mut archive).unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("metrics", None, Bytes::from("local"), true)
+        .unwrap();
+    wal.import_key(archive.as_slice(), true).unwrap();
+    wal.import_key(archive.as_slice(), true).unwrap();
+    wal.append_entry("other", None, Bytes::from("untouched"), true)
+        .unwrap();
+
+    let count_segments = |prefix: &str| {
+        fs::read_dir(wal_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.starts_with(prefix) && name.ends_with(".log")
+            })
+            .count()
+    };
+    assert_eq!(count_segments("metrics-"), 3);
+
+    let before: Vec<Bytes> = wal.enumerate_records("metrics").unwrap().collect();
+    assert_eq!(wal.compact_and_merge("metrics").unwrap(), 3);
+    assert_eq!(count_segments("metrics-"), 1);
+    assert_eq!(count_segments("other-"), 1);
+
+    let after: Vec<Bytes> = wal.enumerate_records("metrics").unwrap().collect();
+    assert_eq!(after, before);
+
+    // Appends continue after the merged segment
+    wal.append_entry("metrics", None, Bytes::from("next"), true)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("metrics").unwrap().collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3], Bytes::from("next"));
+
+    // Nothing left to merge for a single-segment key
+    assert_eq!(wal.compact_and_merge("other").unwrap(), 0);
+
+    wal.shutdown().unwrap();
+}
+
+#[test]
+fn test_per_key_retention_override() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(
+        wal_dir,
+        WalOptions::default()
+            .retention(Duration::from_secs(1000))
+            .segments_per_retention_period(10),
+    )
+    .unwrap();
+
+    assert!(wal.set_key_retention("metrics", Duration::ZERO).is_err());
+    wal.set_key_retention("metrics", Duration::from_secs(100_000))
+        .unwrap();
+
+    wal.append_entry("events", None, Bytes::from("e"), true)
+        .unwrap();
+    wal.append_entry("metrics", None, Bytes::from("m"), true)
+        .unwrap();
+
+    let events_expiration = wal.segment_expiration("events").unwrap().unwrap();
+    let metrics_expiration = wal.segment_expiration("metrics").unwrap().unwrap();
+
+    // Default segments last 100s, overridden ones 10_000s
+    let diff = metrics_expiration - events_expiration;
+    assert!((9_899..=10_001).contains(&diff), "diff was {}", diff);
+
+    wal.shutdown().unwrap();
+}
+
+#[test]
+fn test_freeze_snapshot_ignores_later_appends() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        wal_dir,
+        WalOptions::default()
+            .retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+
+    let first = wal
+        .append_entry("orders", None, Bytes::from("one"), false)
+        .unwrap();
+    wal.append_entry("orders", None, Bytes::from("two"), false)
+        .unwrap();
+
+    let snapshot = wal.freeze().unwrap();
+
+    // Same segment, a new segment after rotation, and a new key
+    let same_segment = wal
+        .append_entry("orders", None, Bytes::from("three"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    let next_segment = wal
+        .append_entry("orders", None, Bytes::from("four"), false)
+        .unwrap();
+    wal.append_entry("users", None, Bytes::from("alice"), false)
+        .unwrap();
+
+    let records: Vec<Bytes> = snapshot.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("one"), Bytes::from("two")]);
+    assert_eq!(snapshot.enumerate_records("users").unwrap().count(), 0);
+    assert_eq!(wal.enumerate_records("orders").unwrap().count(), 4);
+
+    assert_eq!(snapshot.read_entry_at(first).unwrap(), Bytes::from("one"));
+    assert!(matches!(
+        snapshot.read_entry_at(same_segment),
+        Err(WalError::InvalidOffset { .. })
+    ));
+    assert!(matches!(
+        snapshot.read_entry_at(next_segment),
+        Err(WalError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn test_prune_to_segment_count() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        wal_dir,
+        WalOptions::default()
+            .retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+
+    // Five segments for "events", one for "other"
+    for i in 0..5 {
+        wal.append_entry("events", None, Bytes::from(format!("event {}", i)), false)
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+    }
+    clock.set(1_004);
+    wal.append_entry("other", None, Bytes::from("other"), false)
+        .unwrap();
+
+    assert_eq!(wal.prune_to_segment_count(2).unwrap(), 3);
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(
+        records,
+        vec![Bytes::from("event 3"), Bytes::from("event 4")]
+    );
+    assert_eq!(wal.enumerate_records("other").unwrap().count(), 1);
+
+    // The active segments survive even a limit of zero
+    assert_eq!(wal.prune_to_segment_count(0).unwrap(), 1);
+    let entry_ref = wal
+        .append_entry("events", None, Bytes::from("event 5"), false)
+        .unwrap();
+    assert_eq!(
+        wal.read_entry_at(entry_ref).unwrap(),
+        Bytes::from("event 5")
+    );
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(
+        records,
+        vec![Bytes::from("event 4"), Bytes::from("event 5")]
+    );
+    assert_eq!(wal.enumerate_records("other").unwrap().count(), 1);
+}
+
+#[test]
+fn test_stats_summarizes_wal() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        wal_dir,
+        WalOptions::default()
+            .retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+
+    let stats = wal.stats().unwrap();
+    assert_eq!(stats.total_segments, 0);
+    assert_eq!(stats.oldest_expiration, None);
+
+    wal.append_entry("a", None, Bytes::from("1"), false)
+        .unwrap();
+    wal.append_entry("a", Some(Bytes::from("h")), Bytes::from("2"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(5));
+    wal.append_entry("a", None, Bytes::from("3"), false)
+        .unwrap();
+    wal.append_entry("b", None, Bytes::from("4"), false)
+        .unwrap();
+
+    let total_bytes: u64 = fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
+        .map(|e| e.metadata().unwrap().len())
+        .sum();
+
+    let stats = wal.stats().unwrap();
+    assert_eq!(stats.key_count, 2);
+    assert_eq!(stats.total_segments, 3);
+    assert_eq!(stats.active_segments, 2);
+    assert_eq!(stats.total_records, 4);
+    assert_eq!(stats.total_bytes, total_bytes);
+    assert_eq!(stats.oldest_expiration, Some(1_001));
+    assert_eq!(stats.newest_expiration, Some(1_006));
+}
+
+#[test]
+fn test_second_writer_on_locked_directory_fails() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let second = Wal::new(wal_dir, WalOptions::default());
+    assert!(matches!(second, Err(WalError::InvalidConfig(_))));
+
+    // The lock is released when the first writer goes away
+    drop(wal);
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.shutdown().unwrap();
+}
+
+#[test]
+fn test_truncate_after_rolls_back_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        wal_dir,
+        WalOptions::default()
+            .retention(Duration::from_secs(100))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+
+    let mut refs = Vec::new();
+    for i in 0..5 {
+        refs.push(
+            wal.append_entry("txn", None, Bytes::from(format!("r{}", i)), false)
+                .unwrap(),
+        );
+    }
+    clock.advance(Duration::from_secs(10));
+    wal.append_entry("txn", None, Bytes::from("r5"), false)
+        .unwrap();
+    wal.append_entry("other", None, Bytes::from("kept"), false)
+        .unwrap();
+
+    // An offset inside a record is not a boundary
+    let misaligned = EntryRef {
+        offset: refs[2].offset + 1,
+        ..refs[2]
+    };
+    assert!(matches!(
+        wal.truncate_after(misaligned),
+        Err(WalError::EntryNotFound(_))
+    ));
+
+    wal.truncate_after(refs[2]).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("txn").unwrap().collect();
+    assert_eq!(
+        records,
+        vec![Bytes::from("r0"), Bytes::from("r1"), Bytes::from("r2")]
+    );
+    assert_eq!(wal.enumerate_records("other").unwrap().count(), 1);
+
+    // Appends continue after the rollback point
+    wal.append_entry("txn", None, Bytes::from("r3'"), false)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("txn").unwrap().collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3], Bytes::from("r3'"));
+}
+
+#[test]
+fn test_append_entry_detailed_tracks_next_offset_and_rotation() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    let first = wal
+        .append_entry_detailed("key", Some(Bytes::from("h")), Bytes::from("one"), false)
+        .unwrap();
+    assert!(first.rotated);
+    assert_eq!(first.entry_ref.offset, 0);
+    assert_eq!(first.segment_sequence, 1);
+
+    let second = wal
+        .append_entry_detailed("key", None, Bytes::from("two"), false)
+        .unwrap();
+    assert!(!second.rotated);
+    assert_eq!(second.entry_ref.offset, first.next_offset);
+    assert_eq!(
+        wal.read_entry_at(second.entry_ref).unwrap(),
+        Bytes::from("two")
+    );
+
+    clock.advance(Duration::from_secs(1));
+    let third = wal
+        .append_entry_detailed("key", None, Bytes::from("three"), false)
+        .unwrap();
+    assert!(third.rotated);
+    assert_eq!(third.segment_sequence, 2);
+    assert_eq!(third.entry_ref.offset, 0);
+    drop(wal);
+
+    // Resuming the latest segment after a restart is not a rotation
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    let fourth = wal
+        .append_entry_detailed("key", None, Bytes::from("four"), false)
+        .unwrap();
+    assert!(!fourth.rotated);
+    assert_eq!(fourth.segment_sequence, 2);
+    assert_eq!(fourth.entry_ref.offset, third.next_offset);
+}
+
+#[test]
+fn test_on_rotate_reports_sealed_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let sealed: Arc<Mutex<Vec<SegmentInfo>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink = sealed.clone();
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone())
+        .on_rotate(Arc::new(move |info: &SegmentInfo| {
+            sink.lock().unwrap().push(info.clone())
+        }));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let first = wal
+        .append_entry("uploads", None, Bytes::from("one"), false)
+        .unwrap();
+    wal.append_entry("uploads", None, Bytes::from("two"), false)
+        .unwrap();
+    assert!(sealed.lock().unwrap().is_empty());
+
+    clock.advance(Duration::from_secs(1));
+    wal.append_entry("uploads", None, Bytes::from("three"), false)
+        .unwrap();
+
+    let sealed = sealed.lock().unwrap();
+    assert_eq!(sealed.len(), 1);
+    let info = &sealed[0];
+    assert_eq!(info.key, "uploads");
+    assert_eq!(info.key_hash, first.key_hash);
+    assert_eq!(info.sequence_number, first.sequence_number);
+    assert_eq!(info.expiration_timestamp, 1_001);
+    assert_eq!(info.size, fs::metadata(&info.path).unwrap().len());
+}
+
+#[test]
+fn test_append_entry_to_sequence() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    assert!(matches!(
+        wal.append_entry_to_sequence("replica", 0, None, Bytes::from("x"), true),
+        Err(WalError::InvalidConfig(_))
+    ));
+
+    // Creates segment 3 directly and makes it the active segment
+    let r1 = wal
+        .append_entry_to_sequence("replica", 3, None, Bytes::from("a"), true)
+        .unwrap();
+    assert_eq!(r1.sequence_number, 3);
+    let r2 = wal
+        .append_entry("replica", None, Bytes::from("b"), true)
+        .unwrap();
+    assert_eq!(r2.sequence_number, 3);
+    assert!(r2.offset > r1.offset);
+
+    // Appending to the active sequence continues it
+    let r3 = wal
+        .append_entry_to_sequence("replica", 3, None, Bytes::from("c"), true)
+        .unwrap();
+    assert_eq!(r3.sequence_number, 3);
+
+    // Moving ahead seals the current segment; going back is rejected
+    let r4 = wal
+        .append_entry_to_sequence("replica", 5, None, Bytes::from("d"), true)
+        .unwrap();
+    assert_eq!(r4.sequence_number, 5);
+    assert!(matches!(
+        wal.append_entry_to_sequence("replica", 3, None, Bytes::from("e"), true),
+        Err(WalError::InvalidConfig(_))
+    ));
+    drop(wal);
+
+    // After a restart an existing sealed segment can be appended to again,
+    // and the next sequence stays past the highest one used
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let r5 = wal
+        .append_entry_to_sequence("replica", 3, None, Bytes::from("e"), true)
+        .unwrap();
+    assert_eq!((r5.sequence_number, r5.offset > r3.offset), (3, true));
+    assert_eq!(wal.read_entry_at(r5).unwrap(), Bytes::from("e"));
+
+    let records: Vec<Bytes> = wal.enumerate_records("replica").unwrap().collect();
+    assert_eq!(records, vec!["a", "b", "c", "e", "d"]);
+
+    let other = wal
+        .append_entry_to_sequence("other", 2, None, Bytes::from("z"), true)
+        .unwrap();
+    assert_eq!(other.sequence_number, 2);
+}
+
+#[test]
+fn test_rotation_policy_at_expiration_boundary() {
+    for (policy, rotates_at_boundary) in [
+        (RotationPolicy::Strict, true),
+        (RotationPolicy::Lazy, false),
+    ] {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().to_str().unwrap();
+        let clock = Arc::new(ManualClock::new(1_000));
+        let options = WalOptions::with_retention(Duration::from_secs(10))
+            .segments_per_retention_period(5)
+            .rotation_policy(policy)
+            .clock(clock.clone());
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+
+        wal.append_entry("key", None, Bytes::from("one"), false)
+            .unwrap();
+        assert_eq!(wal.segment_expiration("key").unwrap(), Some(1_002));
+
+        // Exactly at the expiration timestamp
+        clock.advance(Duration::from_secs(2));
+        let boundary = wal
+            .append_entry_detailed("key", None, Bytes::from("two"), false)
+            .unwrap();
+        assert_eq!(boundary.rotated, rotates_at_boundary, "{:?}", policy);
+        let expected_sequence = if rotates_at_boundary { 2 } else { 1 };
+        assert_eq!(boundary.segment_sequence, expected_sequence, "{:?}", policy);
+
+        // The same decision applies when resuming after a restart
+        drop(wal);
+        let mut wal = Wal::new(wal_dir, options).unwrap();
+        let resumed = wal
+            .append_entry_detailed("key", None, Bytes::from("three"), false)
+            .unwrap();
+        assert!(!resumed.rotated, "{:?}", policy);
+        assert_eq!(resumed.segment_sequence, expected_sequence, "{:?}", policy);
+
+        // Once the expiration has passed, both policies rotate
+        clock.advance(Duration::from_secs(1));
+        let past = wal
+            .append_entry_detailed("key", None, Bytes::from("four"), false)
+            .unwrap();
+        if !rotates_at_boundary {
+            assert!(past.rotated);
+            assert_eq!(past.segment_sequence, 2);
+        }
+        let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+        assert_eq!(records.len(), 4);
+    }
+}
+
+#[test]
+fn test_metrics_count_appends_syncs_and_reads() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    // Creating the segment syncs its header once
+    let opened = wal.metrics();
+    let first = wal
+        .append_entry_detailed("key", None, Bytes::from("one"), false)
+        .unwrap();
+    let after_first = wal.metrics();
+    assert_eq!(after_first.appends, 1);
+    assert_eq!(after_first.fsyncs, opened.fsyncs + 1);
+    assert_eq!(after_first.bytes_written, first.next_offset);
+
+    // Non-durable appends never sync; durable ones sync once each
+    wal.append_entry("key", None, Bytes::from("two"), false)
+        .unwrap();
+    assert_eq!(wal.metrics().fsyncs, after_first.fsyncs);
+    wal.append_entry("key", None, Bytes::from("three"), true)
+        .unwrap();
+    assert_eq!(wal.metrics().fsyncs, after_first.fsyncs + 1);
+
+    // A durable batch syncs once per key
+    let mut batch = wal.begin_batch();
+    for i in 0..5 {
+        batch
+            .add("key", None, Bytes::from(format!("batched{}", i)))
+            .unwrap();
+    }
+    batch.commit(true).unwrap();
+    let after_batch = wal.metrics();
+    assert_eq!(after_batch.appends, 8);
+    assert_eq!(after_batch.fsyncs, after_first.fsyncs + 2);
+    assert_eq!(after_batch.rotations, 0);
+
+    clock.advance(Duration::from_secs(1));
+    wal.append_entry("key", None, Bytes::from("rotated"), false)
+        .unwrap();
+    assert_eq!(wal.metrics().rotations, 1);
+
+    let before_reads = wal.metrics();
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 9);
+    wal.read_entry_at(first.entry_ref).unwrap();
+    let after_reads = wal.metrics();
+    assert_eq!(after_reads.records_read, before_reads.records_read + 10);
+    assert_eq!(
+        after_reads.segments_opened,
+        before_reads.segments_opened + 3
+    );
+
+    let mut reader = wal.reader_for("key").unwrap();
+    while reader.next().unwrap().is_some() {}
+    assert_eq!(wal.metrics().records_read, after_reads.records_read + 9);
+}
+
+#[test]
+fn test_append_entry_at_time_backfills_with_event_time_retention() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    // Two events in the same window share one already-expired segment
+    let old = wal
+        .append_entry_at_time("events", 500_000_000, None, Bytes::from("old1"), false)
+        .unwrap();
+    let old2 = wal
+        .append_entry_at_time("events", 500_005_000, None, Bytes::from("old2"), false)
+        .unwrap();
+    assert_eq!(old.sequence_number, old2.sequence_number);
+
+    // A recent event starts a segment that expires relative to it
+    let recent = wal
+        .append_entry_at_time("events", 999_995_000, None, Bytes::from("recent"), false)
+        .unwrap();
+    assert_ne!(recent.sequence_number, old.sequence_number);
+    assert_eq!(wal.segment_expiration("events").unwrap(), Some(1_000_005));
+
+    // Out-of-order event times split segments but keep append order
+    let reordered = wal
+        .append_entry_at_time("events", 500_001_000, None, Bytes::from("old3"), false)
+        .unwrap();
+    assert_eq!(reordered.sequence_number, recent.sequence_number + 1);
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(records, vec!["old1", "old2", "recent", "old3"]);
+
+    // Only the backfilled records past retention are compacted away
+    wal.compact().unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(records, vec!["recent"]);
+
+    // Backfill after compaction does not write into a removed segment
+    wal.append_entry_at_time("events", 500_002_000, None, Bytes::from("old4"), false)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(records, vec!["recent", "old4"]);
+
+    // Live appends rotate away from the expired backfilled segment
+    let live = wal
+        .append_entry_detailed("events", None, Bytes::from("live"), false)
+        .unwrap();
+    assert!(live.rotated);
+}
+
+#[test]
+fn test_compact_and_merge_applies_tombstones() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let first = wal
+        .append_entry("cart", None, Bytes::from("add apple"), false)
+        .unwrap();
+    wal.append_entry("cart", None, Bytes::from("add pear"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(10));
+    wal.append_tombstone("cart", first, false).unwrap();
+    wal.append_entry("cart", None, Bytes::from("add plum"), false)
+        .unwrap();
+
+    assert_eq!(wal.compact_and_merge("cart").unwrap(), 2);
+    let records: Vec<Bytes> = wal.enumerate_records("cart").unwrap().collect();
+    assert_eq!(records, vec!["add pear", "add plum"]);
+    let live: Vec<Bytes> = wal.enumerate_live_records("cart").unwrap().collect();
+    assert_eq!(live, records);
+}
+
+#[test]
+fn test_max_open_segments_bounds_active_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(10)
+        .max_open_segments(16)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    const KEYS: usize = 100_000;
+    let mut refs = Vec::with_capacity(KEYS);
+    for i in 0..KEYS {
+        let key = format!("key{}", i);
+        refs.push(
+            wal.append_entry(&key, None, Bytes::from(format!("v{}", i)), false)
+                .unwrap(),
+        );
+        assert!(wal.active_segment_count() <= 16);
+    }
+
+    // Records of evicted keys stay readable
+    assert_eq!(wal.read_entry_at(refs[0]).unwrap(), Bytes::from("v0"));
+    let records: Vec<Bytes> = wal.enumerate_records("key1").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("v1")]);
+
+    // Appending to an evicted key resumes its segment
+    let resumed = wal
+        .append_entry("key0", None, Bytes::from("again"), false)
+        .unwrap();
+    assert_eq!(resumed.sequence_number, refs[0].sequence_number);
+    let records: Vec<Bytes> = wal.enumerate_records("key0").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("v0"), Bytes::from("again")]);
+
+    // Once compaction removed every segment of a key, it starts over
+    clock.advance(Duration::from_secs(20));
+    wal.compact().unwrap();
+    assert_eq!(wal.active_segment_count(), 0);
+    let restarted = wal
+        .append_entry("key2", None, Bytes::from("new"), false)
+        .unwrap();
+    assert_eq!(restarted.sequence_number, 1);
+}
+
+#[test]
+fn test_max_open_segments_must_be_positive() {
+    let options = WalOptions::default().max_open_segments(0);
+    assert!(matches!(
+        options.validate(),
+        Err(WalError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_segments_for_key_marks_active_segment() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(30))
+        .segments_per_retention_period(3)
+        .preallocate(4096)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+
+    for i in 0..3 {
+        wal.append_entry("stream", None, Bytes::from(format!("r{}", i)), false)
+            .unwrap();
+        clock.advance(Duration::from_secs(10));
+    }
+    wal.append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+    // The third segment expires before the last append, so it is sealed
+    wal.append_entry("stream", None, Bytes::from("r3"), false)
+        .unwrap();
+
+    let segments = wal.segments_for_key("stream").unwrap();
+    let sequences: Vec<u64> = segments.iter().map(|s| s.sequence_number).collect();
+    assert_eq!(sequences, vec![1, 2, 3, 4]);
+    let active: Vec<bool> = segments.iter().map(|s| s.active).collect();
+    assert_eq!(active, vec![false, false, false, true]);
+    assert!(segments.iter().all(|s| s.key == "stream"));
+    for segment in &segments[..3] {
+        assert_eq!(segment.size, fs::metadata(&segment.path).unwrap().len());
+    }
+    let latest = &segments[3];
+    assert_eq!(
+        latest.size,
+        Wal::file_header_size(b"stream") as u64 + RECORD_OVERHEAD as u64 + 2
+    );
+    assert!(fs::metadata(&latest.path).unwrap().len() > latest.size);
+
+    // After reopening, the latest segment stays active until it expires
+    wal.close().unwrap();
+    let wal = Wal::new(wal_dir, options).unwrap();
+    assert!(wal.segments_for_key("stream").unwrap()[3].active);
+    clock.advance(Duration::from_secs(10));
+    assert!(!wal.segments_for_key("stream").unwrap()[3].active);
+    assert!(wal.segments_for_key("missing").unwrap().is_empty());
+}
+
+#[test]
+fn test_flush_pending_syncs_only_unsynced_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let mut refs = Vec::new();
+    for i in 0..9 {
+        let key = format!("stream{}", i % 3);
+        refs.push(
+            wal.append_entry(&key, None, Bytes::from(format!("r{}", i)), false)
+                .unwrap(),
+        );
+    }
+    let durable = wal
+        .append_entry("synced", None, Bytes::from("d"), true)
+        .unwrap();
+
+    let before = wal.metrics().fsyncs;
+    wal.flush_pending().unwrap();
+    assert_eq!(wal.metrics().fsyncs, before + 3);
+    wal.flush_pending().unwrap();
+    assert_eq!(wal.metrics().fsyncs, before + 3);
+    drop(wal);
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    for (i, entry_ref) in refs.iter().enumerate() {
+        assert_eq!(
+            wal.read_entry_at(*entry_ref).unwrap(),
+            Bytes::from(format!("r{}", i))
+        );
+    }
+    assert_eq!(wal.read_entry_at(durable).unwrap(), Bytes::from("d"));
+}
+
+#[test]
+fn test_alignment_pads_every_frame() {
+    for (alignment, compact) in [(4096, false), (8, true)] {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().to_str().unwrap();
+        let options = WalOptions::default()
+            .alignment(alignment)
+            .compact_framing(compact);
+        let body_start = Wal::file_header_size(b"orders") as u64;
+        let aligned =
+            |entry_ref: EntryRef| (body_start + entry_ref.offset).is_multiple_of(alignment as u64);
+
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+        let mut refs = Vec::new();
+        for len in [0, 1, 100, 5000] {
+            let content = Bytes::from(vec![b'x'; len]);
+            refs.push(wal.append_entry("orders", None, content, true).unwrap());
+        }
+        let mut batch = wal.begin_batch();
+        batch.add("orders", None, Bytes::from("batched")).unwrap();
+        refs.extend(batch.commit(true).unwrap());
+        refs.extend(
+            wal.append_group(
+                "orders",
+                &[(None, Bytes::from("g1")), (None, Bytes::from("g2"))],
+                true,
+            )
+            .unwrap(),
+        );
+        assert!(refs.iter().all(|entry_ref| aligned(*entry_ref)));
+        assert_eq!(wal.read_entry_at(refs[5]).unwrap(), Bytes::from("g1"));
+        let segment = wal.segments_for_key("orders").unwrap()[0].path.clone();
+        wal.close().unwrap();
+        assert_eq!(fs::metadata(&segment).unwrap().len() % alignment as u64, 0);
+
+        // Padding is skipped by readers, whatever their own alignment
+        for zero_copy in [false, true] {
+            let wal = Wal::new(wal_dir, WalOptions::default().zero_copy_reads(zero_copy)).unwrap();
+            assert_eq!(wal.enumerate_records("orders").unwrap().count(), 7);
+        }
+
+        let mut wal = Wal::new(wal_dir, options).unwrap();
+        let resumed = wal
+            .append_entry("orders", None, Bytes::from("resumed"), true)
+            .unwrap();
+        assert!(aligned(resumed));
+
+        // Rewritten segments are aligned too
+        let outcome = wal
+            .repair_segment(resumed.key_hash, resumed.sequence_number)
+            .unwrap();
+        assert_eq!(outcome.records_kept, 8);
+        assert_eq!(fs::metadata(&segment).unwrap().len() % alignment as u64, 0);
+        let records: Vec<_> = wal.enumerate_records_with_refs("orders").unwrap().collect();
+        assert_eq!(records.len(), 8);
+        assert!(records.iter().all(|(entry_ref, _)| aligned(*entry_ref)));
+    }
+}
+
+#[test]
+fn test_alignment_must_be_power_of_two() {
+    for alignment in [0, 3, 4097] {
+        let result = WalOptions::default().alignment(alignment).validate();
+        assert!(matches!(result, Err(WalError::InvalidConfig(_))));
+    }
+    let options = WalOptions::default().alignment(4096).preallocate(1000);
+    assert!(matches!(
+        options.validate(),
+        Err(WalError::InvalidConfig(_))
+    ));
+    let options = WalOptions::default().alignment(4096).preallocate(8192);
+    assert!(options.validate().is_ok());
+}
+
+#[test]
+fn test_compact_before_uses_cutoff_not_retention() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(1)
+        .clock(clock.clone());
+
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    wal.append_entry("a", None, Bytes::from("a1"), false)
+        .unwrap();
+    wal.append_entry("b", None, Bytes::from("b1"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(150));
+    wal.append_entry("a", None, Bytes::from("a2"), false)
+        .unwrap();
+
+    // Both first segments expire at 1_000_100
+    assert_eq!(wal.compact_key_before("a", 1_000_100).unwrap(), 1);
+    let records: Vec<Bytes> = wal.enumerate_records("a").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("a2")]);
+    assert_eq!(wal.compact_before(1_000_099).unwrap(), 0);
+    assert_eq!(wal.compact_before(1_000_100).unwrap(), 1);
+    assert_eq!(wal.enumerate_records("b").unwrap().count(), 0);
+
+    // A key left without segments starts over, even after its active
+    // segment is removed
+    let b = wal
+        .append_entry("b", None, Bytes::from("b2"), false)
+        .unwrap();
+    assert_eq!(b.sequence_number, 1);
+    assert_eq!(wal.compact_key_before("a", 2_000_000).unwrap(), 1);
+    let a = wal
+        .append_entry("a", None, Bytes::from("a3"), false)
+        .unwrap();
+    assert_eq!(a.sequence_number, 1);
+    assert_eq!(wal.enumerate_records("a").unwrap().count(), 1);
+}
+
+#[test]
+fn test_compact_incremental_resumes_between_calls() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(60))
+        .segments_per_retention_period(1)
+        .clock(clock.clone());
+
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    for i in 0..5 {
+        wal.append_entry(format!("old-{}", i), None, Bytes::from("x"), false)
+            .unwrap();
+    }
+    clock.advance(Duration::from_secs(61));
+    wal.append_entry("live", None, Bytes::from("y"), false)
+        .unwrap();
+
+    let progress = wal.compact_incremental(0).unwrap();
+    assert_eq!((progress.examined, progress.removed), (0, 0));
+    assert!(progress.has_more);
+
+    let mut calls = 0;
+    let mut removed = 0;
+    loop {
+        let progress = wal.compact_incremental(2).unwrap();
+        assert!(progress.examined <= 2);
+        calls += 1;
+        removed += progress.removed;
+        if !progress.has_more {
+            break;
+        }
+    }
+    assert_eq!(calls, 3);
+    assert_eq!(removed, 5);
+    assert_eq!(
+        wal.enumerate_keys().unwrap().collect::<Vec<_>>(),
+        vec!["live"]
+    );
+
+    // Emptied keys start over, and a finished pass starts a new one
+    let entry_ref = wal
+        .append_entry("old-0", None, Bytes::from("z"), false)
+        .unwrap();
+    assert_eq!(entry_ref.sequence_number, 1);
+    let progress = wal.compact_incremental(10).unwrap();
+    assert_eq!((progress.examined, progress.removed), (2, 0));
+    assert!(!progress.has_more);
+}
+
+#[test]
+fn test_current_offset_and_force_rotate() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let rotated = Arc::new(Mutex::new(Vec::new()));
+    let sink = rotated.clone();
+    let options = WalOptions::default().on_rotate(Arc::new(move |info: &SegmentInfo| {
+        sink.lock().unwrap().push(info.clone())
+    }));
+
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    assert_eq!(wal.current_offset("events").unwrap(), None);
+    let first = wal
+        .append_entry("events", None, Bytes::from("e1"), false)
+        .unwrap();
+    let after_first = (RECORD_OVERHEAD + 2) as u64;
+    assert_eq!(wal.current_offset("events").unwrap(), Some(after_first));
+
+    let sealed = wal.force_rotate("events").unwrap().unwrap();
+    assert_eq!(sealed.sequence_number, first.sequence_number);
+    assert!(!sealed.active);
+    assert_eq!(rotated.lock().unwrap().clone(), vec![sealed]);
+    assert_eq!(wal.current_offset("events").unwrap(), Some(0));
+    let second = wal
+        .append_entry("events", None, Bytes::from("e2"), false)
+        .unwrap();
+    assert_eq!(second.sequence_number, first.sequence_number + 1);
+    assert_eq!(second.offset, 0);
+    wal.close().unwrap();
+
+    // After a reopen nothing is active, yet the rotation still takes effect
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(wal.current_offset("events").unwrap(), None);
+    assert!(wal.force_rotate("events").unwrap().is_none());
+    let third = wal
+        .append_entry("events", None, Bytes::from("e3"), false)
+        .unwrap();
+    assert_eq!(third.sequence_number, second.sequence_number + 1);
+    assert_eq!(wal.enumerate_records("events").unwrap().count(), 3);
+}
+
+#[test]
+fn test_auto_sync_every_counts_appends() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().auto_sync_every(10);
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    // Creating segments syncs on its own, so start counting once they exist
+    wal.append_entry("key_0", None, Bytes::from("data"), false)
+        .unwrap();
+    wal.append_entry("key_1", None, Bytes::from("data"), false)
+        .unwrap();
+    wal.sync().unwrap();
+    let fsyncs = wal.metrics().fsyncs;
+
+    for i in 0..9 {
+        let key = format!("key_{}", i % 2);
+        wal.append_entry(&key, None, Bytes::from("data"), false)
+            .unwrap();
+    }
+    assert_eq!(wal.metrics().fsyncs, fsyncs);
+
+    // The tenth append syncs both keys' segments
+    wal.append_entry("key_1", None, Bytes::from("data"), false)
+        .unwrap();
+    assert_eq!(wal.metrics().fsyncs, fsyncs + 2);
+
+    // A durable append still syncs right away
+    wal.append_entry("key_0", None, Bytes::from("data"), true)
+        .unwrap();
+    assert_eq!(wal.metrics().fsyncs, fsyncs + 3);
+
+    // Groups count each record, and an explicit sync restarts the count
+    let group: Vec<_> = (0..8).map(|_| (None, Bytes::from("data"))).collect();
+    wal.append_group("key_0", &group, false).unwrap();
+    wal.sync().unwrap();
+    let fsyncs = wal.metrics().fsyncs;
+    wal.append_group("key_0", &group, false).unwrap();
+    assert_eq!(wal.metrics().fsyncs, fsyncs);
+    wal.append_group("key_0", &group[..2], false).unwrap();
+    assert_eq!(wal.metrics().fsyncs, fsyncs + 1);
+}
+
+#[test]
+fn test_key_record_counts_in_one_pass() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .shard_dirs(4)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert!(wal.key_record_counts().unwrap().is_empty());
+
+    let first = wal
+        .append_entry("a", None, Bytes::from("1"), false)
+        .unwrap();
+    wal.append_entry("a", None, Bytes::from("2"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(5));
+    wal.append_entry("a", None, Bytes::from("3"), false)
+        .unwrap();
+    wal.append_tombstone("a", first, false).unwrap();
+    for i in 0..5 {
+        wal.append_entry("b", None, Bytes::from(format!("{}", i)), false)
+            .unwrap();
+    }
+
+    let counts = wal.key_record_counts().unwrap();
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts["a"], 3);
+    assert_eq!(counts["b"], 5);
+    assert_eq!(counts["b"], wal.record_count_estimate("b").unwrap());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_spans_around_writes() {
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records span names and every field value, in order.
+    #[derive(Default)]
+    struct Recorder {
+        next_id: AtomicU64,
+        spans: Mutex<Vec<String>>,
+        fields: Mutex<Vec<(String, String)>>,
+    }
+
+    impl Visit for &Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            let value = format!("{:?}", value);
+            self.fields
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let name = span.metadata().name().to_string();
+            self.spans.lock().unwrap().push(name);
+            span.record(&mut &*self);
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut &*self);
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            event.record(&mut &*self);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let recorder = Arc::new(Recorder::default());
+    tracing::subscriber::with_default(recorder.clone(), || {
+        wal.append_entry("key", None, Bytes::from("data"), true)
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+        wal.append_entry("key", None, Bytes::from("more"), false)
+            .unwrap();
+        wal.sync().unwrap();
+        wal.compact().unwrap();
+    });
+
+    let spans = recorder.spans.lock().unwrap().clone();
+    assert_eq!(spans, ["append_entry", "append_entry", "sync", "compact"]);
+    let fields = recorder.fields.lock().unwrap().clone();
+    let values = |name: &str| -> Vec<String> {
+        (fields.iter())
+            .filter(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+            .collect()
+    };
+    assert_eq!(values("bytes"), ["4", "4"]);
+    assert_eq!(values("durable"), ["true", "false"]);
+    assert_eq!(values("rotated"), ["true", "true"]);
+    assert_eq!(values("removed"), ["1"]);
+    assert_eq!(values("message"), ["segment rotated"]);
+}
+
+#[test]
+fn test_validate_and_repair_sequences() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    let first = wal
+        .append_entry("orders", None, Bytes::from("a"), true)
+        .unwrap();
+    wal.append_entry("users", None, Bytes::from("u"), true)
+        .unwrap();
+    assert!(wal.validate_sequences().unwrap().is_empty());
+
+    // A segment copied in behind the WAL's back, ahead of its counter
+    let segment = wal.segments_for_key("orders").unwrap()[0].path.clone();
+    let name = segment.file_name().unwrap().to_str().unwrap();
+    let copied = segment.with_file_name(name.replace("-0001.log", "-0005.log"));
+    fs::copy(&segment, &copied).unwrap();
+
+    let mismatches = wal.validate_sequences().unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].key_hash, first.key_hash);
+    assert_eq!(mismatches[0].max_on_disk, 5);
+    assert_eq!(mismatches[0].next_sequence, 2);
+
+    assert_eq!(wal.repair_sequences().unwrap(), 1);
+    assert!(wal.validate_sequences().unwrap().is_empty());
+    assert_eq!(wal.repair_sequences().unwrap(), 0);
+
+    // Appends now land at or after the copied segment, never before it
+    let next = wal
+        .append_entry("orders", None, Bytes::from("b"), true)
+        .unwrap();
+    assert!(next.sequence_number >= 5);
+    let records: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec!["a", "a", "b"]);
+}
+
+#[test]
+fn test_merge_records_orders_keys_by_segment_time() {
+    let temp_dir = TempDir::new().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        temp_dir.path().to_str().unwrap(),
+        WalOptions::default()
+            .retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+
+    wal.append_entry("clicks", None, Bytes::from("c1"), false)
+        .unwrap();
+    wal.append_entry("views", None, Bytes::from("v1"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    wal.append_entry("views", None, Bytes::from("v2"), false)
+        .unwrap();
+    wal.append_entry("clicks", None, Bytes::from("c2"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    wal.append_entry("clicks", None, Bytes::from("c3"), false)
+        .unwrap();
+    wal.append_entry("clicks", None, Bytes::from("c4"), false)
+        .unwrap();
+
+    // Same window: keys come back in the order they were asked for
+    let merged: Vec<(String, Bytes)> = wal.merge_records(&["clicks", "views"]).unwrap().collect();
+    let expected = [
+        ("clicks", "c1"),
+        ("views", "v1"),
+        ("clicks", "c2"),
+        ("views", "v2"),
+        ("clicks", "c3"),
+        ("clicks", "c4"),
+    ];
+    assert_eq!(
+        merged,
+        expected
+            .iter()
+            .map(|(key, record)| (key.to_string(), Bytes::from(*record)))
+            .collect::<Vec<_>>()
+    );
+
+    let views_first: Vec<String> = wal
+        .merge_records(&["views", "clicks"])
+        .unwrap()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(
+        views_first,
+        ["views", "clicks", "views", "clicks", "clicks", "clicks"]
+    );
+    assert_eq!(wal.merge_records(&["missing"]).unwrap().count(), 0);
+}
+
+#[test]
+fn test_merge_records_orders_mixed_retentions_by_segment_start() {
+    let temp_dir = TempDir::new().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let mut wal = Wal::new(
+        temp_dir.path().to_str().unwrap(),
+        WalOptions::default()
+            .retention(Duration::from_secs(100))
+            .segments_per_retention_period(10)
+            .clock(clock.clone()),
+    )
+    .unwrap();
+    wal.set_key_retention("slow", Duration::from_secs(10_000))
+        .unwrap();
+
+    // "slow" segments expire long after "fast" ones opened later
+    wal.append_entry("slow", None, Bytes::from("slow@t0"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(5));
+    wal.append_entry("fast", None, Bytes::from("fast@t5"), false)
+        .unwrap();
+    clock.advance(Duration::from_secs(10));
+    wal.append_entry("fast", None, Bytes::from("fast@t15"), false)
+        .unwrap();
+
+    let merged: Vec<Bytes> = wal
+        .merge_records(&["fast", "slow"])
+        .unwrap()
+        .map(|(_, record)| record)
+        .collect();
+    assert_eq!(merged, vec!["slow@t0", "fast@t5", "fast@t15"]);
+}
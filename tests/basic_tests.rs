@@ -1,7 +1,9 @@
 use bytes::Bytes;
-use nano_wal::{Wal, WalOptions};
+use nano_wal::{EntryRef, ManualClock, Wal, WalError, WalOptions, RECORD_OVERHEAD};
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tempfile::TempDir;
 
 #[test]
@@ -229,6 +231,107 @@ fn test_key_in_filename() {
     wal.shutdown().unwrap();
 }
 
+#[test]
+fn test_keys_sharing_long_prefix_and_renamed_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    // Both keys share the first 25 characters, more than the filename keeps
+    let key_a = "sensor_reading_building_a_floor_1";
+    let key_b = "sensor_reading_building_a_floor_2";
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry(key_a, None, Bytes::from("a"), true)
+            .unwrap();
+        wal.append_entry(key_b, None, Bytes::from("b"), true)
+            .unwrap();
+    }
+
+    // Rewrite the filenames with a different prefix length, as a build with
+    // another truncation length would have produced
+    for entry in fs::read_dir(wal_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let filename = path.file_name().unwrap().to_str().unwrap();
+        let mut parts = filename.rsplitn(3, '-');
+        let (sequence, key_hash) = (parts.next().unwrap(), parts.next().unwrap());
+        let renamed = format!("sensor_reading_building_a_floor-{}-{}", key_hash, sequence);
+        fs::rename(&path, Path::new(wal_dir).join(renamed)).unwrap();
+    }
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records_a: Vec<Bytes> = wal.enumerate_records(key_a).unwrap().collect();
+    let records_b: Vec<Bytes> = wal.enumerate_records(key_b).unwrap().collect();
+    assert_eq!(records_a, vec![Bytes::from("a")]);
+    assert_eq!(records_b, vec![Bytes::from("b")]);
+}
+
+#[test]
+fn test_first_record() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(std::time::Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(wal.first_record("stream").unwrap(), None);
+
+    wal.append_entry(
+        "stream",
+        Some(Bytes::from("meta")),
+        Bytes::from("first"),
+        false,
+    )
+    .unwrap();
+    wal.append_entry("stream", None, Bytes::from("second"), false)
+        .unwrap();
+    clock.advance(std::time::Duration::from_secs(1));
+    wal.append_entry("stream", None, Bytes::from("third"), false)
+        .unwrap();
+
+    assert_eq!(
+        wal.first_record("stream").unwrap(),
+        Some(Bytes::from("first"))
+    );
+    assert_eq!(wal.first_record("other").unwrap(), None);
+}
+
+#[test]
+fn test_latest_records() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(std::time::Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert!(wal.latest_records().unwrap().is_empty());
+
+    wal.append_entry("user:1", None, Bytes::from("v1"), false)
+        .unwrap();
+    let deleted = wal
+        .append_entry("user:2", None, Bytes::from("only"), false)
+        .unwrap();
+    clock.advance(std::time::Duration::from_secs(1));
+    wal.append_entry("user:1", Some(Bytes::from("h")), Bytes::from("v2"), false)
+        .unwrap();
+    wal.append_entry("user:1", None, Bytes::from("v3"), false)
+        .unwrap();
+    // Tombstones are not records, and an empty latest segment falls back
+    wal.append_tombstone("user:2", deleted, false).unwrap();
+    wal.force_rotate("user:1").unwrap();
+
+    let latest = wal.latest_records().unwrap();
+    assert_eq!(latest.len(), 2);
+    assert_eq!(latest["user:1"], "v3");
+    assert_eq!(latest["user:2"], "only");
+}
+
 #[test]
 fn test_header_functionality() {
     let temp_dir = TempDir::new().unwrap();
@@ -286,3 +389,737 @@ fn test_header_functionality() {
 
     wal.shutdown().unwrap();
 }
+
+#[test]
+fn test_configured_max_header_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default().max_header_size(16)).unwrap();
+
+    wal.append_entry("key", Some(Bytes::from(vec![0u8; 16])), Bytes::new(), false)
+        .unwrap();
+    let result = wal.append_entry("key", Some(Bytes::from(vec![0u8; 17])), Bytes::new(), false);
+    assert!(matches!(
+        result,
+        Err(WalError::HeaderTooLarge { size: 17, max: 16 })
+    ));
+}
+
+#[test]
+fn test_header_larger_than_u16_round_trips() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().max_header_size(200_000);
+    let schema = Bytes::from((0..100_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>());
+
+    {
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+        let small = wal
+            .append_entry("events", Some(Bytes::from("v1")), Bytes::from("a"), false)
+            .unwrap();
+        let large = wal
+            .append_entry("events", Some(schema.clone()), Bytes::from("b"), false)
+            .unwrap();
+        let next = wal
+            .append_entry("events", None, Bytes::from("c"), true)
+            .unwrap();
+
+        // Small headers keep the 2-byte length; the large one escapes to a u32
+        assert_eq!(large.offset - small.offset, RECORD_OVERHEAD as u64 + 2 + 1);
+        assert_eq!(
+            next.offset - large.offset,
+            (RECORD_OVERHEAD + 4 + schema.len() + 1) as u64
+        );
+        assert_eq!(
+            wal.record_size(schema.len(), 1),
+            (RECORD_OVERHEAD + 4 + schema.len() + 1) as u64
+        );
+        assert_eq!(wal.read_entry_at(large).unwrap(), Bytes::from("b"));
+    }
+
+    let wal = Wal::new(wal_dir, options).unwrap();
+    let headers: Vec<Option<Bytes>> = wal
+        .enumerate_entries("events")
+        .unwrap()
+        .map(|entry| entry.unwrap().header)
+        .collect();
+    assert_eq!(headers, vec![Some(Bytes::from("v1")), Some(schema), None]);
+    let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+    assert_eq!(records, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_append_batch_reports_partial_write() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let entries: Vec<_> = (0..5)
+        .map(|i| {
+            let header = (i == 3).then(|| Bytes::from(vec![0u8; 65536]));
+            ("batch", header, Bytes::from(format!("entry {}", i)))
+        })
+        .collect();
+
+    let err = wal.append_batch(entries, true).unwrap_err();
+    assert_eq!(err.failed_index, 3);
+    assert_eq!(err.written.len(), 3);
+    assert!(matches!(err.source, WalError::HeaderTooLarge { .. }));
+
+    for (i, entry_ref) in err.written.iter().enumerate() {
+        let content = wal.read_entry_at(*entry_ref).unwrap();
+        assert_eq!(content, Bytes::from(format!("entry {}", i)));
+    }
+    assert_eq!(wal.enumerate_records("batch").unwrap().count(), 3);
+}
+
+#[test]
+fn test_write_batch_commit() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("a", None, Bytes::from("existing"), false)
+        .unwrap();
+
+    let mut batch = wal.begin_batch();
+    assert!(batch.is_empty());
+    for i in 0..6 {
+        let key = if i % 2 == 0 { "a" } else { "b" };
+        let header = (i == 1).then(|| Bytes::from("meta"));
+        batch
+            .add(key, header, Bytes::from(format!("entry {}", i)))
+            .unwrap();
+    }
+    let oversized = Some(Bytes::from(vec![0u8; 65536]));
+    assert!(matches!(
+        batch.add("a", oversized, Bytes::from("x")),
+        Err(WalError::HeaderTooLarge { .. })
+    ));
+    assert_eq!(batch.len(), 6);
+
+    let refs = batch.commit(true).unwrap();
+    assert_eq!(refs.len(), 6);
+    for (i, entry_ref) in refs.iter().enumerate() {
+        let content = wal.read_entry_at(*entry_ref).unwrap();
+        assert_eq!(content, Bytes::from(format!("entry {}", i)));
+    }
+
+    let a: Vec<Bytes> = wal.enumerate_records("a").unwrap().collect();
+    assert_eq!(a, vec!["existing", "entry 0", "entry 2", "entry 4"]);
+    let b: Vec<Bytes> = wal.enumerate_records("b").unwrap().collect();
+    assert_eq!(b, vec!["entry 1", "entry 3", "entry 5"]);
+
+    // Appends after the batch continue where it ended
+    wal.append_entry("b", None, Bytes::from("after"), true)
+        .unwrap();
+    assert_eq!(wal.enumerate_records("b").unwrap().count(), 4);
+
+    // A dropped batch writes nothing
+    let mut batch = wal.begin_batch();
+    batch.add("c", None, Bytes::from("discarded")).unwrap();
+    drop(batch);
+    assert_eq!(wal.enumerate_records("c").unwrap().count(), 0);
+}
+
+#[test]
+fn test_segment_expiration() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let options = WalOptions::default()
+        .retention(std::time::Duration::from_secs(3600))
+        .segments_per_retention_period(4)
+        .clock(Arc::new(ManualClock::new(1_000)));
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    assert_eq!(wal.segment_expiration("unknown").unwrap(), None);
+
+    wal.append_entry("key1", None, Bytes::from("data"), true)
+        .unwrap();
+
+    let expiration = wal.segment_expiration("key1").unwrap().unwrap();
+    assert_eq!(expiration, 1_900);
+    drop(wal);
+
+    // Without an active segment the value comes from the file header
+    let wal = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(wal.segment_expiration("key1").unwrap(), Some(expiration));
+}
+
+#[test]
+fn test_enumerate_keys_checked_reports_damaged_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("good", None, Bytes::from("data"), true)
+        .unwrap();
+
+    fs::write(Path::new(wal_dir).join("bad-1-0001.log"), b"NOT-A-WAL-FILE").unwrap();
+    fs::write(Path::new(wal_dir).join("short-2-0001.log"), b"NANO").unwrap();
+
+    let (keys, damaged) = wal.enumerate_keys_checked().unwrap();
+    assert_eq!(keys, vec!["good".to_string()]);
+    assert_eq!(damaged.len(), 2);
+    assert!(damaged[0].0.ends_with("bad-1-0001.log"));
+    assert!(matches!(damaged[0].1, WalError::CorruptedData(_)));
+    assert!(damaged[1].0.ends_with("short-2-0001.log"));
+    assert!(matches!(damaged[1].1, WalError::Io(_)));
+
+    // The lossy variant still lists only the readable key
+    assert_eq!(wal.enumerate_keys().unwrap().count(), 1);
+}
+
+#[test]
+fn test_zero_copy_reads_match_buffered_reads() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    for i in 0..5 {
+        wal.append_entry(
+            "key",
+            Some(Bytes::from(format!("h{}", i))),
+            Bytes::from(format!("record {}", i)),
+            true,
+        )
+        .unwrap();
+    }
+    let expected: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    drop(wal);
+
+    // Simulate a torn write at the tail of the segment
+    let segment = fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let mut file = fs::OpenOptions::new().append(true).open(&segment).unwrap();
+    std::io::Write::write_all(&mut file, b"NANORC\x05").unwrap();
+    drop(file);
+
+    let wal = Wal::new(wal_dir, WalOptions::default().zero_copy_reads(true)).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, expected);
+    assert_eq!(records.len(), 5);
+}
+
+#[test]
+fn test_append_entry_dedup() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .clock(clock.clone())
+        .dedup_window(Duration::from_secs(60))
+        .dedup_capacity(2);
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let first = wal
+        .append_entry_dedup("payments", b"txn-1", None, Bytes::from("a"), true)
+        .unwrap();
+    assert!(first.is_some());
+    let retry = wal
+        .append_entry_dedup("payments", b"txn-1", None, Bytes::from("a"), true)
+        .unwrap();
+    assert_eq!(retry, None);
+
+    // Ids are tracked per key
+    assert!(wal
+        .append_entry_dedup("refunds", b"txn-1", None, Bytes::from("r"), true)
+        .unwrap()
+        .is_some());
+
+    // The window expires
+    clock.advance(Duration::from_secs(60));
+    assert!(wal
+        .append_entry_dedup("payments", b"txn-1", None, Bytes::from("a"), true)
+        .unwrap()
+        .is_some());
+
+    // Capacity evicts the oldest id
+    wal.append_entry_dedup("payments", b"txn-2", None, Bytes::from("b"), true)
+        .unwrap();
+    wal.append_entry_dedup("payments", b"txn-3", None, Bytes::from("c"), true)
+        .unwrap();
+    assert!(wal
+        .append_entry_dedup("payments", b"txn-1", None, Bytes::from("a"), true)
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        wal.append_entry_dedup("payments", b"txn-3", None, Bytes::from("c"), true)
+            .unwrap(),
+        None
+    );
+
+    let records: Vec<Bytes> = wal.enumerate_records("payments").unwrap().collect();
+    assert_eq!(records, vec!["a", "a", "b", "c", "a"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_try_enumerate_records_reports_unreadable_segment() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    wal.append_entry("key", None, Bytes::from("first"), true)
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    let second = wal
+        .append_entry("key", None, Bytes::from("second"), true)
+        .unwrap();
+
+    let records: Vec<Bytes> = wal
+        .try_enumerate_records("key")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(records, vec!["first", "second"]);
+
+    let segment = fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .ends_with(&format!("-{:04}.log", second.sequence_number))
+        })
+        .unwrap();
+    fs::set_permissions(&segment, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::File::open(&segment).is_ok() {
+        // Running with privileges that ignore file modes
+        return;
+    }
+
+    let result = wal
+        .try_enumerate_records("key")
+        .and_then(|records| records.collect::<Result<Vec<Bytes>, _>>());
+    assert!(matches!(result, Err(WalError::Io(_))));
+    fs::set_permissions(&segment, fs::Permissions::from_mode(0o644)).unwrap();
+}
+
+#[test]
+fn test_enumerate_keys_with_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    // Long keys whose sanitized filenames all look alike
+    let long = "orders:partition-with-a-very-long-name";
+    for key in [
+        format!("{}:0", long),
+        format!("{}:1", long),
+        "orders-archive".to_string(),
+        "payments:0".to_string(),
+    ] {
+        wal.append_entry(key, None, Bytes::from("data"), true)
+            .unwrap();
+    }
+    wal.append_entry(format!("{}:0", long), None, Bytes::from("more"), true)
+        .unwrap();
+
+    let partitions: Vec<String> = wal.enumerate_keys_with_prefix("orders:").unwrap().collect();
+    assert_eq!(
+        partitions,
+        vec![format!("{}:0", long), format!("{}:1", long)]
+    );
+
+    assert_eq!(wal.enumerate_keys_with_prefix("").unwrap().count(), 4);
+    assert_eq!(
+        wal.enumerate_keys_with_prefix("refunds").unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn test_contains_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    assert!(!wal.contains_key("user_123").unwrap());
+
+    wal.append_entry("user_123", None, Bytes::from("data"), true)
+        .unwrap();
+    wal.append_entry("user:123", None, Bytes::from("data"), true)
+        .unwrap();
+
+    assert!(wal.contains_key("user_123").unwrap());
+    assert!(wal.contains_key("user:123").unwrap());
+    assert!(!wal.contains_key("user_1234").unwrap());
+    assert!(!wal.contains_key("user").unwrap());
+}
+
+#[test]
+fn test_record_count_estimate() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    assert_eq!(wal.record_count_estimate("events").unwrap(), 0);
+    for i in 0..7 {
+        if i == 4 {
+            clock.advance(Duration::from_secs(1));
+        }
+        wal.append_entry(
+            "events",
+            Some(Bytes::from("h")),
+            Bytes::from(vec![0u8; 100 * i]),
+            false,
+        )
+        .unwrap();
+    }
+    wal.append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+
+    assert_eq!(wal.record_count_estimate("events").unwrap(), 7);
+    assert_eq!(
+        wal.record_count_estimate("events").unwrap(),
+        wal.enumerate_records("events").unwrap().count() as u64
+    );
+}
+
+#[test]
+fn test_enumerate_records_where() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    wal.append_entry("events", Some(Bytes::from("live")), Bytes::from("a"), false)
+        .unwrap();
+    wal.append_entry(
+        "events",
+        Some(Bytes::from("deleted")),
+        Bytes::from("b"),
+        false,
+    )
+    .unwrap();
+    wal.append_entry("events", None, Bytes::from("c"), false)
+        .unwrap();
+    wal.append_entry("events", Some(Bytes::from("live")), Bytes::from("d"), false)
+        .unwrap();
+
+    let live: Vec<Bytes> = wal
+        .enumerate_records_where("events", |header| header != b"deleted")
+        .unwrap()
+        .collect();
+    assert_eq!(live, vec!["a", "c", "d"]);
+
+    let headerless: Vec<Bytes> = wal
+        .enumerate_records_where("events", |header| header.is_empty())
+        .unwrap()
+        .collect();
+    assert_eq!(headerless, vec!["c"]);
+
+    assert_eq!(
+        wal.enumerate_records_where("missing", |_| true)
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_record_overhead_matches_segment_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    for compact in [false, true] {
+        let dir = Path::new(wal_dir).join(format!("compact-{}", compact));
+        let mut wal = Wal::new(
+            dir.to_str().unwrap(),
+            WalOptions::default().compact_framing(compact),
+        )
+        .unwrap();
+
+        let expected_overhead = if compact { 8 } else { RECORD_OVERHEAD };
+        assert_eq!(wal.record_overhead(), expected_overhead);
+
+        let records = [(0usize, 5u64), (3, 200), (300, 70_000)];
+        let mut expected = Wal::file_header_size(b"sized") as u64;
+        for (header_len, content_len) in records {
+            let header = (header_len > 0).then(|| Bytes::from(vec![1u8; header_len]));
+            wal.append_entry(
+                "sized",
+                header,
+                Bytes::from(vec![2u8; content_len as usize]),
+                true,
+            )
+            .unwrap();
+            expected += wal.record_size(header_len, content_len);
+        }
+        assert_eq!(wal.record_size(0, 5), wal.record_overhead() as u64 + 5);
+
+        let segment = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+            .unwrap();
+        assert_eq!(fs::metadata(segment).unwrap().len(), expected);
+    }
+}
+
+#[cfg(feature = "serde-json")]
+#[test]
+fn test_enumerate_typed() {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Event {
+        id: u32,
+        kind: String,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let events = [
+        Event {
+            id: 1,
+            kind: "created".to_string(),
+        },
+        Event {
+            id: 2,
+            kind: "deleted".to_string(),
+        },
+    ];
+    wal.append_entry(
+        "events",
+        None,
+        Bytes::from(serde_json::to_vec(&events[0]).unwrap()),
+        false,
+    )
+    .unwrap();
+    wal.append_entry("events", None, Bytes::from("not json"), false)
+        .unwrap();
+    wal.append_entry(
+        "events",
+        None,
+        Bytes::from(serde_json::to_vec(&events[1]).unwrap()),
+        true,
+    )
+    .unwrap();
+
+    let typed: Vec<Result<Event, serde_json::Error>> =
+        wal.enumerate_typed("events").unwrap().collect();
+    assert_eq!(typed.len(), 3);
+    assert_eq!(typed[0].as_ref().unwrap(), &events[0]);
+    assert!(typed[1].is_err());
+    assert_eq!(typed[2].as_ref().unwrap(), &events[1]);
+}
+
+#[test]
+fn test_tombstones_hide_records_from_live_enumeration() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let created = wal
+        .append_entry("order:1", None, Bytes::from("created"), false)
+        .unwrap();
+    let paid = wal
+        .append_entry("order:1", None, Bytes::from("paid"), false)
+        .unwrap();
+    let other = wal
+        .append_entry("order:2", None, Bytes::from("created"), false)
+        .unwrap();
+
+    let tombstone = wal.append_tombstone("order:1", created, true).unwrap();
+    wal.append_entry("order:1", None, Bytes::from("shipped"), false)
+        .unwrap();
+
+    // Everything stays visible to the regular readers, tombstones excluded
+    let records: Vec<Bytes> = wal.enumerate_records("order:1").unwrap().collect();
+    assert_eq!(records, vec!["created", "paid", "shipped"]);
+    assert_eq!(wal.record_count_estimate("order:1").unwrap(), 3);
+    assert_eq!(wal.read_entry_at(created).unwrap(), Bytes::from("created"));
+    assert!(wal.read_entry_at(tombstone).is_err());
+
+    let live: Vec<Bytes> = wal.enumerate_live_records("order:1").unwrap().collect();
+    assert_eq!(live, vec!["paid", "shipped"]);
+
+    // Targets must be records of the same key
+    assert!(matches!(
+        wal.append_tombstone("order:1", other, false),
+        Err(WalError::EntryNotFound(_))
+    ));
+    let bogus = EntryRef {
+        offset: paid.offset + 1,
+        ..paid
+    };
+    assert!(wal.append_tombstone("order:1", bogus, false).is_err());
+
+    // Tombstones survive a restart
+    drop(wal);
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let live: Vec<Bytes> = wal.enumerate_live_records("order:1").unwrap().collect();
+    assert_eq!(live, vec!["paid", "shipped"]);
+}
+
+#[test]
+fn test_append_stream() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default().preallocate(4096)).unwrap();
+
+    let blob: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+    let streamed = wal
+        .append_stream(
+            "blobs",
+            Some(Bytes::from("meta")),
+            &blob[..],
+            blob.len() as u64,
+            true,
+        )
+        .unwrap();
+    assert_eq!(wal.read_entry_at(streamed).unwrap(), Bytes::from(blob));
+
+    // A stream that ends early leaves no trace of the record
+    let result = wal.append_stream("blobs", None, &b"short"[..], 100, true);
+    assert!(matches!(result, Err(WalError::CorruptedData(_))));
+
+    // A failing stream is rolled back the same way
+    struct FailingReader;
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disconnected"))
+        }
+    }
+    let result = wal.append_stream("blobs", None, FailingReader, 10, true);
+    assert!(matches!(result, Err(WalError::Io(_))));
+
+    let next = wal
+        .append_entry("blobs", None, Bytes::from("next"), true)
+        .unwrap();
+    assert_eq!(next.sequence_number, streamed.sequence_number);
+    drop(wal);
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("blobs").unwrap().collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1], Bytes::from("next"));
+    assert_eq!(wal.read_entry_at(next).unwrap(), Bytes::from("next"));
+}
+
+#[test]
+fn test_read_entry_reader_streams_content() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+
+    let blob: Vec<u8> = (0..500_000u32).map(|i| (i % 251) as u8).collect();
+    let big = wal
+        .append_entry(
+            "blobs",
+            Some(Bytes::from("meta")),
+            Bytes::from(blob.clone()),
+            true,
+        )
+        .unwrap();
+    let small = wal
+        .append_entry("blobs", None, Bytes::from("tail"), true)
+        .unwrap();
+
+    // The reader stops at the end of the record, before the next one
+    let mut sink = Vec::new();
+    let copied = std::io::copy(&mut wal.read_entry_reader(big).unwrap(), &mut sink).unwrap();
+    assert_eq!(copied, blob.len() as u64);
+    assert_eq!(sink, blob);
+
+    let mut tail = String::new();
+    std::io::Read::read_to_string(&mut wal.read_entry_reader(small).unwrap(), &mut tail).unwrap();
+    assert_eq!(tail, "tail");
+
+    let stale = EntryRef {
+        offset: small.offset + 1_000_000,
+        ..small
+    };
+    assert!(matches!(
+        wal.read_entry_reader(stale),
+        Err(WalError::InvalidOffset { .. })
+    ));
+}
+
+#[test]
+fn test_builder_probes_writability() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().join("wal");
+
+    let mut wal = Wal::builder(&wal_dir)
+        .retention(Duration::from_secs(600))
+        .segments_per_retention_period(6)
+        .build()
+        .unwrap();
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("data"), true)
+        .unwrap();
+    assert_eq!(wal.read_entry_at(entry_ref).unwrap(), Bytes::from("data"));
+    assert!(!wal_dir.join(".nano-wal.probe").exists());
+    wal.close().unwrap();
+
+    // A probe that cannot be written fails the build, not the first append
+    fs::create_dir(wal_dir.join(".nano-wal.probe")).unwrap();
+    let result = Wal::builder(&wal_dir)
+        .retention(Duration::from_secs(600))
+        .segments_per_retention_period(6)
+        .build();
+    assert!(matches!(result, Err(WalError::Io(_))));
+
+    // Invalid options are rejected before touching the directory
+    let result = Wal::builder(temp_dir.path().join("other"))
+        .max_open_segments(0)
+        .build();
+    assert!(matches!(result, Err(WalError::InvalidConfig(_))));
+    assert!(!temp_dir.path().join("other").exists());
+}
+
+#[test]
+fn test_replay_visits_records_in_order() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    for i in 0..5 {
+        wal.append_entry("events", None, Bytes::from(format!("event {}", i)), false)
+            .unwrap();
+    }
+
+    let mut seen = Vec::new();
+    let count = wal
+        .replay("events", |record| {
+            seen.push(record.clone());
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(count, 5);
+    assert_eq!(
+        seen,
+        wal.enumerate_records("events").unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(wal.replay("missing", |_| Ok(())).unwrap(), 0);
+
+    // An error from the callback stops the replay
+    let mut calls = 0;
+    let result = wal.replay("events", |_| {
+        calls += 1;
+        if calls == 2 {
+            return Err(WalError::InvalidConfig("stop".to_string()));
+        }
+        Ok(())
+    });
+    assert!(matches!(result, Err(WalError::InvalidConfig(msg)) if msg == "stop"));
+    assert_eq!(calls, 2);
+}
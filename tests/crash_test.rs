@@ -6,7 +6,7 @@
 
 use bytes::Bytes;
 use nano_wal::{Wal, WalOptions};
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -31,6 +31,8 @@ fn test_crash_durability() {
     // Create a shared atomic counter
     let counter = Arc::new(AtomicU16::new(0));
     let counter_clone = Arc::clone(&counter);
+    let crashed = Arc::new(AtomicBool::new(false));
+    let crashed_clone = Arc::clone(&crashed);
 
     // Create WAL for the worker thread
     let wal_dir_clone = wal_dir.to_string();
@@ -40,7 +42,7 @@ fn test_crash_durability() {
         let mut wal = Wal::new(&wal_dir_clone, WalOptions::default()).unwrap();
         let mut rng_state = 12345u32; // Simple LCG for random numbers
 
-        loop {
+        while !crashed_clone.load(Ordering::SeqCst) {
             // Simple LCG random number generator (to avoid external dependencies)
             rng_state = rng_state.wrapping_mul(1664525).wrapping_add(1013904223);
             let random_delay = 1 + (rng_state % 300); // 1-300ms
@@ -52,7 +54,7 @@ fn test_crash_durability() {
             let content = Bytes::from(format!("record-{}", count));
 
             // Append entry with durability enabled to ensure it's written to disk
-            if let Err(_) = wal.append_entry("crash-test", None, content, true) {
+            if wal.append_entry("crash-test", None, content, true).is_err() {
                 break;
             }
 
@@ -70,15 +72,14 @@ fn test_crash_durability() {
     thread::sleep(Duration::from_millis(wait_duration as u64));
     let elapsed = start_time.elapsed();
 
-    // Get the final counter value before killing the thread
-    let final_counter = counter.load(Ordering::SeqCst);
-
-    // Kill the thread abruptly - this simulates a crash
-    // We don't call join() or any cleanup, just drop the handle
-    drop(worker_handle);
+    // Stop the worker without calling shutdown() - this simulates a crash.
+    // The WAL is only dropped, which releases the directory lock just as
+    // process exit would.
+    crashed.store(true, Ordering::SeqCst);
+    worker_handle.join().unwrap();
 
-    // Give a small delay to ensure any pending file operations complete
-    thread::sleep(Duration::from_millis(100));
+    // Get the final counter value once no append is in flight
+    let final_counter = counter.load(Ordering::SeqCst);
 
     // Now verify the data integrity by reading from a fresh WAL instance
     let verification_wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
@@ -341,6 +342,8 @@ fn test_original_crash_requirements() {
     // Global shared counter as specified
     let global_counter = Arc::new(AtomicU16::new(0));
     let counter_clone = Arc::clone(&global_counter);
+    let killed = Arc::new(AtomicBool::new(false));
+    let killed_clone = Arc::clone(&killed);
 
     let wal_dir_clone = wal_dir.to_string();
 
@@ -349,7 +352,7 @@ fn test_original_crash_requirements() {
         let mut wal = Wal::new(&wal_dir_clone, WalOptions::default()).unwrap();
         let mut rng_state = 42u32; // Simple PRNG seed
 
-        loop {
+        while !killed_clone.load(Ordering::SeqCst) {
             // Generate random interval between 1-300ms
             rng_state = rng_state.wrapping_mul(1664525).wrapping_add(1013904223);
             let random_interval = 1 + (rng_state % 300);
@@ -363,7 +366,7 @@ fn test_original_crash_requirements() {
             let content = Bytes::from(format!("record-{}", current_count));
 
             // Append to WAL with the single key "crash-test"
-            if let Err(_) = wal.append_entry("crash-test", None, content, true) {
+            if wal.append_entry("crash-test", None, content, true).is_err() {
                 // If append fails, decrement counter since record wasn't written
                 counter_clone.fetch_sub(1, Ordering::SeqCst);
                 break;
@@ -379,14 +382,13 @@ fn test_original_crash_requirements() {
 
     thread::sleep(Duration::from_millis(wait_duration as u64));
 
-    // Get the final counter value before killing the thread
-    let final_counter_value = global_counter.load(Ordering::SeqCst);
-
-    // Kill the thread in the most abrupt possible way (no shutdown)
-    drop(worker_thread);
+    // Kill the thread in the most abrupt possible way (no shutdown). Dropping
+    // the WAL releases the directory lock just as process exit would.
+    killed.store(true, Ordering::SeqCst);
+    worker_thread.join().unwrap();
 
-    // Small delay to ensure any pending file operations complete
-    thread::sleep(Duration::from_millis(100));
+    // Get the final counter value once no append is in flight
+    let final_counter_value = global_counter.load(Ordering::SeqCst);
 
     // Verify that the number of records on disk matches the atomic counter
     let verification_wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
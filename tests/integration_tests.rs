@@ -298,6 +298,12 @@ fn test_cache_warming_scenario() {
     let cache_keys: Vec<String> = wal.enumerate_keys().unwrap().collect();
     assert_eq!(cache_keys.len(), 5);
 
+    // Warm the cache with the latest value of every key in one pass
+    let cache = wal.latest_records().unwrap();
+    assert_eq!(cache.len(), 5);
+    assert_eq!(cache["user:123"], user_123_entries[1]);
+    assert_eq!(cache["config:feature_flags"], config_entries[1]);
+
     wal.shutdown().unwrap();
 }
 
@@ -367,6 +373,7 @@ fn test_log_file_naming_with_meaningful_keys() {
         WalOptions {
             entry_retention: Duration::from_secs(20),
             segments_per_retention_period: 10,
+            ..Default::default()
         },
     )
     .unwrap();
@@ -1,6 +1,13 @@
 use bytes::Bytes;
-use nano_wal::{Wal, WalOptions};
+use nano_wal::{
+    Durability, HasherKind, ManualClock, MemVfs, RecordCipher, SegmentAnomaly, Vfs, VfsDirEntry,
+    VfsFile, VfsMetadata, Wal, WalError, WalOptions, RECORD_OVERHEAD,
+};
 
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use tempfile::TempDir;
@@ -226,3 +233,2155 @@ fn test_crash_recovery_with_partial_writes() {
         assert!(keys.len() >= 3, "Should have at least 3 keys available");
     }
 }
+
+#[test]
+fn test_reopen_with_different_hasher_is_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("hashed_key", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+
+    // Opening with a different hasher would orphan the existing segments
+    let result = Wal::new(
+        wal_dir,
+        WalOptions::default().hasher(HasherKind::StdDefault),
+    );
+    assert!(matches!(result, Err(WalError::InvalidConfig(_))));
+
+    // The original hasher still opens the directory and finds the data
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("hashed_key").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("data")]);
+}
+
+#[test]
+fn test_default_hasher_is_stable() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("stable", None, Bytes::from("data"), true)
+        .unwrap();
+
+    // FNV-1a of "stable" must never change between releases or toolchains
+    assert_eq!(entry_ref.key_hash, HasherKind::Fnv1a.hash_key(b"stable"));
+    assert_eq!(HasherKind::Fnv1a.hash_key(b"a"), 0xaf63dc4c8601ec8c);
+}
+
+#[test]
+fn test_export_import_key_round_trip() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+
+    let mut source = Wal::new(source_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    let first_ref = source
+        .append_entry(
+            "orders",
+            Some(Bytes::from("v1")),
+            Bytes::from("order-1"),
+            true,
+        )
+        .unwrap();
+    source
+        .append_entry("orders", None, Bytes::from("order-2"), true)
+        .unwrap();
+    source
+        .append_entry("other", None, Bytes::from("unrelated"), true)
+        .unwrap();
+
+    let mut archive = Vec::new();
+    let written = source.export_key("orders", &mut archive).unwrap();
+    assert_eq!(written, archive.len() as u64);
+
+    let mut target = Wal::new(target_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    let key = target.import_key(archive.as_slice(), false).unwrap();
+    assert_eq!(key, "orders");
+
+    let expected: Vec<Bytes> = source.enumerate_records("orders").unwrap().collect();
+    let imported: Vec<Bytes> = target.enumerate_records("orders").unwrap().collect();
+    assert_eq!(imported, expected);
+    assert_eq!(
+        target.read_entry_at(first_ref).unwrap(),
+        Bytes::from("order-1")
+    );
+    assert!(target.enumerate_records("other").unwrap().next().is_none());
+
+    // Importing an existing key requires an explicit merge
+    assert!(matches!(
+        target.import_key(archive.as_slice(), false),
+        Err(WalError::InvalidConfig(_))
+    ));
+    target.import_key(archive.as_slice(), true).unwrap();
+    let merged: Vec<Bytes> = target.enumerate_records("orders").unwrap().collect();
+    assert_eq!(merged.len(), 4);
+    assert_eq!(merged[2..], expected[..]);
+
+    // New appends land after the merged segments
+    target
+        .append_entry("orders", None, Bytes::from("order-3"), true)
+        .unwrap();
+    let records: Vec<Bytes> = target.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records.last().unwrap(), &Bytes::from("order-3"));
+}
+
+#[test]
+fn test_import_rejects_truncated_archive() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+
+    let mut source = Wal::new(source_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    source
+        .append_entry("orders", None, Bytes::from("order-1"), true)
+        .unwrap();
+
+    let mut archive = Vec::new();
+    source.export_key("orders", &mut archive).unwrap();
+    archive.truncate(archive.len() - 3);
+
+    let mut target = Wal::new(target_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    assert!(matches!(
+        target.import_key(archive.as_slice(), false),
+        Err(WalError::CorruptedData(_))
+    ));
+    assert_eq!(target.enumerate_keys().unwrap().count(), 0);
+}
+
+#[test]
+fn test_failed_import_leaves_key_untouched() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+
+    let mut source = Wal::new(source_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    for i in 0..3 {
+        source
+            .append_entry("orders", None, Bytes::from(format!("order-{}", i)), true)
+            .unwrap();
+        source.force_rotate("orders").unwrap();
+    }
+    let mut archive = Vec::new();
+    source.export_key("orders", &mut archive).unwrap();
+    let truncated = &archive[..archive.len() - 3];
+
+    // Cut inside the last segment: the first two must not be kept
+    let mut target = Wal::new(target_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    assert!(matches!(
+        target.import_key(truncated, false),
+        Err(WalError::CorruptedData(_))
+    ));
+    assert!(target.segments_for_key("orders").unwrap().is_empty());
+    assert!(target.list_orphans().unwrap().is_empty());
+    target.import_key(archive.as_slice(), false).unwrap();
+    let expected: Vec<Bytes> = source.enumerate_records("orders").unwrap().collect();
+    let imported: Vec<Bytes> = target.enumerate_records("orders").unwrap().collect();
+    assert_eq!(imported, expected);
+
+    // A failed merge keeps the active segment open and the sequence as is
+    let before = target
+        .append_entry("orders", None, Bytes::from("local-1"), true)
+        .unwrap();
+    assert!(target.import_key(truncated, true).is_err());
+    let after = target
+        .append_entry("orders", None, Bytes::from("local-2"), true)
+        .unwrap();
+    assert_eq!(after.sequence_number, before.sequence_number);
+    assert_eq!(target.segments_for_key("orders").unwrap().len(), 4);
+
+    target.import_key(archive.as_slice(), true).unwrap();
+    let merged: Vec<Bytes> = target.enumerate_records("orders").unwrap().collect();
+    assert_eq!(merged.len(), 8);
+    assert_eq!(merged[5..], expected[..]);
+}
+
+#[test]
+fn test_interrupted_segment_creation_is_recovered() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("survivor", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+
+    // A crash while writing a new segment header leaves only a partial
+    // temporary file behind, never a half-written .log
+    let partial_header = b"NANO-LOG\x01\x00\x01";
+    let tmp_path = temp_dir.path().join("crashed-123-0001.log.tmp");
+    std::fs::write(&tmp_path, partial_header).unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert!(!tmp_path.exists(), "stray .tmp file should be cleaned up");
+
+    let keys: Vec<String> = wal.enumerate_keys().unwrap().collect();
+    assert_eq!(keys, vec!["survivor".to_string()]);
+
+    wal.append_entry("crashed", None, Bytes::from("retry"), true)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("crashed").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("retry")]);
+
+    // No temporary files remain after normal segment creation
+    let leftovers = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn test_restart_resumes_active_segment() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let first_ref = {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("resumed", None, Bytes::from("before"), true)
+            .unwrap()
+    };
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let second_ref = wal
+        .append_entry("resumed", None, Bytes::from("after"), true)
+        .unwrap();
+
+    // Both records land in the same, still unexpired segment
+    assert_eq!(first_ref.sequence_number, second_ref.sequence_number);
+    assert!(second_ref.offset > first_ref.offset);
+    let log_files = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".log"))
+        .count();
+    assert_eq!(log_files, 1);
+
+    assert_eq!(wal.read_entry_at(first_ref).unwrap(), Bytes::from("before"));
+    assert_eq!(wal.read_entry_at(second_ref).unwrap(), Bytes::from("after"));
+    let records: Vec<Bytes> = wal.enumerate_records("resumed").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("before"), Bytes::from("after")]);
+}
+
+#[test]
+fn test_drop_flushes_non_durable_appends() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        for i in 0..10 {
+            wal.append_entry("lazy", None, Bytes::from(format!("item{}", i)), false)
+                .unwrap();
+        }
+        // Dropped without sync() or shutdown()
+    }
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("lazy").unwrap().collect();
+    assert_eq!(records.len(), 10);
+    assert_eq!(records[9], Bytes::from("item9"));
+}
+
+#[test]
+fn test_durability_levels_round_trip() {
+    for durability in [
+        Durability::DataOnly,
+        Durability::FullFile,
+        Durability::IncludeDir,
+    ] {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().to_str().unwrap();
+        let options = WalOptions::default().durability(durability);
+
+        {
+            let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+            wal.append_entry("key", None, Bytes::from("durable"), true)
+                .unwrap();
+            wal.append_batch(vec![("other", None, Bytes::from("batched"))], true)
+                .unwrap();
+            wal.sync().unwrap();
+        }
+
+        let wal = Wal::new(wal_dir, options).unwrap();
+        let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+        assert_eq!(records, vec![Bytes::from("durable")], "{:?}", durability);
+        assert_eq!(wal.enumerate_records("other").unwrap().count(), 1);
+    }
+}
+
+#[test]
+fn test_custom_signature() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().signature(b"ACME-WAL");
+
+    {
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+        let entry_ref = wal
+            .append_entry("key", Some(Bytes::from("h")), Bytes::from("data"), true)
+            .unwrap();
+        assert_eq!(wal.read_entry_at(entry_ref).unwrap(), Bytes::from("data"));
+    }
+
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let bytes = std::fs::read(&segment).unwrap();
+    assert_eq!(&bytes[..8], b"ACME-WAL");
+    let key_len = u64::from_le_bytes(bytes[24..32].try_into().unwrap()) as usize;
+    assert_eq!(&bytes[32 + key_len..32 + key_len + 6], b"ACMERC");
+
+    let wal = Wal::new(wal_dir, options).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("data")]);
+    drop(wal);
+
+    // A WAL expecting the default magic opens but leaves the foreign
+    // segment alone, reporting it
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 0);
+    let (keys, damaged) = wal.enumerate_keys_checked().unwrap();
+    assert!(keys.is_empty());
+    assert_eq!(damaged.len(), 1);
+    assert!(matches!(damaged[0].1, WalError::CorruptedData(_)));
+    assert!(segment.exists());
+}
+
+#[test]
+fn test_repair_segment_keeps_intact_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let entry_ref = {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        for i in 0..3 {
+            wal.append_entry(
+                "torn",
+                Some(Bytes::from("h")),
+                Bytes::from(format!("r{}", i)),
+                true,
+            )
+            .unwrap();
+        }
+        wal.append_entry("torn", None, Bytes::from("r3"), true)
+            .unwrap()
+    };
+
+    // Opening would already cut the torn record, so tear it while open
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    // Simulate a torn write: the last record lost the end of its content
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let intact_len = std::fs::metadata(&segment).unwrap().len() - (16 + 2);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&segment)
+        .unwrap();
+    file.set_len(intact_len + 10).unwrap();
+    drop(file);
+
+    let outcome = wal
+        .repair_segment(entry_ref.key_hash, entry_ref.sequence_number)
+        .unwrap();
+    assert_eq!(outcome.records_kept, 3);
+    assert_eq!(outcome.bytes_truncated, 10);
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), intact_len);
+
+    wal.append_entry("torn", None, Bytes::from("after"), true)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("torn").unwrap().collect();
+    assert_eq!(
+        records,
+        vec![
+            Bytes::from("r0"),
+            Bytes::from("r1"),
+            Bytes::from("r2"),
+            Bytes::from("after")
+        ]
+    );
+}
+
+#[test]
+fn test_inspect_segment_reports_records_and_anomalies() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let first = wal
+        .append_entry("orders", Some(Bytes::from("h")), Bytes::from("a"), true)
+        .unwrap();
+    wal.append_entry("orders", None, Bytes::from("b"), true)
+        .unwrap();
+    wal.append_tombstone("orders", first, true).unwrap();
+    wal.close().unwrap();
+
+    // Inspect a copy outside the WAL directory
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let copy_dir = TempDir::new().unwrap();
+    let copy = copy_dir.path().join(segment.file_name().unwrap());
+    std::fs::copy(&segment, &copy).unwrap();
+
+    let inspection = Wal::inspect_segment(&copy).unwrap();
+    assert_eq!(inspection.key, "orders");
+    assert_eq!(inspection.key_hash, Some(first.key_hash));
+    assert_eq!(inspection.sequence_number, Some(1));
+    assert_eq!(inspection.record_count, 2);
+    assert_eq!(inspection.tombstone_count, 1);
+    assert_eq!(inspection.records_size, inspection.body_size);
+    assert!(inspection.anomalies.is_empty());
+
+    // A record cut short by the end of the file
+    let mut bytes = std::fs::read(&copy).unwrap();
+    bytes.extend_from_slice(b"NANORC\x00");
+    std::fs::write(&copy, &bytes).unwrap();
+    let inspection = Wal::inspect_segment(&copy).unwrap();
+    assert_eq!(
+        inspection.anomalies,
+        vec![SegmentAnomaly::TornRecord {
+            offset: inspection.records_size,
+            len: 7
+        }]
+    );
+
+    // Zeroed space and unrecognized bytes after the last record
+    let zeroed = bytes.len() - 7;
+    bytes.truncate(zeroed);
+    bytes.extend_from_slice(&[0; 64]);
+    std::fs::write(&copy, &bytes).unwrap();
+    let inspection = Wal::inspect_segment(&copy).unwrap();
+    assert!(matches!(
+        inspection.anomalies[..],
+        [SegmentAnomaly::ZeroFilledTail { len: 64, .. }]
+    ));
+    bytes[zeroed + 10] = 1;
+    std::fs::write(&copy, &bytes).unwrap();
+    let inspection = Wal::inspect_segment(&copy).unwrap();
+    assert!(matches!(
+        inspection.anomalies[..],
+        [SegmentAnomaly::UnrecognizedBytes { len: 64, .. }]
+    ));
+    assert_eq!(inspection.record_count, 2);
+}
+
+#[test]
+fn test_compact_framing_mixed_with_fixed_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let large = Bytes::from(vec![7u8; 70_000]);
+    let large_header = Bytes::from(vec![1u8; 300]);
+
+    let fixed_refs = {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        vec![
+            wal.append_entry("mixed", None, Bytes::from("fixed small"), true)
+                .unwrap(),
+            wal.append_entry("mixed", Some(large_header.clone()), large.clone(), true)
+                .unwrap(),
+        ]
+    };
+
+    // A compact WAL starts a new segment rather than resuming a fixed one
+    let compact_refs = {
+        let mut wal = Wal::new(wal_dir, WalOptions::default().compact_framing(true)).unwrap();
+        let refs = vec![
+            wal.append_entry(
+                "mixed",
+                Some(Bytes::from("h")),
+                Bytes::from("compact small"),
+                true,
+            )
+            .unwrap(),
+            wal.append_entry("mixed", Some(large_header), large.clone(), true)
+                .unwrap(),
+        ];
+        assert_ne!(refs[0].sequence_number, fixed_refs[0].sequence_number);
+        // Signature plus one-byte varints for both lengths
+        assert_eq!(refs[1].offset, 6 + 1 + 1 + 1 + 13);
+        refs
+    };
+    assert_eq!(fixed_refs[1].offset, 6 + 2 + 8 + 11);
+
+    let expected = vec![
+        Bytes::from("fixed small"),
+        large.clone(),
+        Bytes::from("compact small"),
+        large.clone(),
+    ];
+    for options in [
+        WalOptions::default(),
+        WalOptions::default().compact_framing(true),
+        WalOptions::default().zero_copy_reads(true),
+    ] {
+        let wal = Wal::new(wal_dir, options).unwrap();
+        let records: Vec<Bytes> = wal.enumerate_records("mixed").unwrap().collect();
+        assert_eq!(records, expected);
+
+        for (entry_ref, content) in fixed_refs.iter().chain(&compact_refs).zip(&expected) {
+            assert_eq!(&wal.read_entry_at(*entry_ref).unwrap(), content);
+        }
+    }
+}
+
+#[test]
+fn test_corrupt_segment_header_is_skipped() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("key", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let original = std::fs::read(&segment).unwrap();
+
+    // A huge key length, a flipped key byte or a zeroed expiration is
+    // caught without failing the open, and the segment is left alone
+    let mut damages = Vec::new();
+    let mut bytes = original.clone();
+    bytes[24..32].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    damages.push(bytes);
+    let mut bytes = original.clone();
+    bytes[32] ^= 0xff;
+    damages.push(bytes);
+    let mut bytes = original.clone();
+    bytes[16..24].fill(0);
+    damages.push(bytes);
+    for bytes in damages {
+        std::fs::write(&segment, &bytes).unwrap();
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        assert_eq!(wal.enumerate_records("key").unwrap().count(), 0);
+        let (_, damaged) = wal.enumerate_keys_checked().unwrap();
+        assert_eq!(damaged.len(), 1);
+        assert!(matches!(damaged[0].1, WalError::CorruptedData(_)));
+        wal.compact().unwrap();
+        assert!(segment.exists());
+    }
+
+    // Appends go to a new segment, after the damaged one once it is restored
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("more"), true)
+        .unwrap();
+    assert_eq!(entry_ref.sequence_number, 2);
+    drop(wal);
+    std::fs::write(&segment, &original).unwrap();
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("data"), Bytes::from("more")]);
+}
+
+#[test]
+fn test_preallocated_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().preallocate(4096);
+
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    let refs: Vec<_> = (0..3)
+        .map(|i| {
+            wal.append_entry("key", None, Bytes::from(format!("record {}", i)), true)
+                .unwrap()
+        })
+        .collect();
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let logical_len = 32 + 3 + refs[2].offset + 6 + 2 + 8 + 8;
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), 32 + 3 + 4096);
+    assert_eq!(wal.read_entry_at(refs[1]).unwrap(), Bytes::from("record 1"));
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 3);
+
+    // Keep a copy of the file as a crash would leave it
+    let crashed = std::fs::read(&segment).unwrap();
+    drop(wal);
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), logical_len);
+
+    std::fs::write(&segment, &crashed).unwrap();
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 3);
+    let next = wal
+        .append_entry("key", None, Bytes::from("after crash"), true)
+        .unwrap();
+    assert_eq!(next.offset, logical_len - 32 - 3);
+
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3], Bytes::from("after crash"));
+}
+
+#[test]
+fn test_empty_segment_files_are_quarantined() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("key", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+    let empty = temp_dir.path().join("lost-1234-0001.log");
+    let short = temp_dir.path().join("torn-5678-0001.log");
+    std::fs::write(&empty, b"").unwrap();
+    std::fs::write(&short, b"NANO-LOG").unwrap();
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert!(!empty.exists());
+    assert!(!short.exists());
+    let mut quarantined = wal.quarantined_segments().to_vec();
+    quarantined.sort();
+    assert_eq!(
+        quarantined,
+        vec![
+            temp_dir.path().join("lost-1234-0001.log.corrupt"),
+            temp_dir.path().join("torn-5678-0001.log.corrupt"),
+        ]
+    );
+
+    let keys: Vec<String> = wal.enumerate_keys().unwrap().collect();
+    assert_eq!(keys, vec!["key".to_string()]);
+    let (_, damaged) = wal.enumerate_keys_checked().unwrap();
+    assert!(damaged.is_empty());
+}
+
+#[test]
+fn test_rotation_syncs_sealed_segment() {
+    for sync_on_rotate in [true, false] {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().to_str().unwrap();
+        let clock = Arc::new(ManualClock::new(1_000));
+        let options = WalOptions::with_retention(Duration::from_secs(10))
+            .segments_per_retention_period(10)
+            .sync_on_rotate(sync_on_rotate)
+            .clock(clock.clone());
+
+        {
+            let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+            let opened = wal.metrics().fsyncs;
+            for i in 0..3 {
+                wal.append_entry("events", None, Bytes::from(format!("before {}", i)), false)
+                    .unwrap();
+            }
+            // Creating the first segment is all the appends synced
+            let before = wal.metrics().fsyncs;
+            let creation = before - opened;
+
+            // Time-based rotation seals the segment holding the records
+            clock.advance(Duration::from_secs(1));
+            let after = wal
+                .append_entry("events", None, Bytes::from("after"), false)
+                .unwrap();
+            assert_eq!(after.sequence_number, 2);
+            let rotation_syncs = wal.metrics().fsyncs - before - creation;
+            assert_eq!(rotation_syncs, u64::from(sync_on_rotate));
+
+            // A forced rotation of an already synced segment costs nothing
+            wal.sync().unwrap();
+            let synced = wal.metrics().fsyncs;
+            wal.force_rotate("events").unwrap();
+            assert_eq!(wal.metrics().fsyncs, synced + creation);
+        }
+
+        let wal = Wal::new(wal_dir, options).unwrap();
+        let records: Vec<Bytes> = wal.enumerate_records("events").unwrap().collect();
+        assert_eq!(records, vec!["before 0", "before 1", "before 2", "after"]);
+    }
+}
+
+#[test]
+fn test_list_and_purge_orphans() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        wal.append_entry("key", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+    let empty = temp_dir.path().join("lost-1234-0001.log");
+    let segment_tmp = temp_dir.path().join("crashed-123-0001.log.tmp");
+    let manifest_tmp = temp_dir.path().join(".nano-wal.manifest.tmp");
+    let unrelated = temp_dir.path().join("notes.tmp");
+    std::fs::write(&empty, b"").unwrap();
+    std::fs::write(&segment_tmp, b"partial").unwrap();
+    std::fs::write(&manifest_tmp, b"partial").unwrap();
+    std::fs::write(&unrelated, b"mine").unwrap();
+
+    // Opening removes temporary files but keeps the quarantined segment
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert!(!segment_tmp.exists());
+    assert!(!manifest_tmp.exists());
+    let corrupt = temp_dir.path().join("lost-1234-0001.log.corrupt");
+    assert_eq!(wal.list_orphans().unwrap(), vec![corrupt.clone()]);
+
+    // A temporary file appearing later is listed too
+    std::fs::write(&segment_tmp, b"partial").unwrap();
+    assert_eq!(
+        wal.list_orphans().unwrap(),
+        vec![segment_tmp.clone(), corrupt.clone()]
+    );
+
+    assert_eq!(wal.purge_orphans().unwrap(), 2);
+    assert!(!corrupt.exists());
+    assert!(wal.quarantined_segments().is_empty());
+    assert!(wal.list_orphans().unwrap().is_empty());
+    assert_eq!(wal.purge_orphans().unwrap(), 0);
+
+    // Files the WAL did not write are left alone
+    assert!(unrelated.exists());
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("data")]);
+    drop(wal);
+
+    // A leftover that cannot be removed does not stop the WAL from opening
+    let stuck = temp_dir.path().join("stuck-123-0001.log.tmp");
+    std::fs::create_dir(&stuck).unwrap();
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert_eq!(wal.list_orphans().unwrap(), vec![stuck]);
+}
+
+/// Test cipher that prefixes the associated data and flips every bit,
+/// for records up to 1 KiB.
+#[derive(Debug)]
+struct TaggingCipher;
+
+impl RecordCipher for TaggingCipher {
+    fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut sealed = aad.to_vec();
+        sealed.extend(plaintext.iter().map(|b| !b));
+        sealed
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> nano_wal::Result<Vec<u8>> {
+        match ciphertext.strip_prefix(aad) {
+            Some(body) => Ok(body.iter().map(|b| !b).collect()),
+            None => Err(WalError::CorruptedData("aad mismatch".to_string())),
+        }
+    }
+
+    fn max_plaintext_len(&self) -> u64 {
+        1024
+    }
+}
+
+#[test]
+fn test_encrypted_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let plain = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let encrypted = plain.clone().encryption(Box::new(TaggingCipher));
+
+    // A plaintext segment, then encrypted ones in a new segment
+    {
+        let mut wal = Wal::new(wal_dir, plain.clone()).unwrap();
+        wal.append_entry("user", None, Bytes::from("plain@example.com"), true)
+            .unwrap();
+    }
+    let mut wal = Wal::new(wal_dir, encrypted.clone()).unwrap();
+    let secret = wal
+        .append_entry(
+            "user",
+            Some(Bytes::from("meta")),
+            Bytes::from("secret@example.com"),
+            true,
+        )
+        .unwrap();
+    clock.advance(Duration::from_secs(1));
+    let mut batch = wal.begin_batch();
+    batch
+        .add("user", None, Bytes::from("batched@example.com"))
+        .unwrap();
+    let batched = batch.commit(true).unwrap()[0];
+    assert_ne!(secret.sequence_number, batched.sequence_number);
+
+    let expected = vec![
+        Bytes::from("plain@example.com"),
+        Bytes::from("secret@example.com"),
+        Bytes::from("batched@example.com"),
+    ];
+    let records: Vec<Bytes> = wal.enumerate_records("user").unwrap().collect();
+    assert_eq!(records, expected);
+    assert_eq!(
+        wal.read_entry_at(secret).unwrap(),
+        Bytes::from("secret@example.com")
+    );
+    assert_eq!(
+        wal.read_entry_at(batched).unwrap(),
+        Bytes::from("batched@example.com")
+    );
+    let mut reader = wal.reader_for("user").unwrap();
+    assert_eq!(reader.read_at(secret).unwrap(), expected[1]);
+    let snapshot = wal.freeze().unwrap();
+    assert_eq!(snapshot.enumerate_records("user").unwrap().count(), 3);
+
+    // Content never reaches the disk in plaintext
+    for entry in std::fs::read_dir(wal_dir).unwrap() {
+        let data = std::fs::read(entry.unwrap().path()).unwrap();
+        assert!(!data.windows(6).any(|w| w == b"secret" || w == b"batche"));
+    }
+
+    // Merging re-encrypts records for their new offsets
+    assert_eq!(wal.compact_and_merge("user").unwrap(), 3);
+    let records: Vec<Bytes> = wal.enumerate_records("user").unwrap().collect();
+    assert_eq!(records, expected);
+    drop(wal);
+
+    let wal = Wal::new(wal_dir, encrypted.zero_copy_reads(true)).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("user").unwrap().collect();
+    assert_eq!(records, expected);
+    drop(wal);
+
+    // Without a cipher, encrypted segments are refused rather than misread
+    let wal = Wal::new(wal_dir, plain).unwrap();
+    assert_eq!(wal.enumerate_records("user").unwrap().count(), 0);
+    assert!(matches!(
+        wal.try_enumerate_records("user").unwrap().next(),
+        Some(Err(WalError::InvalidConfig(_)))
+    ));
+    assert!(matches!(
+        wal.reader_for("user").unwrap().next(),
+        Err(WalError::InvalidConfig(_))
+    ));
+    assert!(wal.contains_key("user").unwrap());
+}
+
+#[test]
+fn test_encrypted_record_bound_to_its_offset() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().encryption(Box::new(TaggingCipher));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let first = wal
+        .append_entry("key", None, Bytes::from("aaaa"), true)
+        .unwrap();
+    let second = wal
+        .append_entry("key", None, Bytes::from("bbbb"), true)
+        .unwrap();
+    drop(wal);
+
+    // Swap the two equally sized records on disk
+    let path = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    let mut data = std::fs::read(&path).unwrap();
+    let body = data.len() - 2 * (second.offset - first.offset) as usize;
+    let frame = (second.offset - first.offset) as usize;
+    let (a, b) = data[body..].split_at_mut(frame);
+    a.swap_with_slice(b);
+    std::fs::write(&path, data).unwrap();
+
+    let options = WalOptions::default().encryption(Box::new(TaggingCipher));
+    let wal = Wal::new(wal_dir, options).unwrap();
+    assert!(matches!(
+        wal.read_entry_at(first),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_encrypted_record_bound_to_its_segment() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = || WalOptions::default().encryption(Box::new(TaggingCipher));
+    let mut wal = Wal::new(wal_dir, options()).unwrap();
+
+    let first = wal
+        .append_entry("key", None, Bytes::from("aaaa"), true)
+        .unwrap();
+    wal.force_rotate("key").unwrap();
+    let second = wal
+        .append_entry("key", None, Bytes::from("bbbb"), true)
+        .unwrap();
+    assert_eq!(first.offset, second.offset);
+    let paths: Vec<_> = wal
+        .segments_for_key("key")
+        .unwrap()
+        .into_iter()
+        .map(|segment| segment.path)
+        .collect();
+    drop(wal);
+
+    // Both records sit at offset 0 of their segments; swap them
+    let start = first.absolute_offset("key".len()) as usize;
+    let mut first_data = std::fs::read(&paths[0]).unwrap();
+    let mut second_data = std::fs::read(&paths[1]).unwrap();
+    first_data[start..].swap_with_slice(&mut second_data[start..]);
+    std::fs::write(&paths[0], first_data).unwrap();
+    std::fs::write(&paths[1], second_data).unwrap();
+
+    let wal = Wal::new(wal_dir, options()).unwrap();
+    assert!(matches!(
+        wal.read_entry_at(first),
+        Err(WalError::CorruptedData(_))
+    ));
+    assert!(matches!(
+        wal.read_entry_at(second),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_encrypted_import_merge_and_content_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().encryption(Box::new(TaggingCipher));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    wal.append_entry("key", None, Bytes::from("first"), true)
+        .unwrap();
+    let mut archive = Vec::new();
+    wal.export_key("key", &mut archive).unwrap();
+
+    // Merged segments get new sequence numbers and are encrypted for them
+    wal.import_key(archive.as_slice(), true).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec!["first", "first"]);
+    assert!(wal.list_orphans().unwrap().is_empty());
+
+    // Content the cipher cannot encrypt is refused up front
+    let too_long = Bytes::from(vec![0u8; 1025]);
+    assert!(matches!(
+        wal.append_entry("key", None, too_long, true),
+        Err(WalError::InvalidConfig(_))
+    ));
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 2);
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_aes_256_gcm_cipher() {
+    use nano_wal::Aes256GcmCipher;
+
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().encryption(Box::new(Aes256GcmCipher::new(&[1u8; 32])));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let entry_ref = wal
+        .append_entry("ip", None, Bytes::from("192.168.0.1"), true)
+        .unwrap();
+    assert_eq!(
+        wal.read_entry_at(entry_ref).unwrap(),
+        Bytes::from("192.168.0.1")
+    );
+    assert_eq!(wal.entry_size_at(entry_ref).unwrap(), 11 + 28);
+    drop(wal);
+
+    let options = WalOptions::default().encryption(Box::new(Aes256GcmCipher::new(&[2u8; 32])));
+    let wal = Wal::new(wal_dir, options).unwrap();
+    assert!(matches!(
+        wal.read_entry_at(entry_ref),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_reopen_with_different_options_is_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let original = WalOptions::default().segments_per_retention_period(10);
+    let changed = WalOptions::default().segments_per_retention_period(168);
+
+    {
+        let mut wal = Wal::new(wal_dir, original.clone()).unwrap();
+        wal.append_entry("key", None, Bytes::from("data"), true)
+            .unwrap();
+    }
+
+    match Wal::new(wal_dir, changed.clone()) {
+        Err(WalError::InvalidConfig(msg)) => {
+            assert!(msg.contains("segments_per_retention_period 10 (configured 168)"));
+        }
+        other => panic!("expected InvalidConfig, got {:?}", other),
+    }
+    assert!(matches!(
+        Wal::new(
+            wal_dir,
+            original.clone().retention(Duration::from_secs(3600))
+        ),
+        Err(WalError::InvalidConfig(_))
+    ));
+    drop(Wal::new(wal_dir, original).unwrap());
+
+    // The escape hatch accepts the change and records the new options
+    drop(Wal::new(wal_dir, changed.clone().allow_option_change(true)).unwrap());
+    let wal = Wal::new(wal_dir, changed.clone()).unwrap();
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 1);
+    drop(wal);
+
+    // A directory without a manifest adopts the options it is opened with
+    let manifest = temp_dir.path().join(".nano-wal.manifest");
+    std::fs::remove_file(&manifest).unwrap();
+    drop(Wal::new(wal_dir, WalOptions::default()).unwrap());
+    assert!(manifest.exists());
+    assert!(matches!(
+        Wal::new(wal_dir, changed),
+        Err(WalError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_close_keeps_data_and_releases_lock() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default().preallocate(4096);
+
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    let entry_ref = wal
+        .append_entry("kept", None, Bytes::from("survives close"), false)
+        .unwrap();
+    wal.close().unwrap();
+
+    // The original `Wal` is still alive, but the directory is free
+    let reopened = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(
+        reopened.read_entry_at(entry_ref).unwrap(),
+        Bytes::from("survives close")
+    );
+
+    // Preallocated space was trimmed
+    let segment = std::fs::read_dir(wal_dir)
+        .unwrap()
+        .flatten()
+        .find(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .unwrap();
+    assert!(segment.metadata().unwrap().len() < 4096);
+    drop(wal);
+}
+
+#[test]
+fn test_reopen_scans_many_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(1_000))
+        .segments_per_retention_period(1_000)
+        .clock(clock.clone());
+
+    {
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+        for round in 0..20 {
+            for key in 0..10 {
+                wal.append_entry(format!("key{}", key), None, Bytes::from("x"), false)
+                    .unwrap();
+            }
+            if round % 2 == 0 {
+                clock.advance(Duration::from_secs(1));
+            }
+        }
+    }
+
+    clock.advance(Duration::from_secs(1));
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    let mut keys: Vec<String> = wal.enumerate_keys().unwrap().collect();
+    keys.sort();
+    let expected: Vec<String> = (0..10).map(|key| format!("key{}", key)).collect();
+    assert_eq!(keys, expected);
+    assert_eq!(
+        wal.enumerate_keys_with_prefix("key")
+            .unwrap()
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    // Every key continues after its highest existing sequence
+    for key in &expected {
+        let entry_ref = wal
+            .append_entry(key.as_str(), None, Bytes::from("y"), false)
+            .unwrap();
+        assert_eq!(entry_ref.sequence_number, 12);
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_parallel_enumeration_keeps_order() {
+    let temp_dir = TempDir::new().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(60))
+        .segments_per_retention_period(60)
+        .clock(clock.clone());
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), options).unwrap();
+
+    // Twenty segments of three records each
+    let mut expected = Vec::new();
+    for segment in 0..20 {
+        for record in 0..3 {
+            let content = Bytes::from(format!("{}-{}", segment, record));
+            wal.append_entry("sensor", None, content.clone(), false)
+                .unwrap();
+            expected.push(content);
+        }
+        clock.advance(Duration::from_secs(1));
+    }
+    wal.append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+    assert_eq!(wal.stats().unwrap().total_segments, 21);
+
+    assert_eq!(wal.enumerate_records_parallel("sensor").unwrap(), expected);
+    let serial: Vec<Bytes> = wal.enumerate_records("sensor").unwrap().collect();
+    assert_eq!(serial, expected);
+    assert!(wal
+        .enumerate_records_parallel("missing")
+        .unwrap()
+        .is_empty());
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_contains_record_by_content_hash() {
+    use sha2::{Digest, Sha256};
+
+    let hash = |content: &str| -> [u8; 32] { Sha256::digest(content).into() };
+    for indexed in [false, true] {
+        let temp_dir = TempDir::new().unwrap();
+        let options = WalOptions::default().content_index(indexed);
+        let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), options).unwrap();
+
+        let first = wal
+            .append_entry("invoices", None, Bytes::from("inv-1"), false)
+            .unwrap();
+        wal.append_entry("invoices", None, Bytes::from("inv-1"), false)
+            .unwrap();
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-1")).unwrap(),
+            Some(first)
+        );
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-2")).unwrap(),
+            None
+        );
+        assert_eq!(wal.contains_record("other", &hash("inv-1")).unwrap(), None);
+
+        // Appends after the key was looked up are found too
+        let second = wal
+            .append_entry(
+                "invoices",
+                Some(Bytes::from("meta")),
+                Bytes::from("inv-2"),
+                false,
+            )
+            .unwrap();
+        let grouped = wal
+            .append_group("invoices", &[(None, Bytes::from("inv-3"))], false)
+            .unwrap();
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-2")).unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-3")).unwrap(),
+            Some(grouped[0])
+        );
+
+        // Truncation drops records the index must no longer report
+        wal.truncate_after(first).unwrap();
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-2")).unwrap(),
+            None
+        );
+        assert_eq!(
+            wal.contains_record("invoices", &hash("inv-1")).unwrap(),
+            Some(first)
+        );
+    }
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_digest_ignores_segmentation() {
+    let primary_dir = TempDir::new().unwrap();
+    let replica_dir = TempDir::new().unwrap();
+    let records = [
+        (None, "created"),
+        (Some(Bytes::from("v2")), "updated"),
+        (None, "shipped"),
+    ];
+
+    // The primary keeps every record in one segment
+    let mut primary =
+        Wal::new(primary_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+    for (header, content) in records.iter().cloned() {
+        primary
+            .append_entry("order", header, Bytes::from(content), false)
+            .unwrap();
+    }
+    primary
+        .append_entry("customer", None, Bytes::from("alice"), false)
+        .unwrap();
+
+    // The replica rotates between records and frames them differently
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(30))
+        .segments_per_retention_period(3)
+        .compact_framing(true)
+        .clock(clock.clone());
+    let mut replica = Wal::new(replica_dir.path().to_str().unwrap(), options).unwrap();
+    for (header, content) in records.iter().cloned() {
+        replica
+            .append_entry("order", header, Bytes::from(content), false)
+            .unwrap();
+        clock.advance(Duration::from_secs(10));
+    }
+    assert_eq!(replica.stats().unwrap().total_segments, 3);
+
+    assert_eq!(
+        primary.digest_key("order").unwrap(),
+        replica.digest_key("order").unwrap()
+    );
+    let primary_digests = primary.digest_all().unwrap();
+    assert_ne!(primary_digests, replica.digest_all().unwrap());
+
+    replica
+        .append_entry("customer", None, Bytes::from("alice"), false)
+        .unwrap();
+    assert_eq!(primary_digests, replica.digest_all().unwrap());
+
+    replica
+        .append_entry("order", None, Bytes::from("returned"), false)
+        .unwrap();
+    assert_ne!(
+        primary.digest_key("order").unwrap(),
+        replica.digest_key("order").unwrap()
+    );
+}
+
+#[test]
+fn test_shard_dirs_keep_old_layouts_readable() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(100))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+
+    // A flat directory written before sharding
+    let flat = {
+        let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+        wal.append_entry("flat", None, Bytes::from("f1"), true)
+            .unwrap()
+    };
+
+    let mut wal = Wal::new(wal_dir, options.clone().shard_dirs(4)).unwrap();
+    let resumed = wal
+        .append_entry("flat", None, Bytes::from("f2"), true)
+        .unwrap();
+    assert_eq!(resumed.sequence_number, flat.sequence_number);
+    let mut refs = Vec::new();
+    for i in 0..20 {
+        let key = format!("key{}", i);
+        refs.push(
+            wal.append_entry(&key, None, Bytes::from(key.clone()), true)
+                .unwrap(),
+        );
+    }
+
+    // New segments live in subdirectories named by the low key hash bits
+    for entry_ref in &refs {
+        let shard = temp_dir
+            .path()
+            .join(format!("{:x}", entry_ref.key_hash & 0xf));
+        assert!(std::fs::read_dir(shard).unwrap().any(|e| e
+            .unwrap()
+            .file_name()
+            .to_str()
+            .unwrap()
+            .contains(&format!("-{}-", entry_ref.key_hash))));
+    }
+    assert_eq!(wal.read_entry_at(flat).unwrap(), Bytes::from("f1"));
+    assert_eq!(wal.read_entry_at(refs[7]).unwrap(), Bytes::from("key7"));
+    assert_eq!(wal.enumerate_keys().unwrap().count(), 21);
+    wal.close().unwrap();
+
+    // Reopening with a different shard width still finds every segment
+    let mut wal = Wal::new(wal_dir, options.shard_dirs(8)).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("flat").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("f1"), Bytes::from("f2")]);
+    for (i, entry_ref) in refs.iter().enumerate() {
+        assert_eq!(
+            wal.read_entry_at(*entry_ref).unwrap(),
+            Bytes::from(format!("key{}", i))
+        );
+        assert!(wal.contains_key(format!("key{}", i)).unwrap());
+    }
+    let next = wal
+        .append_entry("key3", None, Bytes::from("again"), true)
+        .unwrap();
+    assert_eq!(next.sequence_number, refs[3].sequence_number);
+    assert_eq!(wal.stats().unwrap().total_segments, 21);
+
+    clock.advance(Duration::from_secs(20));
+    wal.compact().unwrap();
+    assert_eq!(wal.stats().unwrap().total_segments, 0);
+}
+
+#[test]
+fn test_shard_dirs_limit() {
+    assert!(WalOptions::default().shard_dirs(16).validate().is_ok());
+    assert!(matches!(
+        WalOptions::default().shard_dirs(17).validate(),
+        Err(WalError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_append_group_is_all_or_nothing() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("txn", None, Bytes::from("before"), true)
+        .unwrap();
+    let refs = wal
+        .append_group(
+            "txn",
+            &[
+                (Some(Bytes::from("h")), Bytes::from("debit")),
+                (None, Bytes::from("credit")),
+            ],
+            true,
+        )
+        .unwrap();
+    assert_eq!(refs.len(), 2);
+    assert_eq!(wal.read_entry_at(refs[1]).unwrap(), Bytes::from("credit"));
+    let records: Vec<Bytes> = wal.enumerate_records("txn").unwrap().collect();
+    assert_eq!(records, vec!["before", "debit", "credit"]);
+    assert!(wal.append_group("txn", &[], true).unwrap().is_empty());
+    let segment = wal.segments_for_key("txn").unwrap()[0].path.clone();
+    wal.close().unwrap();
+
+    // A crash before the commit marker reached the disk
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&segment)
+        .unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - RECORD_OVERHEAD as u64).unwrap();
+    drop(file);
+
+    let inspection = Wal::inspect_segment(&segment).unwrap();
+    assert_eq!(inspection.record_count, 1);
+    assert!(matches!(
+        inspection.anomalies[..],
+        [SegmentAnomaly::UncommittedGroup { offset, .. }]
+            if offset == refs[0].offset - (RECORD_OVERHEAD as u64 + 8)
+    ));
+
+    for zero_copy in [false, true] {
+        let wal = Wal::new(wal_dir, WalOptions::default().zero_copy_reads(zero_copy)).unwrap();
+        let records: Vec<Bytes> = wal.enumerate_records("txn").unwrap().collect();
+        assert_eq!(records, vec!["before"]);
+    }
+
+    // The next append overwrites the uncommitted group
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("txn", None, Bytes::from("after"), true)
+        .unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("txn").unwrap().collect();
+    assert_eq!(records, vec!["before", "after"]);
+}
+
+#[test]
+fn test_newer_format_version_is_unsupported() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("key", None, Bytes::from("data"), true)
+        .unwrap();
+    let segment = wal.segments_for_key("key").unwrap()[0].path.clone();
+    wal.close().unwrap();
+
+    // A manifest written by a newer release
+    let manifest = temp_dir.path().join(".nano-wal.manifest");
+    let original = std::fs::read_to_string(&manifest).unwrap();
+    let newer = original
+        .lines()
+        .map(|line| {
+            if line.starts_with("format_version=") {
+                "format_version=999"
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    std::fs::write(&manifest, newer).unwrap();
+    assert!(matches!(
+        Wal::new(wal_dir, WalOptions::default()),
+        Err(WalError::UnsupportedFormat { found: 999, .. })
+    ));
+    std::fs::write(&manifest, original).unwrap();
+
+    // A segment whose version field, right after the signature, is newer
+    let mut bytes = std::fs::read(&segment).unwrap();
+    bytes[8..10].copy_from_slice(&999u16.to_le_bytes());
+    std::fs::write(&segment, &bytes).unwrap();
+    let err = Wal::new(wal_dir, WalOptions::default()).unwrap_err();
+    assert!(matches!(
+        err,
+        WalError::UnsupportedFormat { found: 999, supported } if supported < 999
+    ));
+    assert!(err.to_string().contains("newer nano-wal"));
+    assert!(matches!(
+        Wal::inspect_segment(&segment),
+        Err(WalError::UnsupportedFormat { found: 999, .. })
+    ));
+}
+
+#[test]
+fn test_append_idempotent_survives_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let (first, duplicate) = wal
+        .append_idempotent("orders", 7, 1, None, Bytes::from("a"), true)
+        .unwrap();
+    assert!(!duplicate);
+    let (retry, duplicate) = wal
+        .append_idempotent("orders", 7, 1, None, Bytes::from("a"), true)
+        .unwrap();
+    assert!(duplicate);
+    assert_eq!(retry, first);
+    // Producers are tracked independently
+    let (_, duplicate) = wal
+        .append_idempotent("orders", 8, 1, None, Bytes::from("b"), true)
+        .unwrap();
+    assert!(!duplicate);
+    let (second, _) = wal
+        .append_idempotent("orders", 7, 2, None, Bytes::from("c"), true)
+        .unwrap();
+    wal.close().unwrap();
+
+    // The last-seen sequences are rebuilt from the segment
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let (retry, duplicate) = wal
+        .append_idempotent("orders", 7, 2, None, Bytes::from("c"), true)
+        .unwrap();
+    assert!(duplicate);
+    assert_eq!(retry, second);
+    assert!(matches!(
+        wal.append_idempotent("orders", 7, 1, None, Bytes::from("a"), true),
+        Err(WalError::InvalidConfig(_))
+    ));
+    let records: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec!["a", "b", "c"]);
+    assert_eq!(wal.read_entry_at(second).unwrap(), "c");
+
+    wal.append_idempotent("orders", 7, 3, None, Bytes::from("d"), true)
+        .unwrap();
+    let segment = wal.segments_for_key("orders").unwrap()[0].path.clone();
+    wal.close().unwrap();
+
+    // A torn record leaves its marker behind, which must not count as seen
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&segment)
+        .unwrap();
+    file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+    drop(file);
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let (_, duplicate) = wal
+        .append_idempotent("orders", 7, 3, None, Bytes::from("d"), true)
+        .unwrap();
+    assert!(!duplicate);
+    let records: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec!["a", "b", "c", "d"]);
+}
+
+#[test]
+fn test_open_truncates_torn_final_record() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    wal.append_entry("key", None, Bytes::from("first"), true)
+        .unwrap();
+    wal.append_entry("key", None, Bytes::from("second"), true)
+        .unwrap();
+    let segment = wal.segments_for_key("key").unwrap()[0].path.clone();
+    wal.close().unwrap();
+    let intact_len = std::fs::metadata(&segment).unwrap().len();
+
+    // A record that claims 100 bytes of content but got only 10 to disk
+    let mut torn = b"NANORC".to_vec();
+    torn.extend_from_slice(&0u16.to_le_bytes());
+    torn.extend_from_slice(&100u64.to_le_bytes());
+    torn.extend_from_slice(&[0xAB; 10]);
+    let mut bytes = std::fs::read(&segment).unwrap();
+    bytes.extend_from_slice(&torn);
+    std::fs::write(&segment, &bytes).unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), intact_len);
+
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("third"), true)
+        .unwrap();
+    assert_eq!(wal.read_entry_at(entry_ref).unwrap(), "third");
+    wal.close().unwrap();
+
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec!["first", "second", "third"]);
+    let inspection = Wal::inspect_segment(&segment).unwrap();
+    assert!(inspection.anomalies.is_empty());
+}
+
+#[test]
+fn test_reopen_keeps_records_after_corrupted_frame() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let refs: Vec<_> = (0..5)
+        .map(|i| {
+            wal.append_entry("key", None, Bytes::from(format!("record-{}", i)), true)
+                .unwrap()
+        })
+        .collect();
+    let segment = wal.segments_for_key("key").unwrap()[0].path.clone();
+    let key_len = "key".len();
+    wal.close().unwrap();
+
+    // Flip a signature byte of the second record
+    let mut bytes = std::fs::read(&segment).unwrap();
+    bytes[refs[1].absolute_offset(key_len) as usize] ^= 0xFF;
+    std::fs::write(&segment, &bytes).unwrap();
+    let corrupted_len = bytes.len() as u64;
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("after"), true)
+        .unwrap();
+    assert_ne!(entry_ref.sequence_number, refs[0].sequence_number);
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), corrupted_len);
+
+    for (i, entry_ref) in refs.iter().enumerate().skip(2) {
+        assert_eq!(
+            wal.read_entry_at(*entry_ref).unwrap(),
+            format!("record-{}", i)
+        );
+    }
+    assert_eq!(wal.read_entry_at(entry_ref).unwrap(), "after");
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_compressed_segments_stay_readable() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let content = Bytes::from("compressible payload ".repeat(50));
+
+    let mut wal = Wal::new(path, WalOptions::default()).unwrap();
+    let mut refs = Vec::new();
+    for _ in 0..2 {
+        for _ in 0..4 {
+            refs.push(
+                wal.append_entry("orders", Some(Bytes::from("h")), content.clone(), false)
+                    .unwrap(),
+            );
+        }
+        wal.force_rotate("orders").unwrap();
+    }
+    let snapshot = wal.freeze().unwrap();
+
+    let saved = wal.compress_sealed_segments().unwrap();
+    assert!(saved > 0);
+    assert_eq!(wal.compress_sealed_segments().unwrap(), 0);
+    let mut names: Vec<String> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("orders-"))
+        .collect();
+    names.sort();
+    assert!(names[0].ends_with("-0001.logz"));
+    assert!(names[1].ends_with("-0002.logz"));
+    assert!(names[2].ends_with("-0003.log"));
+
+    for entry_ref in &refs {
+        assert_eq!(wal.read_entry_at(*entry_ref).unwrap(), content);
+        assert_eq!(wal.entry_size_at(*entry_ref).unwrap(), content.len() as u64);
+        assert_eq!(snapshot.read_entry_at(*entry_ref).unwrap(), content);
+    }
+    assert_eq!(wal.enumerate_records("orders").unwrap().count(), 8);
+    assert_eq!(wal.stats().unwrap().total_records, 8);
+    let inspection = Wal::inspect_segment(&temp_dir.path().join(&names[0])).unwrap();
+    assert!(inspection.compressed);
+    assert_eq!(inspection.record_count, 4);
+    assert!(inspection.anomalies.is_empty());
+    drop(wal);
+
+    // Appends continue in the uncompressed segment after a reopen
+    let mut wal = Wal::new(path, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("orders", None, Bytes::from("after"), true)
+        .unwrap();
+    assert_eq!(entry_ref.sequence_number, 3);
+    assert_eq!(wal.enumerate_records("orders").unwrap().count(), 9);
+    assert_eq!(wal.read_entry_at(refs[5]).unwrap(), content);
+
+    // Truncating into a compressed segment writes it back uncompressed
+    wal.truncate_after(refs[1]).unwrap();
+    assert!(temp_dir
+        .path()
+        .join(names[0].trim_end_matches('z'))
+        .exists());
+    assert!(!temp_dir.path().join(&names[0]).exists());
+    assert_eq!(wal.enumerate_records("orders").unwrap().count(), 2);
+}
+
+#[test]
+fn test_wal_over_in_memory_vfs() {
+    let vfs = Arc::new(MemVfs::new());
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+
+    let (ref1, ref2) = {
+        let mut wal = Wal::with_vfs("/wal", options.clone(), vfs.clone()).unwrap();
+        let ref1 = wal
+            .append_entry("user", None, Bytes::from("first"), true)
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+        let ref2 = wal
+            .append_entry("user", None, Bytes::from("second"), true)
+            .unwrap();
+        assert_ne!(ref1.sequence_number, ref2.sequence_number);
+
+        // The directory lock lives in the vfs as well
+        let locked = Wal::with_vfs("/wal", options.clone(), vfs.clone());
+        assert!(matches!(locked, Err(WalError::InvalidConfig(_))));
+        (ref1, ref2)
+    };
+
+    let segments = vfs
+        .read_dir(Path::new("/wal"))
+        .unwrap()
+        .into_iter()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .count();
+    assert_eq!(segments, 2);
+
+    let mut wal = Wal::with_vfs("/wal", options, vfs.clone()).unwrap();
+    assert_eq!(wal.read_entry_at(ref1).unwrap(), Bytes::from("first"));
+    assert_eq!(wal.read_entry_at(ref2).unwrap(), Bytes::from("second"));
+    let records: Vec<Bytes> = wal.enumerate_records("user").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("first"), Bytes::from("second")]);
+    assert_eq!(wal.stats().unwrap().total_records, 2);
+
+    // Expired segments are removed from the vfs by compaction
+    clock.advance(Duration::from_secs(30));
+    wal.compact().unwrap();
+    assert!(wal.enumerate_records("user").unwrap().next().is_none());
+    assert!(vfs.metadata(Path::new("/wal")).unwrap().is_dir);
+}
+
+/// A vfs whose writes fail once a byte budget runs out, after writing
+/// the part of the buffer that still fit, like a disk filling up.
+#[derive(Debug)]
+struct FailingVfs {
+    inner: MemVfs,
+    budget: Arc<AtomicI64>,
+}
+
+#[derive(Debug)]
+struct FailingFile {
+    inner: Box<dyn VfsFile>,
+    budget: Arc<AtomicI64>,
+}
+
+impl Read for FailingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for FailingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let budget = self.budget.fetch_sub(buf.len() as i64, Ordering::SeqCst);
+        if budget < buf.len() as i64 {
+            self.inner.write_all(&buf[..budget.max(0) as usize])?;
+            self.budget.store(0, Ordering::SeqCst);
+            return Err(io::Error::other("out of space"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for FailingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl VfsFile for FailingFile {
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        self.inner.sync_all()
+    }
+
+    fn try_lock(&self) -> io::Result<bool> {
+        self.inner.try_lock()
+    }
+}
+
+impl FailingVfs {
+    fn wrap(&self, file: Box<dyn VfsFile>) -> Box<dyn VfsFile> {
+        Box::new(FailingFile {
+            inner: file,
+            budget: self.budget.clone(),
+        })
+    }
+}
+
+impl Vfs for FailingVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.inner.open(path)
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.inner.open_write(path).map(|file| self.wrap(file))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.inner.create(path).map(|file| self.wrap(file))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.inner.create_new(path).map(|file| self.wrap(file))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<VfsDirEntry>> {
+        self.inner.read_dir(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        self.inner.metadata(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.sync_dir(path)
+    }
+}
+
+#[test]
+fn test_failed_writes_do_not_corrupt_later_appends() {
+    let budget = Arc::new(AtomicI64::new(i64::MAX));
+    let vfs = Arc::new(FailingVfs {
+        inner: MemVfs::new(),
+        budget: budget.clone(),
+    });
+    let mut wal = Wal::with_vfs("/wal", WalOptions::default(), vfs).unwrap();
+    wal.append_entry("key", None, Bytes::from("first"), false)
+        .unwrap();
+
+    // A small record fails when it is flushed, a large one while written
+    for content in [Bytes::from("lost"), Bytes::from(vec![7u8; 64 * 1024])] {
+        budget.store(10, Ordering::SeqCst);
+        assert!(wal.append_entry("key", None, content, false).is_err());
+        budget.store(i64::MAX, Ordering::SeqCst);
+    }
+
+    budget.store(10, Ordering::SeqCst);
+    let batch = vec![("key", None, Bytes::from("a")), ("key", None, Bytes::from("b"))];
+    let error = wal.append_batch(batch, false).unwrap_err();
+    assert!(error.written.is_empty());
+    budget.store(i64::MAX, Ordering::SeqCst);
+
+    // A complete record buffered ahead of a failed one is kept intact,
+    // and dropped as a whole if the batch cannot be flushed either
+    for budget_left in [100, 10] {
+        budget.store(budget_left, Ordering::SeqCst);
+        let batch = vec![
+            ("key", None, Bytes::from("kept")),
+            ("key", None, Bytes::from(vec![7u8; 64 * 1024])),
+        ];
+        let error = wal.append_batch(batch, false).unwrap_err();
+        budget.store(i64::MAX, Ordering::SeqCst);
+        for entry_ref in &error.written {
+            assert_eq!(wal.read_entry_at(*entry_ref).unwrap(), "kept");
+        }
+        assert_eq!(error.written.len(), usize::from(budget_left == 100));
+    }
+
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("second"), false)
+        .unwrap();
+    assert_eq!(wal.read_entry_at(entry_ref).unwrap(), "second");
+
+    budget.store(10, Ordering::SeqCst);
+    assert!(wal.append_tombstone("key", entry_ref, false).is_err());
+    budget.store(i64::MAX, Ordering::SeqCst);
+    wal.append_tombstone("key", entry_ref, false).unwrap();
+
+    let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+    assert_eq!(records, vec!["first", "kept", "second"]);
+    assert_eq!(wal.enumerate_live_records("key").unwrap().count(), 2);
+}
+
+#[test]
+fn test_checkpoint_records_latest_entry_per_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let checkpoint_path = temp_dir.path().join(".nano-wal.checkpoint");
+
+    let last_order = {
+        let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+        assert!(wal.read_checkpoint().unwrap().is_empty());
+
+        wal.append_entry("orders", None, Bytes::from("o1"), false)
+            .unwrap();
+        let last_order = wal
+            .append_entry("orders", None, Bytes::from("o2"), false)
+            .unwrap();
+        let last_user = wal
+            .append_entry("users", None, Bytes::from("u1"), false)
+            .unwrap();
+        wal.write_checkpoint().unwrap();
+
+        let checkpoint = wal.read_checkpoint().unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        assert_eq!(checkpoint["orders"], last_order);
+        assert_eq!(checkpoint["users"], last_user);
+        assert!(!temp_dir.path().join(".nano-wal.checkpoint.tmp").exists());
+
+        // Later appends are not in the checkpoint until it is rewritten
+        wal.append_entry("orders", None, Bytes::from("o3"), false)
+            .unwrap();
+        assert_eq!(wal.read_checkpoint().unwrap()["orders"], last_order);
+        last_order
+    };
+
+    // After a restart, a consumer resumes past the checkpointed record
+    let wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let resume_from = wal.read_checkpoint().unwrap()["orders"];
+    assert_eq!(resume_from, last_order);
+    let remaining: Vec<Bytes> = wal
+        .enumerate_records_from("orders", resume_from)
+        .unwrap()
+        .collect();
+    assert_eq!(remaining, vec![Bytes::from("o3")]);
+    drop(wal);
+
+    // By default, sync and close keep it current
+    let options = WalOptions::default();
+    let mut wal = Wal::new(wal_dir, options.clone()).unwrap();
+    let synced = wal
+        .append_entry("orders", None, Bytes::from("o4"), false)
+        .unwrap();
+    wal.sync().unwrap();
+    assert_eq!(wal.read_checkpoint().unwrap()["orders"], synced);
+    let closed = wal
+        .append_entry("users", None, Bytes::from("u2"), false)
+        .unwrap();
+    wal.close().unwrap();
+    let wal = Wal::new(wal_dir, options).unwrap();
+    assert_eq!(wal.read_checkpoint().unwrap()["users"], closed);
+
+    // A damaged checkpoint is reported rather than trusted
+    let mut contents = std::fs::read(&checkpoint_path).unwrap();
+    let last = contents.len() - 1;
+    contents[last] ^= 0xff;
+    std::fs::write(&checkpoint_path, contents).unwrap();
+    assert!(matches!(
+        wal.read_checkpoint(),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_checkpoint_syncs_closed_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let options = WalOptions::default()
+        .max_open_segments(1)
+        .sync_on_rotate(false);
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    // Appending to `users` closes the `orders` segment without syncing it
+    let last_order = wal
+        .append_entry("orders", None, Bytes::from("o1"), false)
+        .unwrap();
+    wal.append_entry("users", None, Bytes::from("u1"), false)
+        .unwrap();
+
+    // The first checkpoint syncs both segments, the next one neither
+    let before = wal.metrics().fsyncs;
+    wal.write_checkpoint().unwrap();
+    let first = wal.metrics().fsyncs - before;
+    let before = wal.metrics().fsyncs;
+    wal.write_checkpoint().unwrap();
+    let second = wal.metrics().fsyncs - before;
+    assert_eq!(first, second + 2);
+    assert_eq!(wal.read_checkpoint().unwrap()["orders"], last_order);
+}
+
+#[test]
+fn test_checkpoint_on_sync_is_kept_in_memory() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    for i in 0..20 {
+        wal.append_entry(format!("key{}", i), None, Bytes::from("v1"), false)
+            .unwrap();
+    }
+    wal.sync().unwrap();
+    assert_eq!(wal.read_checkpoint().unwrap().len(), 20);
+
+    // Later syncs write the checkpoint without opening any segment,
+    // across rotations too
+    let opened = wal.metrics().segments_opened;
+    let latest = wal
+        .append_entry("key3", None, Bytes::from("v2"), false)
+        .unwrap();
+    wal.sync().unwrap();
+    clock.advance(Duration::from_secs(1));
+    let rotated = wal
+        .append_batch(vec![("key4", None, Bytes::from("v2"))], true)
+        .unwrap();
+    wal.force_rotate("key4").unwrap();
+    wal.sync().unwrap();
+    assert_eq!(wal.metrics().segments_opened, opened);
+    let checkpoint = wal.read_checkpoint().unwrap();
+    assert_eq!(checkpoint["key3"], latest);
+    assert_eq!(checkpoint["key4"], rotated[0]);
+
+    // Keys whose records expire drop out once compaction removes them
+    clock.advance(Duration::from_secs(60));
+    let kept = wal
+        .append_entry("key5", None, Bytes::from("v3"), false)
+        .unwrap();
+    wal.compact().unwrap();
+    wal.sync().unwrap();
+    let checkpoint = wal.read_checkpoint().unwrap();
+    assert_eq!(checkpoint.len(), 1);
+    assert_eq!(checkpoint["key5"], kept);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_checkpoint_skips_unreadable_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let content = Bytes::from("compressible payload ".repeat(50));
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let first = wal
+        .append_entry("orders", None, content.clone(), false)
+        .unwrap();
+    wal.force_rotate("orders").unwrap();
+    wal.append_entry("orders", None, content.clone(), false)
+        .unwrap();
+    wal.force_rotate("orders").unwrap();
+    wal.compress_sealed_segments().unwrap();
+
+    // Damage the compressed body of the second segment, keeping its header
+    let damaged = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with("-0002.logz"))
+        .unwrap();
+    let mut contents = std::fs::read(&damaged).unwrap();
+    contents.truncate(contents.len() - 8);
+    std::fs::write(&damaged, contents).unwrap();
+
+    // The checkpoint falls back to the last record of the first segment
+    wal.sync().unwrap();
+    assert_eq!(wal.read_checkpoint().unwrap()["orders"], first);
+}
+
+#[test]
+fn test_promote_swaps_in_staged_wal() {
+    let temp_dir = TempDir::new().unwrap();
+    let target = temp_dir.path().join("wal");
+    let staging = temp_dir.path().join("wal.rebuilt");
+    let backup = temp_dir.path().join("wal.backup");
+
+    let mut live = Wal::new(target.to_str().unwrap(), WalOptions::default()).unwrap();
+    live.append_entry("orders", None, Bytes::from("old"), true)
+        .unwrap();
+    let mut rebuilt = Wal::new(staging.to_str().unwrap(), WalOptions::default()).unwrap();
+    rebuilt
+        .append_entry("orders", None, Bytes::from("new"), true)
+        .unwrap();
+
+    // Neither directory may be open while it moves
+    assert!(matches!(
+        Wal::promote(&staging, &target, WalOptions::default()),
+        Err(WalError::InvalidConfig(_))
+    ));
+    rebuilt.close().unwrap();
+    assert!(matches!(
+        Wal::promote(&staging, &target, WalOptions::default()),
+        Err(WalError::InvalidConfig(_))
+    ));
+    live.close().unwrap();
+
+    let mut promoted = Wal::promote(&staging, &target, WalOptions::default()).unwrap();
+    assert!(!staging.exists());
+    let records: Vec<Bytes> = promoted.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("new")]);
+    promoted
+        .append_entry("orders", None, Bytes::from("newer"), true)
+        .unwrap();
+    promoted.close().unwrap();
+
+    let old = Wal::new(backup.to_str().unwrap(), WalOptions::default()).unwrap();
+    let records: Vec<Bytes> = old.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec![Bytes::from("old")]);
+    drop(old);
+
+    // A backup from the previous promotion is never overwritten
+    Wal::new(staging.to_str().unwrap(), WalOptions::default())
+        .unwrap()
+        .close()
+        .unwrap();
+    assert!(matches!(
+        Wal::promote(&staging, &target, WalOptions::default()),
+        Err(WalError::InvalidConfig(_))
+    ));
+    assert!(staging.exists());
+    let wal = Wal::new(target.to_str().unwrap(), WalOptions::default()).unwrap();
+    assert_eq!(wal.enumerate_records("orders").unwrap().count(), 2);
+}
+
+#[test]
+fn test_single_file_wal_round_trips() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("events.nwal");
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::default()
+        .retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+
+    let (order, user) = {
+        let mut wal = Wal::open_single_file(&path, options.clone()).unwrap();
+        assert!(matches!(
+            Wal::open_single_file(&path, options.clone()),
+            Err(WalError::InvalidConfig(_))
+        ));
+        let order = wal
+            .append_entry("orders", None, Bytes::from("o1"), false)
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+        wal.append_entry("orders", None, Bytes::from("o2"), false)
+            .unwrap();
+        let user = wal
+            .append_entry("users", Some(Bytes::from("h")), Bytes::from("u1"), true)
+            .unwrap();
+        wal.append_entry("bulk", None, Bytes::from(vec![7u8; 300_000]), true)
+            .unwrap();
+        wal.close().unwrap();
+        (order, user)
+    };
+
+    // Everything lives in the one file
+    let names: Vec<_> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["events.nwal"]);
+
+    // A torn operation at the end is dropped on open
+    let mut contents = std::fs::read(&path).unwrap();
+    contents.extend_from_slice(&[0xab; 7]);
+    std::fs::write(&path, contents).unwrap();
+
+    let mut wal = Wal::open_single_file(&path, options.clone()).unwrap();
+    assert_eq!(wal.read_entry_at(order).unwrap(), Bytes::from("o1"));
+    assert_eq!(wal.read_entry_at(user).unwrap(), Bytes::from("u1"));
+    let orders: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(orders, vec!["o1", "o2"]);
+    assert_eq!(wal.enumerate_keys().unwrap().count(), 3);
+    wal.append_entry("orders", None, Bytes::from("o3"), true)
+        .unwrap();
+
+    // Once most of the data has expired, compaction rewrites the file
+    let full_len = std::fs::metadata(&path).unwrap().len();
+    clock.advance(Duration::from_secs(20));
+    wal.compact().unwrap();
+    assert!(std::fs::metadata(&path).unwrap().len() < full_len / 2);
+    assert!(matches!(
+        Wal::open_single_file(&path, options.clone()),
+        Err(WalError::InvalidConfig(_))
+    ));
+    wal.append_entry("orders", None, Bytes::from("o4"), true)
+        .unwrap();
+    drop(wal);
+    let wal = Wal::open_single_file(&path, options).unwrap();
+    let orders: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(orders, vec!["o4"]);
+
+    std::fs::write(temp_dir.path().join("other"), b"not a wal").unwrap();
+    assert!(matches!(
+        Wal::open_single_file(temp_dir.path().join("other"), WalOptions::default()),
+        Err(WalError::CorruptedData(_))
+    ));
+}
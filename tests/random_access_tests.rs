@@ -1,5 +1,7 @@
 use bytes::Bytes;
-use nano_wal::{EntryRef, Wal, WalOptions};
+use nano_wal::{Entry, EntryRef, ManualClock, RecordKind, Wal, WalError, WalOptions};
+use std::sync::Arc;
+use std::time::Duration;
 
 use tempfile::TempDir;
 
@@ -166,6 +168,75 @@ fn test_read_entry_at_nonexistent_segment() {
     drop(wal);
 }
 
+#[test]
+fn test_read_entry_at_offset_out_of_range() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("data"), true)
+        .unwrap();
+    let segment_len = wal.entry_size_at(entry_ref).unwrap() + 16;
+
+    // Past the end of the segment is a stale ref, not corruption
+    let stale_ref = EntryRef {
+        offset: segment_len,
+        ..entry_ref
+    };
+    assert!(matches!(
+        wal.read_entry_at(stale_ref),
+        Err(WalError::InvalidOffset { offset, segment_len: len })
+            if offset == segment_len && len == segment_len
+    ));
+    assert!(matches!(
+        wal.entry_size_at(stale_ref),
+        Err(WalError::InvalidOffset { .. })
+    ));
+
+    // Inside the segment but not on a record boundary is corruption
+    let misaligned_ref = EntryRef {
+        offset: 1,
+        ..entry_ref
+    };
+    assert!(matches!(
+        wal.read_entry_at(misaligned_ref),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_read_entry_at_corrupt_content_len() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+    let entry_ref = wal
+        .append_entry("key", None, Bytes::from("payload"), true)
+        .unwrap();
+    let path = wal.segments_for_key("key").unwrap()[0].path.clone();
+
+    // The 8-byte content length sits just before the content
+    let mut bytes = std::fs::read(&path).unwrap();
+    let content_start = bytes
+        .windows(7)
+        .position(|window| window == b"payload")
+        .unwrap();
+    bytes[content_start - 8..content_start].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+
+    // Rejected before allocating, not a panic or an abort
+    assert!(matches!(
+        wal.read_entry_at(entry_ref),
+        Err(WalError::CorruptedData(_))
+    ));
+    assert!(matches!(
+        wal.entry_size_at(entry_ref),
+        Err(WalError::CorruptedData(_))
+    ));
+    assert_eq!(wal.enumerate_records("key").unwrap().count(), 0);
+}
+
 #[test]
 fn test_read_entry_at_with_different_key_types() {
     let temp_dir = TempDir::new().unwrap();
@@ -241,6 +312,7 @@ fn test_entry_ref_across_segment_rotation() {
         WalOptions {
             entry_retention: std::time::Duration::from_secs(10),
             segments_per_retention_period: 10,
+            ..Default::default()
         },
     )
     .unwrap();
@@ -308,7 +380,7 @@ fn test_random_access_performance_characteristics() {
     for i in 0..100 {
         let data = Bytes::from(format!("entry_{}", i));
         let entry_ref = wal
-            .append_entry(&format!("key_{}", i), None, data, false)
+            .append_entry(format!("key_{}", i), None, data, false)
             .unwrap();
         refs.push(entry_ref);
     }
@@ -324,3 +396,581 @@ fn test_random_access_performance_characteristics() {
 
     wal.shutdown().unwrap();
 }
+
+#[test]
+fn test_iter_all_records_refs_match_appends() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let ref_b1 = wal
+        .append_entry("beta", Some(Bytes::from("meta")), Bytes::from("b1"), false)
+        .unwrap();
+    let ref_a1 = wal
+        .append_entry("alpha", None, Bytes::from("a1"), false)
+        .unwrap();
+    let ref_b2 = wal
+        .append_entry("beta", None, Bytes::from("b2"), true)
+        .unwrap();
+
+    let all: Vec<(String, EntryRef, Bytes)> = wal
+        .iter_all_records()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(
+        all,
+        vec![
+            ("alpha".to_string(), ref_a1, Bytes::from("a1")),
+            ("beta".to_string(), ref_b1, Bytes::from("b1")),
+            ("beta".to_string(), ref_b2, Bytes::from("b2")),
+        ]
+    );
+
+    // Yielded refs are usable for random access
+    for (_, entry_ref, content) in all {
+        assert_eq!(wal.read_entry_at(entry_ref).unwrap(), content);
+    }
+
+    wal.shutdown().unwrap();
+}
+
+#[test]
+fn test_entry_size_at() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let small_ref = wal
+        .append_entry("sizes", Some(Bytes::from("meta")), Bytes::from("abc"), true)
+        .unwrap();
+    let large_ref = wal
+        .append_entry("sizes", None, Bytes::from(vec![7u8; 4096]), true)
+        .unwrap();
+    let empty_ref = wal.append_entry("sizes", None, Bytes::new(), true).unwrap();
+
+    assert_eq!(wal.entry_size_at(small_ref).unwrap(), 3);
+    assert_eq!(wal.entry_size_at(large_ref).unwrap(), 4096);
+    assert_eq!(wal.entry_size_at(empty_ref).unwrap(), 0);
+
+    let missing = EntryRef {
+        sequence_number: 999,
+        ..small_ref
+    };
+    assert!(matches!(
+        wal.entry_size_at(missing),
+        Err(WalError::EntryNotFound(_))
+    ));
+
+    let misaligned = EntryRef {
+        offset: small_ref.offset + 1,
+        ..small_ref
+    };
+    assert!(matches!(
+        wal.entry_size_at(misaligned),
+        Err(WalError::CorruptedData(_))
+    ));
+
+    wal.shutdown().unwrap();
+}
+
+#[test]
+fn test_record_reader_spans_segments() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let mut refs = Vec::new();
+    for i in 0..6 {
+        if i == 3 {
+            clock.advance(Duration::from_secs(1));
+        }
+        refs.push(
+            wal.append_entry("stream", None, Bytes::from(format!("r{}", i)), false)
+                .unwrap(),
+        );
+    }
+    wal.append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+    assert_ne!(refs[0].sequence_number, refs[5].sequence_number);
+
+    let mut reader = wal.reader_for("stream").unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = reader.next().unwrap() {
+        records.push(record);
+    }
+    let expected: Vec<Bytes> = (0..6).map(|i| Bytes::from(format!("r{}", i))).collect();
+    assert_eq!(records, expected);
+    assert_eq!(reader.next().unwrap(), None);
+
+    // Random access switches segments and resumes sequential reads after it
+    assert_eq!(reader.read_at(refs[4]).unwrap(), Bytes::from("r4"));
+    assert_eq!(reader.read_at(refs[1]).unwrap(), Bytes::from("r1"));
+    assert_eq!(reader.next().unwrap(), Some(Bytes::from("r2")));
+    assert_eq!(reader.next().unwrap(), Some(Bytes::from("r3")));
+
+    reader.seek_to(refs[5]).unwrap();
+    assert_eq!(reader.next().unwrap(), Some(Bytes::from("r5")));
+    assert_eq!(reader.next().unwrap(), None);
+
+    let past_end = EntryRef {
+        offset: 10_000,
+        ..refs[0]
+    };
+    assert!(matches!(
+        reader.read_at(past_end),
+        Err(WalError::InvalidOffset { offset: 10_000, .. })
+    ));
+    let foreign = wal
+        .append_entry("other", None, Bytes::from("y"), false)
+        .unwrap();
+    assert!(matches!(
+        reader.seek_to(foreign),
+        Err(WalError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn test_read_nth_record() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    assert_eq!(wal.read_nth_record("stream", 0).unwrap(), None);
+
+    for i in 0..6 {
+        if i == 2 || i == 5 {
+            clock.advance(Duration::from_secs(1));
+        }
+        let header = Bytes::from(format!("h{}", i));
+        wal.append_entry(
+            "stream",
+            Some(header),
+            Bytes::from(format!("r{}", i)),
+            false,
+        )
+        .unwrap();
+        wal.append_entry("other", None, Bytes::from("x"), false)
+            .unwrap();
+    }
+
+    for i in 0..6 {
+        assert_eq!(
+            wal.read_nth_record("stream", i).unwrap(),
+            Some(Bytes::from(format!("r{}", i)))
+        );
+    }
+    assert_eq!(wal.read_nth_record("stream", 6).unwrap(), None);
+    assert_eq!(wal.read_nth_record("stream", u64::MAX).unwrap(), None);
+}
+
+#[test]
+fn test_read_records_page() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(10))
+        .segments_per_retention_period(10)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    assert_eq!(
+        wal.read_records_page("stream", 0, 10).unwrap(),
+        (vec![], false)
+    );
+
+    for i in 0..7 {
+        if i == 3 {
+            clock.advance(Duration::from_secs(1));
+        }
+        wal.append_entry("stream", None, Bytes::from(format!("r{}", i)), false)
+            .unwrap();
+    }
+    let page = |skip: u64, limit: u64| {
+        let (records, has_more) = wal.read_records_page("stream", skip, limit).unwrap();
+        let records: Vec<String> = records
+            .iter()
+            .map(|r| String::from_utf8(r.to_vec()).unwrap())
+            .collect();
+        (records, has_more)
+    };
+
+    assert_eq!(
+        page(0, 3),
+        (vec!["r0".into(), "r1".into(), "r2".into()], true)
+    );
+    assert_eq!(
+        page(2, 3),
+        (vec!["r2".into(), "r3".into(), "r4".into()], true)
+    );
+    assert_eq!(
+        page(4, 3),
+        (vec!["r4".into(), "r5".into(), "r6".into()], false)
+    );
+    assert_eq!(page(6, 10), (vec!["r6".into()], false));
+    assert_eq!(page(7, 10), (vec![], false));
+    assert_eq!(page(3, 0), (vec![], true));
+}
+
+#[test]
+fn test_open_segment_yields_raw_records() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let first = wal
+        .append_entry("index", Some(Bytes::from("h1")), Bytes::from("one"), false)
+        .unwrap();
+    let second = wal
+        .append_entry("index", None, Bytes::from("two"), false)
+        .unwrap();
+    wal.sync().unwrap();
+
+    let mut segment = wal
+        .open_segment(first.key_hash, first.sequence_number)
+        .unwrap();
+    assert_eq!(segment.key(), b"index");
+    assert!(segment.expiration_timestamp() > 0);
+    assert_eq!(segment.key_hash(), first.key_hash);
+    assert_eq!(segment.sequence_number(), first.sequence_number);
+
+    let (offset, header, content) = segment.next().unwrap().unwrap();
+    assert_eq!(segment.entry_ref(offset), first);
+    assert_eq!((header, content), (Bytes::from("h1"), Bytes::from("one")));
+
+    let (offset, header, content) = segment.next().unwrap().unwrap();
+    assert_eq!(segment.entry_ref(offset), second);
+    assert_eq!((header, content), (Bytes::new(), Bytes::from("two")));
+    assert!(segment.next().unwrap().is_none());
+
+    assert!(matches!(
+        wal.open_segment(first.key_hash, first.sequence_number + 1),
+        Err(WalError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn test_enumerate_records_with_refs() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(30))
+        .segments_per_retention_period(3)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let mut appended = Vec::new();
+    for i in 0..6 {
+        let header = (i % 2 == 0).then(|| Bytes::from("h"));
+        let content = Bytes::from(format!("record {}", i));
+        let entry_ref = wal
+            .append_entry("orders", header, content.clone(), false)
+            .unwrap();
+        appended.push((entry_ref, content));
+        if i % 2 == 1 {
+            clock.advance(Duration::from_secs(10));
+        }
+    }
+    wal.append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+
+    let enumerated: Vec<(EntryRef, Bytes)> =
+        wal.enumerate_records_with_refs("orders").unwrap().collect();
+    assert_eq!(enumerated, appended);
+    assert_eq!(enumerated[5].0.sequence_number, 3);
+    for (entry_ref, content) in &enumerated {
+        assert_eq!(&wal.read_entry_at(*entry_ref).unwrap(), content);
+    }
+}
+
+#[test]
+fn test_read_entry_meta_at_reports_active_segment() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let options = WalOptions::with_retention(Duration::from_secs(60))
+        .segments_per_retention_period(1)
+        .clock(clock.clone());
+
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+    let first = wal
+        .append_entry("feed", Some(Bytes::from("h1")), Bytes::from("one"), true)
+        .unwrap();
+    let entry = wal.read_entry_meta_at(first).unwrap();
+    assert_eq!(entry.header, Some(Bytes::from("h1")));
+    assert_eq!(entry.content, Bytes::from("one"));
+    assert!(entry.read_from_active);
+
+    // Rotation seals the first segment
+    clock.advance(Duration::from_secs(61));
+    let second = wal
+        .append_entry("feed", None, Bytes::from("two"), true)
+        .unwrap();
+    assert!(!wal.read_entry_meta_at(first).unwrap().read_from_active);
+    let entry = wal.read_entry_meta_at(second).unwrap();
+    assert_eq!(entry.header, None);
+    assert!(entry.read_from_active);
+
+    let misaligned = EntryRef {
+        offset: second.offset + 1,
+        ..second
+    };
+    assert!(matches!(
+        wal.read_entry_meta_at(misaligned),
+        Err(WalError::CorruptedData(_))
+    ));
+}
+
+#[test]
+fn test_find_refs_for_key_after_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = || {
+        WalOptions::with_retention(Duration::from_secs(30))
+            .segments_per_retention_period(3)
+            .clock(clock.clone())
+    };
+    let mut wal = Wal::new(wal_dir, options()).unwrap();
+
+    let mut appended = Vec::new();
+    for i in 0..4 {
+        let content = Bytes::from(format!("record {}", i));
+        appended.push(wal.append_entry("orders", None, content, false).unwrap());
+        clock.advance(Duration::from_secs(5));
+    }
+    // Tombstones and group markers are not records
+    wal.append_tombstone("orders", appended[0], false).unwrap();
+    let group = vec![(None, Bytes::from("g1")), (None, Bytes::from("g2"))];
+    appended.extend(wal.append_group("orders", &group, true).unwrap());
+    wal.append_entry("other", None, Bytes::from("x"), true)
+        .unwrap();
+    drop(wal);
+
+    let wal = Wal::new(wal_dir, options()).unwrap();
+    let refs = wal.find_refs_for_key("orders").unwrap();
+    assert_eq!(refs, appended);
+    assert!(refs.iter().any(|r| r.sequence_number > 1));
+    assert_eq!(wal.read_entry_at(refs[5]).unwrap(), "g2");
+    assert!(wal.find_refs_for_key("missing").unwrap().is_empty());
+}
+
+#[test]
+fn test_read_your_writes_without_durability() {
+    for zero_copy in [false, true] {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().to_str().unwrap();
+        let options = WalOptions::default()
+            .zero_copy_reads(zero_copy)
+            .preallocate(4096);
+        let mut wal = Wal::new(wal_dir, options).unwrap();
+
+        // Every write method, none of them durable
+        let mut refs = vec![wal
+            .append_entry("key", None, Bytes::from("a"), false)
+            .unwrap()];
+        refs.extend(
+            wal.append_batch(vec![("key", None, Bytes::from("b"))], false)
+                .unwrap(),
+        );
+        let mut batch = wal.begin_batch();
+        batch.add("key", None, Bytes::from("c")).unwrap();
+        refs.extend(batch.commit(false).unwrap());
+        refs.extend(
+            wal.append_group("key", &[(None, Bytes::from("d"))], false)
+                .unwrap(),
+        );
+        refs.push(wal.append_stream("key", None, &b"e"[..], 1, false).unwrap());
+        let (entry_ref, _) = wal
+            .append_idempotent("key", 1, 1, None, Bytes::from("f"), false)
+            .unwrap();
+        refs.push(entry_ref);
+
+        let expected = ["a", "b", "c", "d", "e", "f"];
+        for (entry_ref, content) in refs.iter().zip(expected) {
+            assert_eq!(wal.read_entry_at(*entry_ref).unwrap(), content);
+        }
+        let records: Vec<Bytes> = wal.enumerate_records("key").unwrap().collect();
+        assert_eq!(records, expected);
+        let mut reader = wal.reader_for("key").unwrap();
+        for content in expected {
+            assert_eq!(reader.next().unwrap().unwrap(), content);
+        }
+        assert!(reader.next().unwrap().is_none());
+    }
+}
+
+#[test]
+fn test_enumerate_records_from_checkpoint() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let options = WalOptions::with_retention(Duration::from_secs(30))
+        .segments_per_retention_period(3)
+        .clock(clock.clone());
+    let mut wal = Wal::new(wal_dir, options).unwrap();
+
+    let mut refs = Vec::new();
+    for i in 0..6 {
+        let content = Bytes::from(format!("record {}", i));
+        refs.push(wal.append_entry("orders", None, content, false).unwrap());
+        if i % 2 == 1 {
+            clock.advance(Duration::from_secs(10));
+        }
+    }
+    let other = wal
+        .append_entry("other", None, Bytes::from("x"), false)
+        .unwrap();
+
+    // Resuming mid-segment continues through the later segments
+    let resumed: Vec<Bytes> = wal
+        .enumerate_records_from("orders", refs[2])
+        .unwrap()
+        .collect();
+    let expected: Vec<Bytes> = (3..6)
+        .map(|i| Bytes::from(format!("record {}", i)))
+        .collect();
+    assert_eq!(resumed, expected);
+
+    // A checkpoint at the last record of a segment starts the next one
+    let resumed: Vec<Bytes> = wal
+        .enumerate_records_from("orders", refs[3])
+        .unwrap()
+        .collect();
+    assert_eq!(resumed, expected[1..]);
+
+    assert_eq!(
+        wal.enumerate_records_from("orders", refs[5])
+            .unwrap()
+            .count(),
+        0
+    );
+    assert!(matches!(
+        wal.enumerate_records_from("orders", other),
+        Err(WalError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn test_key_for_ref() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_dir = temp_dir.path().to_str().unwrap();
+    let mut wal = Wal::new(wal_dir, WalOptions::default()).unwrap();
+
+    let order = wal
+        .append_entry("orders/eu", None, Bytes::from("a"), true)
+        .unwrap();
+    let user = wal
+        .append_entry("user:42", None, Bytes::from("b"), true)
+        .unwrap();
+    assert_eq!(wal.key_for_ref(order).unwrap(), "orders/eu");
+    assert_eq!(wal.key_for_ref(user).unwrap(), "user:42");
+
+    let missing = EntryRef {
+        sequence_number: 99,
+        ..order
+    };
+    assert!(matches!(
+        wal.key_for_ref(missing),
+        Err(WalError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn test_enumerate_entries_reports_kinds() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+
+    let first = wal
+        .append_entry("orders", Some(Bytes::from("v1")), Bytes::from("a"), false)
+        .unwrap();
+    let tombstone = wal.append_tombstone("orders", first, false).unwrap();
+    let group = [(None, Bytes::from("b")), (None, Bytes::from("c"))];
+    let grouped = wal.append_group("orders", &group, false).unwrap();
+    let last = wal
+        .append_entry("orders", None, Bytes::from("d"), false)
+        .unwrap();
+
+    let entries: Vec<Entry> = wal
+        .enumerate_entries("orders")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let kinds: Vec<RecordKind> = entries.iter().map(|entry| entry.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            RecordKind::Data,
+            RecordKind::Tombstone,
+            RecordKind::GroupBegin,
+            RecordKind::Data,
+            RecordKind::Data,
+            RecordKind::GroupCommit,
+            RecordKind::Data,
+        ]
+    );
+    assert_eq!(entries[0].entry_ref, first);
+    assert_eq!(entries[0].header, Some(Bytes::from("v1")));
+    assert_eq!(entries[1].entry_ref, tombstone);
+    assert_eq!(entries[2].content.len(), 8);
+    assert_eq!(entries[3].entry_ref, grouped[0]);
+    assert_eq!(entries[4].content, Bytes::from("c"));
+    assert!(entries[5].content.is_empty());
+    assert_eq!(entries[6].entry_ref, last);
+
+    // Plain enumeration still sees data records only
+    let records: Vec<Bytes> = wal.enumerate_records("orders").unwrap().collect();
+    assert_eq!(records, vec!["a", "b", "c", "d"]);
+}
+
+#[test]
+fn test_absolute_offsets_point_into_segment_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut wal = Wal::new(temp_dir.path().to_str().unwrap(), WalOptions::default()).unwrap();
+
+    wal.append_entry("orders", None, Bytes::from("first"), false)
+        .unwrap();
+    let second = wal
+        .append_entry(
+            "orders",
+            Some(Bytes::from("h")),
+            Bytes::from("second"),
+            true,
+        )
+        .unwrap();
+    let absolute = second.absolute_offset("orders".len());
+    assert_eq!(
+        absolute,
+        Wal::file_header_size(b"orders") as u64 + second.offset
+    );
+
+    // The raw file has the record frame at that position
+    let path = wal.segments_for_key("orders").unwrap()[0].path.clone();
+    let raw = std::fs::read(path).unwrap();
+    assert_eq!(&raw[absolute as usize..absolute as usize + 4], b"NANO");
+    assert!(raw[absolute as usize..]
+        .windows(6)
+        .any(|window| window == b"second"));
+
+    let content = wal
+        .read_entry_at_absolute(second.key_hash, second.sequence_number, absolute)
+        .unwrap();
+    assert_eq!(content, Bytes::from("second"));
+
+    // Inside the file header is not a record
+    assert!(matches!(
+        wal.read_entry_at_absolute(second.key_hash, second.sequence_number, 8),
+        Err(WalError::InvalidOffset { offset: 8, .. })
+    ));
+}